@@ -0,0 +1,407 @@
+//! The `--ast` dump: renders a parsed program as an indented tree or as
+//! parenthesized s-expressions, for debugging the parser, or as JSON for
+//! other tools.
+
+use std::fmt::Write;
+
+use crate::parser::{Expr, FunctionDecl, Pattern, Stmt};
+
+/// How [`print_program`] lays out the syntax tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AstFormat {
+    /// One node per line, with children indented under their parent. This is
+    /// the same layout as the `Display` impls of [`Stmt`] and [`Expr`], but
+    /// without their depth limit.
+    #[default]
+    Tree,
+    /// One statement per line in the Lisp-like style *Crafting Interpreters*
+    /// uses, such as `(print (+ 1 (* 2 3)))`.
+    SExpr,
+    /// The whole program as one JSON array of statements, in the layout
+    /// `serde` derives for [`Stmt`] and [`Expr`].
+    #[cfg(feature = "serde")]
+    Json,
+}
+
+/// The depth passed to the `Display` impls for [`AstFormat::Tree`]. It is
+/// the largest precision a format string accepts, far deeper than any real
+/// program nests.
+const TREE_DEPTH: usize = u16::MAX as usize;
+
+/// Renders every statement in `program`, each ending with a newline.
+pub fn print_program(program: &[Stmt], format: AstFormat) -> String {
+    let mut output = String::new();
+
+    #[cfg(feature = "serde")]
+    if format == AstFormat::Json {
+        let json = serde_json::to_string(program).expect("syntax trees only hold JSON types");
+        return json + "\n";
+    }
+
+    for stmt in program {
+        match format {
+            AstFormat::Tree => write!(output, "{stmt:.*}", TREE_DEPTH),
+            AstFormat::SExpr => writeln!(output, "{}", stmt_sexpr(stmt)),
+            #[cfg(feature = "serde")]
+            AstFormat::Json => unreachable!("JSON is written in one go"),
+        }
+        .expect("writing to a String can't fail");
+    }
+
+    output
+}
+
+/// Renders a statement as an s-expression.
+pub fn stmt_sexpr(stmt: &Stmt) -> String {
+    match stmt {
+        Stmt::Expression(expr) => parenthesize(";", [expr_sexpr(expr)]),
+        Stmt::Function(function) => function_sexpr(function),
+        Stmt::Class {
+            name,
+            superclass,
+            methods,
+            ..
+        } => {
+            let mut parts = vec![name.lexeme.to_string()];
+            if let Some(superclass) = superclass {
+                parts.push("<".into());
+                parts.push(expr_sexpr(superclass));
+            }
+            parts.extend(methods.iter().map(|method| function_sexpr(method)));
+            parenthesize("class", parts)
+        }
+        Stmt::Import { path, .. } => parenthesize("import", [format!("\"{}\"", path.lexeme)]),
+        Stmt::Print { values, .. } => parenthesize("print", values.iter().map(expr_sexpr)),
+        Stmt::Var {
+            name, initializer, ..
+        } => parenthesize(
+            "var",
+            std::iter::once(name.lexeme.to_string()).chain(initializer.iter().map(expr_sexpr)),
+        ),
+        Stmt::Unpack {
+            names, initializer, ..
+        } => parenthesize(
+            "var",
+            [
+                parenthesize("", names.iter().map(|name| name.lexeme.to_string())),
+                expr_sexpr(initializer),
+            ],
+        ),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => parenthesize(
+            "if",
+            [expr_sexpr(condition), stmt_sexpr(then_branch)]
+                .into_iter()
+                .chain(else_branch.iter().map(|stmt| stmt_sexpr(stmt))),
+        ),
+        Stmt::While {
+            condition,
+            body,
+            increment,
+            ..
+        } => parenthesize(
+            "while",
+            [expr_sexpr(condition), stmt_sexpr(body)]
+                .into_iter()
+                .chain(increment.iter().map(expr_sexpr)),
+        ),
+        Stmt::Return { value, .. } => parenthesize("return", value.iter().map(expr_sexpr)),
+        Stmt::Block { statements, .. } => parenthesize("block", statements.iter().map(stmt_sexpr)),
+        Stmt::Break { .. } => "(break)".into(),
+        Stmt::Continue { .. } => "(continue)".into(),
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+            ..
+        } => {
+            let mut parts = vec![parenthesize("block", body.iter().map(stmt_sexpr))];
+            if let Some(catch) = catch {
+                parts.push(parenthesize(
+                    "catch",
+                    std::iter::once(catch.name.lexeme.to_string())
+                        .chain(catch.body.iter().map(stmt_sexpr)),
+                ));
+            }
+            if let Some(finally) = finally {
+                parts.push(parenthesize("finally", finally.iter().map(stmt_sexpr)));
+            }
+            parenthesize("try", parts)
+        }
+        Stmt::Throw { value, .. } => parenthesize("throw", [expr_sexpr(value)]),
+        Stmt::Defer { statement, .. } => parenthesize("defer", [stmt_sexpr(statement)]),
+        Stmt::ForIn {
+            names,
+            iterable,
+            body,
+            ..
+        } => {
+            let names = names.iter().map(|name| name.lexeme.to_string());
+            parenthesize(
+                "for-in",
+                [
+                    parenthesize("", names),
+                    expr_sexpr(iterable),
+                    stmt_sexpr(body),
+                ],
+            )
+        }
+        Stmt::Use {
+            name,
+            initializer,
+            body,
+            ..
+        } => parenthesize(
+            "use",
+            [name.lexeme.to_string(), expr_sexpr(initializer)]
+                .into_iter()
+                .chain(body.iter().map(stmt_sexpr)),
+        ),
+    }
+}
+
+/// Renders an expression as an s-expression. Names and literals stand on
+/// their own; everything else is a parenthesized operator and its operands.
+pub fn expr_sexpr(expr: &Expr) -> String {
+    match expr {
+        Expr::Literal { value, .. } => value.to_string(),
+        Expr::This { .. } => "this".into(),
+        Expr::Variable { name, .. } => name.lexeme.to_string(),
+        Expr::Grouping { group, .. } => parenthesize("group", [expr_sexpr(group)]),
+        Expr::Super { method, .. } => parenthesize("super", [method.lexeme.to_string()]),
+        Expr::Get { name, object } => {
+            parenthesize(".", [expr_sexpr(object), name.lexeme.to_string()])
+        }
+        Expr::Call {
+            callee,
+            arguments,
+            keywords,
+            ..
+        } => {
+            let positional = arguments.len() - keywords.len();
+            let named = keywords
+                .iter()
+                .zip(&arguments[positional..])
+                .map(|(name, argument)| format!("{}: {}", name.lexeme, expr_sexpr(argument)));
+            parenthesize(
+                "call",
+                std::iter::once(expr_sexpr(callee))
+                    .chain(arguments[..positional].iter().map(expr_sexpr))
+                    .chain(named),
+            )
+        }
+        Expr::Unary { operator, right } => parenthesize(&operator.lexeme, [expr_sexpr(right)]),
+        Expr::Update {
+            operator,
+            target,
+            prefix,
+        } => {
+            let position = if *prefix { "pre" } else { "post" };
+            parenthesize(
+                &format!("{position}{}", operator.lexeme),
+                [expr_sexpr(target)],
+            )
+        }
+        Expr::Compound {
+            target,
+            operator,
+            value,
+        } => parenthesize(
+            &format!("{}=", operator.lexeme),
+            [expr_sexpr(target), expr_sexpr(value)],
+        ),
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        }
+        | Expr::Logical {
+            left,
+            operator,
+            right,
+        } => parenthesize(&operator.lexeme, [expr_sexpr(left), expr_sexpr(right)]),
+        Expr::Conditional {
+            condition,
+            then_branch,
+            else_branch,
+        } => parenthesize(
+            "?:",
+            [
+                expr_sexpr(condition),
+                expr_sexpr(then_branch),
+                expr_sexpr(else_branch),
+            ],
+        ),
+        Expr::Assign { name, value, .. } => {
+            parenthesize("=", [name.lexeme.to_string(), expr_sexpr(value)])
+        }
+        Expr::Set {
+            object,
+            name,
+            value,
+        } => parenthesize(
+            "=",
+            [
+                parenthesize(".", [expr_sexpr(object), name.lexeme.to_string()]),
+                expr_sexpr(value),
+            ],
+        ),
+        Expr::List { elements, .. } => parenthesize("list", elements.iter().map(expr_sexpr)),
+        Expr::Tuple { elements, .. } => parenthesize("tuple", elements.iter().map(expr_sexpr)),
+        Expr::Match {
+            subject,
+            arms,
+            otherwise,
+            ..
+        } => parenthesize(
+            "match",
+            std::iter::once(expr_sexpr(subject))
+                .chain(arms.iter().map(|arm| {
+                    parenthesize("=>", [pattern_sexpr(&arm.pattern), expr_sexpr(&arm.body)])
+                }))
+                .chain([parenthesize("else", [expr_sexpr(otherwise)])]),
+        ),
+        Expr::Map { entries, .. } => parenthesize(
+            "map",
+            entries
+                .iter()
+                .flat_map(|(key, value)| [expr_sexpr(key), expr_sexpr(value)]),
+        ),
+        Expr::Index { object, index, .. } => {
+            parenthesize("[]", [expr_sexpr(object), expr_sexpr(index)])
+        }
+        Expr::Slice {
+            object, start, end, ..
+        } => {
+            // A missing bound behaves the same as a `nil` one.
+            let bound = |bound: &Option<Box<Expr>>| match bound {
+                Some(bound) => expr_sexpr(bound),
+                None => "nil".into(),
+            };
+            parenthesize("[:]", [expr_sexpr(object), bound(start), bound(end)])
+        }
+        Expr::SetIndex {
+            object,
+            index,
+            value,
+            ..
+        } => parenthesize(
+            "=",
+            [
+                parenthesize("[]", [expr_sexpr(object), expr_sexpr(index)]),
+                expr_sexpr(value),
+            ],
+        ),
+    }
+}
+
+fn pattern_sexpr(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Literal(literal) => expr_sexpr(literal),
+        Pattern::Binding(name) => name.lexeme.to_string(),
+        Pattern::Tuple { elements, .. } => {
+            parenthesize("tuple", elements.iter().map(pattern_sexpr))
+        }
+        Pattern::Instance { class, fields, .. } => parenthesize(
+            &expr_sexpr(class),
+            fields.iter().map(|field| field.lexeme.to_string()),
+        ),
+    }
+}
+
+fn function_sexpr(function: &FunctionDecl) -> String {
+    let params = function
+        .params
+        .iter()
+        .map(|param| &*param.lexeme)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let head = match function.is_class_method {
+        true => "class-fun",
+        false => "fun",
+    };
+    // A getter has no parameter list at all, not even an empty one.
+    let params = match function.is_getter {
+        true => None,
+        false => Some(format!("({params})")),
+    };
+
+    parenthesize(
+        head,
+        std::iter::once(function.name.lexeme.to_string())
+            .chain(params)
+            .chain(function.body.iter().map(stmt_sexpr)),
+    )
+}
+
+fn parenthesize(name: &str, parts: impl IntoIterator<Item = String>) -> String {
+    let mut output = format!("({name}");
+    for part in parts {
+        output.push(' ');
+        output.push_str(&part);
+    }
+    output.push(')');
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{print_program, AstFormat};
+    use crate::{Parser, Scanner};
+
+    fn print(source: &str, format: AstFormat) -> String {
+        let output = Parser::new(Scanner::new(source).scan_tokens().tokens).parse();
+        assert!(output.errors.is_empty());
+        print_program(&output.program, format)
+    }
+
+    #[test]
+    fn programs_print_as_s_expressions_one_statement_per_line() {
+        let source = "var a = 1 + 2 * 3;\nif (a > 6) print a; else a = -a;\n\
+                      fun f(x) { return x; }\nclass C < B { m() { super.m(); } }";
+        assert_eq!(
+            print(source, AstFormat::SExpr),
+            "(var a (+ 1 (* 2 3)))\n\
+             (if (> a 6) (print a) (; (= a (- a))))\n\
+             (fun f (x) (return x))\n\
+             (class C < B (fun m () (; (call (super m)))))\n"
+        );
+    }
+
+    #[test]
+    fn programs_print_as_indented_trees() {
+        assert_eq!(
+            print("print (1 + 2) * 3;\nwhile (true) break;", AstFormat::Tree),
+            "Print\n  Binary *\n    Grouping\n      Binary +\n        Literal 1\n        \
+             Literal 2\n    Literal 3\nWhile\n  Literal true\n  Break\n"
+        );
+    }
+
+    #[test]
+    fn trees_print_past_the_display_depth_limit() {
+        let source = format!("print {}1{};", "(".repeat(40), ")".repeat(40));
+        let tree = print(&source, AstFormat::Tree);
+
+        assert_eq!(tree.lines().count(), 42);
+        assert_eq!(
+            tree.lines().last(),
+            Some(&*format!("{}Literal 1", "  ".repeat(41)))
+        );
+        assert!(!tree.contains("..."));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn programs_print_as_one_json_array() {
+        let json = print("print 1;\nprint 2;", AstFormat::Json);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert!(json.ends_with("]\n") && json.matches('\n').count() == 1);
+        assert_eq!(value.as_array().map(Vec::len), Some(2));
+    }
+}
@@ -0,0 +1,273 @@
+use std::{
+    any::Any,
+    cell::RefCell,
+    fmt::{Debug, Display},
+    rc::Rc,
+};
+
+use crate::{
+    environment::Environment,
+    error::{ErrorCode, Message, Result, RuntimeError},
+    gc::{self, Trace, Visitor},
+    interpreter::{Interpreter, Unwind},
+    parser::FunctionDecl,
+    symbol::Symbol,
+    token::Token,
+    value::Value,
+};
+
+/// Anything that can be invoked with `()` from Lox code.
+pub trait LoxCallable: Any + Debug + Display {
+    fn arity(&self) -> usize;
+
+    /// Whether it also takes any number of arguments past `arity`, which is
+    /// then the fewest it takes.
+    fn variadic(&self) -> bool {
+        false
+    }
+
+    fn call(self: Rc<Self>, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value>;
+
+    /// The name calls to this are listed under in a stack trace, or `None`
+    /// to leave them out, as natives are.
+    fn frame_name(&self) -> Option<String> {
+        None
+    }
+
+    /// The names of its parameters, which keyword arguments are matched
+    /// against, or `None` if it can't take keyword arguments, as natives
+    /// can't.
+    fn parameters(&self) -> Option<Vec<Symbol>> {
+        None
+    }
+}
+
+/// The error message for calling something that takes `arity` arguments,
+/// or at least that many if it's `variadic`, with `count`, if they don't
+/// fit.
+pub(crate) fn arity_mismatch(arity: usize, variadic: bool, count: usize) -> Option<Message> {
+    let message = match variadic {
+        false if count != arity => format!("Expected {arity} arguments but got {count}"),
+        true if count < arity => format!("Expected at least {arity} arguments but got {count}"),
+        _ => return None,
+    };
+    Some(Message::new(ErrorCode::ArgumentCount, message))
+}
+
+/// Puts the arguments of a call to `callee`, the last `keywords.len()` of
+/// which were passed by name, in the order of its parameters.
+pub(crate) fn arrange_arguments(
+    callee: &dyn LoxCallable,
+    mut arguments: Vec<Value>,
+    keywords: &[Symbol],
+) -> Result<Vec<Value>> {
+    let Some(parameters) = callee.parameters() else {
+        return Err(RuntimeError::GeneralError(Message::new(
+            ErrorCode::KeywordArguments,
+            format!("{callee} doesn't take keyword arguments"),
+        )));
+    };
+
+    let named = arguments.split_off(arguments.len() - keywords.len());
+    if arguments.len() > parameters.len() {
+        let count = arguments.len() + named.len();
+        let message = arity_mismatch(parameters.len(), false, count);
+        return Err(RuntimeError::GeneralError(
+            message.expect("more arguments than parameters"),
+        ));
+    }
+
+    let mut slots: Vec<Option<Value>> = arguments.into_iter().map(Some).collect();
+    slots.resize(parameters.len(), None);
+    for (&name, value) in keywords.iter().zip(named) {
+        let Some(index) = parameters.iter().position(|&parameter| parameter == name) else {
+            return Err(RuntimeError::GeneralError(Message::new(
+                ErrorCode::KeywordArguments,
+                format!("{callee} has no parameter named `{name}`"),
+            )));
+        };
+        if slots[index].is_some() {
+            return Err(RuntimeError::GeneralError(Message::new(
+                ErrorCode::KeywordArguments,
+                format!("Argument `{name}` is given twice"),
+            )));
+        }
+        slots[index] = Some(value);
+    }
+
+    slots
+        .into_iter()
+        .zip(parameters)
+        .map(|(slot, name)| {
+            slot.ok_or_else(|| {
+                RuntimeError::GeneralError(Message::new(
+                    ErrorCode::KeywordArguments,
+                    format!("Missing the argument `{name}`"),
+                ))
+            })
+        })
+        .collect()
+}
+
+type NativeFn = dyn Fn(&mut Interpreter, Vec<Value>) -> Result<Value>;
+
+/// A function implemented in Rust and exposed to Lox code, usually through
+/// [`Interpreter::define_native`]. Arguments are checked against `arity`
+/// before the function runs. An error it returns as
+/// [`RuntimeError::GeneralError`](crate::RuntimeError::GeneralError) is
+/// reported at the line of the call.
+pub struct NativeFunction {
+    name: String,
+    arity: usize,
+    variadic: bool,
+    function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: impl Into<String>,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, Vec<Value>) -> Result<Value> + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            arity,
+            variadic: false,
+            function: Box::new(function),
+        }
+    }
+
+    /// A native that takes `arity` arguments or more.
+    pub fn variadic(
+        name: impl Into<String>,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, Vec<Value>) -> Result<Value> + 'static,
+    ) -> Self {
+        Self {
+            variadic: true,
+            ..Self::new(name, arity, function)
+        }
+    }
+}
+
+impl LoxCallable for NativeFunction {
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn variadic(&self) -> bool {
+        self.variadic
+    }
+
+    fn call(self: Rc<Self>, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+        (self.function)(interpreter, arguments)
+    }
+}
+
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .field("variadic", &self.variadic)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Display for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+/// A function declared in Lox source, along with the environment it was
+/// declared in so it can keep using that scope's variables after it ends.
+#[derive(Debug)]
+pub(crate) struct LoxFunction {
+    declaration: Rc<FunctionDecl>,
+    closure: Rc<RefCell<Environment>>,
+    is_initializer: bool,
+}
+
+impl LoxFunction {
+    pub(crate) fn new(
+        declaration: Rc<FunctionDecl>,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
+    ) -> Self {
+        Self {
+            declaration,
+            closure,
+            is_initializer,
+        }
+    }
+
+    /// Creates a copy of this method whose closure defines `this`, which is
+    /// an instance, or the class itself for a class method.
+    pub(crate) fn bind(&self, this: Value) -> Rc<LoxFunction> {
+        let mut environment = Environment::with_enclosing(self.closure.clone());
+        environment.define(Symbol::THIS, this);
+
+        gc::alloc(LoxFunction::new(
+            self.declaration.clone(),
+            gc::alloc(RefCell::new(environment)),
+            self.is_initializer,
+        ))
+    }
+
+    pub(crate) fn is_getter(&self) -> bool {
+        self.declaration.is_getter
+    }
+
+    /// An initializer always evaluates to the instance it was bound to.
+    fn bound_instance(&self) -> Value {
+        self.closure
+            .borrow()
+            .lookup(Symbol::THIS)
+            .expect("initializer should be bound to an instance")
+    }
+}
+
+impl LoxCallable for LoxFunction {
+    fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    fn frame_name(&self) -> Option<String> {
+        Some(self.declaration.name.lexeme.to_string())
+    }
+
+    fn parameters(&self) -> Option<Vec<Symbol>> {
+        Some(self.declaration.params.iter().map(Token::symbol).collect())
+    }
+
+    fn call(self: Rc<Self>, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+        let mut environment = Environment::with_enclosing(self.closure.clone());
+
+        for (param, argument) in self.declaration.params.iter().zip(arguments) {
+            environment.define(param.symbol(), argument);
+        }
+
+        match interpreter.execute_block(&self.declaration.body, environment) {
+            Ok(()) | Err(Unwind::Return(_)) if self.is_initializer => Ok(self.bound_instance()),
+            Ok(()) => Ok(Value::Nil),
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Error(err)) => Err(err),
+            Err(Unwind::Break | Unwind::Continue) => {
+                unreachable!("parser rejects loop control outside loops")
+            }
+        }
+    }
+}
+
+impl Trace for LoxFunction {
+    fn trace(&self, visit: &mut Visitor) {
+        visit(gc::address(&self.closure));
+    }
+}
+
+impl Display for LoxFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fn {}>", self.declaration.name.lexeme)
+    }
+}
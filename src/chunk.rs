@@ -0,0 +1,187 @@
+use std::rc::Rc;
+
+use crate::{symbol::Symbol, token::Token, value::Value};
+
+/// A single bytecode instruction. Operands are stored inline, so an `Op` is
+/// always one element of [`Chunk::code`]. Jump targets are absolute indexes
+/// into the same chunk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Op {
+    /// Pushes `constants[index]` from the module's constant pool.
+    Constant(usize),
+    Nil,
+    True,
+    False,
+    Pop,
+    /// Pushes a copy of the top of the stack.
+    Dup,
+    /// Pushes copies of the top two values, in the same order.
+    DupPair,
+    /// Swaps the top two values.
+    Swap,
+    /// Pushes the local in stack slot `slot` of the current frame.
+    GetLocal(usize),
+    SetLocal(usize),
+    /// Pushes the global `name`.
+    GetGlobal(Symbol),
+    DefineGlobal(Symbol),
+    /// Defines the global `name` as the class on top of the stack, or adds
+    /// the class's methods to the class the global already holds if classes
+    /// can be reopened.
+    DefineClass(Symbol),
+    SetGlobal(Symbol),
+    GetUpvalue(usize),
+    SetUpvalue(usize),
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    /// The remainder of a truncating division, with the sign of the dividend.
+    Modulo,
+    Power,
+    Not,
+    Negate,
+    /// Adds `delta` to the number on top of the stack, for `++` and `--`.
+    Step(i64),
+    /// Pops an index and the target below it, adds `delta` to the number at
+    /// that index and pushes the new number if `prefix`, or the old one.
+    StepIndex(i64, bool),
+    /// Runs the script whose path is `constants[index]`, unless the program
+    /// has already imported it.
+    Import(usize),
+    /// Prints the top `count` values separated by spaces.
+    Print(usize),
+    /// Replaces the top `count` values with a list of them.
+    List(usize),
+    /// Replaces the top `count` values with a tuple of them.
+    Tuple(usize),
+    /// Replaces a tuple or list of `count` elements with its elements.
+    Unpack(usize),
+    /// Replaces the top value with whether it's a tuple of `count` elements.
+    IsTuple(usize),
+    /// Pops a class and the value below it and pushes whether the value is
+    /// an instance of the class or a subclass.
+    IsInstance,
+    /// Replaces the instance on top of the stack with whether it has the
+    /// field `name`.
+    HasField(Symbol),
+    /// Replaces the top `count` key and value pairs with a map of them.
+    Map(usize),
+    /// Pops an instance or a class and pushes its property `name`, running
+    /// it first if it's a getter.
+    GetProperty(Symbol),
+    /// Pops a value and the instance below it, stores the value in the field
+    /// `name` and pushes it back as the result of the assignment.
+    SetProperty(Symbol),
+    /// Pops a superclass and `this` below it and pushes the superclass's
+    /// method `name` bound to `this`, running it first if it's a getter.
+    GetSuper(Symbol),
+    /// Replaces the top `methods` closures, and the superclass below them if
+    /// the class `inherits`, with the class `name`.
+    Class {
+        name: Symbol,
+        inherits: bool,
+        methods: usize,
+    },
+    /// Pops an index and the value below it and pushes that value's element.
+    GetIndex,
+    /// Pops a value, an index and the target below them, stores the value
+    /// and pushes it back as the result of the assignment.
+    SetIndex,
+    /// Pops the end and start of a slice, either of which may be `nil`, and
+    /// the value below them, and pushes that part of the value.
+    Slice,
+    Jump(usize),
+    /// Jumps if the top of the stack is falsey, leaving it in place.
+    JumpIfFalse(usize),
+    /// Calls the value below the top `count` arguments.
+    Call(usize),
+    /// Replaces the value on top with the iterator a for-in loop with that
+    /// many variables goes through it with.
+    Iterate(usize),
+    /// Like [`Op::Call`], with the last arguments passed by the names in
+    /// [`Chunk::keywords`] at the second index.
+    CallWithKeywords(usize, usize),
+    /// Wraps `functions[index]` in a closure, capturing its upvalues.
+    Closure(usize),
+    /// Moves the local on top of the stack into the heap, for the closures
+    /// that captured it, then pops it.
+    CloseUpvalue,
+    Return,
+    /// Starts a `try` block. An error in it unwinds to where the block
+    /// started and jumps to `handler` with what `catch` binds pushed, or
+    /// with `nil` pushed and the error kept for [`Op::Rethrow`] if `rethrow`.
+    Try(usize, bool),
+    /// Ends the innermost `try` block.
+    EndTry,
+    /// Pops a value and raises it as an error.
+    Throw,
+    /// Pops the value pushed by a `rethrow` handler and raises the error it
+    /// caught again, after a `finally` block has run.
+    Rethrow,
+}
+
+/// A compiled sequence of instructions along with the functions they refer
+/// to. Constants live in the [`Module`] rather than in each chunk.
+#[derive(Debug, Default)]
+pub(crate) struct Chunk {
+    pub code: Vec<Op>,
+    /// The token each instruction in `code` was compiled from, so an error
+    /// can point at it in the source.
+    pub tokens: Vec<Token>,
+    /// Functions declared in this chunk, referenced by [`Op::Closure`].
+    pub functions: Vec<Rc<Function>>,
+    /// The names of the keyword arguments of each [`Op::CallWithKeywords`].
+    pub keywords: Vec<Vec<Symbol>>,
+}
+
+impl Chunk {
+    pub(crate) fn write(&mut self, op: Op, token: Token) -> usize {
+        self.code.push(op);
+        self.tokens.push(token);
+        self.code.len() - 1
+    }
+}
+
+/// Where a closure finds a captured variable when it is created: a local
+/// slot of the enclosing function, or one of that function's own upvalues.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct UpvalueSource {
+    pub is_local: bool,
+    pub index: usize,
+}
+
+/// A compiled function, shared by every closure created from it.
+#[derive(Debug, Default)]
+pub(crate) struct Function {
+    pub name: String,
+    pub arity: usize,
+    /// The names of the parameters, which keyword arguments are matched
+    /// against.
+    pub parameters: Vec<Symbol>,
+    /// A method declared without a parameter list, which runs as soon as the
+    /// property is read.
+    pub is_getter: bool,
+    /// A method declared with `class`, which belongs to the class itself.
+    pub is_class_method: bool,
+    /// Compiled from an expression rather than declared, such as a match, so
+    /// it's left out of tracebacks.
+    pub inline: bool,
+    pub chunk: Chunk,
+    pub upvalues: Vec<UpvalueSource>,
+}
+
+/// A compiled script: its top-level function and the constant pool shared
+/// by every chunk in it. Each distinct number or string literal appears in
+/// the pool once, however many functions use it.
+#[derive(Debug)]
+pub(crate) struct Module {
+    pub script: Rc<Function>,
+    pub constants: Rc<[Value]>,
+}
@@ -0,0 +1,358 @@
+use std::{cell::RefCell, collections::HashMap, fmt::Display, rc::Rc};
+
+use crate::{
+    callable::{LoxCallable, LoxFunction},
+    error::{ErrorCode, Message, Result, RuntimeError},
+    gc::{self, Trace, Visitor},
+    interpreter::Interpreter,
+    symbol::Symbol,
+    token::Token,
+    value::Value,
+};
+
+/// A method of a [`LoxClass`]: a [`LoxFunction`] when the tree-walking
+/// interpreter declared the class, or a compiled closure when the vm did.
+pub(crate) trait Method: LoxCallable {
+    /// Creates a copy of the method in which `this` is `this`, an instance
+    /// or, for a class method, the class itself.
+    fn bind(self: Rc<Self>, this: Value) -> Rc<dyn LoxCallable>;
+
+    /// Whether the method was declared without a parameter list, so it runs
+    /// as soon as the property is read.
+    fn is_getter(&self) -> bool;
+}
+
+impl Method for LoxFunction {
+    fn bind(self: Rc<Self>, this: Value) -> Rc<dyn LoxCallable> {
+        LoxFunction::bind(&self, this)
+    }
+
+    fn is_getter(&self) -> bool {
+        LoxFunction::is_getter(self)
+    }
+}
+
+type NativeMethodFn = fn(&mut Interpreter, &Rc<RefCell<LoxInstance>>, Vec<Value>) -> Result<Value>;
+
+/// A method of a class rlox declares itself, such as `Error`, implemented in
+/// Rust. It gets the instance it was read from as `this`.
+#[derive(Debug)]
+pub(crate) struct NativeMethod {
+    name: &'static str,
+    arity: usize,
+    function: NativeMethodFn,
+    /// The instance the method is bound to, or `None` while it's only held
+    /// by its class.
+    this: Option<Value>,
+}
+
+impl NativeMethod {
+    pub(crate) fn new(name: &'static str, arity: usize, function: NativeMethodFn) -> Self {
+        Self {
+            name,
+            arity,
+            function,
+            this: None,
+        }
+    }
+}
+
+impl LoxCallable for NativeMethod {
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(self: Rc<Self>, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+        match &self.this {
+            Some(Value::Instance(this)) => (self.function)(interpreter, this, arguments),
+            _ => Err(RuntimeError::GeneralError(
+                format!("`{}` can only be called on an instance", self.name).into(),
+            )),
+        }
+    }
+}
+
+impl Method for NativeMethod {
+    fn bind(self: Rc<Self>, this: Value) -> Rc<dyn LoxCallable> {
+        gc::alloc(NativeMethod {
+            this: Some(this),
+            ..*self
+        })
+    }
+
+    fn is_getter(&self) -> bool {
+        false
+    }
+}
+
+impl Trace for NativeMethod {
+    fn trace(&self, visit: &mut Visitor) {
+        if let Some(this) = &self.this {
+            this.trace(visit);
+        }
+    }
+}
+
+impl Display for NativeMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+/// A property read from an instance or a class, before any getter has run.
+pub(crate) enum Property {
+    Value(Value),
+    /// A getter bound to the object it was read from, which has to be
+    /// called to get the property's value.
+    Getter(Rc<dyn LoxCallable>),
+}
+
+impl Property {
+    /// Binds `method` to `this`, as the value of a property of `this`.
+    pub(crate) fn method(method: &Rc<dyn Method>, this: Value) -> Self {
+        let is_getter = method.is_getter();
+        let method = method.clone().bind(this);
+        match is_getter {
+            true => Property::Getter(method),
+            false => Property::Value(Value::Callable(method)),
+        }
+    }
+}
+
+/// A class declared in Lox source. Calling it constructs a new instance.
+#[derive(Debug)]
+pub struct LoxClass {
+    name: Symbol,
+    superclass: Option<Rc<LoxClass>>,
+    /// Only changed when the class is reopened.
+    methods: RefCell<HashMap<Symbol, Rc<dyn Method>>>,
+    /// Methods declared with `class`, which are properties of the class
+    /// itself and of its subclasses.
+    class_methods: RefCell<HashMap<Symbol, Rc<dyn Method>>>,
+}
+
+impl LoxClass {
+    pub(crate) fn new(
+        name: Symbol,
+        superclass: Option<Rc<LoxClass>>,
+        methods: HashMap<Symbol, Rc<dyn Method>>,
+        class_methods: HashMap<Symbol, Rc<dyn Method>>,
+    ) -> Self {
+        Self {
+            name,
+            superclass,
+            methods: RefCell::new(methods),
+            class_methods: RefCell::new(class_methods),
+        }
+    }
+
+    /// Adds the methods of `reopened`, a later declaration of this class,
+    /// replacing those with the same names. It can only name the superclass
+    /// this class already has.
+    pub(crate) fn reopen(&self, reopened: &LoxClass) -> std::result::Result<(), String> {
+        if let Some(superclass) = &reopened.superclass {
+            if !self
+                .superclass
+                .as_ref()
+                .is_some_and(|existing| Rc::ptr_eq(existing, superclass))
+            {
+                return Err(format!(
+                    "Can't change the superclass of `{}` by reopening it",
+                    self.name
+                ));
+            }
+        }
+
+        let methods = reopened.methods.borrow();
+        self.methods.borrow_mut().extend(methods.clone());
+        let class_methods = reopened.class_methods.borrow();
+        self.class_methods
+            .borrow_mut()
+            .extend(class_methods.clone());
+        Ok(())
+    }
+
+    /// Finds a method on this class, falling back to its superclass chain.
+    pub(crate) fn find_method(&self, name: Symbol) -> Option<Rc<dyn Method>> {
+        let method = self.methods.borrow().get(&name).cloned();
+        match (method, &self.superclass) {
+            (Some(method), _) => Some(method),
+            (None, Some(superclass)) => superclass.find_method(name),
+            (None, None) => None,
+        }
+    }
+
+    /// Whether this is `class` or one of its subclasses.
+    pub(crate) fn inherits(&self, class: &LoxClass) -> bool {
+        std::ptr::eq(self, class)
+            || self
+                .superclass
+                .as_ref()
+                .is_some_and(|superclass| superclass.inherits(class))
+    }
+
+    fn find_class_method(&self, name: Symbol) -> Option<Rc<dyn Method>> {
+        let method = self.class_methods.borrow().get(&name).cloned();
+        match (method, &self.superclass) {
+            (Some(method), _) => Some(method),
+            (None, Some(superclass)) => superclass.find_class_method(name),
+            (None, None) => None,
+        }
+    }
+
+    /// Looks up a class method, bound so that `this` in its body is `class`.
+    pub(crate) fn get(class: &Rc<LoxClass>, name: &Token) -> Result<Property> {
+        match class.find_class_method(name.symbol()) {
+            Some(method) => Ok(Property::method(&method, Value::Class(class.clone()))),
+            None => Err(RuntimeError::InterpretError(
+                Message::new(
+                    ErrorCode::UndefinedProperty,
+                    format!("Undefined property `{}`", name.lexeme),
+                ),
+                name.clone(),
+            )),
+        }
+    }
+}
+
+impl LoxCallable for LoxClass {
+    fn arity(&self) -> usize {
+        self.find_method(Symbol::INIT)
+            .map(|initializer| initializer.arity())
+            .unwrap_or(0)
+    }
+
+    fn frame_name(&self) -> Option<String> {
+        Some(self.name.to_string())
+    }
+
+    fn parameters(&self) -> Option<Vec<Symbol>> {
+        match self.find_method(Symbol::INIT) {
+            Some(initializer) => initializer.parameters(),
+            None => Some(vec![]),
+        }
+    }
+
+    fn call(self: Rc<Self>, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+        let instance = gc::alloc(RefCell::new(LoxInstance::new(self.clone())));
+
+        if let Some(initializer) = self.find_method(Symbol::INIT) {
+            initializer
+                .bind(Value::Instance(instance.clone()))
+                .call(interpreter, arguments)?;
+        }
+
+        Ok(Value::Instance(instance))
+    }
+}
+
+impl Trace for LoxClass {
+    fn trace(&self, visit: &mut Visitor) {
+        if let Some(superclass) = &self.superclass {
+            visit(gc::address(superclass));
+        }
+        for methods in [&self.methods, &self.class_methods] {
+            if let Ok(methods) = methods.try_borrow() {
+                methods
+                    .values()
+                    .for_each(|method| visit(gc::address(method)));
+            }
+        }
+    }
+}
+
+impl Display for LoxClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// An instance of a [`LoxClass`], holding its own set of fields.
+#[derive(Debug)]
+pub struct LoxInstance {
+    class: Rc<LoxClass>,
+    fields: HashMap<Symbol, Value>,
+}
+
+impl LoxInstance {
+    pub(crate) fn new(class: Rc<LoxClass>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+
+    /// Looks up a property: fields shadow methods, and methods come back bound
+    /// to `instance` so `this` works when they are called later.
+    pub(crate) fn get(instance: &Rc<RefCell<LoxInstance>>, name: &Token) -> Result<Property> {
+        let this = instance.borrow();
+
+        if let Some(value) = this.fields.get(&name.symbol()) {
+            return Ok(Property::Value(value.clone()));
+        }
+
+        match this.class.find_method(name.symbol()) {
+            Some(method) => Ok(Property::method(&method, Value::Instance(instance.clone()))),
+            None => Err(RuntimeError::InterpretError(
+                Message::new(
+                    ErrorCode::UndefinedProperty,
+                    format!("Undefined property `{}`", name.lexeme),
+                ),
+                name.clone(),
+            )),
+        }
+    }
+
+    /// Looks up a method the runtime calls on its own, such as `__add__` for
+    /// `+`, bound to `instance`. Unlike [`LoxInstance::get`], fields are
+    /// ignored.
+    pub(crate) fn special_method(
+        instance: &Rc<RefCell<LoxInstance>>,
+        name: &str,
+    ) -> Option<Rc<dyn LoxCallable>> {
+        let method = instance.borrow().class.find_method(Symbol::intern(name))?;
+        Some(method.bind(Value::Instance(instance.clone())))
+    }
+
+    pub(crate) fn set(&mut self, name: &Token, value: Value) {
+        self.fields.insert(name.symbol(), value);
+    }
+
+    /// The field `name`, for the runtime to read without a token.
+    pub(crate) fn field(&self, name: Symbol) -> Option<Value> {
+        self.fields.get(&name).cloned()
+    }
+
+    pub(crate) fn set_field(&mut self, name: Symbol, value: Value) {
+        self.fields.insert(name, value);
+    }
+
+    pub(crate) fn class(&self) -> &Rc<LoxClass> {
+        &self.class
+    }
+}
+
+impl Trace for RefCell<LoxInstance> {
+    fn trace(&self, visit: &mut Visitor) {
+        let Ok(instance) = self.try_borrow() else {
+            return;
+        };
+
+        visit(gc::address(&instance.class));
+        for value in instance.fields.values() {
+            value.trace(visit);
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut instance) = self.try_borrow_mut() {
+            instance.fields.clear();
+        }
+    }
+}
+
+impl Display for LoxInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
@@ -0,0 +1,1390 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    chunk::{Chunk, Function, Module, Op, UpvalueSource},
+    interpreter::equals_token,
+    parser::{Catch, Expr, FunctionDecl, Literal, MatchArm, Pattern, Stmt},
+    symbol::Symbol,
+    token::{Token, TokenValue},
+    value::Value,
+};
+
+struct Local {
+    name: Symbol,
+    depth: usize,
+    is_captured: bool,
+}
+
+/// A loop being compiled, collecting the jumps out of its body until their
+/// targets are known.
+struct Loop {
+    /// The scope depth outside the body; locals deeper than this are popped
+    /// before jumping.
+    scope_depth: usize,
+    /// How many `try` blocks were around the loop, so `break` and `continue`
+    /// leave only those inside it.
+    tries: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// What runs however a `try` statement ends: its `finally` block, or the
+/// statement a `defer` put off, run after the rest of its block as though
+/// they were in a `try` block.
+#[derive(Clone)]
+enum Cleanup {
+    Finally(Rc<[Stmt]>),
+    Defer(Rc<Stmt>),
+}
+
+impl Cleanup {
+    fn statements(&self) -> &[Stmt] {
+        match self {
+            Cleanup::Finally(statements) => statements,
+            Cleanup::Defer(statement) => std::slice::from_ref(statement),
+        }
+    }
+}
+
+/// A `try` block or `catch` clause being compiled, whose handler has to be
+/// ended, and `finally` block run, ahead of any jump out of it.
+struct TryBlock {
+    finally: Option<Cleanup>,
+    /// The scope depth outside the block, whose locals the `finally` block
+    /// can see.
+    scope_depth: usize,
+}
+
+/// One step from a matched value into the part of it a pattern looks at.
+enum Step<'a> {
+    Element(usize),
+    Field(&'a Token),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+    Function,
+    Method,
+    /// An instance's `init` method, which always returns the instance.
+    Initializer,
+}
+
+/// The state of one function being compiled. A nested function declaration
+/// pushes a new one, so upvalues can be found by walking outwards.
+struct FunctionScope {
+    function: Function,
+    kind: FunctionKind,
+    /// Mirrors the function's stack slots. Slot zero holds the function
+    /// itself, with a name no identifier can match, or in a method the
+    /// object it was bound to, named `this`.
+    locals: Vec<Local>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+    tries: Vec<TryBlock>,
+}
+
+impl FunctionScope {
+    fn new(name: String, arity: usize, kind: FunctionKind) -> Self {
+        let receiver = match kind {
+            FunctionKind::Function => Symbol::EMPTY,
+            FunctionKind::Method | FunctionKind::Initializer => Symbol::THIS,
+        };
+
+        Self {
+            function: Function {
+                name,
+                arity,
+                ..Function::default()
+            },
+            kind,
+            locals: vec![Local {
+                name: receiver,
+                depth: 0,
+                is_captured: false,
+            }],
+            scope_depth: 0,
+            loops: vec![],
+            tries: vec![],
+        }
+    }
+}
+
+/// Lowers a resolved program into bytecode for the [`Vm`](crate::vm::Vm).
+/// Locals live in stack slots there rather than in environments, so the
+/// compiler tracks scopes itself instead of using the resolver's distances.
+pub(crate) struct Compiler {
+    functions: Vec<FunctionScope>,
+    constants: Vec<Value>,
+    /// The index of each constant already in `constants`, so repeated
+    /// literals and names share one entry.
+    constant_indexes: HashMap<ConstantKey, usize>,
+    /// The token the instructions being emitted come from.
+    token: Token,
+    /// Whether integer literals compile to integers, from
+    /// [`LoxOptions::integers`](crate::LoxOptions::integers).
+    integers: bool,
+}
+
+/// Identifies a constant for deduplication. Numbers are compared by their
+/// bits, which keeps `0` and `-0` apart.
+#[derive(PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Int(i64),
+    Number(u64),
+    String(String),
+}
+
+impl Compiler {
+    pub(crate) fn new() -> Self {
+        Self {
+            functions: vec![FunctionScope::new(
+                "script".into(),
+                0,
+                FunctionKind::Function,
+            )],
+            constants: vec![],
+            constant_indexes: HashMap::new(),
+            token: Token::new(TokenValue::Eof, "", 0),
+            integers: false,
+        }
+    }
+
+    pub(crate) fn with_integers(mut self, integers: bool) -> Self {
+        self.integers = integers;
+        self
+    }
+
+    /// Compiles `statements` as the body of a top-level script function.
+    pub(crate) fn compile(mut self, statements: &[Stmt]) -> Module {
+        self.statements(statements);
+        self.emit_return();
+        self.finish()
+    }
+
+    /// Compiles `statements`, then `value`, as the body of a top-level
+    /// script function that returns the value, for [`Lox::eval`] and the
+    /// REPL.
+    ///
+    /// [`Lox::eval`]: crate::Lox::eval
+    pub(crate) fn compile_with_value(mut self, statements: &[Stmt], value: &Expr) -> Module {
+        self.statements(statements);
+        self.expression(value);
+        self.emit(Op::Return);
+        self.finish()
+    }
+
+    fn finish(mut self) -> Module {
+        let script = self.functions.pop().expect("script scope is never popped");
+        Module {
+            script: Rc::new(script.function),
+            constants: self.constants.into(),
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) => {
+                self.expression(expr);
+                self.emit(Op::Pop);
+            }
+            Stmt::Function(declaration) => {
+                // A local function is in scope in its own body, so it can
+                // call itself recursively.
+                if self.scope().scope_depth > 0 {
+                    self.add_local(declaration.name.symbol());
+                    self.function(declaration, FunctionKind::Function);
+                } else {
+                    self.function(declaration, FunctionKind::Function);
+                    self.emit(Op::DefineGlobal(declaration.name.symbol()));
+                }
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => self.class(name, superclass.as_ref(), methods),
+            Stmt::Import { keyword, path, .. } => {
+                let TokenValue::String(path) = &path.value else {
+                    unreachable!("the parser only accepts a string to import");
+                };
+                let constant = self.add_constant(Value::String(path.to_string()));
+                self.token = keyword.clone();
+                self.emit(Op::Import(constant));
+            }
+            Stmt::Print { values, .. } => {
+                for value in values {
+                    self.expression(value);
+                }
+                self.emit(Op::Print(values.len()));
+            }
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                match initializer {
+                    Some(initializer) => self.expression(initializer),
+                    None => {
+                        self.emit(Op::Nil);
+                    }
+                }
+                self.define_variable(name);
+            }
+            Stmt::Unpack {
+                names, initializer, ..
+            } => {
+                self.expression(initializer);
+                self.token = names[0].clone();
+                self.emit(Op::Unpack(names.len()));
+                // A global is defined from the top of the stack, so the
+                // last name goes first.
+                match self.scope().scope_depth > 0 {
+                    true => names.iter().for_each(|name| self.define_variable(name)),
+                    false => names
+                        .iter()
+                        .rev()
+                        .for_each(|name| self.define_variable(name)),
+                }
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.expression(condition);
+                let then_jump = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.statement(then_branch);
+
+                let else_jump = self.emit(Op::Jump(0));
+                self.patch_jump(then_jump);
+                self.emit(Op::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+                self.patch_jump(else_jump);
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                let loop_start = self.chunk().code.len();
+                self.expression(condition);
+                let exit_jump = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+
+                let scope_depth = self.scope().scope_depth;
+                let tries = self.scope().tries.len();
+                self.scope_mut().loops.push(Loop {
+                    scope_depth,
+                    tries,
+                    breaks: vec![],
+                    continues: vec![],
+                });
+                self.statement(body);
+                let body_loop = self.scope_mut().loops.pop().expect("loop was pushed");
+
+                for jump in body_loop.continues {
+                    self.patch_jump(jump);
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                    self.emit(Op::Pop);
+                }
+                self.emit(Op::Jump(loop_start));
+
+                self.patch_jump(exit_jump);
+                self.emit(Op::Pop);
+                for jump in body_loop.breaks {
+                    self.patch_jump(jump);
+                }
+            }
+            Stmt::ForIn {
+                keyword,
+                names,
+                iterable,
+                body,
+                ..
+            } => {
+                // The iterator is kept in a local no Lox name can refer to.
+                self.begin_scope();
+                self.expression(iterable);
+                self.token = keyword.clone();
+                self.emit(Op::Iterate(names.len()));
+                self.add_local(Symbol::intern("for iterator"));
+                let iterator = self.scope().locals.len() - 1;
+
+                let loop_start = self.chunk().code.len();
+                self.call_method(iterator, keyword, "hasNext");
+                let exit_jump = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+
+                let scope_depth = self.scope().scope_depth;
+                let tries = self.scope().tries.len();
+                self.scope_mut().loops.push(Loop {
+                    scope_depth,
+                    tries,
+                    breaks: vec![],
+                    continues: vec![],
+                });
+                self.begin_scope();
+                self.call_method(iterator, keyword, "next");
+                if names.len() > 1 {
+                    self.token = names[0].clone();
+                    self.emit(Op::Unpack(names.len()));
+                }
+                names.iter().for_each(|name| self.add_local(name.symbol()));
+                self.statement(body);
+                self.end_scope();
+                let body_loop = self.scope_mut().loops.pop().expect("loop was pushed");
+
+                for jump in body_loop.continues {
+                    self.patch_jump(jump);
+                }
+                self.emit(Op::Jump(loop_start));
+
+                self.patch_jump(exit_jump);
+                self.emit(Op::Pop);
+                for jump in body_loop.breaks {
+                    self.patch_jump(jump);
+                }
+                self.end_scope();
+            }
+            Stmt::Break { .. } => {
+                let tries = self.innermost_loop().tries;
+                self.leave_tries(tries);
+                self.discard_loop_locals();
+                let jump = self.emit(Op::Jump(0));
+                self.innermost_loop().breaks.push(jump);
+            }
+            Stmt::Continue { .. } => {
+                let tries = self.innermost_loop().tries;
+                self.leave_tries(tries);
+                self.discard_loop_locals();
+                let jump = self.emit(Op::Jump(0));
+                self.innermost_loop().continues.push(jump);
+            }
+            Stmt::Return { value, .. } => match value {
+                // The value is kept in a slot of its own while any `finally`
+                // blocks run.
+                Some(value) if !self.scope().tries.is_empty() => {
+                    self.expression(value);
+                    self.begin_scope();
+                    self.add_local(Symbol::EMPTY);
+                    self.leave_tries(0);
+                    self.emit(Op::GetLocal(self.scope().locals.len() - 1));
+                    self.emit(Op::Return);
+                    self.end_scope();
+                }
+                Some(value) => {
+                    self.expression(value);
+                    self.emit(Op::Return);
+                }
+                None => {
+                    self.leave_tries(0);
+                    self.emit_return();
+                }
+            },
+            Stmt::Block { statements, .. } => self.block(statements),
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => self.try_statement(body, catch.as_ref(), finally.clone().map(Cleanup::Finally)),
+            Stmt::Throw { keyword, value, .. } => {
+                self.expression(value);
+                self.token = keyword.clone();
+                self.emit(Op::Throw);
+            }
+            Stmt::Defer { .. } => unreachable!("blocks compile their `defer`s themselves"),
+            Stmt::Use {
+                keyword,
+                name,
+                initializer,
+                body,
+                ..
+            } => {
+                self.begin_scope();
+                self.expression(initializer);
+                self.add_local(name.symbol());
+                let close = Rc::new(Stmt::Expression(Stmt::close_call(keyword, name)));
+                self.try_statement(body, None, Some(Cleanup::Defer(close)));
+                self.end_scope();
+            }
+        }
+    }
+
+    /// Calls the method `name` of the local in `slot` with no arguments, as
+    /// a for-in loop at `keyword` does to go through its iterator.
+    fn call_method(&mut self, slot: usize, keyword: &Token, name: &str) {
+        self.emit(Op::GetLocal(slot));
+        self.token = Token::identifier_at(keyword, name);
+        self.emit(Op::GetProperty(Symbol::intern(name)));
+        self.token = keyword.clone();
+        self.emit(Op::Call(0));
+    }
+
+    fn block(&mut self, statements: &[Stmt]) {
+        self.begin_scope();
+        self.statements(statements);
+        self.end_scope();
+    }
+
+    /// Compiles the statements of a block. The ones after a `defer` are
+    /// compiled as the body of a `try` statement with the deferred one as
+    /// its `finally` block.
+    fn statements(&mut self, statements: &[Stmt]) {
+        for (index, statement) in statements.iter().enumerate() {
+            if let Stmt::Defer { statement, .. } = statement {
+                let rest = &statements[index + 1..];
+                self.try_statement(rest, None, Some(Cleanup::Defer(statement.clone())));
+                return;
+            }
+            self.statement(statement);
+        }
+    }
+
+    /// Compiles `try`. The body runs under a handler that an error jumps to
+    /// with the caught value pushed, which is then the `catch` variable. A
+    /// `finally` block is copied into each way out: after the body or the
+    /// `catch` clause, ahead of a `return`, `break` or `continue` leaving
+    /// them, and on an error that `catch` doesn't handle, which is raised
+    /// again once the block has run.
+    fn try_statement(&mut self, body: &[Stmt], catch: Option<&Catch>, finally: Option<Cleanup>) {
+        let handler = self.emit(Op::Try(0, catch.is_none()));
+        self.guarded(finally.clone(), |compiler| compiler.block(body));
+        self.emit(Op::EndTry);
+        let mut exits = vec![self.emit(Op::Jump(0))];
+        self.patch_jump(handler);
+
+        match (catch, &finally) {
+            (Some(catch), Some(finally)) => {
+                self.begin_scope();
+                self.add_local(catch.name.symbol());
+                let handler = self.emit(Op::Try(0, true));
+                self.guarded(Some(finally.clone()), |compiler| {
+                    compiler.block(&catch.body)
+                });
+                self.emit(Op::EndTry);
+                self.end_scope();
+                exits.push(self.emit(Op::Jump(0)));
+
+                // The `catch` variable is still on the stack under the error.
+                self.patch_jump(handler);
+                self.rethrow_after(finally.statements(), 2);
+            }
+            (Some(catch), None) => {
+                self.begin_scope();
+                self.add_local(catch.name.symbol());
+                self.block(&catch.body);
+                self.end_scope();
+            }
+            (None, Some(finally)) => self.rethrow_after(finally.statements(), 1),
+            (None, None) => unreachable!("the parser requires `catch` or `finally`"),
+        }
+
+        for exit in exits {
+            self.patch_jump(exit);
+        }
+        if let Some(finally) = finally {
+            self.block(finally.statements());
+        }
+    }
+
+    /// Compiles `compile` as the part of a `try` statement a handler guards,
+    /// so jumps out of it leave the handler and run `finally`.
+    fn guarded(&mut self, finally: Option<Cleanup>, compile: impl FnOnce(&mut Self)) {
+        let scope_depth = self.scope().scope_depth;
+        self.scope_mut().tries.push(TryBlock {
+            finally,
+            scope_depth,
+        });
+        compile(self);
+        self.scope_mut().tries.pop();
+    }
+
+    /// Compiles the handler that runs `finally` after an error and then
+    /// raises it again. `values` are left on the stack by the handler.
+    fn rethrow_after(&mut self, finally: &[Stmt], values: usize) {
+        self.begin_scope();
+        for _ in 0..values {
+            self.add_local(Symbol::EMPTY);
+        }
+        self.block(finally);
+        self.emit(Op::Rethrow);
+        self.end_scope();
+    }
+
+    /// Leaves the `try` blocks past the first `keep`, innermost first, ahead
+    /// of a jump out of them: ends each one's handler and runs its `finally`
+    /// block. Compilation of the blocks carries on after the jump, so they
+    /// are put back afterwards.
+    fn leave_tries(&mut self, keep: usize) {
+        let mut left = vec![];
+
+        while self.scope().tries.len() > keep {
+            let block = self.scope_mut().tries.pop().expect("more than `keep`");
+            self.emit(Op::EndTry);
+            if let Some(finally) = &block.finally {
+                // The locals declared inside the block are still on the
+                // stack, but out of the `finally` block's scope.
+                let hidden: Vec<_> = self
+                    .scope_mut()
+                    .locals
+                    .iter_mut()
+                    .filter(|local| local.depth > block.scope_depth)
+                    .map(|local| std::mem::replace(&mut local.name, Symbol::EMPTY))
+                    .collect();
+                self.block(finally.statements());
+                let locals = &mut self.scope_mut().locals;
+                for (local, name) in locals
+                    .iter_mut()
+                    .filter(|local| local.depth > block.scope_depth)
+                    .zip(hidden)
+                {
+                    local.name = name;
+                }
+            }
+            left.push(block);
+        }
+
+        self.scope_mut().tries.extend(left.into_iter().rev());
+    }
+
+    /// Compiles a class declaration. The methods are compiled as closures,
+    /// which [`Op::Class`] gathers into the class. A local class has its slot
+    /// before they are compiled so they can refer to it, and the methods of
+    /// a subclass close over a scope that binds `super`.
+    fn class(&mut self, name: &Token, superclass: Option<&Expr>, methods: &[Rc<FunctionDecl>]) {
+        self.token = name.clone();
+        let slot = (self.scope().scope_depth > 0).then(|| {
+            self.emit(Op::Nil);
+            self.add_local(name.symbol());
+            self.scope().locals.len() - 1
+        });
+
+        if let Some(superclass) = superclass {
+            self.begin_scope();
+            self.expression(superclass);
+            self.add_local(Symbol::SUPER);
+            let (get, _) = self.accessors(Symbol::SUPER);
+            self.emit(get);
+        }
+
+        for method in methods {
+            let kind = match method.name.symbol() == Symbol::INIT && !method.is_class_method {
+                true => FunctionKind::Initializer,
+                false => FunctionKind::Method,
+            };
+            self.function(method, kind);
+        }
+
+        // The class can only fail to be created if the superclass isn't one.
+        if let Some(token) = superclass.and_then(Expr::token) {
+            self.token = token.clone();
+        }
+        self.emit(Op::Class {
+            name: name.symbol(),
+            inherits: superclass.is_some(),
+            methods: methods.len(),
+        });
+        match slot {
+            Some(slot) => {
+                self.emit(Op::SetLocal(slot));
+                self.emit(Op::Pop);
+            }
+            None => {
+                self.emit(Op::DefineClass(name.symbol()));
+            }
+        }
+
+        if superclass.is_some() {
+            self.end_scope();
+        }
+    }
+
+    /// Compiles a function body into its own [`Function`] and emits the
+    /// instruction that creates a closure over it.
+    fn function(&mut self, declaration: &FunctionDecl, kind: FunctionKind) {
+        let mut scope = FunctionScope::new(
+            declaration.name.lexeme.to_string(),
+            declaration.params.len(),
+            kind,
+        );
+        scope.function.parameters = declaration.params.iter().map(Token::symbol).collect();
+        scope.function.is_getter = declaration.is_getter;
+        scope.function.is_class_method = declaration.is_class_method;
+        self.functions.push(scope);
+        self.begin_scope();
+
+        for param in &declaration.params {
+            self.add_local(param.symbol());
+        }
+        self.statements(&declaration.body);
+
+        self.emit_return();
+        self.end_function();
+    }
+
+    /// Pops the function being compiled and emits the instruction that
+    /// creates a closure over it in the enclosing one.
+    fn end_function(&mut self) {
+        let scope = self.functions.pop().expect("function scope was pushed");
+        let chunk = self.chunk_mut();
+        chunk.functions.push(Rc::new(scope.function));
+        let index = chunk.functions.len() - 1;
+        self.emit(Op::Closure(index));
+    }
+
+    /// Compiles a match as a closure that's called on the matched value
+    /// straight away, so each arm's bindings are locals of their own however
+    /// deep in an expression the match is.
+    fn match_expression(
+        &mut self,
+        keyword: &Token,
+        subject: &Expr,
+        arms: &[MatchArm],
+        otherwise: &Expr,
+    ) {
+        let mut scope = FunctionScope::new("match".into(), 1, FunctionKind::Function);
+        scope.function.inline = true;
+        self.functions.push(scope);
+        self.begin_scope();
+        self.add_local(Symbol::EMPTY);
+
+        for arm in arms {
+            let mut fails = vec![];
+            self.pattern_test(keyword, &arm.pattern, &mut vec![], &mut fails);
+
+            self.begin_scope();
+            self.bind_pattern(&arm.pattern, &mut vec![]);
+            self.expression(&arm.body);
+            self.emit(Op::Return);
+            self.end_scope();
+
+            // A failed test jumps here with its result still on the stack.
+            for &fail in &fails {
+                self.patch_jump(fail);
+            }
+            if !fails.is_empty() {
+                self.emit(Op::Pop);
+            }
+        }
+        self.expression(otherwise);
+        self.emit(Op::Return);
+        self.end_function();
+
+        self.expression(subject);
+        self.token = keyword.clone();
+        self.emit(Op::Call(1));
+    }
+
+    /// Emits the checks that the part of the matched value at `path` fits
+    /// `pattern`, adding the jumps taken when one fails to `fails`.
+    fn pattern_test<'a>(
+        &mut self,
+        keyword: &Token,
+        pattern: &'a Pattern,
+        path: &mut Vec<Step<'a>>,
+        fails: &mut Vec<usize>,
+    ) {
+        match pattern {
+            Pattern::Literal(literal) => {
+                self.load_path(path);
+                self.expression(literal);
+                self.token = equals_token(keyword);
+                self.emit(Op::Equal);
+                self.fail_unless(fails);
+            }
+            Pattern::Binding(_) => {}
+            Pattern::Tuple { elements, .. } => {
+                self.load_path(path);
+                self.emit(Op::IsTuple(elements.len()));
+                self.fail_unless(fails);
+
+                for (index, element) in elements.iter().enumerate() {
+                    path.push(Step::Element(index));
+                    self.pattern_test(keyword, element, path, fails);
+                    path.pop();
+                }
+            }
+            Pattern::Instance { class, fields, .. } => {
+                self.load_path(path);
+                self.expression(class);
+                self.emit(Op::IsInstance);
+                self.fail_unless(fails);
+
+                for field in fields {
+                    self.load_path(path);
+                    self.token = field.clone();
+                    self.emit(Op::HasField(field.symbol()));
+                    self.fail_unless(fails);
+                }
+            }
+        }
+    }
+
+    fn fail_unless(&mut self, fails: &mut Vec<usize>) {
+        fails.push(self.emit(Op::JumpIfFalse(0)));
+        self.emit(Op::Pop);
+    }
+
+    /// Declares what `pattern` binds as locals, in the order the resolver
+    /// declared them.
+    fn bind_pattern<'a>(&mut self, pattern: &'a Pattern, path: &mut Vec<Step<'a>>) {
+        match pattern {
+            Pattern::Literal(_) => {}
+            Pattern::Binding(name) => {
+                self.load_path(path);
+                self.add_local(name.symbol());
+            }
+            Pattern::Tuple { elements, .. } => {
+                for (index, element) in elements.iter().enumerate() {
+                    path.push(Step::Element(index));
+                    self.bind_pattern(element, path);
+                    path.pop();
+                }
+            }
+            Pattern::Instance { fields, .. } => {
+                for field in fields {
+                    path.push(Step::Field(field));
+                    self.load_path(path);
+                    self.add_local(field.symbol());
+                    path.pop();
+                }
+            }
+        }
+    }
+
+    /// Pushes the part of the matched value at `path`.
+    fn load_path(&mut self, path: &[Step]) {
+        self.emit(Op::GetLocal(1));
+        for step in path {
+            match step {
+                Step::Element(index) => {
+                    let constant = self.add_constant(Value::Number(*index as f64));
+                    self.emit(Op::Constant(constant));
+                    self.emit(Op::GetIndex);
+                }
+                Step::Field(name) => {
+                    self.token = (*name).clone();
+                    self.emit(Op::GetProperty(name.symbol()));
+                }
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        if let Some(token) = expr.token() {
+            self.token = token.clone();
+        }
+
+        match expr {
+            Expr::Literal { value: literal, .. } => {
+                match literal {
+                    Literal::Nil => self.emit(Op::Nil),
+                    Literal::True => self.emit(Op::True),
+                    Literal::False => self.emit(Op::False),
+                    literal => {
+                        let value = Value::from_literal(literal, self.integers);
+                        let constant = self.add_constant(value);
+                        self.emit(Op::Constant(constant))
+                    }
+                };
+            }
+            Expr::Grouping { group, .. } => self.expression(group),
+            Expr::Unary { operator, right } => {
+                self.expression(right);
+                match operator.value {
+                    TokenValue::Bang => self.emit(Op::Not),
+                    TokenValue::Minus => self.emit(Op::Negate),
+                    _ => unreachable!("invalid unary operator `{}`", operator.lexeme),
+                };
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left);
+                self.expression(right);
+                self.token = operator.clone();
+                self.emit(binary_op(operator));
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left);
+
+                if operator.value == TokenValue::And {
+                    let end_jump = self.emit(Op::JumpIfFalse(0));
+                    self.emit(Op::Pop);
+                    self.expression(right);
+                    self.patch_jump(end_jump);
+                } else {
+                    let else_jump = self.emit(Op::JumpIfFalse(0));
+                    let end_jump = self.emit(Op::Jump(0));
+                    self.patch_jump(else_jump);
+                    self.emit(Op::Pop);
+                    self.expression(right);
+                    self.patch_jump(end_jump);
+                }
+            }
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                let else_jump = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.expression(then_branch);
+
+                let end_jump = self.emit(Op::Jump(0));
+                self.patch_jump(else_jump);
+                self.emit(Op::Pop);
+                self.expression(else_branch);
+                self.patch_jump(end_jump);
+            }
+            Expr::Variable { name, .. } | Expr::This { keyword: name, .. } => {
+                let (get, _) = self.accessors(name.symbol());
+                self.emit(get);
+            }
+            Expr::Assign { name, value, .. } => {
+                self.expression(value);
+                let (_, set) = self.accessors(name.symbol());
+                self.emit(set);
+            }
+            Expr::Update {
+                operator,
+                target,
+                prefix,
+            } => {
+                let delta = match operator.value {
+                    TokenValue::PlusPlus => 1,
+                    _ => -1,
+                };
+
+                match target.as_ref() {
+                    // A postfix update keeps a copy of the old value under the
+                    // new one, which is popped once it has been stored.
+                    Expr::Variable { name, .. } => {
+                        let (get, set) = self.accessors(name.symbol());
+                        self.emit(get);
+                        if !prefix {
+                            self.emit(Op::Dup);
+                        }
+                        self.emit(Op::Step(delta));
+                        self.emit(set);
+                        if !prefix {
+                            self.emit(Op::Pop);
+                        }
+                    }
+                    Expr::Index { object, index, .. } => {
+                        self.expression(object);
+                        self.expression(index);
+                        self.token = operator.clone();
+                        self.emit(Op::StepIndex(delta, *prefix));
+                    }
+                    // A postfix update stores the new value from copies of the
+                    // object and the old value, then drops the object from
+                    // under the old value.
+                    Expr::Get { object, name } => {
+                        self.expression(object);
+                        self.emit(Op::Dup);
+                        self.token = name.clone();
+                        self.emit(Op::GetProperty(name.symbol()));
+                        if !prefix {
+                            self.emit(Op::DupPair);
+                        }
+                        self.token = operator.clone();
+                        self.emit(Op::Step(delta));
+                        self.token = name.clone();
+                        self.emit(Op::SetProperty(name.symbol()));
+                        if !prefix {
+                            self.emit(Op::Pop);
+                            self.emit(Op::Swap);
+                            self.emit(Op::Pop);
+                        }
+                    }
+                    _ => unreachable!("the parser only allows assignable update targets"),
+                }
+            }
+            Expr::Compound {
+                target,
+                operator,
+                value,
+            } => match target.as_ref() {
+                Expr::Variable { name, .. } => {
+                    let (get, set) = self.accessors(name.symbol());
+                    self.emit(get);
+                    self.expression(value);
+                    self.token = operator.clone();
+                    self.emit(binary_op(operator));
+                    self.emit(set);
+                }
+                // The object and index stay on the stack under the element
+                // read from them, for the store.
+                Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                } => {
+                    self.expression(object);
+                    self.expression(index);
+                    self.emit(Op::DupPair);
+                    self.token = bracket.clone();
+                    self.emit(Op::GetIndex);
+                    self.expression(value);
+                    self.token = operator.clone();
+                    self.emit(binary_op(operator));
+                    self.token = bracket.clone();
+                    self.emit(Op::SetIndex);
+                }
+                Expr::Get { object, name } => {
+                    self.expression(object);
+                    self.emit(Op::Dup);
+                    self.token = name.clone();
+                    self.emit(Op::GetProperty(name.symbol()));
+                    self.expression(value);
+                    self.token = operator.clone();
+                    self.emit(binary_op(operator));
+                    self.token = name.clone();
+                    self.emit(Op::SetProperty(name.symbol()));
+                }
+                _ => unreachable!("the parser only allows assignable targets"),
+            },
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+                keywords,
+            } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+                self.token = paren.clone();
+                if keywords.is_empty() {
+                    self.emit(Op::Call(arguments.len()));
+                } else {
+                    let chunk = self.chunk_mut();
+                    chunk
+                        .keywords
+                        .push(keywords.iter().map(Token::symbol).collect());
+                    let index = chunk.keywords.len() - 1;
+                    self.emit(Op::CallWithKeywords(arguments.len(), index));
+                }
+            }
+            Expr::Super { method, .. } => {
+                let (this, _) = self.accessors(Symbol::THIS);
+                self.emit(this);
+                let (superclass, _) = self.accessors(Symbol::SUPER);
+                self.emit(superclass);
+                self.token = method.clone();
+                self.emit(Op::GetSuper(method.symbol()));
+            }
+            Expr::Get { object, name } => {
+                self.expression(object);
+                self.token = name.clone();
+                self.emit(Op::GetProperty(name.symbol()));
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(value);
+                self.token = name.clone();
+                self.emit(Op::SetProperty(name.symbol()));
+            }
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                self.emit(Op::Map(entries.len()));
+            }
+            Expr::List { elements, .. } => {
+                for element in elements {
+                    self.expression(element);
+                }
+                self.emit(Op::List(elements.len()));
+            }
+            Expr::Tuple { elements, .. } => {
+                for element in elements {
+                    self.expression(element);
+                }
+                self.emit(Op::Tuple(elements.len()));
+            }
+            Expr::Match {
+                keyword,
+                subject,
+                arms,
+                otherwise,
+                ..
+            } => self.match_expression(keyword, subject, arms, otherwise),
+            Expr::Index { object, index, .. } => {
+                self.expression(object);
+                self.expression(index);
+                self.emit(Op::GetIndex);
+            }
+            Expr::Slice {
+                object,
+                bracket,
+                start,
+                end,
+            } => {
+                self.expression(object);
+                for bound in [start, end] {
+                    match bound {
+                        Some(bound) => self.expression(bound),
+                        None => {
+                            self.emit(Op::Nil);
+                        }
+                    }
+                }
+                self.token = bracket.clone();
+                self.emit(Op::Slice);
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                bracket,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+                self.token = bracket.clone();
+                self.emit(Op::SetIndex);
+            }
+        }
+    }
+
+    fn scope(&self) -> &FunctionScope {
+        self.functions.last().expect("script scope is never popped")
+    }
+
+    fn scope_mut(&mut self) -> &mut FunctionScope {
+        self.functions
+            .last_mut()
+            .expect("script scope is never popped")
+    }
+
+    fn chunk(&self) -> &Chunk {
+        &self.scope().function.chunk
+    }
+
+    fn chunk_mut(&mut self) -> &mut Chunk {
+        &mut self.scope_mut().function.chunk
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let token = self.token.clone();
+        self.chunk_mut().write(op, token)
+    }
+
+    /// Returns from the function without a value, which in an initializer
+    /// returns the instance.
+    fn emit_return(&mut self) {
+        match self.scope().kind {
+            FunctionKind::Initializer => self.emit(Op::GetLocal(0)),
+            FunctionKind::Function | FunctionKind::Method => self.emit(Op::Nil),
+        };
+        self.emit(Op::Return);
+    }
+
+    /// Points the jump at `index` to the next instruction to be emitted.
+    fn patch_jump(&mut self, index: usize) {
+        let target = self.chunk().code.len();
+
+        match &mut self.chunk_mut().code[index] {
+            Op::Jump(to) | Op::JumpIfFalse(to) | Op::Try(to, _) => *to = target,
+            op => unreachable!("patching non-jump instruction {op:?}"),
+        }
+    }
+
+    /// Adds `value` to the module's constant pool, reusing the existing
+    /// entry if an equal number or string is already there.
+    fn add_constant(&mut self, value: Value) -> usize {
+        let key = match &value {
+            Value::Int(n) => ConstantKey::Int(*n),
+            Value::Number(n) => ConstantKey::Number(n.to_bits()),
+            Value::String(s) => ConstantKey::String(s.clone()),
+            value => unreachable!("{value:?} is not a constant"),
+        };
+
+        if let Some(&index) = self.constant_indexes.get(&key) {
+            return index;
+        }
+
+        self.constants.push(value);
+        let index = self.constants.len() - 1;
+        self.constant_indexes.insert(key, index);
+        index
+    }
+
+    fn begin_scope(&mut self) {
+        self.scope_mut().scope_depth += 1;
+    }
+
+    /// Pops the scope's locals off the stack, moving any that a closure
+    /// captured into the heap first.
+    fn end_scope(&mut self) {
+        let scope = self.scope_mut();
+        scope.scope_depth -= 1;
+
+        let depth = scope.scope_depth;
+        while let Some(local) = self.scope().locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+
+            let op = match local.is_captured {
+                true => Op::CloseUpvalue,
+                false => Op::Pop,
+            };
+            self.emit(op);
+            self.scope_mut().locals.pop();
+        }
+    }
+
+    fn innermost_loop(&mut self) -> &mut Loop {
+        self.scope_mut()
+            .loops
+            .last_mut()
+            .expect("parser rejects loop control outside loops")
+    }
+
+    /// Pops the locals declared inside the innermost loop's body, ahead of a
+    /// jump out of it. They stay in `locals`, since compilation of the body
+    /// carries on after the jump.
+    fn discard_loop_locals(&mut self) {
+        let depth = self.innermost_loop().scope_depth;
+        let ops: Vec<Op> = self
+            .scope()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| match local.is_captured {
+                true => Op::CloseUpvalue,
+                false => Op::Pop,
+            })
+            .collect();
+
+        for op in ops {
+            self.emit(op);
+        }
+    }
+
+    /// Binds the value on top of the stack to `name`: as a global at the top
+    /// level, otherwise by leaving it in place as a new local.
+    fn define_variable(&mut self, name: &Token) {
+        if self.scope().scope_depth > 0 {
+            self.add_local(name.symbol());
+        } else {
+            self.emit(Op::DefineGlobal(name.symbol()));
+        }
+    }
+
+    fn add_local(&mut self, name: Symbol) {
+        let scope = self.scope_mut();
+        scope.locals.push(Local {
+            name,
+            depth: scope.scope_depth,
+            is_captured: false,
+        });
+    }
+
+    /// The instructions that read and write the variable `name`, wherever
+    /// it was declared.
+    fn accessors(&mut self, name: Symbol) -> (Op, Op) {
+        let current = self.functions.len() - 1;
+
+        if let Some(slot) = self.resolve_local(current, name) {
+            return (Op::GetLocal(slot), Op::SetLocal(slot));
+        }
+        if let Some(index) = self.resolve_upvalue(current, name) {
+            return (Op::GetUpvalue(index), Op::SetUpvalue(index));
+        }
+
+        (Op::GetGlobal(name), Op::SetGlobal(name))
+    }
+
+    fn resolve_local(&self, function: usize, name: Symbol) -> Option<usize> {
+        self.functions[function]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+    }
+
+    /// Finds `name` in an enclosing function, threading it through the
+    /// upvalues of every function in between.
+    fn resolve_upvalue(&mut self, function: usize, name: Symbol) -> Option<usize> {
+        let enclosing = function.checked_sub(1)?;
+
+        if let Some(slot) = self.resolve_local(enclosing, name) {
+            self.functions[enclosing].locals[slot].is_captured = true;
+            return Some(self.add_upvalue(
+                function,
+                UpvalueSource {
+                    is_local: true,
+                    index: slot,
+                },
+            ));
+        }
+
+        let index = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_upvalue(
+            function,
+            UpvalueSource {
+                is_local: false,
+                index,
+            },
+        ))
+    }
+
+    fn add_upvalue(&mut self, function: usize, source: UpvalueSource) -> usize {
+        let upvalues = &mut self.functions[function].function.upvalues;
+
+        if let Some(index) = upvalues.iter().position(|upvalue| {
+            upvalue.is_local == source.is_local && upvalue.index == source.index
+        }) {
+            return index;
+        }
+
+        upvalues.push(source);
+        upvalues.len() - 1
+    }
+}
+
+fn binary_op(operator: &Token) -> Op {
+    match operator.value {
+        TokenValue::Plus => Op::Add,
+        TokenValue::Minus => Op::Subtract,
+        TokenValue::Star => Op::Multiply,
+        TokenValue::Slash => Op::Divide,
+        TokenValue::Percent => Op::Modulo,
+        TokenValue::StarStar => Op::Power,
+        TokenValue::Greater => Op::Greater,
+        TokenValue::GreaterEqual => Op::GreaterEqual,
+        TokenValue::Less => Op::Less,
+        TokenValue::LessEqual => Op::LessEqual,
+        TokenValue::EqualEqual => Op::Equal,
+        TokenValue::BangEqual => Op::NotEqual,
+        _ => unreachable!("invalid binary operator `{}`", operator.lexeme),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lox, scanner::ScannerOptions};
+
+    fn compile(source: &str) -> Module {
+        let program = lox::parse(source, ScannerOptions::default(), false)
+            .unwrap_or_else(|err| panic!("`{source}` failed: {err}"));
+        Compiler::new().compile(&program)
+    }
+
+    #[test]
+    fn locals_live_in_stack_slots_and_globals_by_name() {
+        let module = compile("var a = 1; { var b = a; print b; }");
+        let a = Symbol::intern("a");
+
+        assert_eq!(
+            module.script.chunk.code,
+            [
+                Op::Constant(0),
+                Op::DefineGlobal(a),
+                Op::GetGlobal(a),
+                Op::GetLocal(1),
+                Op::Print(1),
+                Op::Pop,
+                Op::Nil,
+                Op::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_literals_share_a_constant() {
+        let module = compile("print 1; print \"a\"; fun f() { return 1 + 0; } print \"a\";");
+
+        assert_eq!(module.constants.len(), 3);
+        assert_eq!(
+            module.script.chunk.functions[0].chunk.code[0],
+            Op::Constant(0)
+        );
+    }
+
+    #[test]
+    fn captured_locals_are_closed_over() {
+        let module = compile("{ var n = 0; fun next() { n = n + 1; } }");
+        let next = &module.script.chunk.functions[0];
+
+        assert_eq!(next.upvalues.len(), 1);
+        assert!(next.upvalues[0].is_local);
+        assert_eq!(next.upvalues[0].index, 1);
+        assert_eq!(
+            module.script.chunk.code[module.script.chunk.code.len() - 4..],
+            [Op::Pop, Op::CloseUpvalue, Op::Nil, Op::Return]
+        );
+    }
+
+    #[test]
+    fn methods_find_this_in_slot_zero() {
+        let module = compile("class A { init() { return; } get { return this.x; } }");
+        let [init, get] = &module.script.chunk.functions[..] else {
+            panic!("expected two methods");
+        };
+
+        assert_eq!(
+            init.chunk.code,
+            [Op::GetLocal(0), Op::Return, Op::GetLocal(0), Op::Return]
+        );
+        assert!(get.is_getter);
+        assert_eq!(
+            get.chunk.code,
+            [
+                Op::GetLocal(0),
+                Op::GetProperty(Symbol::intern("x")),
+                Op::Return,
+                Op::Nil,
+                Op::Return,
+            ]
+        );
+        assert!(module.script.chunk.code.contains(&Op::Class {
+            name: Symbol::intern("A"),
+            inherits: false,
+            methods: 2,
+        }));
+    }
+
+    #[test]
+    fn subclass_methods_close_over_super() {
+        let module = compile("class A {} class B < A { m() { return super.m; } }");
+        let method = &module.script.chunk.functions[0];
+
+        assert_eq!(
+            method.chunk.code[..3],
+            [
+                Op::GetLocal(0),
+                Op::GetUpvalue(0),
+                Op::GetSuper(Symbol::intern("m"))
+            ]
+        );
+        assert_eq!(
+            module.script.chunk.code[module.script.chunk.code.len() - 3..],
+            [Op::CloseUpvalue, Op::Nil, Op::Return]
+        );
+    }
+}
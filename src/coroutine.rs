@@ -0,0 +1,143 @@
+//! Cooperative coroutines and the channels they pass values over.
+//!
+//! `spawn(fn)` only works on the bytecode vm, which gives each coroutine a
+//! stack of its own and switches to the next one that can run when the
+//! running one calls `yieldTo()` or waits in `recv` on an empty channel.
+//! Nothing switches otherwise, so the code between those calls runs
+//! without interruption. Once the script ends, the coroutines it spawned
+//! run until each has finished or waits on a channel nothing can send to.
+//!
+//! `spawn`, `yieldTo` and `recv` are only supported on the vm. The
+//! tree-walking interpreter has no coroutines besides the script, so there
+//! each of them fails with an error saying so. Channels themselves, with
+//! `chanNew` and `send`, work on either backend.
+
+use std::{cell::RefCell, collections::VecDeque, fmt::Display, rc::Rc};
+
+use crate::{
+    callable::LoxCallable,
+    error::{Result, RuntimeError},
+    gc::{self, Trace, Visitor},
+    interpreter::Interpreter,
+    value::Value,
+};
+
+/// The error for `recv` on an empty channel when no other coroutine can run
+/// to send to it.
+pub(crate) const DEADLOCK: &str = "Deadlock: every coroutine is waiting to receive";
+
+/// A queue of values sent with `send` and waiting for `recv`. Sending never
+/// waits, however many values haven't been received.
+#[derive(Debug, Default)]
+pub struct Channel {
+    values: RefCell<VecDeque<Value>>,
+}
+
+impl Channel {
+    pub(crate) fn send(&self, value: Value) {
+        self.values.borrow_mut().push_back(value);
+    }
+
+    /// Takes the value sent first, if any are waiting.
+    pub(crate) fn receive(&self) -> Option<Value> {
+        self.values.borrow_mut().pop_front()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.values.borrow().is_empty()
+    }
+}
+
+impl Trace for Channel {
+    fn trace(&self, visit: &mut Visitor) {
+        if let Ok(values) = self.values.try_borrow() {
+            values.iter().for_each(|value| value.trace(visit));
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut values) = self.values.try_borrow_mut() {
+            values.clear();
+        }
+    }
+}
+
+/// The natives that can switch coroutines, which the vm runs itself rather
+/// than calling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Scheduling {
+    /// `spawn(fn)` starts a coroutine that calls `fn` with no arguments.
+    Spawn,
+    /// `yieldTo()` lets the other coroutines that can run go first.
+    Yield,
+    /// `recv(channel)` takes the next value sent on the channel, waiting
+    /// for one if there isn't any yet.
+    Receive,
+}
+
+/// One of the [`Scheduling`] natives, which the vm recognizes and runs
+/// itself. Called any other way, as the tree-walking interpreter does, it
+/// fails.
+#[derive(Debug)]
+pub(crate) struct SchedulingNative(pub(crate) Scheduling);
+
+impl SchedulingNative {
+    fn name(&self) -> &'static str {
+        match self.0 {
+            Scheduling::Spawn => "spawn",
+            Scheduling::Yield => "yieldTo",
+            Scheduling::Receive => "recv",
+        }
+    }
+}
+
+impl LoxCallable for SchedulingNative {
+    fn arity(&self) -> usize {
+        match self.0 {
+            Scheduling::Spawn | Scheduling::Receive => 1,
+            Scheduling::Yield => 0,
+        }
+    }
+
+    fn call(self: Rc<Self>, _: &mut Interpreter, _: Vec<Value>) -> Result<Value> {
+        Err(RuntimeError::GeneralError(
+            format!(
+                "`{}` is not supported on this backend, run with --backend=vm",
+                self.name()
+            )
+            .into(),
+        ))
+    }
+}
+
+impl Display for SchedulingNative {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name())
+    }
+}
+
+/// Defines the coroutine and channel natives.
+pub(crate) fn define_globals(interpreter: &mut Interpreter) {
+    for scheduling in [Scheduling::Spawn, Scheduling::Yield, Scheduling::Receive] {
+        let native = SchedulingNative(scheduling);
+        interpreter.define_global(native.name(), Value::Callable(Rc::new(native)));
+    }
+    interpreter.define_native("chanNew", 0, |_, _| {
+        Ok(Value::Channel(gc::alloc(Channel::default())))
+    });
+    interpreter.define_native("send", 2, |_, mut arguments| {
+        let value = arguments.pop().expect("arity is checked");
+        channel(&arguments[0])?.send(value);
+        Ok(Value::Nil)
+    });
+}
+
+/// The channel `value` holds, or an error if it isn't one.
+pub(crate) fn channel(value: &Value) -> Result<Rc<Channel>> {
+    match value {
+        Value::Channel(channel) => Ok(channel.clone()),
+        value => Err(RuntimeError::GeneralError(
+            format!("Expected a channel but got a {}", value.type_name()).into(),
+        )),
+    }
+}
@@ -0,0 +1,279 @@
+//! `rlox debug`: runs a script on the tree-walking interpreter, pausing
+//! before a statement at a breakpoint or after a step so the variables in
+//! scope can be looked at. Commands are read a line at a time.
+//!
+//! Only the main script's source is known, so nothing pauses while an
+//! imported script is running.
+
+use std::{
+    collections::BTreeSet,
+    io::{BufRead, Write},
+    rc::Rc,
+};
+
+use crate::{
+    error::{Result, RuntimeError},
+    interpreter::{Hook, Interpreter},
+    parser::Stmt,
+    symbol::Symbol,
+};
+
+const DEBUG_HELP: &str = "\
+break <line>    Pause whenever the script reaches <line>, also `b`
+delete <line>   Remove the breakpoint on <line>
+breakpoints     List the breakpoints
+step            Run to the next statement, going into calls, also `s`
+next            Run to the next statement, stepping over calls, also `n`
+continue        Run until a breakpoint, also `c`
+print <name>    Show a variable's value, also `p`
+locals          List the variables in the scopes around the paused line
+globals         List the globals the script has defined
+list            Show the lines around the paused one, also `l`
+quit            Stop the script, also `q`
+help            Show this message";
+
+/// How many lines `list` shows on each side of the paused one.
+const LIST_CONTEXT: usize = 2;
+
+/// When to pause next, besides at a breakpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    /// Before the next statement.
+    Step,
+    /// Before the next statement no deeper in calls than `depth`.
+    Next(usize),
+    /// Only at a breakpoint.
+    Continue,
+}
+
+pub(crate) struct Debugger {
+    source: String,
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+    /// The line of the last statement seen, so a breakpoint pauses once
+    /// when its line is reached rather than at every statement on it.
+    last_line: Option<usize>,
+    /// How many scripts were running when the main script started, so
+    /// statements from the scripts it imports can be told apart.
+    main_script: Option<usize>,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+}
+
+impl Debugger {
+    /// Debugs the script in `source`, reading commands from `input` and
+    /// writing what they show to `output`. It starts paused before the
+    /// first statement.
+    pub(crate) fn new(source: &str, input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Self {
+            source: source.to_string(),
+            breakpoints: BTreeSet::new(),
+            mode: Mode::Step,
+            last_line: None,
+            main_script: None,
+            input,
+            output,
+        }
+    }
+
+    /// Shows where the script is paused, then runs commands until one lets
+    /// it carry on. Running out of commands lets the script run to its end.
+    fn pause(&mut self, interpreter: &Interpreter, line: usize) -> Result<()> {
+        self.list(line, 0)?;
+
+        loop {
+            write!(self.output, "(debug) ")?;
+            self.output.flush()?;
+
+            let mut input = String::new();
+            if self.input.read_line(&mut input)? == 0 {
+                writeln!(self.output)?;
+                self.breakpoints.clear();
+                self.mode = Mode::Continue;
+                return Ok(());
+            }
+
+            let input = input.trim();
+            let (command, argument) = input.split_once(' ').unwrap_or((input, ""));
+            let argument = argument.trim();
+            match command {
+                "" => {}
+                "s" | "step" => {
+                    self.mode = Mode::Step;
+                    return Ok(());
+                }
+                "n" | "next" => {
+                    self.mode = Mode::Next(interpreter.call_depth());
+                    return Ok(());
+                }
+                "c" | "continue" => {
+                    self.mode = Mode::Continue;
+                    return Ok(());
+                }
+                "q" | "quit" => return Err(RuntimeError::Exit(0)),
+                "b" | "break" => match self.line_number(argument) {
+                    Some(line) => {
+                        self.breakpoints.insert(line);
+                        writeln!(self.output, "Breakpoint on line {line}")?;
+                    }
+                    None => self.bad_line(argument)?,
+                },
+                "delete" => match self.line_number(argument) {
+                    Some(line) if self.breakpoints.remove(&line) => {
+                        writeln!(self.output, "Removed the breakpoint on line {line}")?
+                    }
+                    Some(line) => writeln!(self.output, "No breakpoint on line {line}")?,
+                    None => self.bad_line(argument)?,
+                },
+                "breakpoints" if self.breakpoints.is_empty() => {
+                    writeln!(self.output, "No breakpoints")?
+                }
+                "breakpoints" => {
+                    let lines: Vec<_> = self.breakpoints.iter().map(usize::to_string).collect();
+                    writeln!(self.output, "Breakpoints on lines {}", lines.join(", "))?;
+                }
+                "p" | "print" => {
+                    let value = interpreter
+                        .environment()
+                        .borrow()
+                        .lookup(Symbol::intern(argument));
+                    match value {
+                        Some(value) => writeln!(self.output, "{argument} = {value:#}")?,
+                        None => writeln!(self.output, "Undefined variable `{argument}`")?,
+                    }
+                }
+                "locals" => self.locals(interpreter)?,
+                "globals" => {
+                    let globals = interpreter.user_globals();
+                    if globals.is_empty() {
+                        writeln!(self.output, "No globals defined")?;
+                    }
+                    for (name, value) in globals {
+                        writeln!(self.output, "{name} = {value}")?;
+                    }
+                }
+                "l" | "list" => self.list(line, LIST_CONTEXT)?,
+                "h" | "help" => writeln!(self.output, "{DEBUG_HELP}")?,
+                _ => writeln!(self.output, "Unknown command `{command}`, see help")?,
+            }
+        }
+    }
+
+    /// Lists the variables in each scope from the innermost out, stopping
+    /// short of the globals.
+    fn locals(&mut self, interpreter: &Interpreter) -> Result<()> {
+        let globals = interpreter.globals();
+        let mut scope = Some(interpreter.environment());
+        let mut any = false;
+
+        while let Some(environment) = scope.filter(|scope| !Rc::ptr_eq(scope, &globals)) {
+            let environment = environment.borrow();
+            let mut bindings: Vec<_> = environment.bindings().collect();
+            bindings.sort_by_key(|(name, _)| name.as_str());
+            for (name, value) in bindings {
+                writeln!(self.output, "{name} = {value}")?;
+                any = true;
+            }
+            scope = environment.enclosing();
+        }
+
+        if !any {
+            writeln!(self.output, "No local variables")?;
+        }
+        Ok(())
+    }
+
+    /// Shows `line` with `context` lines on each side, marking `line`.
+    fn list(&mut self, line: usize, context: usize) -> Result<()> {
+        let first = line.saturating_sub(context).max(1);
+        let lines = self.source.lines().enumerate().skip(first - 1);
+        let width = (line + context).to_string().len();
+
+        for (index, text) in lines.take(line - first + context + 1) {
+            let number = index + 1;
+            let marker = if number == line { "->" } else { "  " };
+            writeln!(self.output, "{marker} {number:>width$} | {text}")?;
+        }
+        Ok(())
+    }
+
+    fn line_number(&self, argument: &str) -> Option<usize> {
+        let line = argument.parse().ok()?;
+        (1..=self.source.lines().count())
+            .contains(&line)
+            .then_some(line)
+    }
+
+    fn bad_line(&mut self, argument: &str) -> Result<()> {
+        writeln!(self.output, "`{argument}` isn't a line of the script")?;
+        Ok(())
+    }
+}
+
+impl Hook for Debugger {
+    fn on_statement(&mut self, interpreter: &Interpreter, stmt: &Stmt) -> Result<()> {
+        let scripts = interpreter.running_scripts();
+        if scripts > *self.main_script.get_or_insert(scripts) {
+            return Ok(());
+        }
+
+        let line = stmt.span().line_in(&self.source);
+        let reached = self.last_line != Some(line) && self.breakpoints.contains(&line);
+        self.last_line = Some(line);
+
+        let pause = reached
+            || match self.mode {
+                Mode::Step => true,
+                Mode::Next(depth) => interpreter.call_depth() <= depth,
+                Mode::Continue => false,
+            };
+        match pause {
+            true => self.pause(interpreter, line),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io::Cursor, rc::Rc};
+
+    use super::*;
+    use crate::{Capture, Lox};
+
+    /// A buffer the debugger writes to while the test keeps a handle on it.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn debugger_pauses_at_breakpoints_and_shows_locals() {
+        let source = "fun f(n) {\n  var m = n + 1;\n  return m;\n}\nprint f(1);";
+        let commands = "break 3\ncontinue\nlocals\nprint n\ncontinue\n";
+        let shown = Shared::default();
+        let debugger = Debugger::new(
+            source,
+            Box::new(Cursor::new(commands)),
+            Box::new(shown.clone()),
+        );
+        let capture = Capture::default();
+        let mut lox = Lox::new().with_output(capture.clone());
+
+        assert!(lox.debug_source(source, debugger).is_ok());
+        assert_eq!(capture.printed(), "2\n");
+        assert_eq!(
+            String::from_utf8_lossy(&shown.0.borrow()),
+            "-> 1 | fun f(n) {\n(debug) Breakpoint on line 3\n\
+             (debug) -> 3 |   return m;\n(debug) m = 2\nn = 1\n(debug) n = 1\n(debug) "
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+
+use crate::{
+    error::{ErrorCode, Message, Result, RuntimeError},
+    gc::{self, Trace, Visitor},
+    symbol::Symbol,
+    token::Token,
+    value::Value,
+};
+
+/// A single lexical scope mapping variable names to values. Blocks and
+/// function calls push a new environment whose `enclosing` is the scope their
+/// variables should fall back to. Environments are shared, since a closure
+/// keeps the scope it was declared in alive after that scope has been exited.
+#[derive(Debug)]
+pub(crate) struct Environment {
+    values: HashMap<Symbol, Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+thread_local! {
+    /// How many environments have been created on this thread, for
+    /// `rlox bench`.
+    static CREATED: Cell<usize> = const { Cell::new(0) };
+}
+
+impl Environment {
+    pub(crate) fn new() -> Self {
+        Self::create(None)
+    }
+
+    pub(crate) fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Self::create(Some(enclosing))
+    }
+
+    fn create(enclosing: Option<Rc<RefCell<Environment>>>) -> Self {
+        CREATED.with(|created| created.set(created.get() + 1));
+        Self {
+            values: HashMap::new(),
+            enclosing,
+        }
+    }
+
+    /// How many environments have been created on this thread so far.
+    pub(crate) fn created() -> usize {
+        CREATED.with(Cell::get)
+    }
+
+    pub(crate) fn define(&mut self, name: Symbol, value: Value) {
+        self.values.insert(name, value);
+    }
+
+    /// The variables declared directly in this scope, in no particular
+    /// order.
+    pub(crate) fn bindings(&self) -> impl Iterator<Item = (Symbol, &Value)> {
+        self.values.iter().map(|(name, value)| (*name, value))
+    }
+
+    pub(crate) fn enclosing(&self) -> Option<Rc<RefCell<Environment>>> {
+        self.enclosing.clone()
+    }
+
+    pub(crate) fn get(&self, name: &Token) -> Result<Value> {
+        self.lookup(name.symbol())
+            .ok_or_else(|| undefined_variable(name))
+    }
+
+    /// Finds `name` in this scope or any enclosing one.
+    pub(crate) fn lookup(&self, name: Symbol) -> Option<Value> {
+        match (self.values.get(&name), &self.enclosing) {
+            (Some(value), _) => Some(value.clone()),
+            (None, Some(enclosing)) => enclosing.borrow().lookup(name),
+            (None, None) => None,
+        }
+    }
+
+    pub(crate) fn assign(&mut self, name: &Token, value: Value) -> Result<()> {
+        if let Some(slot) = self.values.get_mut(&name.symbol()) {
+            *slot = value;
+            return Ok(());
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => Err(undefined_variable(name)),
+        }
+    }
+
+    /// Walks `distance` scopes out from `environment`. The resolver guarantees
+    /// the chain is at least that long.
+    pub(crate) fn ancestor(
+        environment: &Rc<RefCell<Environment>>,
+        distance: usize,
+    ) -> Rc<RefCell<Environment>> {
+        let mut environment = environment.clone();
+
+        for _ in 0..distance {
+            let enclosing = environment
+                .borrow()
+                .enclosing
+                .clone()
+                .expect("resolved scope distance exceeds environment depth");
+            environment = enclosing;
+        }
+
+        environment
+    }
+
+    /// Reads `name` from the scope exactly `distance` out, as computed by
+    /// the resolver.
+    pub(crate) fn get_at(
+        environment: &Rc<RefCell<Environment>>,
+        distance: usize,
+        name: Symbol,
+    ) -> Option<Value> {
+        Self::ancestor(environment, distance)
+            .borrow()
+            .values
+            .get(&name)
+            .cloned()
+    }
+
+    pub(crate) fn assign_at(
+        environment: &Rc<RefCell<Environment>>,
+        distance: usize,
+        name: &Token,
+        value: Value,
+    ) {
+        Self::ancestor(environment, distance)
+            .borrow_mut()
+            .define(name.symbol(), value);
+    }
+}
+
+impl Trace for RefCell<Environment> {
+    fn trace(&self, visit: &mut Visitor) {
+        let Ok(environment) = self.try_borrow() else {
+            return;
+        };
+
+        for value in environment.values.values() {
+            value.trace(visit);
+        }
+        if let Some(enclosing) = &environment.enclosing {
+            visit(gc::address(enclosing));
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut environment) = self.try_borrow_mut() {
+            environment.values.clear();
+            environment.enclosing = None;
+        }
+    }
+}
+
+fn undefined_variable(name: &Token) -> RuntimeError {
+    RuntimeError::InterpretError(
+        Message::new(
+            ErrorCode::UndefinedVariable,
+            format!("Undefined variable `{}`", name.lexeme),
+        ),
+        name.clone(),
+    )
+}
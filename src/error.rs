@@ -1,67 +1,722 @@
 use core::result::Result as CoreResult;
-use std::fmt::Display;
+use std::{collections::HashSet, fmt::Display, ops::Deref};
 
-use crate::token::Token;
+use crate::{span::Span, text, token::Token, value::Value};
 
-#[derive(Debug, Clone)]
-pub(crate) struct Error {
-    line: usize,
-    column: usize,
-    offset: usize,
-    message: String,
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    /// The source the error is about, which is underlined when the
+    /// diagnostic is shown in context. Empty when there isn't one.
+    pub span: Span,
+    pub message: String,
+    /// The error's code in the catalog `rlox --explain` reads, if it has
+    /// one, which is shown after `Error`.
+    pub code: Option<ErrorCode>,
+    /// A second location that helps explain the error, such as where a
+    /// duplicated name was first declared.
+    pub note: Option<Note>,
 }
 
-impl Display for Error {
+/// Extra context attached to a [`Diagnostic`], pointing at another place in
+/// the source.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Note {
+    pub line: usize,
+    pub column: usize,
+    pub span: Span,
+    pub message: String,
+}
+
+impl Display for Note {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}:{} | Note: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+/// `Error`, along with the error's code when it has one, as in
+/// `Error[E0001]`.
+fn heading(code: Option<ErrorCode>) -> String {
+    match code {
+        Some(code) => format!("Error[{code}]"),
+        None => "Error".into(),
+    }
+}
+
+impl Display for Diagnostic {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let _offset = self.offset; // @todo - use offset somehow
         let line = self.line;
         let message = self.message.as_str();
-        write!(f, "line {line} | Error: {message}")
+        let code = self
+            .code
+            .map(|code| format!("[{code}]"))
+            .unwrap_or_default();
+        match self.column {
+            0 => write!(f, "line {line} | Error{code}: {message}"),
+            column => write!(f, "line {line}:{column} | Error{code}: {message}"),
+        }
+    }
+}
+
+/// Every error found in a program before it runs, kept together with the
+/// source so each one can be shown in context. Displaying it prints each
+/// diagnostic followed by the offending line, with carets under the span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostics {
+    source: String,
+    diagnostics: Vec<Diagnostic>,
+    /// The imported script the errors are in, or `None` for the main one.
+    file: Option<String>,
+}
+
+impl Diagnostics {
+    pub(crate) fn new(source: &str, errors: Vec<RuntimeError>) -> Self {
+        Self {
+            source: source.into(),
+            diagnostics: errors.into_iter().map(Diagnostic::from).collect(),
+            file: None,
+        }
+    }
+
+    /// Marks the errors as being in the imported script `file`, unless
+    /// they're already known to be in a script it imports in turn.
+    pub(crate) fn in_file(mut self, file: &str) -> Self {
+        self.file.get_or_insert_with(|| file.into());
+        self
+    }
+
+    pub(crate) fn push(&mut self, error: RuntimeError) {
+        self.diagnostics.push(Diagnostic::from(error));
+    }
+
+    /// Sorts the diagnostics by where they are and drops any repeating the
+    /// message of an earlier one on the same line, which are almost always
+    /// cascading from it.
+    pub(crate) fn tidy(mut self) -> Self {
+        self.diagnostics
+            .sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+        let mut seen = HashSet::new();
+        self.diagnostics
+            .retain(|diagnostic| seen.insert((diagnostic.line, diagnostic.message.clone())));
+        self
+    }
+
+    /// The imported script the errors are in, or `None` for the main one.
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+}
+
+/// Writes `line` of `source` on a new line, with carets under `span` when
+/// the column is known.
+fn write_context(
+    f: &mut std::fmt::Formatter,
+    source: &str,
+    line: usize,
+    column: usize,
+    span: Span,
+) -> std::fmt::Result {
+    let Some(text) = line
+        .checked_sub(1)
+        .and_then(|index| source.lines().nth(index))
+    else {
+        return Ok(());
+    };
+
+    let gutter = line.to_string();
+    let blank = " ".repeat(gutter.len());
+    write!(f, "\n {gutter} | {text}")?;
+
+    if column > 0 {
+        // Columns count chars, but a letter and its combining accents take
+        // up one place on screen, so pad by character instead. Keep tabs so
+        // the caret lines up with the text above it.
+        let before: String = text.chars().take(column - 1).collect();
+        let padding: String = text::characters(&before)
+            .map(|c| if c == "\t" { '\t' } else { ' ' })
+            .collect();
+        // Underline the span, but only as far as the end of this line.
+        let width = source
+            .get(span.start..span.end)
+            .and_then(|spanned| spanned.lines().next())
+            .map_or(0, |spanned| text::characters(spanned).count())
+            .max(1);
+        write!(f, "\n {blank} | {padding}{}", "^".repeat(width))?;
+    }
+
+    Ok(())
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            writeln!(f, "In {file}:")?;
+        }
+
+        for (index, diagnostic) in self.diagnostics.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{diagnostic}")?;
+            write_context(
+                f,
+                &self.source,
+                diagnostic.line,
+                diagnostic.column,
+                diagnostic.span,
+            )?;
+
+            if let Some(note) = &diagnostic.note {
+                write!(f, "\n{note}")?;
+                write_context(f, &self.source, note.line, note.column, note.span)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.diagnostics.into_iter()
     }
 }
 
 pub type Result<T> = CoreResult<T, RuntimeError>;
 
+/// The errors `rlox --explain` and the REPL's `:why` have a longer
+/// explanation of, each shown with its code, such as `E0001`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    UndefinedVariable,
+    OperandTypes,
+    NotCallable,
+    ArgumentCount,
+    UndefinedProperty,
+    AlreadyDeclared,
+    OwnInitializer,
+    InvalidReturn,
+    OutsideConstruct,
+    StackOverflow,
+    IntegerArithmetic,
+    MissingSemicolon,
+    KeywordArguments,
+    StepLimit,
+    Timeout,
+}
+
+impl ErrorCode {
+    /// The code as it's shown and looked up, such as `E0001`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::UndefinedVariable => "E0001",
+            ErrorCode::OperandTypes => "E0002",
+            ErrorCode::NotCallable => "E0003",
+            ErrorCode::ArgumentCount => "E0004",
+            ErrorCode::UndefinedProperty => "E0005",
+            ErrorCode::AlreadyDeclared => "E0006",
+            ErrorCode::OwnInitializer => "E0007",
+            ErrorCode::InvalidReturn => "E0008",
+            ErrorCode::OutsideConstruct => "E0009",
+            ErrorCode::StackOverflow => "E0010",
+            ErrorCode::IntegerArithmetic => "E0011",
+            ErrorCode::MissingSemicolon => "E0012",
+            ErrorCode::KeywordArguments => "E0013",
+            ErrorCode::StepLimit => "E0014",
+            ErrorCode::Timeout => "E0015",
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What an error says, along with its [`ErrorCode`] when it's raised as one
+/// the catalog explains. It reads as the text alone.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Message {
+    text: Box<str>,
+    code: Option<ErrorCode>,
+}
+
+impl Message {
+    pub(crate) fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Self {
+            text: text.into().into_boxed_str(),
+            code: Some(code),
+        }
+    }
+
+    pub fn code(&self) -> Option<ErrorCode> {
+        self.code
+    }
+}
+
+impl Deref for Message {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Self {
+            text: text.into(),
+            code: None,
+        }
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Self {
+        text.to_string().into()
+    }
+}
+
+impl From<Message> for String {
+    fn from(message: Message) -> Self {
+        message.text.into()
+    }
+}
+
+/// A call that was still running when a runtime error happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    /// The name of the function or class that was called.
+    pub function: String,
+    /// The line the call was made on.
+    pub line: usize,
+}
+
 pub enum RuntimeError {
     ScanError {
         line: usize,
         column: usize,
-        offset: usize,
+        span: Span,
         message: String,
     },
-    ParseError(String, Token),
-    InvalidArgumentTarget(String),
-    GeneralError(String),
+    ParseError(Message, Token),
+    /// Source that ends partway through a construct, such as an unclosed
+    /// brace, parenthesis or string. The REPL reads another line when it
+    /// gets one instead of reporting it.
+    UnexpectedEof(Message, Token),
+    /// A mistake found by the resolver after parsing, such as `return` at the
+    /// top level or a variable declared twice in one scope.
+    ResolveError(Message, Token),
+    /// A name declared twice where only one declaration is allowed, along
+    /// with the first declaration so the report can point at both.
+    DuplicateDeclaration {
+        message: Message,
+        name: Token,
+        first: Box<Token>,
+    },
+    /// An assignment to something that can't be assigned to, such as
+    /// `1 = 2`, at its `=`.
+    InvalidArgumentTarget(Message, Token),
+    InterpretError(Message, Token),
+    /// An [`InterpretError`](RuntimeError::InterpretError) that happened
+    /// inside a function, along with the calls that led to it, innermost
+    /// first. It is shown with a traceback of those calls.
+    Traceback {
+        message: Message,
+        token: Token,
+        stack: Vec<StackFrame>,
+    },
+    GeneralError(Message),
+    /// All of the scan, parse and resolve errors found in a program, which
+    /// is not run when there are any.
+    Diagnostics(Diagnostics),
+    /// The diagnostics of several scripts checked together, one set for
+    /// each script with errors, in order of their paths.
+    Files(Vec<Diagnostics>),
+    /// A runtime error along with the source of the script it happened in,
+    /// so it can be shown in context like [`Diagnostics`] are.
+    InSource {
+        error: Box<RuntimeError>,
+        source: String,
+    },
+    /// A value raised by `throw`, which `catch` gets back as it was thrown.
+    /// `error` is how it's reported if nothing catches it, with the calls
+    /// it ended.
+    Thrown {
+        value: Value,
+        error: Box<RuntimeError>,
+    },
+    /// A call to `exit`, which stops the program with this status. It isn't
+    /// a failure, so the `rlox` binary exits without reporting it.
+    Exit(i32),
+    /// A panic inside rlox itself, caught before it could abort the process.
+    InternalError {
+        message: String,
+        location: Option<String>,
+        line: Option<usize>,
+    },
 }
 
 impl RuntimeError {
-    pub(crate) fn scan_error(message: String, line: usize, column: usize, offset: usize) -> Self {
+    pub(crate) fn scan_error(message: String, line: usize, column: usize, span: Span) -> Self {
         Self::ScanError {
             line,
             column,
-            offset,
+            span,
             message,
         }
     }
 
-    pub(crate) fn general_error(message: &str) -> Self {
-        Self::GeneralError(message.into())
+    /// Records that this error ended a call to `function` made on `line`,
+    /// adding it to the error's stack. Only runtime errors have a stack, so
+    /// any other error is returned unchanged.
+    pub(crate) fn called_from(self, function: String, line: usize) -> Self {
+        let frame = StackFrame { function, line };
+        match self {
+            RuntimeError::InterpretError(message, token) => RuntimeError::Traceback {
+                message,
+                token,
+                stack: vec![frame],
+            },
+            RuntimeError::Traceback {
+                message,
+                token,
+                mut stack,
+            } => {
+                stack.push(frame);
+                RuntimeError::Traceback {
+                    message,
+                    token,
+                    stack,
+                }
+            }
+            RuntimeError::InSource { error, source } => RuntimeError::InSource {
+                error: Box::new(error.called_from(frame.function, frame.line)),
+                source,
+            },
+            RuntimeError::Thrown { value, error } => RuntimeError::Thrown {
+                value,
+                error: Box::new(error.called_from(frame.function, frame.line)),
+            },
+            err => err,
+        }
+    }
+
+    /// Attaches the `source` of the script a runtime error happened in, so
+    /// it's shown with the line it happened on. Any other error, or one
+    /// that already has its source, is returned unchanged.
+    pub(crate) fn in_source(self, source: &str) -> Self {
+        match self {
+            RuntimeError::InterpretError(..) | RuntimeError::Traceback { .. } => {
+                RuntimeError::InSource {
+                    error: Box::new(self),
+                    source: source.into(),
+                }
+            }
+            RuntimeError::Thrown { value, error } => RuntimeError::Thrown {
+                value,
+                error: Box::new(error.in_source(source)),
+            },
+            err => err,
+        }
+    }
+
+    /// Whether `catch` can catch this error: one raised by the program as it
+    /// runs, rather than one that stops it outright, such as `exit` or a bug
+    /// in rlox.
+    pub(crate) fn is_catchable(&self) -> bool {
+        match self {
+            RuntimeError::InterpretError(..)
+            | RuntimeError::Traceback { .. }
+            | RuntimeError::GeneralError(_)
+            | RuntimeError::Thrown { .. } => true,
+            RuntimeError::InSource { error, .. } => error.is_catchable(),
+            _ => false,
+        }
+    }
+}
+
+/// How many times in a row the same frame is shown before the rest of its
+/// repeats are summarized, as in deep recursion.
+const REPEATED_FRAMES_SHOWN: usize = 3;
+
+/// Writes the calls in `stack` oldest first, like Python. Each line is where
+/// a function was when the error happened: where it made the next call, or
+/// `error_line` for the innermost one.
+fn write_traceback(
+    f: &mut std::fmt::Formatter,
+    stack: &[StackFrame],
+    error_line: usize,
+) -> std::fmt::Result {
+    let functions =
+        std::iter::once("script").chain(stack.iter().rev().map(|frame| frame.function.as_str()));
+    let lines = stack
+        .iter()
+        .rev()
+        .map(|frame| frame.line)
+        .chain(std::iter::once(error_line));
+
+    let mut previous = None;
+    let mut repeats = 0;
+    let mut hidden = 0;
+
+    f.write_str("\nTraceback (most recent call last):")?;
+    for frame in functions.zip(lines) {
+        if previous == Some(frame) {
+            repeats += 1;
+            if repeats >= REPEATED_FRAMES_SHOWN {
+                hidden += 1;
+                continue;
+            }
+        } else {
+            write_hidden(f, hidden)?;
+            previous = Some(frame);
+            repeats = 0;
+            hidden = 0;
+        }
+
+        let (function, line) = frame;
+        write!(f, "\n  line {line}, in {function}")?;
+    }
+
+    write_hidden(f, hidden)
+}
+
+fn write_hidden(f: &mut std::fmt::Formatter, hidden: usize) -> std::fmt::Result {
+    match hidden {
+        0 => Ok(()),
+        1 => f.write_str("\n  [Previous line repeated 1 more time]"),
+        hidden => write!(f, "\n  [Previous line repeated {hidden} more times]"),
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RuntimeError::ScanError {
+                line,
+                column,
+                span: _,
+                message,
+            } => write!(f, "line {line}:{column} | Error: {message}"),
+            RuntimeError::ParseError(message, token)
+            | RuntimeError::UnexpectedEof(message, token)
+            | RuntimeError::ResolveError(message, token)
+            | RuntimeError::InvalidArgumentTarget(message, token)
+            | RuntimeError::InterpretError(message, token) => {
+                write!(
+                    f,
+                    "line {} | {}: {message}",
+                    token.line,
+                    heading(message.code())
+                )
+            }
+            RuntimeError::Traceback {
+                message,
+                token,
+                stack,
+            } => {
+                write!(
+                    f,
+                    "line {} | {}: {message}",
+                    token.line,
+                    heading(message.code())
+                )?;
+                write_traceback(f, stack, token.line)
+            }
+            RuntimeError::DuplicateDeclaration { message, name, .. } => {
+                write!(
+                    f,
+                    "line {} | {}: {message}",
+                    name.line,
+                    heading(message.code())
+                )
+            }
+            RuntimeError::GeneralError(message) => f.write_str(message),
+            RuntimeError::Diagnostics(diagnostics) => diagnostics.fmt(f),
+            RuntimeError::Files(files) => {
+                for (index, diagnostics) in files.iter().enumerate() {
+                    if index > 0 {
+                        writeln!(f, "\n")?;
+                    }
+                    diagnostics.fmt(f)?;
+                }
+                Ok(())
+            }
+            RuntimeError::InSource { error, source } => {
+                let (RuntimeError::InterpretError(message, token)
+                | RuntimeError::Traceback { message, token, .. }) = error.as_ref()
+                else {
+                    return error.fmt(f);
+                };
+
+                let heading = heading(message.code());
+                match token.column {
+                    0 => write!(f, "line {} | {heading}: {message}", token.line)?,
+                    column => write!(f, "line {}:{column} | {heading}: {message}", token.line)?,
+                }
+                // Only show the line when the token was scanned from this
+                // source. The vm doesn't keep its tokens, and a function from
+                // an imported script can fail after the import returns.
+                let spanned = source.get(token.span.start..token.span.end);
+                if !token.lexeme.is_empty() && spanned == Some(&*token.lexeme) {
+                    write_context(f, source, token.line, token.column, token.span)?;
+                }
+                match error.as_ref() {
+                    RuntimeError::Traceback { stack, .. } => write_traceback(f, stack, token.line),
+                    _ => Ok(()),
+                }
+            }
+            RuntimeError::Thrown { error, .. } => error.fmt(f),
+            RuntimeError::Exit(code) => write!(f, "Exited with status {code}"),
+            RuntimeError::InternalError {
+                message,
+                location,
+                line,
+            } => {
+                match line {
+                    Some(line) => write!(f, "line {line} | Internal error: {message}")?,
+                    None => write!(f, "Internal error: {message}")?,
+                }
+                if let Some(location) = location {
+                    write!(f, " (at {location})")?;
+                }
+                write!(f, "\nThis is a bug in rlox, please report it.")
+            }
+        }
     }
 }
 
 impl From<std::io::Error> for RuntimeError {
     fn from(value: std::io::Error) -> Self {
-        RuntimeError::GeneralError(value.to_string())
+        RuntimeError::GeneralError(value.to_string().into())
     }
 }
 
-impl From<Error> for RuntimeError {
-    fn from(value: Error) -> Self {
+impl From<Diagnostic> for RuntimeError {
+    fn from(value: Diagnostic) -> Self {
         RuntimeError::ScanError {
             line: value.line,
             column: value.column,
-            offset: value.offset,
+            span: value.span,
             message: value.message,
         }
     }
 }
+
+impl From<RuntimeError> for Diagnostic {
+    fn from(value: RuntimeError) -> Self {
+        match value {
+            RuntimeError::ScanError {
+                line,
+                column,
+                span,
+                message,
+            } => Diagnostic {
+                line,
+                column,
+                span,
+                message,
+                code: None,
+                note: None,
+            },
+            RuntimeError::ParseError(message, token)
+            | RuntimeError::UnexpectedEof(message, token)
+            | RuntimeError::ResolveError(message, token)
+            | RuntimeError::InvalidArgumentTarget(message, token)
+            | RuntimeError::InterpretError(message, token)
+            | RuntimeError::Traceback { message, token, .. } => Diagnostic {
+                line: token.line,
+                column: token.column,
+                span: token.span,
+                code: message.code(),
+                message: message.into(),
+                note: None,
+            },
+            RuntimeError::DuplicateDeclaration {
+                message,
+                name,
+                first,
+            } => Diagnostic {
+                line: name.line,
+                column: name.column,
+                span: name.span,
+                code: message.code(),
+                message: message.into(),
+                note: Some(Note {
+                    line: first.line,
+                    column: first.column,
+                    span: first.span,
+                    message: format!("`{}` was first declared here", first.lexeme),
+                }),
+            },
+            RuntimeError::GeneralError(message) => Diagnostic {
+                line: 0,
+                column: 0,
+                span: Span::default(),
+                code: message.code(),
+                message: message.into(),
+                note: None,
+            },
+            RuntimeError::Diagnostics(diagnostics) => {
+                diagnostics.into_iter().next().unwrap_or_default()
+            }
+            RuntimeError::Files(files) => files.into_iter().flatten().next().unwrap_or_default(),
+            RuntimeError::InSource { error, .. } | RuntimeError::Thrown { error, .. } => {
+                Diagnostic::from(*error)
+            }
+            RuntimeError::Exit(code) => Diagnostic {
+                line: 0,
+                column: 0,
+                span: Span::default(),
+                message: format!("Exited with status {code}"),
+                code: None,
+                note: None,
+            },
+            RuntimeError::InternalError { message, line, .. } => Diagnostic {
+                line: line.unwrap_or(0),
+                column: 0,
+                span: Span::default(),
+                message,
+                code: None,
+                note: None,
+            },
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! Longer explanations of the errors rlox reports, looked up by code with
+//! `rlox --explain <code>` or, for the last error, with the REPL's `:why`.
+//! The errors explained here are raised with their [`ErrorCode`], which
+//! their reports show.
+
+use crate::{
+    error::{ErrorCode, RuntimeError},
+    scanner::Scanner,
+    symbol::Symbol,
+    token::TokenValue,
+    value::Value,
+};
+
+/// One entry of the catalog.
+pub(crate) struct Explanation {
+    pub(crate) code: ErrorCode,
+    pub(crate) text: &'static str,
+}
+
+const CATALOG: &[Explanation] = &[
+    Explanation {
+        code: ErrorCode::UndefinedVariable,
+        text: "\
+A variable was used that isn't declared in any scope around the use.
+
+Variables have to be declared with `var` before they're read or assigned,
+and a local declared in a block is gone once the block ends. Check the
+spelling, including case, and that the declaration runs first.",
+    },
+    Explanation {
+        code: ErrorCode::OperandTypes,
+        text: "\
+An operator was applied to values of a type it doesn't work on.
+
+Arithmetic and comparison need numbers, and `+` takes either two numbers or
+two strings. Lox doesn't convert between types on its own, so `\"1\" + 2` is
+an error unless --string-conversion is on. A class can give its instances
+an operator by defining the method for it, such as `__add__` for `+`.",
+    },
+    Explanation {
+        code: ErrorCode::NotCallable,
+        text: "\
+Something that isn't a function or a class was called with `()`.
+
+Only functions, methods and classes can be called. This usually means a
+variable holds a different value than expected, or a field has the same
+name as the method meant to be called.",
+    },
+    Explanation {
+        code: ErrorCode::ArgumentCount,
+        text: "\
+A function was called with a different number of arguments than it
+declares parameters.
+
+Lox has no default parameter values, so every parameter needs an argument.
+For a class, the arguments go to its `init` method.",
+    },
+    Explanation {
+        code: ErrorCode::UndefinedProperty,
+        text: "\
+A property was read that the object doesn't have, or from a value that
+can't have properties.
+
+Only instances of classes, and classes themselves, have properties. An
+instance's fields exist once they've been assigned, usually in `init`, and
+its methods come from its class and superclasses.",
+    },
+    Explanation {
+        code: ErrorCode::AlreadyDeclared,
+        text: "\
+A name was declared twice in the same scope.
+
+Within one block, function or class, each name can only be declared once.
+Give the second one a different name, or assign to the first instead of
+declaring it again.",
+    },
+    Explanation {
+        code: ErrorCode::OwnInitializer,
+        text: "\
+A local variable was read in the expression that initializes it.
+
+The new variable shadows any outer one of the same name from the moment
+it's declared, so its initializer can't refer to the outer variable. Rename
+one of them.",
+    },
+    Explanation {
+        code: ErrorCode::InvalidReturn,
+        text: "\
+`return` was used where it isn't allowed.
+
+A `return` has to be inside a function, and an `init` method can only use
+a bare `return;`, since it always returns the new instance.",
+    },
+    Explanation {
+        code: ErrorCode::OutsideConstruct,
+        text: "\
+A keyword was used outside the construct it belongs to.
+
+`break` and `continue` only work inside a loop, `this` and `super` only
+inside a method, and `super` only in a class declared with a superclass.",
+    },
+    Explanation {
+        code: ErrorCode::StackOverflow,
+        text: "\
+Calls nested deeper than the call depth allows.
+
+This is nearly always a recursive function that never reaches its base
+case. Check that each recursive call moves towards the case that returns
+without recursing.",
+    },
+    Explanation {
+        code: ErrorCode::IntegerArithmetic,
+        text: "\
+Integer arithmetic failed.
+
+With --integers on, integers are 64-bit and checked: dividing by zero or
+going past the largest or smallest integer is an error rather than a wrong
+answer. Mixing in a number with a decimal point does the sum in floating
+point instead.",
+    },
+    Explanation {
+        code: ErrorCode::MissingSemicolon,
+        text: "\
+A statement wasn't ended with `;`.
+
+Every statement that isn't a block ends with a semicolon. The error is
+reported where the semicolon was expected, which is often the start of the
+next line.",
+    },
+    Explanation {
+        code: ErrorCode::KeywordArguments,
+        text: "\
+Keyword arguments didn't match the parameters of the function called.
+
+A keyword argument has to name one of the function's parameters, no
+parameter can get two arguments, and every parameter needs one, whether
+it's passed by position or by name. Natives only take positional arguments.",
+    },
+    Explanation {
+        code: ErrorCode::StepLimit,
+        text: "\
+The program ran more steps than the host allows.
+
+A host embedding rlox can cap the steps a program takes, to stop one that
+loops forever. A `catch` can handle the error, with its `kind` being
+`stepLimit`, but only has a few more steps to do so before the program is
+stopped for good.",
+    },
+    Explanation {
+        code: ErrorCode::Timeout,
+        text: "\
+The program ran for longer than the host allows.
+
+A host embedding rlox can cap how long a program runs. A `catch` can
+handle the error, with its `kind` being `timeout`, but only has a few more
+steps to do so before the program is stopped for good.",
+    },
+];
+
+/// The explanation for `code`, ignoring case.
+pub(crate) fn find(code: &str) -> Option<&'static Explanation> {
+    CATALOG
+        .iter()
+        .find(|explanation| explanation.code.as_str().eq_ignore_ascii_case(code))
+}
+
+/// The explanation of the errors raised with `code`.
+fn for_code(code: ErrorCode) -> Option<&'static Explanation> {
+    CATALOG.iter().find(|explanation| explanation.code == code)
+}
+
+/// The message, code and line of `err`, or of the first of several
+/// diagnostics.
+pub(crate) fn message(err: &RuntimeError) -> Option<(String, Option<ErrorCode>, usize)> {
+    match err {
+        RuntimeError::ScanError { line, message, .. } => Some((message.clone(), None, *line)),
+        RuntimeError::ParseError(message, token)
+        | RuntimeError::UnexpectedEof(message, token)
+        | RuntimeError::ResolveError(message, token)
+        | RuntimeError::InvalidArgumentTarget(message, token)
+        | RuntimeError::InterpretError(message, token)
+        | RuntimeError::Traceback { message, token, .. }
+        | RuntimeError::DuplicateDeclaration {
+            message,
+            name: token,
+            ..
+        } => Some((message.to_string(), message.code(), token.line)),
+        RuntimeError::GeneralError(message) => Some((message.to_string(), message.code(), 0)),
+        RuntimeError::Diagnostics(diagnostics) => diagnostics.iter().next().map(|diagnostic| {
+            let message = diagnostic.message.clone();
+            (message, diagnostic.code, diagnostic.line)
+        }),
+        RuntimeError::Files(files) => files.iter().flatten().next().map(|diagnostic| {
+            let message = diagnostic.message.clone();
+            (message, diagnostic.code, diagnostic.line)
+        }),
+        RuntimeError::InSource { error, .. } | RuntimeError::Thrown { error, .. } => message(error),
+        RuntimeError::Exit(_) | RuntimeError::InternalError { .. } => None,
+    }
+}
+
+/// The last error the REPL reported, kept for `:why`.
+pub(crate) struct LastError {
+    message: String,
+    code: Option<ErrorCode>,
+    /// The line of input the error was on, if it's known.
+    line: Option<String>,
+}
+
+impl LastError {
+    /// Keeps `err`, which running `input` ended with.
+    pub(crate) fn new(err: &RuntimeError, input: &str) -> Option<Self> {
+        let (message, code, line) = message(err)?;
+        let line = line
+            .checked_sub(1)
+            .and_then(|index| input.lines().nth(index))
+            .map(String::from);
+        Some(Self {
+            message,
+            code,
+            line,
+        })
+    }
+
+    /// Explains the error: its code and long text, if the catalog has it,
+    /// then the type and value of each variable on its line that `lookup`
+    /// finds.
+    pub(crate) fn explain(&self, lookup: impl Fn(Symbol) -> Option<Value>) -> String {
+        let mut why = match self.code.and_then(for_code) {
+            Some(explanation) => format!(
+                "error[{}]: {}\n\n{}\n",
+                explanation.code, self.message, explanation.text
+            ),
+            None => format!(
+                "error: {}\n\nThere's no longer explanation of this error.\n",
+                self.message
+            ),
+        };
+
+        let mut names: Vec<Symbol> = vec![];
+        let tokens = Scanner::new(self.line.as_deref().unwrap_or_default());
+        for token in tokens.flatten() {
+            if let TokenValue::Identifier(name) = token.value {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+
+        let values: Vec<String> = names
+            .into_iter()
+            .filter_map(|name| {
+                let value = lookup(name)?;
+                Some(format!("  {name}: {} = {value:#}", value.type_name()))
+            })
+            .collect();
+        if !values.is_empty() {
+            why.push_str(&format!("\nValues involved:\n{}\n", values.join("\n")));
+        }
+
+        why
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_explained_by_the_code_they_are_raised_with() {
+        let mut lox = crate::Lox::new();
+        let code = |err: RuntimeError| message(&err).and_then(|(_, code, _)| code);
+
+        let err = lox.eval("undefined;").err().unwrap();
+        assert_eq!(code(err), Some(ErrorCode::UndefinedVariable));
+        let err = lox.eval("fun f(a) {} f();").err().unwrap();
+        assert_eq!(code(err), Some(ErrorCode::ArgumentCount));
+        let err = lox.eval("print 1").err().unwrap();
+        assert_eq!(code(err), Some(ErrorCode::MissingSemicolon));
+        let err = lox.eval("clock(\"\");").err().unwrap();
+        assert_eq!(code(err), Some(ErrorCode::ArgumentCount));
+        let err = lox.eval("print \"a\" - 1;").err().unwrap();
+        assert_eq!(code(err), Some(ErrorCode::OperandTypes));
+
+        // A message that only reads like a catalogued one isn't given its
+        // code.
+        let err = lox.eval("throw \"Undefined variable `a`\";").err().unwrap();
+        assert_eq!(code(err), None);
+
+        assert_eq!(
+            find("e0002").map(|explanation| explanation.code),
+            Some(ErrorCode::OperandTypes)
+        );
+    }
+}
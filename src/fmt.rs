@@ -0,0 +1,775 @@
+//! `rlox fmt`: reprints a program with canonical indentation, spacing and
+//! brace placement, keeping its comments.
+//!
+//! The output is built from the syntax tree, so anything the parser
+//! desugars is recovered from the source: literals keep their original
+//! spelling, and `for` loops are told apart from `while` loops by their
+//! keyword.
+
+use crate::{
+    error::{Diagnostics, Result, RuntimeError},
+    parser::{Expr, FunctionDecl, Parser, Pattern, Stmt},
+    scanner::Scanner,
+    span::Span,
+    token::{Token, TokenValue},
+};
+
+const INDENT: &str = "    ";
+
+/// How many unchanged lines [`diff`] shows around each change.
+const DIFF_CONTEXT: usize = 3;
+
+/// The line terminator `rlox fmt` writes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`, whatever the source used.
+    #[default]
+    Lf,
+    /// `\r\n`, whatever the source used.
+    Crlf,
+    /// Whichever of the two ends the source's first line.
+    Keep,
+}
+
+impl LineEnding {
+    fn terminator(self, source: &str) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Keep => match source.find(['\r', '\n']) {
+                Some(end) if source[end..].starts_with("\r\n") => "\r\n",
+                _ => "\n",
+            },
+        }
+    }
+}
+
+/// Formats `source`, or returns its scan and parse errors if it doesn't
+/// parse. Lines end in `\n`. Formatting is idempotent: formatted source
+/// comes back unchanged.
+pub fn format_source(source: &str) -> Result<String> {
+    format_source_with(source, LineEnding::Lf)
+}
+
+/// Formats `source` like [`format_source`], ending lines as `line_ending`
+/// says. Every `\r\n` and lone `\r` in the source, including those inside
+/// comments and strings, counts as a line break and is rewritten.
+pub fn format_source_with(source: &str, line_ending: LineEnding) -> Result<String> {
+    let terminator = line_ending.terminator(source);
+    let source = &source.replace("\r\n", "\n").replace('\r', "\n");
+    let formatted = format_normalized(source)?;
+
+    Ok(match terminator {
+        "\n" => formatted,
+        terminator => formatted.replace('\n', terminator),
+    })
+}
+
+/// Formats `source`, whose lines all end in `\n`.
+fn format_normalized(source: &str) -> Result<String> {
+    let scanned = Scanner::new(source).with_comments(true).scan_tokens();
+    let (comments, tokens): (Vec<Token>, Vec<Token>) = scanned
+        .tokens
+        .into_iter()
+        .partition(|token| token.value == TokenValue::Comment);
+    let parsed = Parser::new(tokens).without_folding().parse();
+
+    let mut errors = scanned.errors;
+    errors.extend(parsed.errors);
+    if !errors.is_empty() {
+        return Err(RuntimeError::Diagnostics(Diagnostics::new(source, errors)));
+    }
+
+    let mut formatter = Formatter {
+        source,
+        comments,
+        next_comment: 0,
+        output: String::new(),
+        depth: 0,
+        last_end: 0,
+        at_block_start: true,
+    };
+    formatter.statements(&parsed.program, source.len());
+
+    Ok(formatter.output)
+}
+
+/// A unified diff from `old` to `new`, both labelled `path`, or an empty
+/// string if they are the same. Lines are compared without their `\r\n` or
+/// `\n`, so if only those differ a note says so instead.
+pub fn diff(path: &str, old_source: &str, new_source: &str) -> String {
+    let old: Vec<&str> = old_source.lines().collect();
+    let new: Vec<&str> = new_source.lines().collect();
+    let changes = diff_lines(&old, &new);
+
+    // Group the changes into hunks, merging those whose context overlaps.
+    let mut hunks: Vec<std::ops::Range<usize>> = vec![];
+    for (index, _) in changes.iter().enumerate().filter(|(_, c)| c.0 != ' ') {
+        let start = index.saturating_sub(DIFF_CONTEXT);
+        let end = (index + DIFF_CONTEXT + 1).min(changes.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.end => last.end = end,
+            _ => hunks.push(start..end),
+        }
+    }
+
+    if hunks.is_empty() {
+        return match old_source == new_source {
+            true => String::new(),
+            false => format!("{path}: only the line endings differ\n"),
+        };
+    }
+
+    let mut output = format!("--- {path}\n+++ {path} (formatted)\n");
+    for hunk in hunks {
+        let before = &changes[..hunk.start];
+        let old_start = before.iter().filter(|c| c.0 != '+').count() + 1;
+        let new_start = before.iter().filter(|c| c.0 != '-').count() + 1;
+        let lines = &changes[hunk];
+        let old_len = lines.iter().filter(|c| c.0 != '+').count();
+        let new_len = lines.iter().filter(|c| c.0 != '-').count();
+
+        output.push_str(&format!(
+            "@@ -{old_start},{old_len} +{new_start},{new_len} @@\n"
+        ));
+        for (kind, line) in lines {
+            output.push_str(&format!("{kind}{line}\n"));
+        }
+    }
+
+    output
+}
+
+/// The edits that turn `old` into `new`, each a line prefixed with `' '` if
+/// it is kept, `'-'` if removed or `'+'` if added. Found with a longest
+/// common subsequence, which is quadratic but fine for source files.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(char, &'a str)> {
+    // lengths[i][j] is the LCS length of old[i..] and new[j..].
+    let mut lengths = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = match old[i] == new[j] {
+                true => lengths[i + 1][j + 1] + 1,
+                false => lengths[i + 1][j].max(lengths[i][j + 1]),
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut changes = vec![];
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            changes.push((' ', old[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if i < old.len() && (j == new.len() || lengths[i + 1][j] >= lengths[i][j + 1]) {
+            changes.push(('-', old[i]));
+            i += 1;
+        } else {
+            changes.push(('+', new[j]));
+            j += 1;
+        }
+    }
+
+    changes
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    /// Every comment in the source, in order. Those before `next_comment`
+    /// have been written.
+    comments: Vec<Token>,
+    next_comment: usize,
+    output: String,
+    /// How many blocks deep the line being written is.
+    depth: usize,
+    /// Where the last statement or comment written ended in the source, to
+    /// find the blank lines that followed it.
+    last_end: usize,
+    /// Whether nothing has been written in the current block yet, where a
+    /// blank line is never kept.
+    at_block_start: bool,
+}
+
+impl Formatter<'_> {
+    /// Writes `statements` one per line, followed by any comments left
+    /// before `end`, the end of the enclosing block.
+    fn statements(&mut self, statements: &[Stmt], end: usize) {
+        for stmt in statements {
+            self.line(stmt.span(), end, |formatter| formatter.statement(stmt));
+        }
+        self.comments_before(end);
+    }
+
+    /// Writes a line for the item at `span`, preceded by the comments before
+    /// it and followed by those on the same line. Comments inside the item
+    /// that weren't written with a nested statement also go at its end.
+    /// `limit` is the end of the enclosing block, whose comments are left
+    /// for it.
+    fn line(&mut self, span: Span, limit: usize, write: impl FnOnce(&mut Self)) {
+        self.comments_before(span.start);
+        self.blank_line_before(span.start);
+        self.indent();
+        write(self);
+        self.last_end = span.end;
+        self.trailing_comments(span.end, limit);
+        self.output.push('\n');
+    }
+
+    fn indent(&mut self) {
+        self.output.push_str(&INDENT.repeat(self.depth));
+    }
+
+    /// Keeps one blank line where the source had at least one before `start`.
+    fn blank_line_before(&mut self, start: usize) {
+        let gap = &self.source[self.last_end.min(start)..start];
+        if !self.at_block_start && gap.matches('\n').count() > 1 {
+            self.output.push('\n');
+        }
+        self.at_block_start = false;
+    }
+
+    fn next_comment(&self) -> Option<&Token> {
+        self.comments.get(self.next_comment)
+    }
+
+    /// Writes each comment that starts before `position` on its own line.
+    fn comments_before(&mut self, position: usize) {
+        while let Some(comment) = self.next_comment().cloned() {
+            if comment.span.start >= position {
+                break;
+            }
+
+            self.blank_line_before(comment.span.start);
+            self.indent();
+            self.output.push_str(comment.lexeme.trim_end());
+            self.output.push('\n');
+            self.last_end = comment.span.end;
+            self.next_comment += 1;
+        }
+    }
+
+    /// Writes the comments inside an item ending at `end`, and one after it
+    /// on the same line, at the end of the item's line. Only the first can
+    /// share the line; any more go on lines of their own.
+    fn trailing_comments(&mut self, end: usize, limit: usize) {
+        let mut first = true;
+
+        while let Some(comment) = self.next_comment().cloned() {
+            let inside = comment.span.start < end;
+            let same_line = comment.span.start < limit
+                && !self.source[end.min(comment.span.start)..comment.span.start].contains('\n');
+            if !(inside || first && same_line) {
+                break;
+            }
+
+            match first {
+                true => self.output.push(' '),
+                false => {
+                    self.output.push('\n');
+                    self.indent();
+                }
+            }
+            self.output.push_str(comment.lexeme.trim_end());
+            self.last_end = self.last_end.max(comment.span.end);
+            self.next_comment += 1;
+            first = false;
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) => {
+                let expr = self.expr(expr);
+                self.output.push_str(&format!("{expr};"));
+            }
+            Stmt::Function(function) => {
+                self.output.push_str("fun ");
+                self.function(function);
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                span,
+            } => {
+                self.output.push_str(&format!("class {}", name.lexeme));
+                if let Some(superclass) = superclass {
+                    let superclass = self.expr(superclass);
+                    self.output.push_str(&format!(" < {superclass}"));
+                }
+
+                let end = span.end - 1;
+                self.open_block(methods.is_empty(), end, |formatter| {
+                    for method in methods {
+                        formatter.line(method.span, end, |formatter| formatter.function(method));
+                    }
+                });
+            }
+            Stmt::Import { path, .. } => {
+                let path = &self.source[path.span.start..path.span.end];
+                self.output.push_str(&format!("import {path};"));
+            }
+            Stmt::Print { values, .. } => {
+                let values = self.exprs(values);
+                self.output.push_str(&format!("print {values};"));
+            }
+            Stmt::Var {
+                name, initializer, ..
+            } => match initializer {
+                Some(initializer) => {
+                    let initializer = self.expr(initializer);
+                    self.output
+                        .push_str(&format!("var {} = {initializer};", name.lexeme));
+                }
+                None => self.output.push_str(&format!("var {};", name.lexeme)),
+            },
+            Stmt::Unpack {
+                names, initializer, ..
+            } => {
+                let names: Vec<_> = names.iter().map(|name| name.lexeme.as_ref()).collect();
+                let initializer = self.expr(initializer);
+                self.output
+                    .push_str(&format!("var ({}) = {initializer};", names.join(", ")));
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let condition = self.expr(condition);
+                self.output.push_str(&format!("if ({condition})"));
+                self.body(then_branch);
+
+                if let Some(else_branch) = else_branch {
+                    match then_branch.as_ref() {
+                        Stmt::Block { .. } => self.output.push_str(" else"),
+                        _ => {
+                            self.output.push('\n');
+                            self.indent();
+                            self.output.push_str("else");
+                        }
+                    }
+                    match else_branch.as_ref() {
+                        Stmt::If { .. } => {
+                            self.output.push(' ');
+                            self.statement(else_branch);
+                        }
+                        _ => self.body(else_branch),
+                    }
+                }
+            }
+            // A `for` with an initializer is desugared into a block holding
+            // it and the loop, both spanning the whole statement.
+            Stmt::Block { statements, span } => match statements.as_slice() {
+                [initializer, while_loop @ Stmt::While { .. }] if while_loop.span() == *span => {
+                    self.for_loop(Some(initializer), while_loop)
+                }
+                _ => self.block(statements, *span),
+            },
+            Stmt::While {
+                condition, span, ..
+            } => match self.source[span.start..].starts_with("for") {
+                true => self.for_loop(None, stmt),
+                false => {
+                    let condition = self.expr(condition);
+                    self.output.push_str(&format!("while ({condition})"));
+                    let Stmt::While { body, .. } = stmt else {
+                        unreachable!()
+                    };
+                    self.body(body);
+                }
+            },
+            Stmt::Return { value, .. } => match value {
+                Some(Expr::Tuple { elements, .. }) => {
+                    let values = self.exprs(elements);
+                    self.output.push_str(&format!("return {values};"));
+                }
+                Some(value) => {
+                    let value = self.expr(value);
+                    self.output.push_str(&format!("return {value};"));
+                }
+                None => self.output.push_str("return;"),
+            },
+            Stmt::Break { .. } => self.output.push_str("break;"),
+            Stmt::Continue { .. } => self.output.push_str("continue;"),
+            Stmt::Try {
+                body,
+                body_span,
+                catch,
+                finally,
+                span,
+            } => {
+                self.output.push_str("try");
+                self.block(body, *body_span);
+                if let Some(catch) = catch {
+                    self.output
+                        .push_str(&format!(" catch ({})", catch.name.lexeme));
+                    self.block(&catch.body, catch.span);
+                }
+                if let Some(finally) = finally {
+                    self.output.push_str(" finally");
+                    self.block(finally, *span);
+                }
+            }
+            Stmt::Throw { value, .. } => {
+                let value = self.expr(value);
+                self.output.push_str(&format!("throw {value};"));
+            }
+            Stmt::Defer { statement, .. } => {
+                self.output.push_str("defer");
+                self.body(statement);
+            }
+            Stmt::ForIn {
+                names,
+                iterable,
+                body,
+                ..
+            } => {
+                let names: Vec<_> = names.iter().map(|name| name.lexeme.as_ref()).collect();
+                let iterable = self.expr(iterable);
+                self.output
+                    .push_str(&format!("for ({} in {iterable})", names.join(", ")));
+                self.body(body);
+            }
+            Stmt::Use {
+                name,
+                initializer,
+                body,
+                body_span,
+                ..
+            } => {
+                let initializer = self.expr(initializer);
+                self.output
+                    .push_str(&format!("use (var {} = {initializer})", name.lexeme));
+                self.block(body, *body_span);
+            }
+        }
+    }
+
+    /// Writes the body of an `if`, `else` or loop: a block on the same line,
+    /// or any other statement after a space.
+    fn body(&mut self, stmt: &Stmt) {
+        self.output.push(' ');
+        self.statement(stmt);
+    }
+
+    /// Writes a `for` loop from the `while` it was desugared into.
+    fn for_loop(&mut self, initializer: Option<&Stmt>, while_loop: &Stmt) {
+        let Stmt::While {
+            condition,
+            body,
+            increment,
+            ..
+        } = while_loop
+        else {
+            unreachable!("a for loop is desugared into a while loop");
+        };
+
+        self.output.push_str("for (");
+        match initializer {
+            Some(initializer) => self.statement(initializer),
+            None => self.output.push(';'),
+        }
+        // A missing condition is parsed as an empty `true`.
+        if !condition.span().is_empty() {
+            let condition = self.expr(condition);
+            self.output.push_str(&format!(" {condition}"));
+        }
+        self.output.push(';');
+        if let Some(increment) = increment {
+            let increment = self.expr(increment);
+            self.output.push_str(&format!(" {increment}"));
+        }
+        self.output.push(')');
+        self.body(body);
+    }
+
+    fn block(&mut self, statements: &[Stmt], span: Span) {
+        let end = span.end - 1;
+        self.open_block(statements.is_empty(), end, |formatter| {
+            formatter.statements(statements, end)
+        });
+    }
+
+    /// Writes ` {`, the block's lines from `write` and the closing `}` at
+    /// `end`, or just ` {}` if the block is `empty` and holds no comments.
+    fn open_block(&mut self, empty: bool, end: usize, write: impl FnOnce(&mut Self)) {
+        let has_comments = self
+            .next_comment()
+            .is_some_and(|comment| comment.span.start < end);
+
+        if self.output.ends_with(|c: char| !c.is_whitespace()) && !self.output.ends_with('(') {
+            self.output.push(' ');
+        }
+        if empty && !has_comments {
+            self.output.push_str("{}");
+            return;
+        }
+
+        self.output.push_str("{\n");
+        self.depth += 1;
+        self.at_block_start = true;
+        write(self);
+        self.depth -= 1;
+        self.indent();
+        self.output.push('}');
+    }
+
+    fn function(&mut self, function: &FunctionDecl) {
+        let params: Vec<&str> = function.params.iter().map(|param| &*param.lexeme).collect();
+        if function.is_class_method {
+            self.output.push_str("class ");
+        }
+        self.output.push_str(&function.name.lexeme);
+        if !function.is_getter {
+            self.output.push_str(&format!("({})", params.join(", ")));
+        }
+
+        let end = function.span.end - 1;
+        self.open_block(function.body.is_empty(), end, |formatter| {
+            formatter.statements(&function.body, end)
+        });
+    }
+
+    fn exprs(&self, exprs: &[Expr]) -> String {
+        let exprs: Vec<String> = exprs.iter().map(|expr| self.expr(expr)).collect();
+        exprs.join(", ")
+    }
+
+    fn expr(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::Literal { value, span } => match span.is_empty() {
+                true => value.to_string(),
+                false => self.source[span.start..span.end].to_string(),
+            },
+            Expr::This { .. } => "this".into(),
+            Expr::Variable { name, .. } => name.lexeme.to_string(),
+            Expr::Super { method, .. } => format!("super.{}", method.lexeme),
+            Expr::Grouping { group, .. } => format!("({})", self.expr(group)),
+            Expr::Get { object, name } => format!("{}.{}", self.expr(object), name.lexeme),
+            Expr::Call {
+                callee,
+                arguments,
+                keywords,
+                ..
+            } => {
+                let positional = arguments.len() - keywords.len();
+                let mut parts: Vec<String> = arguments[..positional]
+                    .iter()
+                    .map(|argument| self.expr(argument))
+                    .collect();
+                parts.extend(
+                    keywords
+                        .iter()
+                        .zip(&arguments[positional..])
+                        .map(|(name, argument)| {
+                            format!("{}: {}", name.lexeme, self.expr(argument))
+                        }),
+                );
+                format!("{}({})", self.expr(callee), parts.join(", "))
+            }
+            Expr::Unary { operator, right } => {
+                let right = self.expr(right);
+                // Keep `- -a` and `- --a` from running together into `--`.
+                match operator.value == TokenValue::Minus && right.starts_with('-') {
+                    true => format!("{} {right}", operator.lexeme),
+                    false => format!("{}{right}", operator.lexeme),
+                }
+            }
+            Expr::Update {
+                operator,
+                target,
+                prefix: true,
+            } => format!("{}{}", operator.lexeme, self.expr(target)),
+            Expr::Update {
+                operator, target, ..
+            } => format!("{}{}", self.expr(target), operator.lexeme),
+            Expr::Compound {
+                target,
+                operator,
+                value,
+            } => format!(
+                "{} {}= {}",
+                self.expr(target),
+                operator.lexeme,
+                self.expr(value)
+            ),
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            }
+            | Expr::Logical {
+                left,
+                operator,
+                right,
+            } => format!(
+                "{} {} {}",
+                self.expr(left),
+                operator.lexeme,
+                self.expr(right)
+            ),
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => format!(
+                "{} ? {} : {}",
+                self.expr(condition),
+                self.expr(then_branch),
+                self.expr(else_branch)
+            ),
+            Expr::Assign { name, value, .. } => self.assignment(name.lexeme.to_string(), value),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                let target = format!("{}.{}", self.expr(object), name.lexeme);
+                self.assignment(target, value)
+            }
+            Expr::List { elements, .. } => format!("[{}]", self.exprs(elements)),
+            Expr::Tuple { elements, .. } => format!("({})", self.exprs(elements)),
+            Expr::Match {
+                subject,
+                arms,
+                otherwise,
+                ..
+            } => {
+                let arms: Vec<String> = arms
+                    .iter()
+                    .map(|arm| {
+                        format!("{} => {}", self.pattern(&arm.pattern), self.expr(&arm.body))
+                    })
+                    .collect();
+                format!(
+                    "match ({}) {{ {}, else => {} }}",
+                    self.expr(subject),
+                    arms.join(", "),
+                    self.expr(otherwise)
+                )
+            }
+            Expr::Map { entries, .. } => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", self.expr(key), self.expr(value)))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            Expr::Index { object, index, .. } => {
+                format!("{}[{}]", self.expr(object), self.expr(index))
+            }
+            Expr::Slice {
+                object, start, end, ..
+            } => {
+                let bound = |bound: &Option<Box<Expr>>| match bound {
+                    Some(bound) => self.expr(bound),
+                    None => String::new(),
+                };
+                format!("{}[{}:{}]", self.expr(object), bound(start), bound(end))
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                let target = format!("{}[{}]", self.expr(object), self.expr(index));
+                self.assignment(target, value)
+            }
+        }
+    }
+
+    fn pattern(&self, pattern: &Pattern) -> String {
+        match pattern {
+            Pattern::Literal(literal) => self.expr(literal),
+            Pattern::Binding(name) => name.lexeme.to_string(),
+            Pattern::Tuple { elements, .. } => {
+                let elements: Vec<String> = elements
+                    .iter()
+                    .map(|element| self.pattern(element))
+                    .collect();
+                format!("({})", elements.join(", "))
+            }
+            Pattern::Instance { class, fields, .. } => {
+                let fields: Vec<&str> = fields.iter().map(|field| &*field.lexeme).collect();
+                format!("{}({})", self.expr(class), fields.join(", "))
+            }
+        }
+    }
+
+    fn assignment(&self, target: String, value: &Expr) -> String {
+        format!("{target} = {}", self.expr(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(source: &str) -> String {
+        match format_source(source) {
+            Ok(formatted) => formatted,
+            Err(err) => panic!("`{source}` failed: {err}"),
+        }
+    }
+
+    #[test]
+    fn code_is_reindented_and_respaced() {
+        assert_eq!(
+            format("fun f(a,b){if(a<b){return a;}else return b;}\nprint f( 1,2 );"),
+            "fun f(a, b) {\n    if (a < b) {\n        return a;\n    } else return b;\n}\nprint f(1, 2);\n"
+        );
+    }
+
+    #[test]
+    fn comments_and_single_blank_lines_are_kept() {
+        let source = "// header\nvar a = 1; // one\n\n\n\n/* block */\nprint a;\n";
+        assert_eq!(
+            format(source),
+            "// header\nvar a = 1; // one\n\n/* block */\nprint a;\n"
+        );
+    }
+
+    #[test]
+    fn desugared_syntax_is_written_as_it_was() {
+        let source = "for (var i = 0; i < 3; i++) {\n    x += i;\n}\nprint \"a\" + \"b\";\n";
+        assert_eq!(format(source), source);
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let source = "class A<B{init(x){this.x=x;}\nget{return this.x**2;}}\nvar m={\"a\":[1,2],};";
+        let once = format(source);
+        assert_eq!(format(&once), once);
+    }
+
+    #[test]
+    fn source_that_doesnt_parse_is_left_alone() {
+        assert!(matches!(
+            format_source("print (1;"),
+            Err(RuntimeError::Diagnostics(_))
+        ));
+    }
+
+    #[test]
+    fn line_endings_are_normalized() {
+        let source = "var a = 1;\r\n\r\n// note\rprint a;\r\n";
+        let lf = "var a = 1;\n\n// note\nprint a;\n";
+
+        assert_eq!(format_source(source).ok().as_deref(), Some(lf));
+        assert_eq!(
+            format_source_with(source, LineEnding::Crlf).ok().as_deref(),
+            Some(lf.replace('\n', "\r\n").as_str())
+        );
+        assert_eq!(
+            format_source_with(source, LineEnding::Keep).ok().as_deref(),
+            Some(lf.replace('\n', "\r\n").as_str())
+        );
+        assert_eq!(
+            format_source_with(lf, LineEnding::Keep).ok().as_deref(),
+            Some(lf)
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+/// How many objects may be tracked before the first automatic collection.
+const FIRST_COLLECTION: usize = 1 << 14;
+
+/// Called with the address of every object a traced object holds a strong
+/// reference to.
+pub(crate) type Visitor<'a> = dyn FnMut(*const ()) + 'a;
+
+/// A runtime object that can hold references to other runtime objects, and
+/// so can end up in a reference cycle that `Rc` alone would leak.
+pub(crate) trait Trace {
+    /// Reports each strong reference this object holds. Reporting one it
+    /// doesn't hold could free a live object; missing one only means a cycle
+    /// through it isn't collected.
+    fn trace(&self, visit: &mut Visitor);
+
+    /// Drops the references this object holds, breaking any cycle through
+    /// it. Only called once the object is known to be unreachable.
+    fn clear(&self) {}
+}
+
+/// Every object allocated through [`alloc`], so cycles among them can be
+/// found. The objects are owned by their `Rc`s as usual; the heap only
+/// keeps weak references.
+struct Heap {
+    objects: Vec<Weak<dyn Trace>>,
+    next_collection: usize,
+    /// Collect on every allocation, to shake out objects that are freed
+    /// while still in use.
+    stress: bool,
+    stats: GcStats,
+}
+
+/// Running totals of the collector's work on this thread, for `rlox bench`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct GcStats {
+    /// Objects allocated through [`alloc`].
+    pub(crate) allocated: usize,
+    /// Times [`collect`] has run.
+    pub(crate) collections: usize,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap {
+        objects: vec![],
+        next_collection: FIRST_COLLECTION,
+        stress: false,
+        stats: GcStats {
+            allocated: 0,
+            collections: 0,
+        },
+    });
+}
+
+/// Wraps `object` in an `Rc` and tracks it for cycle collection, collecting
+/// if enough objects have been allocated since the last collection.
+pub(crate) fn alloc<T: Trace + 'static>(object: T) -> Rc<T> {
+    let object = Rc::new(object);
+
+    let due = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.objects.push(Rc::downgrade(&object) as Weak<dyn Trace>);
+        heap.stats.allocated += 1;
+        heap.stress || heap.objects.len() >= heap.next_collection
+    });
+    if due {
+        collect();
+    }
+
+    object
+}
+
+pub(crate) fn set_stress(stress: bool) {
+    HEAP.with(|heap| heap.borrow_mut().stress = stress);
+}
+
+pub(crate) fn stats() -> GcStats {
+    HEAP.with(|heap| heap.borrow().stats)
+}
+
+/// How many objects allocated through [`alloc`] haven't been freed yet.
+pub(crate) fn live() -> usize {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        heap.objects
+            .iter()
+            .filter(|object| object.strong_count() > 0)
+            .count()
+    })
+}
+
+/// The address identifying a traced object, for use with a [`Visitor`].
+pub(crate) fn address<T: ?Sized>(object: &Rc<T>) -> *const () {
+    Rc::as_ptr(object) as *const ()
+}
+
+/// Frees every tracked object that is only reachable through reference
+/// cycles, returning how many there were.
+///
+/// There is no list of roots to mark from: references from the Rust side,
+/// such as the interpreter's current scope or a native's arguments, are
+/// invisible. Instead each object's reference count is compared with the
+/// references other tracked objects hold to it, and any object with
+/// references left over is held from outside and treated as a root.
+pub(crate) fn collect() -> usize {
+    let objects: Vec<Rc<dyn Trace>> = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.objects.retain(|object| object.strong_count() > 0);
+        heap.objects.iter().filter_map(Weak::upgrade).collect()
+    });
+
+    let indexes: HashMap<*const (), usize> = objects
+        .iter()
+        .enumerate()
+        .map(|(index, object)| (address(object), index))
+        .collect();
+
+    // Less one for the reference held by `objects` itself.
+    let mut external: Vec<usize> = objects
+        .iter()
+        .map(|object| Rc::strong_count(object) - 1)
+        .collect();
+    for object in &objects {
+        object.trace(&mut |target| {
+            if let Some(&index) = indexes.get(&target) {
+                external[index] -= 1;
+            }
+        });
+    }
+
+    let mut reachable: Vec<bool> = external.iter().map(|&count| count > 0).collect();
+    let mut pending: Vec<usize> = (0..objects.len()).filter(|&i| reachable[i]).collect();
+    while let Some(index) = pending.pop() {
+        objects[index].trace(&mut |target| {
+            if let Some(&index) = indexes.get(&target) {
+                if !reachable[index] {
+                    reachable[index] = true;
+                    pending.push(index);
+                }
+            }
+        });
+    }
+
+    let mut freed = 0;
+    for (object, reachable) in objects.iter().zip(&reachable) {
+        if !reachable {
+            object.clear();
+            freed += 1;
+        }
+    }
+    drop(objects);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.objects.retain(|object| object.strong_count() > 0);
+        heap.next_collection = (heap.objects.len() * 2).max(FIRST_COLLECTION);
+        heap.stats.collections += 1;
+    });
+
+    freed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{value::Value, Capture, Lox};
+
+    /// A list holding itself, with a weak reference to tell if it was freed.
+    fn self_referencing_list() -> (Value, Weak<RefCell<Vec<Value>>>) {
+        let list = Value::list(vec![]);
+        let Value::List(elements) = &list else {
+            unreachable!("`Value::list` makes a list");
+        };
+        elements.borrow_mut().push(list.clone());
+        let weak = Rc::downgrade(elements);
+        (list, weak)
+    }
+
+    #[test]
+    fn unreachable_cycles_are_freed() {
+        let (list, weak) = self_referencing_list();
+        drop(list);
+        assert!(weak.upgrade().is_some(), "the cycle keeps itself alive");
+
+        assert!(collect() >= 1);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn cycles_held_from_outside_are_kept() {
+        let (list, weak) = self_referencing_list();
+        collect();
+
+        assert!(weak.upgrade().is_some());
+        assert_eq!(list.to_string(), "[[...]]");
+    }
+
+    #[test]
+    fn cycles_through_a_tuple_held_from_outside_are_kept() {
+        let (list, weak) = self_referencing_list();
+        let tuple = Value::tuple(vec![list.clone()]);
+        let Value::List(elements) = &list else {
+            unreachable!("`Value::list` makes a list");
+        };
+        elements.borrow_mut()[0] = tuple.clone();
+        drop(list);
+        collect();
+
+        assert!(weak.upgrade().is_some());
+        assert_eq!(tuple.to_string(), "([(...)])");
+    }
+
+    #[test]
+    fn scripts_run_the_same_under_stress() {
+        let source = "
+            fun counter() {
+                var n = 0;
+                fun next() { n = n + 1; return n; }
+                return next;
+            }
+            var c = counter();
+            var nodes = [];
+            for (var i = 0; i < 20; i = i + 1) {
+                var node = {\"i\": i};
+                node[\"self\"] = node;
+                push(nodes, node);
+            }
+            c(); c();
+            print c();
+            print nodes[19][\"self\"][\"i\"];
+        ";
+        let capture = Capture::default();
+        let mut lox = Lox::new().with_output(capture.clone()).with_gc_stress(true);
+        let result = lox.run_source(source);
+        set_stress(false);
+
+        if let Err(err) = result {
+            panic!("{err}");
+        }
+        assert_eq!(capture.printed(), "3\n19\n");
+    }
+
+    #[test]
+    fn the_collect_native_reports_what_it_freed() {
+        let mut lox = Lox::new();
+        let source = "
+            for (var i = 0; i < 3; i = i + 1) {
+                var node = {};
+                node[\"self\"] = node;
+            }
+            collect();
+        ";
+
+        match lox.eval(source) {
+            Ok(Value::Number(freed)) => assert!(freed >= 3.0, "freed {freed}"),
+            Ok(value) => panic!("collect() returned {value}"),
+            Err(err) => panic!("{err}"),
+        }
+    }
+}
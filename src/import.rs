@@ -0,0 +1,201 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    compiler::Compiler,
+    error::{Diagnostics, Result, RuntimeError},
+    interpreter::Interpreter,
+    lox::{self, Backend},
+    scanner::ScannerOptions,
+    vm::Vm,
+};
+
+/// The scripts a program has run, so that `import` runs each one only once,
+/// and the ones still running, to catch import cycles. Scripts are known by
+/// their canonical paths.
+#[derive(Debug, Default)]
+pub(crate) struct Imports {
+    loaded: HashSet<PathBuf>,
+    /// The main script, if it came from a file, then each import in
+    /// progress, innermost last.
+    running: Vec<PathBuf>,
+    /// Applied to imported scripts as well as the main one.
+    pub(crate) scanner: ScannerOptions,
+}
+
+impl Imports {
+    /// Records that the script at `path` has started running.
+    pub(crate) fn enter(&mut self, path: PathBuf) {
+        self.loaded.insert(path.clone());
+        self.running.push(path);
+    }
+
+    /// How many scripts are running: the main one, if it came from a file,
+    /// and each import in progress.
+    pub(crate) fn running(&self) -> usize {
+        self.running.len()
+    }
+
+    /// The canonical paths of the scripts the program has run: the main
+    /// one, if it came from a file, and each one it has imported.
+    pub(crate) fn loaded(&self) -> impl Iterator<Item = &PathBuf> {
+        self.loaded.iter()
+    }
+
+    /// Records that the innermost running script has finished.
+    pub(crate) fn exit(&mut self) {
+        self.running.pop();
+    }
+
+    /// Finds the script `path` refers to, relative to the directory of the
+    /// script importing it, or to the working directory if that didn't come
+    /// from a file.
+    fn resolve(&self, path: &str) -> std::io::Result<PathBuf> {
+        match self.running.last().and_then(|importer| importer.parent()) {
+            Some(directory) => directory.join(path).canonicalize(),
+            None => Path::new(path).canonicalize(),
+        }
+    }
+}
+
+/// Runs the script at `path` on `backend`, for an `import` on `line` of the
+/// running script, unless the program has already run it. Its globals are
+/// defined alongside the importer's.
+///
+/// An error in the imported script is reported as coming from it, while a
+/// failure to import it at all, including when the options don't allow
+/// filesystem access, is returned as a
+/// [`GeneralError`](RuntimeError::GeneralError) for the caller to locate.
+pub(crate) fn run(
+    interpreter: &mut Interpreter,
+    path: &str,
+    line: usize,
+    backend: Backend,
+) -> Result<()> {
+    if !interpreter.options().allow_filesystem {
+        return Err(RuntimeError::GeneralError(
+            format!("Can't import `{path}`, as filesystem access isn't allowed").into(),
+        ));
+    }
+
+    let imports = interpreter.imports();
+    let resolved = imports.resolve(path).map_err(|err| {
+        RuntimeError::GeneralError(format!("Can't import `{path}`: {err}").into())
+    })?;
+
+    if let Some(start) = imports
+        .running
+        .iter()
+        .position(|running| *running == resolved)
+    {
+        let cycle: Vec<_> = imports.running[start..]
+            .iter()
+            .chain([&resolved])
+            .map(|script| script.file_name().unwrap_or_default().to_string_lossy())
+            .collect();
+        return Err(RuntimeError::GeneralError(
+            format!("Import cycle: {}", cycle.join(" -> ")).into(),
+        ));
+    }
+    if imports.loaded.contains(&resolved) {
+        return Ok(());
+    }
+
+    let source = std::fs::read_to_string(&resolved).map_err(|err| {
+        RuntimeError::GeneralError(format!("Can't import `{path}`: {err}").into())
+    })?;
+
+    execute(interpreter, resolved, path, &source, backend).map_err(|err| match err {
+        RuntimeError::Diagnostics(_) => err,
+        err => err.in_source(&source).called_from(path.to_string(), line),
+    })
+}
+
+/// Runs the imported script at `path` again, for watch mode, so what it
+/// declares replaces the globals it declared before while the rest of the
+/// program's globals stay as they were. Scripts it imports that have run
+/// already aren't run again.
+pub(crate) fn reload(interpreter: &mut Interpreter, path: &Path, backend: Backend) -> Result<()> {
+    let name = path.display().to_string();
+    let source = std::fs::read_to_string(path).map_err(|err| {
+        RuntimeError::GeneralError(format!("Can't reload `{name}`: {err}").into())
+    })?;
+
+    execute(interpreter, path.to_path_buf(), &name, &source, backend).map_err(|err| match err {
+        RuntimeError::Diagnostics(_) => err,
+        err => err.in_source(&source),
+    })
+}
+
+/// Parses and runs `source`, the script at `resolved`, which the importer
+/// called `path`. Errors found before it runs, and those in any script it
+/// imports, are reported as coming from it; the caller places the rest.
+fn execute(
+    interpreter: &mut Interpreter,
+    resolved: PathBuf,
+    path: &str,
+    source: &str,
+    backend: Backend,
+) -> Result<()> {
+    let in_file = |err: RuntimeError| match err {
+        RuntimeError::Diagnostics(diagnostics) => {
+            RuntimeError::Diagnostics(diagnostics.in_file(path))
+        }
+        err => RuntimeError::Diagnostics(Diagnostics::new(source, vec![err]).in_file(path)),
+    };
+
+    let program = lox::parse(source, interpreter.imports().scanner, false).map_err(in_file)?;
+    let module = match backend {
+        Backend::TreeWalk => None,
+        Backend::Vm => Some(
+            Compiler::new()
+                .with_integers(interpreter.options().integers)
+                .compile(&program),
+        ),
+    };
+
+    interpreter.imports().enter(resolved);
+    let result = match module {
+        Some(module) => Vm::new(interpreter).run_module(module),
+        None => interpreter.execute_program(&program),
+    };
+    interpreter.imports().exit();
+
+    result.map_err(|err| match err {
+        RuntimeError::Diagnostics(diagnostics) => {
+            RuntimeError::Diagnostics(diagnostics.in_file(path))
+        }
+        err => err,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, Lox, LoxOptions};
+
+    #[test]
+    fn imports_are_refused_without_filesystem_access() {
+        let dir = std::env::temp_dir().join(format!("rlox-sandbox-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let module = dir.join("module.lox");
+        std::fs::write(&module, "var imported = true;").unwrap();
+        let source = format!("import \"{}\";", module.display());
+
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            let options = LoxOptions::default().with_filesystem(false);
+            let mut lox = Lox::new().with_backend(backend).with_options(options);
+            let err = lox.run_source(&source).err().unwrap();
+            assert!(
+                err.to_string().contains("filesystem access isn't allowed"),
+                "{err}"
+            );
+            assert!(lox.eval("imported").is_err());
+
+            let mut lox = Lox::new().with_backend(backend);
+            assert!(lox.run_source(&source).is_ok());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
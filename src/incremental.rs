@@ -0,0 +1,325 @@
+//! Parsing a script again after each edit, for an editor that wants fresh
+//! diagnostics on every keystroke.
+//!
+//! A [`Document`] keeps the script split into its top-level declarations.
+//! An edit re-scans and re-parses from the declaration before the one it
+//! touches, since that one's parse looked a token ahead, and stops as soon
+//! as a declaration starts where an untouched one from before the edit now
+//! starts. Everything from there on is kept, moved by however much the edit
+//! grew or shrank the source. Their diagnostics are moved along with them
+//! right away, while their statements, whose tokens all record where they
+//! were, are only parsed again where they now are once they're asked for.
+
+use std::{cell::OnceCell, ops::Range};
+
+use crate::{
+    error::Diagnostic,
+    parser::{Parser, Stmt},
+    scanner::{error_token, Scanner},
+    span::Span,
+    token::TokenValue,
+};
+
+/// A script being edited, with the syntax tree and scan and parse errors of
+/// each top-level declaration. Resolver errors need the whole program and
+/// aren't included.
+pub struct Document {
+    source: String,
+    declarations: Vec<Declaration>,
+}
+
+/// One top-level declaration, or the tokens skipped over after an error.
+struct Declaration {
+    /// From the declaration's first token, or the start of the source, to
+    /// the next declaration's first token, so the declarations cover the
+    /// source between them.
+    span: Span,
+    /// Where `span` starts.
+    line: usize,
+    column: usize,
+    /// Whether the declaration's first token scanned without an error. An
+    /// error token is placed where the error was found rather than where
+    /// the token started, so it doesn't show where scanning had got to.
+    scanned: bool,
+    /// `None` if the declaration didn't parse. Emptied when an edit moves
+    /// the declaration, to be parsed again where it now is when it's next
+    /// asked for.
+    stmt: OnceCell<Option<Stmt>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Declaration {
+    /// Moves the declaration `bytes` further into the source and `lines`
+    /// further down it. Its columns stay the same, so it mustn't start on the
+    /// line an edit ended on.
+    fn shift(&mut self, bytes: isize, lines: isize) {
+        if bytes != 0 || lines != 0 {
+            self.stmt.take();
+        }
+
+        let shift_span = |span: &mut Span| {
+            span.start = span.start.saturating_add_signed(bytes);
+            span.end = span.end.saturating_add_signed(bytes);
+        };
+
+        shift_span(&mut self.span);
+        self.line = self.line.saturating_add_signed(lines);
+        for diagnostic in &mut self.diagnostics {
+            shift_span(&mut diagnostic.span);
+            diagnostic.line = diagnostic.line.saturating_add_signed(lines);
+            if let Some(note) = &mut diagnostic.note {
+                shift_span(&mut note.span);
+                note.line = note.line.saturating_add_signed(lines);
+            }
+        }
+    }
+}
+
+impl Document {
+    pub fn new(source: impl Into<String>) -> Self {
+        let mut document = Self {
+            source: source.into(),
+            declarations: vec![],
+        };
+        document.declarations = document.parse_from((0, 1, 1), &mut vec![]);
+        document
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The statements that parsed, in source order, with the positions
+    /// they're at in the source as it now is.
+    pub fn program(&self) -> impl Iterator<Item = &Stmt> {
+        self.declarations.iter().filter_map(|declaration| {
+            let stmt = declaration
+                .stmt
+                .get_or_init(|| self.parse_moved(declaration));
+            stmt.as_ref()
+        })
+    }
+
+    /// The scan and parse errors in the source, in source order.
+    pub fn diagnostics(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.declarations
+            .iter()
+            .flat_map(|declaration| &declaration.diagnostics)
+    }
+
+    /// Replaces the bytes in `range` with `text`, and returns how many
+    /// declarations were parsed again. Panics, as [`String::replace_range`]
+    /// does, if `range` doesn't fall on character boundaries.
+    pub fn edit(&mut self, range: Range<usize>, text: &str) -> usize {
+        let end_line = Span::new(range.end, range.end).line_in(&self.source);
+        let removed_lines = self.source[range.clone()].matches('\n').count();
+        self.source.replace_range(range.clone(), text);
+        let bytes = text.len() as isize - range.len() as isize;
+        let lines = text.matches('\n').count() as isize - removed_lines as isize;
+
+        // The first declaration reaching the edit, or the space before it, is
+        // parsed again, and so is the one before, whose last lookahead token
+        // may have been changed.
+        let first = self
+            .declarations
+            .iter()
+            .position(|declaration| declaration.span.end >= range.start)
+            .unwrap_or(self.declarations.len())
+            .saturating_sub(1);
+        let mut edited = self.declarations.split_off(first);
+
+        // Those after the edit can be kept if the new parse gets back in step
+        // with them. One on the edit's last line would have its columns moved
+        // as well, so it's parsed again instead.
+        let after = edited
+            .iter()
+            .position(|declaration| {
+                declaration.span.start > range.end && declaration.line > end_line
+            })
+            .unwrap_or(edited.len());
+        let mut after = edited.split_off(after);
+        after
+            .iter_mut()
+            .for_each(|declaration| declaration.shift(bytes, lines));
+
+        let start = edited.first().map_or((0, 1, 1), |declaration| {
+            (declaration.span.start, declaration.line, declaration.column)
+        });
+        let parsed = self.parse_from(start, &mut after);
+        let count = parsed.len();
+        self.declarations.extend(parsed);
+        self.declarations.extend(after);
+        count
+    }
+
+    /// Parses `declaration` again where an edit has moved it to. Its errors
+    /// are already known, having been moved along with it.
+    fn parse_moved(&self, declaration: &Declaration) -> Option<Stmt> {
+        let (start, line, column) = (declaration.span.start, declaration.line, declaration.column);
+        let scanner = Scanner::new(&self.source).starting_at(start, line, column);
+        let tokens = scanner.map(|token| token.unwrap_or_else(|err| error_token(&err)));
+        Parser::new(tokens).recovering_declaration()
+    }
+
+    /// Parses declarations from `start`, the byte offset, line and column
+    /// to scan from, until one would start where the first of `after` does.
+    /// The declarations of `after` passed over on the way are removed.
+    fn parse_from(
+        &self,
+        (start, line, column): (usize, usize, usize),
+        after: &mut Vec<Declaration>,
+    ) -> Vec<Declaration> {
+        let mut errors = vec![];
+        let scanner = Scanner::new(&self.source).starting_at(start, line, column);
+        let tokens = scanner.map(|token| {
+            token.unwrap_or_else(|err| {
+                let token = error_token(&err);
+                errors.push(err);
+                token
+            })
+        });
+        let mut parser = Parser::new(tokens);
+        let mut declarations: Vec<Declaration> = vec![];
+        let mut from = (start, line, column);
+        // Where the parse got back in step with `after`, if it did.
+        let mut caught_up = self.source.len();
+
+        while start < self.source.len() && !parser.is_at_end() {
+            let next = parser.next_token().span.start;
+            let scanned = parser.next_token().value != TokenValue::Error;
+            let passed = after
+                .iter()
+                .take_while(|declaration| declaration.span.start < next)
+                .count();
+            after.drain(..passed);
+            if scanned
+                && after.first().is_some_and(|declaration| {
+                    declaration.scanned && declaration.span.start == next
+                })
+            {
+                // The first declaration takes in the space before it, which
+                // has nothing left to parse.
+                let declaration = &mut after[0];
+                (declaration.span.start, declaration.line, declaration.column) = from;
+                caught_up = next;
+                break;
+            }
+
+            let stmt = parser.recovering_declaration();
+            let end = match parser.is_at_end() {
+                true => self.source.len(),
+                false => parser.next_token().span.start,
+            };
+            declarations.push(Declaration {
+                span: Span::new(from.0, end),
+                line: from.1,
+                column: from.2,
+                scanned,
+                stmt: OnceCell::from(stmt),
+                diagnostics: vec![],
+            });
+            // A token's line is the one it ends on, so a string running over
+            // several lines can't say where the next declaration starts.
+            let skipped = &self.source[from.0..end];
+            let column = match skipped.rfind('\n') {
+                Some(newline) => skipped[newline + 1..].chars().count() + 1,
+                None => from.2 + skipped.chars().count(),
+            };
+            from = (end, from.1 + skipped.matches('\n').count(), column);
+        }
+
+        if parser.is_at_end() {
+            after.clear();
+        }
+        let parse_errors = parser.into_errors();
+        errors.extend(parse_errors);
+
+        // Each error goes with the last declaration starting at or before it.
+        // The lookahead token scanned where the parse got back in step is in
+        // a declaration that's kept, with its own errors.
+        let mut diagnostics: Vec<Diagnostic> = errors
+            .into_iter()
+            .map(Diagnostic::from)
+            .filter(|diagnostic| diagnostic.span.start < caught_up)
+            .collect();
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+        for diagnostic in diagnostics {
+            let index = declarations
+                .partition_point(|declaration| declaration.span.start <= diagnostic.span.start)
+                .saturating_sub(1);
+            if let Some(declaration) = declarations.get_mut(index) {
+                declaration.diagnostics.push(diagnostic);
+            }
+        }
+
+        declarations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The diagnostics of `document` as `line:column message`.
+    fn diagnostics(document: &Document) -> Vec<String> {
+        document
+            .diagnostics()
+            .map(|diagnostic| {
+                format!(
+                    "{}:{} {}",
+                    diagnostic.line, diagnostic.column, diagnostic.message
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edits_parse_only_the_declarations_around_them_again() {
+        let source = "var a = 1;\nfun f() {\n  return a;\n}\nvar b = ;\nclass C {}\nprint b;\n";
+        let mut document = Document::new(source);
+        assert_eq!(document.program().count(), 4);
+        assert_eq!(diagnostics(&document), diagnostics(&Document::new(source)));
+        assert_eq!(diagnostics(&document).len(), 1);
+
+        // Fixing `var b = ;` parses it and the function before it again.
+        let at = source.find(" ;").unwrap();
+        assert_eq!(document.edit(at..at, " 2"), 2);
+        assert!(diagnostics(&document).is_empty());
+        assert_eq!(document.program().count(), 5);
+
+        // Breaking the function moves the error to the lines after it.
+        let at = document.source().find("return a;").unwrap() + "return a".len();
+        assert_eq!(document.edit(at..at + 1, "\n\n"), 2);
+        assert_eq!(
+            diagnostics(&document),
+            diagnostics(&Document::new(document.source()))
+        );
+
+        // An unclosed string swallows the rest of the source.
+        document.edit(0..0, "print \"open;\n");
+        assert_eq!(
+            diagnostics(&document),
+            diagnostics(&Document::new(document.source()))
+        );
+        assert_eq!(document.program().count(), 0);
+    }
+
+    #[test]
+    fn declarations_kept_from_before_an_edit_report_where_they_now_are() {
+        let mut document = Document::new("var a = 1;\nfun f() {\n  return a;\n}\n");
+        assert_eq!(document.edit(0..0, "// a note\n\n"), 1);
+
+        let Some(Stmt::Function(function)) = document.program().nth(1) else {
+            panic!("`f` didn't parse");
+        };
+        let Stmt::Return { keyword, .. } = &function.body[0] else {
+            panic!("`f` doesn't return");
+        };
+        assert_eq!((function.name.line, function.name.column), (4, 5));
+        assert_eq!((keyword.line, keyword.column), (5, 3));
+        assert_eq!(
+            function.span.start,
+            document.source().find("fun f").unwrap()
+        );
+    }
+}
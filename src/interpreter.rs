@@ -0,0 +1,1603 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+
+use crate::{
+    callable::{arity_mismatch, arrange_arguments, LoxCallable, LoxFunction, NativeFunction},
+    class::{LoxClass, LoxInstance, Method, Property},
+    environment::Environment,
+    error::{ErrorCode, Message, Result, RuntimeError},
+    gc,
+    import::{self, Imports},
+    iterator,
+    lox::Backend,
+    options::{self, Budget, LoxOptions},
+    output::{Output, Stdout},
+    parser::{Expr, FunctionDecl, Pattern, Stmt},
+    stdlib,
+    symbol::Symbol,
+    token::{Token, TokenValue},
+    value::Value,
+};
+
+/// Why execution of a statement stopped before reaching its end. Errors
+/// propagate all the way up, while `Return` is caught by the enclosing
+/// function call and `Break` and `Continue` by the enclosing loop.
+pub(crate) enum Unwind {
+    Error(RuntimeError),
+    Return(Value),
+    Break,
+    Continue,
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(value: RuntimeError) -> Self {
+        Unwind::Error(value)
+    }
+}
+
+type Exec = core::result::Result<(), Unwind>;
+
+/// Watches the tree-walking interpreter run a program, once installed with
+/// [`Interpreter::set_hook`] or [`Lox::with_hook`](crate::Lox::with_hook).
+/// `rlox debug` and `rlox --profile` are built on it. Every method does
+/// nothing unless it is overridden. The vm calls every method but
+/// `on_statement`, since it doesn't run statements one at a time.
+pub trait Hook {
+    /// Called before each statement runs. An error stops the program.
+    fn on_statement(&mut self, _interpreter: &Interpreter, _stmt: &Stmt) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called as a call to the Lox function, method or class `name` starts.
+    /// Calls to natives aren't reported.
+    fn on_call(&mut self, _name: &str) {}
+
+    /// Called as the innermost call started returns `value`, or with `None`
+    /// if it failed.
+    fn on_return(&mut self, _value: Option<&Value>) {}
+
+    /// Called with the runtime error that stopped a program, before it is
+    /// returned.
+    fn on_error(&mut self, _error: &RuntimeError) {}
+}
+
+/// Runs resolved programs. Globals, including those defined by earlier
+/// programs, persist for the life of the interpreter.
+pub struct Interpreter {
+    globals: Rc<RefCell<Environment>>,
+    /// The globals defined from Rust rather than by programs: the natives,
+    /// including any the host added, and values like `PI`.
+    builtins: HashMap<Symbol, Value>,
+    environment: Rc<RefCell<Environment>>,
+    line: Option<usize>,
+    options: LoxOptions,
+    budget: Budget,
+    /// How many calls are in progress, for [`LoxOptions::max_call_depth`].
+    depth: usize,
+    /// Where the stack was when the outermost call in progress started, for
+    /// [`LoxOptions::max_stack`].
+    stack_base: usize,
+    imports: Imports,
+    output: Box<dyn Output>,
+    hook: Option<Box<dyn Hook>>,
+    /// The `Error` class, kept here so a script that shadows the global
+    /// still catches runtime errors as instances of it.
+    error_class: Rc<LoxClass>,
+    /// The class of the iterators for-in loops over collections use.
+    iterator_class: Rc<LoxClass>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+
+        let mut interpreter = Self {
+            globals: globals.clone(),
+            builtins: HashMap::new(),
+            environment: globals,
+            line: None,
+            options: LoxOptions::default(),
+            budget: Budget::default(),
+            depth: 0,
+            stack_base: 0,
+            imports: Imports::default(),
+            output: Box::new(Stdout),
+            hook: None,
+            error_class: stdlib::error_class(),
+            iterator_class: iterator::iterator_class(),
+        };
+        stdlib::define_globals(&mut interpreter);
+
+        interpreter
+    }
+
+    /// Stops programs that go past `options`; see [`LoxOptions`].
+    pub fn with_options(mut self, options: LoxOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Sends what programs print to `output` instead of stdout.
+    pub fn with_output(mut self, output: impl Output + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Swaps in `output` for what programs print, returning the one it
+    /// replaces.
+    pub(crate) fn replace_output(&mut self, output: Box<dyn Output>) -> Box<dyn Output> {
+        std::mem::replace(&mut self.output, output)
+    }
+
+    /// Hands a line printed by the program to the output, on either backend.
+    pub(crate) fn print(&mut self, line: &str) -> Result<()> {
+        Ok(self.output.print(line)?)
+    }
+
+    pub(crate) fn eprint(&mut self, line: &str) -> Result<()> {
+        Ok(self.output.eprint(line)?)
+    }
+
+    /// Starts over with only the globals defined from Rust, as a new
+    /// interpreter would have them but with any natives the host added,
+    /// forgetting the programs run and the scripts they imported. The
+    /// options, the output and the hook are kept.
+    pub(crate) fn reset(&mut self) {
+        let mut globals = Environment::new();
+        for (name, value) in &self.builtins {
+            globals.define(*name, value.clone());
+        }
+        self.globals = Rc::new(RefCell::new(globals));
+        self.environment = self.globals.clone();
+        self.line = None;
+        self.depth = 0;
+        let scanner = self.imports.scanner;
+        self.imports = Imports::default();
+        self.imports.scanner = scanner;
+    }
+
+    pub(crate) fn options(&self) -> LoxOptions {
+        self.options
+    }
+
+    /// Starts counting steps for a new run, on either backend.
+    pub(crate) fn start_budget(&mut self) {
+        self.budget.start(&self.options);
+    }
+
+    /// Counts one step of the running program, or returns why it must stop.
+    pub(crate) fn step(&mut self) -> core::result::Result<(), Message> {
+        self.budget.step(&self.options)
+    }
+
+    pub(crate) fn imports(&mut self) -> &mut Imports {
+        &mut self.imports
+    }
+
+    /// The source line of the most recently evaluated expression, used to
+    /// point internal error reports at the Lox code being run.
+    pub(crate) fn current_line(&self) -> Option<usize> {
+        self.line
+    }
+
+    pub(crate) fn globals(&self) -> Rc<RefCell<Environment>> {
+        self.globals.clone()
+    }
+
+    /// The globals defined by programs, sorted by name. The ones defined
+    /// from Rust, such as the natives, are left out unless a program has
+    /// assigned to them.
+    pub(crate) fn user_globals(&self) -> Vec<(Symbol, Value)> {
+        let globals = self.globals.borrow();
+        let mut bindings: Vec<_> = globals
+            .bindings()
+            .filter(|(name, value)| self.builtins.get(name) != Some(*value))
+            .map(|(name, value)| (name, value.clone()))
+            .collect();
+        bindings.sort_by_key(|(name, _)| name.as_str());
+        bindings
+    }
+
+    /// The innermost scope of whatever is running.
+    pub(crate) fn environment(&self) -> Rc<RefCell<Environment>> {
+        self.environment.clone()
+    }
+
+    /// How many calls are in progress.
+    pub fn call_depth(&self) -> usize {
+        self.depth
+    }
+
+    /// How many scripts are running: the main one and each import still in
+    /// progress.
+    pub fn running_scripts(&self) -> usize {
+        self.imports.running()
+    }
+
+    /// Has `hook` watch programs run from now on, or none, returning the hook
+    /// it replaces.
+    pub fn set_hook(&mut self, hook: Option<Box<dyn Hook>>) -> Option<Box<dyn Hook>> {
+        std::mem::replace(&mut self.hook, hook)
+    }
+
+    /// Tells the hook, if there is one, that a call to `name` is starting.
+    pub(crate) fn hook_call(&mut self, name: &str) {
+        if let Some(hook) = &mut self.hook {
+            hook.on_call(name);
+        }
+    }
+
+    /// Tells the hook, if there is one, that the innermost call returned
+    /// `value`, or failed.
+    pub(crate) fn hook_return(&mut self, value: Option<&Value>) {
+        if let Some(hook) = &mut self.hook {
+            hook.on_return(value);
+        }
+    }
+
+    /// Tells the hook, if there is one, that `error` stopped the program.
+    pub(crate) fn hook_error(&mut self, error: &RuntimeError) {
+        if let Some(hook) = &mut self.hook {
+            hook.on_error(error);
+        }
+    }
+
+    /// Binds `name` in the global scope, replacing any existing binding.
+    /// Unlike the globals programs define, it survives the REPL's `:reset`
+    /// and the isolation between `--batch` lines.
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        let name = Symbol::intern(name);
+        self.builtins.insert(name, value.clone());
+        self.globals.borrow_mut().define(name, value);
+    }
+
+    /// Exposes a Rust function to Lox code as the global `name`. See
+    /// [`NativeFunction`] for how arguments and errors are handled.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, Vec<Value>) -> Result<Value> + 'static,
+    ) {
+        let native = NativeFunction::new(name, arity, function);
+        self.define_global(name, Value::Callable(Rc::new(native)));
+    }
+
+    /// Like [`define_native`](Self::define_native), for a function that
+    /// takes `arity` arguments or more.
+    pub fn define_variadic_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, Vec<Value>) -> Result<Value> + 'static,
+    ) {
+        let native = NativeFunction::variadic(name, arity, function);
+        self.define_global(name, Value::Callable(Rc::new(native)));
+    }
+
+    /// Drops back to the global scope. Used after a panic unwound through
+    /// `execute_block` without restoring the enclosing scope.
+    pub(crate) fn reset_scope(&mut self) {
+        self.environment = self.globals.clone();
+        self.depth = 0;
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "interpret", skip_all, err(Display))
+    )]
+    /// Executes `statements` in order, stopping at the first runtime error.
+    /// They must have been through a [`Resolver`](crate::Resolver) first, or
+    /// local variables will be looked up as globals.
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<()> {
+        self.start_budget();
+        let result = self.execute_program(statements);
+        if let Err(err) = &result {
+            self.hook_error(err);
+        }
+        result
+    }
+
+    /// Executes the top-level statements of a program as part of the run
+    /// already in progress.
+    pub(crate) fn execute_program(&mut self, statements: &[Stmt]) -> Result<()> {
+        for statement in statements {
+            match self.execute(statement) {
+                Ok(()) => {}
+                Err(Unwind::Error(err)) => return Err(err),
+                Err(Unwind::Return(_)) => unreachable!("resolver rejects top-level return"),
+                Err(Unwind::Break | Unwind::Continue) => {
+                    unreachable!("parser rejects loop control outside loops")
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Exec {
+        if let Err(message) = self.step() {
+            return Err(RuntimeError::InterpretError(message, self.current_token()).into());
+        }
+        if self.stack_exhausted() {
+            return Err(RuntimeError::InterpretError(
+                Message::new(ErrorCode::StackOverflow, options::STACK_OVERFLOW),
+                self.current_token(),
+            )
+            .into());
+        }
+
+        // The hook is taken out while it runs, so it can look at the
+        // interpreter it's watching.
+        if let Some(mut hook) = self.hook.take() {
+            let result = hook.on_statement(self, stmt);
+            self.hook = Some(hook);
+            result?;
+        }
+
+        match stmt {
+            Stmt::Expression(expr) => {
+                self.evaluate(expr)?;
+            }
+            Stmt::Function(declaration) => {
+                let function = gc::alloc(LoxFunction::new(
+                    declaration.clone(),
+                    self.environment.clone(),
+                    false,
+                ));
+                self.environment
+                    .borrow_mut()
+                    .define(declaration.name.symbol(), Value::Callable(function));
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                let superclass = match superclass {
+                    Some(expr) => match (self.evaluate(expr)?, expr) {
+                        (Value::Class(class), _) => Some(class),
+                        (_, Expr::Variable { name, .. }) => {
+                            return Err(RuntimeError::InterpretError(
+                                "Superclass must be a class".into(),
+                                name.clone(),
+                            )
+                            .into())
+                        }
+                        _ => unreachable!("superclass is always parsed as a variable"),
+                    },
+                    None => None,
+                };
+
+                // Methods of a subclass close over an extra scope that binds
+                // `super`, sitting between the class's scope and `this`.
+                let closure = match &superclass {
+                    Some(superclass) => {
+                        let mut environment = Environment::with_enclosing(self.environment.clone());
+                        environment.define(Symbol::SUPER, Value::Class(superclass.clone()));
+                        gc::alloc(RefCell::new(environment))
+                    }
+                    None => self.environment.clone(),
+                };
+
+                let (class_methods, methods): (Vec<_>, Vec<_>) =
+                    methods.iter().partition(|method| method.is_class_method);
+                let declare = |methods: Vec<&Rc<FunctionDecl>>| {
+                    methods
+                        .into_iter()
+                        .map(|method| {
+                            let is_initializer =
+                                method.name.symbol() == Symbol::INIT && !method.is_class_method;
+                            let function: Rc<dyn Method> = gc::alloc(LoxFunction::new(
+                                method.clone(),
+                                closure.clone(),
+                                is_initializer,
+                            ));
+                            (method.name.symbol(), function)
+                        })
+                        .collect()
+                };
+
+                let class = gc::alloc(LoxClass::new(
+                    name.symbol(),
+                    superclass,
+                    declare(methods),
+                    declare(class_methods),
+                ));
+
+                // The resolver only lets a name be declared twice in one
+                // scope at the top level, so only a global class is reopened.
+                let existing = match self.options.reopen_classes {
+                    true => Environment::get_at(&self.environment, 0, name.symbol()),
+                    false => None,
+                };
+                match existing {
+                    Some(Value::Class(existing)) => existing.reopen(&class).map_err(|message| {
+                        RuntimeError::InterpretError(message.into(), name.clone())
+                    })?,
+                    _ => self
+                        .environment
+                        .borrow_mut()
+                        .define(name.symbol(), Value::Class(class)),
+                }
+            }
+            Stmt::Import { keyword, path, .. } => {
+                let TokenValue::String(path) = &path.value else {
+                    unreachable!("the parser only accepts a string to import");
+                };
+                import::run(self, path, keyword.line, Backend::TreeWalk)
+                    .map_err(|err| at_token(err, keyword))?;
+            }
+            Stmt::Print { values, .. } => {
+                // Every value is evaluated before any is formatted, so a list
+                // changed by a later value prints the same as in the vm.
+                let values = values
+                    .iter()
+                    .map(|expr| self.evaluate(expr))
+                    .collect::<Result<Vec<_>>>()?;
+                let values = values
+                    .iter()
+                    .map(|value| self.stringify(value))
+                    .collect::<Result<Vec<_>>>()?;
+                self.print(&values.join(" "))
+                    .map_err(|err| at_token(err, &self.current_token()))?;
+            }
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                let value = match initializer {
+                    Some(expr) => self.evaluate(expr)?,
+                    None => Value::Nil,
+                };
+                self.environment.borrow_mut().define(name.symbol(), value);
+            }
+            Stmt::Unpack {
+                names, initializer, ..
+            } => {
+                let values = self
+                    .evaluate(initializer)?
+                    .unpack(names.len())
+                    .map_err(|err| at_token(err, &names[0]))?;
+                for (name, value) in names.iter().zip(values) {
+                    self.environment.borrow_mut().define(name.symbol(), value);
+                }
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                if self.evaluate(condition)?.is_truthy() {
+                    self.execute(then_branch)?;
+                } else if let Some(else_branch) = else_branch {
+                    self.execute(else_branch)?;
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                while self.evaluate(condition)?.is_truthy() {
+                    match self.execute(body) {
+                        Ok(()) | Err(Unwind::Continue) => {}
+                        Err(Unwind::Break) => break,
+                        Err(unwind) => return Err(unwind),
+                    }
+
+                    if let Some(increment) = increment {
+                        self.evaluate(increment)?;
+                    }
+                }
+            }
+            Stmt::ForIn {
+                keyword,
+                names,
+                iterable,
+                body,
+                ..
+            } => {
+                let iterable = self.evaluate(iterable)?;
+                let iterator = iterator::iterator(self, iterable, names.len())
+                    .map_err(|err| at_token(err, keyword))?;
+
+                while self.call_method(&iterator, keyword, "hasNext")?.is_truthy() {
+                    let value = self.call_method(&iterator, keyword, "next")?;
+                    let values = match names.len() {
+                        1 => vec![value],
+                        count => value
+                            .unpack(count)
+                            .map_err(|err| at_token(err, &names[0]))?,
+                    };
+                    let mut environment = Environment::with_enclosing(self.environment.clone());
+                    for (name, value) in names.iter().zip(values) {
+                        environment.define(name.symbol(), value);
+                    }
+
+                    match self.execute_block(std::slice::from_ref(body), environment) {
+                        Ok(()) | Err(Unwind::Continue) => {}
+                        Err(Unwind::Break) => break,
+                        Err(unwind) => return Err(unwind),
+                    }
+                }
+            }
+            Stmt::Return { value, .. } => {
+                let value = match value {
+                    Some(expr) => self.evaluate(expr)?,
+                    None => Value::Nil,
+                };
+                return Err(Unwind::Return(value));
+            }
+            Stmt::Block { statements, .. } => {
+                let environment = Environment::with_enclosing(self.environment.clone());
+                self.execute_block(statements, environment)?;
+            }
+            Stmt::Break { .. } => return Err(Unwind::Break),
+            Stmt::Continue { .. } => return Err(Unwind::Continue),
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                let environment = Environment::with_enclosing(self.environment.clone());
+                let result = match (self.execute_block(body, environment), catch) {
+                    (Err(Unwind::Error(err)), Some(catch)) if err.is_catchable() => {
+                        let mut environment = Environment::with_enclosing(self.environment.clone());
+                        environment.define(catch.name.symbol(), self.caught(err));
+                        self.execute_block(&catch.body, environment)
+                    }
+                    (result, _) => result,
+                };
+
+                // Leaving `finally` early replaces however the rest ended.
+                if let Some(finally) = finally {
+                    let environment = Environment::with_enclosing(self.environment.clone());
+                    self.execute_block(finally, environment)?;
+                }
+                return result;
+            }
+            Stmt::Throw { keyword, value, .. } => {
+                let value = self.evaluate(value)?;
+                return Err(self.thrown(value, keyword).into());
+            }
+            Stmt::Defer { .. } => unreachable!("blocks run their `defer`s themselves"),
+            Stmt::Use {
+                keyword,
+                name,
+                initializer,
+                body,
+                ..
+            } => {
+                let resource = self.evaluate(initializer)?;
+                let mut environment = Environment::with_enclosing(self.environment.clone());
+                environment.define(name.symbol(), resource);
+                let previous =
+                    std::mem::replace(&mut self.environment, gc::alloc(RefCell::new(environment)));
+
+                let environment = Environment::with_enclosing(self.environment.clone());
+                let result = self.execute_block(body, environment);
+                // As with `finally`, an error closing it replaces however
+                // the body ended.
+                let closed = self.evaluate(&Stmt::close_call(keyword, name));
+                self.environment = previous;
+
+                closed?;
+                return result;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs `statements` in `environment`, restoring the current scope
+    /// afterwards no matter how execution ends.
+    pub(crate) fn execute_block(&mut self, statements: &[Stmt], environment: Environment) -> Exec {
+        let previous =
+            std::mem::replace(&mut self.environment, gc::alloc(RefCell::new(environment)));
+
+        let result = self.execute_statements(statements);
+
+        self.environment = previous;
+
+        result
+    }
+
+    /// Runs the statements of a block in order. At a `defer`, the rest run
+    /// first, then the deferred statement however they ended; as with
+    /// `finally`, leaving it early replaces how they ended.
+    fn execute_statements(&mut self, statements: &[Stmt]) -> Exec {
+        for (index, statement) in statements.iter().enumerate() {
+            if let Stmt::Defer { statement, .. } = statement {
+                let result = self.execute_statements(&statements[index + 1..]);
+                self.execute(statement)?;
+                return result;
+            }
+            self.execute(statement)?;
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates a single resolved expression in the current scope.
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<Value> {
+        if let Some(token) = expr.token() {
+            self.line = Some(token.line);
+        }
+
+        match expr {
+            Expr::Literal { value: literal, .. } => {
+                Ok(Value::from_literal(literal, self.options.integers))
+            }
+            Expr::Grouping { group, .. } => self.evaluate(group),
+            Expr::Unary { operator, right } => {
+                let right = self.evaluate(right)?;
+
+                match operator.value {
+                    TokenValue::Bang => Ok(Value::Bool(!right.is_truthy())),
+                    TokenValue::Minus => match right {
+                        Value::Int(n) => n.checked_neg().map(Value::Int).ok_or_else(|| {
+                            RuntimeError::InterpretError(
+                                Message::new(ErrorCode::IntegerArithmetic, "Integer overflow"),
+                                operator.clone(),
+                            )
+                        }),
+                        Value::Number(n) => Ok(Value::Number(-n)),
+                        Value::Instance(instance) => {
+                            match LoxInstance::special_method(&instance, "__neg__") {
+                                Some(method) => self.call(method, vec![], operator),
+                                None => Err(RuntimeError::InterpretError(
+                                    Message::new(
+                                        ErrorCode::OperandTypes,
+                                        "Operand must be a number",
+                                    ),
+                                    operator.clone(),
+                                )),
+                            }
+                        }
+                        _ => Err(RuntimeError::InterpretError(
+                            Message::new(ErrorCode::OperandTypes, "Operand must be a number"),
+                            operator.clone(),
+                        )),
+                    },
+                    _ => unreachable!("invalid unary operator `{}`", operator.lexeme),
+                }
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                self.binary(left, operator, right)
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.evaluate(left)?;
+
+                match (&operator.value, left.is_truthy()) {
+                    (TokenValue::Or, true) | (TokenValue::And, false) => Ok(left),
+                    _ => self.evaluate(right),
+                }
+            }
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => match self.evaluate(condition)?.is_truthy() {
+                true => self.evaluate(then_branch),
+                false => self.evaluate(else_branch),
+            },
+            Expr::This { keyword, depth } => self.look_up_variable(keyword, depth),
+            Expr::Super { method, depth, .. } => {
+                let distance = depth
+                    .get()
+                    .expect("resolver rejects `super` outside of a subclass");
+
+                // `this` is always bound in the scope just inside `super`.
+                let Some(Value::Class(superclass)) =
+                    Environment::get_at(&self.environment, distance, Symbol::SUPER)
+                else {
+                    unreachable!("`super` resolved to a non-class value");
+                };
+                let Some(this) = Environment::get_at(&self.environment, distance - 1, Symbol::THIS)
+                else {
+                    unreachable!("`super` is only bound alongside `this`");
+                };
+
+                // In a class method `this` is the class, so `super` looks in
+                // the superclass's class methods instead.
+                let property = match this {
+                    Value::Class(_) => LoxClass::get(&superclass, method)?,
+                    Value::Instance(instance) => match superclass.find_method(method.symbol()) {
+                        Some(found) => Property::method(&found, Value::Instance(instance)),
+                        None => {
+                            return Err(RuntimeError::InterpretError(
+                                Message::new(
+                                    ErrorCode::UndefinedProperty,
+                                    format!("Undefined property `{}`", method.lexeme),
+                                ),
+                                method.clone(),
+                            ))
+                        }
+                    },
+                    _ => unreachable!("`this` is always an instance or a class"),
+                };
+                self.property_value(property, method)
+            }
+            Expr::Variable { name, depth } => self.look_up_variable(name, depth),
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+                keywords,
+            } => {
+                let callee = self.evaluate(callee)?;
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.evaluate(argument))
+                    .collect::<Result<Vec<_>>>()?;
+
+                let callable: Rc<dyn LoxCallable> = match callee {
+                    Value::Callable(callable) => callable,
+                    Value::Class(class) => class,
+                    _ => {
+                        return Err(RuntimeError::InterpretError(
+                            Message::new(
+                                ErrorCode::NotCallable,
+                                "Can only call functions and classes",
+                            ),
+                            paren.clone(),
+                        ))
+                    }
+                };
+
+                let arguments = match keywords.is_empty() {
+                    true => arguments,
+                    false => {
+                        let keywords: Vec<_> = keywords.iter().map(Token::symbol).collect();
+                        arrange_arguments(&*callable, arguments, &keywords)
+                            .map_err(|err| at_token(err, paren))?
+                    }
+                };
+                self.call(callable, arguments, paren)
+            }
+            Expr::Get { name, object } => {
+                let object = self.evaluate(object)?;
+                self.get_property(object, name)
+            }
+            Expr::Assign { name, value, depth } => {
+                let value = self.evaluate(value)?;
+                self.assign_variable(name, depth, value.clone())?;
+                Ok(value)
+            }
+            Expr::Update {
+                operator,
+                target,
+                prefix,
+            } => self.update(operator, target, *prefix),
+            Expr::Compound {
+                target,
+                operator,
+                value,
+            } => {
+                let (_, new) = self.modify(target, |interpreter, old| {
+                    let value = interpreter.evaluate(value)?;
+                    interpreter.binary(old, operator, value)
+                })?;
+                Ok(new)
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                let Value::Instance(instance) = self.evaluate(object)? else {
+                    return Err(RuntimeError::InterpretError(
+                        Message::new(ErrorCode::UndefinedProperty, "Only instances have fields"),
+                        name.clone(),
+                    ));
+                };
+
+                let value = self.evaluate(value)?;
+                instance.borrow_mut().set(name, value.clone());
+                Ok(value)
+            }
+            Expr::List { elements, .. } => {
+                let elements = elements
+                    .iter()
+                    .map(|element| self.evaluate(element))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Value::list(elements))
+            }
+            Expr::Tuple { elements, .. } => {
+                let elements = elements
+                    .iter()
+                    .map(|element| self.evaluate(element))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Value::tuple(elements))
+            }
+            Expr::Match {
+                keyword,
+                subject,
+                arms,
+                otherwise,
+                ..
+            } => {
+                let subject = self.evaluate(subject)?;
+                let equals = equals_token(keyword);
+                for arm in arms {
+                    let mut environment = Environment::with_enclosing(self.environment.clone());
+                    if !self.matches(&arm.pattern, &subject, &equals, &mut environment)? {
+                        continue;
+                    }
+
+                    let previous = std::mem::replace(
+                        &mut self.environment,
+                        gc::alloc(RefCell::new(environment)),
+                    );
+                    let result = self.evaluate(&arm.body);
+                    self.environment = previous;
+                    return result;
+                }
+                self.evaluate(otherwise)
+            }
+            Expr::Map { brace, entries, .. } => {
+                let entries = entries
+                    .iter()
+                    .map(|(key, value)| Ok((self.evaluate(key)?, self.evaluate(value)?)))
+                    .collect::<Result<Vec<_>>>()?;
+                Value::map(entries).map_err(|err| at_token(err, brace))
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                object
+                    .get_index(&index)
+                    .map_err(|err| at_token(err, bracket))
+            }
+            Expr::Slice {
+                object,
+                bracket,
+                start,
+                end,
+            } => {
+                let object = self.evaluate(object)?;
+                let start = self.evaluate_bound(start)?;
+                let end = self.evaluate_bound(end)?;
+                object
+                    .get_slice(&start, &end)
+                    .map_err(|err| at_token(err, bracket))
+            }
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                let value = self.evaluate(value)?;
+                object
+                    .set_index(&index, value.clone())
+                    .map_err(|err| at_token(err, bracket))?;
+                Ok(value)
+            }
+        }
+    }
+
+    /// Whether `value` matches `pattern`, defining what the pattern binds in
+    /// `environment` as it goes. Literals are compared as `==` would, with
+    /// `equals` as its operator.
+    fn matches(
+        &mut self,
+        pattern: &Pattern,
+        value: &Value,
+        equals: &Token,
+        environment: &mut Environment,
+    ) -> Result<bool> {
+        match pattern {
+            Pattern::Literal(literal) => {
+                let literal = self.evaluate(literal)?;
+                Ok(self.binary(value.clone(), equals, literal)?.is_truthy())
+            }
+            Pattern::Binding(name) => {
+                environment.define(name.symbol(), value.clone());
+                Ok(true)
+            }
+            Pattern::Tuple { elements, .. } => {
+                let Value::Tuple(tuple) = value else {
+                    return Ok(false);
+                };
+                if tuple.len() != elements.len() {
+                    return Ok(false);
+                }
+                for (pattern, element) in elements.iter().zip(tuple.iter()) {
+                    if !self.matches(pattern, element, equals, environment)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Pattern::Instance { class, fields, .. } => {
+                let class = match self.evaluate(class)? {
+                    Value::Class(class) => class,
+                    other => {
+                        return Err(RuntimeError::InterpretError(
+                            format!("Expected a class to match but got a {}", other.type_name())
+                                .into(),
+                            class
+                                .token()
+                                .expect("a class pattern names its class")
+                                .clone(),
+                        ))
+                    }
+                };
+                let Value::Instance(instance) = value else {
+                    return Ok(false);
+                };
+                let instance = instance.borrow();
+                if !instance.class().inherits(&class) {
+                    return Ok(false);
+                }
+                for field in fields {
+                    match instance.field(field.symbol()) {
+                        Some(value) => environment.define(field.symbol(), value),
+                        None => return Ok(false),
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    /// Evaluates `++` or `--` on `target`, evaluating its object and index
+    /// only once, and returns the new value if `prefix` or the old one if not.
+    /// Evaluates a slice bound, which is `nil` when it's left out.
+    fn evaluate_bound(&mut self, bound: &Option<Box<Expr>>) -> Result<Value> {
+        match bound {
+            Some(bound) => self.evaluate(bound),
+            None => Ok(Value::Nil),
+        }
+    }
+
+    /// Whether the calls in progress have used more of the stack than
+    /// [`LoxOptions::max_stack`] allows.
+    fn stack_exhausted(&self) -> bool {
+        self.depth > 0
+            && self
+                .options
+                .max_stack
+                .is_some_and(|max| stack_address().abs_diff(self.stack_base) > max)
+    }
+
+    /// Calls `callable` for a call whose closing parenthesis, the name of the
+    /// getter it runs, or the operator it overloads, is `token`.
+    fn call(
+        &mut self,
+        callable: Rc<dyn LoxCallable>,
+        arguments: Vec<Value>,
+        token: &Token,
+    ) -> Result<Value> {
+        let count = arguments.len();
+        if let Some(message) = arity_mismatch(callable.arity(), callable.variadic(), count) {
+            return Err(RuntimeError::InterpretError(message, token.clone()));
+        }
+
+        if self.depth == 0 {
+            self.stack_base = stack_address();
+        }
+        if self
+            .options
+            .max_call_depth
+            .is_some_and(|max| self.depth >= max)
+            || self.stack_exhausted()
+        {
+            return Err(RuntimeError::InterpretError(
+                Message::new(ErrorCode::StackOverflow, options::STACK_OVERFLOW),
+                token.clone(),
+            ));
+        }
+
+        // Natives have no source location of their own, so their
+        // errors are reported at the call site.
+        let frame_name = callable.frame_name();
+        if let Some(name) = &frame_name {
+            self.hook_call(name);
+        }
+        self.depth += 1;
+        let result = callable.call(self, arguments);
+        self.depth -= 1;
+        if frame_name.is_some() {
+            self.hook_return(result.as_ref().ok());
+        }
+        result.map_err(|err| {
+            let err = at_token(err, token);
+            match frame_name {
+                Some(name) => err.called_from(name, token.line),
+                None => err,
+            }
+        })
+    }
+
+    /// Reads the property `name` of `object`, which may be an instance or,
+    /// for its class methods, a class.
+    fn get_property(&mut self, object: Value, name: &Token) -> Result<Value> {
+        let property = match object {
+            Value::Instance(instance) => LoxInstance::get(&instance, name)?,
+            Value::Class(class) => LoxClass::get(&class, name)?,
+            _ => {
+                return Err(RuntimeError::InterpretError(
+                    Message::new(
+                        ErrorCode::UndefinedProperty,
+                        "Only instances have properties",
+                    ),
+                    name.clone(),
+                ))
+            }
+        };
+        self.property_value(property, name)
+    }
+
+    /// Calls `object`'s method `name` with no arguments, as a for-in loop
+    /// at `keyword` does to go through an iterator.
+    fn call_method(&mut self, object: &Value, keyword: &Token, name: &str) -> Result<Value> {
+        let name = Token::identifier_at(keyword, name);
+        match self.get_property(object.clone(), &name)? {
+            Value::Callable(method) => self.call(method, vec![], &name),
+            _ => Err(RuntimeError::InterpretError(
+                format!("`{}` must be a method", name.lexeme).into(),
+                name,
+            )),
+        }
+    }
+
+    /// Runs a getter to get its property's value.
+    fn property_value(&mut self, property: Property, name: &Token) -> Result<Value> {
+        match property {
+            Property::Value(value) => Ok(value),
+            Property::Getter(getter) => self.call(getter, Vec::new(), name),
+        }
+    }
+
+    /// Applies a binary operator, giving instances the chance to overload it.
+    /// The left operand's method is tried first, then the right operand's
+    /// reflected one, and only then the operator's built-in meaning, so
+    /// `vector * 2` and `2 * vector` can both work. `!=` is the negation of
+    /// whatever `__eq__` returns.
+    pub(crate) fn binary(&mut self, left: Value, operator: &Token, right: Value) -> Result<Value> {
+        if let Some((method, reflected)) = operator_methods(&operator.value) {
+            let overload = special_method(&left, method)
+                .map(|method| (method, right.clone()))
+                .or_else(|| special_method(&right, reflected).map(|method| (method, left.clone())));
+
+            if let Some((method, other)) = overload {
+                let result = self.call(method, vec![other], operator)?;
+                return Ok(match operator.value {
+                    TokenValue::BangEqual => Value::Bool(!result.is_truthy()),
+                    _ => result,
+                });
+            }
+        }
+
+        match (&operator.value, left, right) {
+            (TokenValue::Plus, Value::String(l), right)
+                if self.options.string_conversion && !matches!(right, Value::String(_)) =>
+            {
+                Ok(Value::String(l + &self.stringify(&right)?))
+            }
+            (TokenValue::Plus, left, Value::String(r))
+                if self.options.string_conversion && !matches!(left, Value::String(_)) =>
+            {
+                Ok(Value::String(self.stringify(&left)? + &r))
+            }
+            (_, left, right) => binary(left, operator, right),
+        }
+    }
+
+    /// Formats `value` for `print` and `str`, calling `__str__` on an
+    /// instance whose class defines it.
+    pub(crate) fn stringify(&mut self, value: &Value) -> Result<String> {
+        let Some(method) = special_method(value, "__str__") else {
+            return Ok(value.to_string());
+        };
+
+        match method.call(self, vec![])? {
+            Value::String(string) => Ok(string.to_string()),
+            _ => Err(RuntimeError::InterpretError(
+                "__str__ must return a string".into(),
+                self.current_token(),
+            )),
+        }
+    }
+
+    pub(crate) fn error_class(&self) -> Rc<LoxClass> {
+        self.error_class.clone()
+    }
+
+    pub(crate) fn iterator_class(&self) -> Rc<LoxClass> {
+        self.iterator_class.clone()
+    }
+
+    /// The error `throw` raises for `value` at `token`.
+    pub(crate) fn thrown(&mut self, value: Value, token: &Token) -> RuntimeError {
+        let message = match self.stringify(&value) {
+            Ok(message) => message,
+            Err(err) => return err,
+        };
+
+        RuntimeError::Thrown {
+            value,
+            error: Box::new(RuntimeError::InterpretError(
+                format!("Uncaught {message}").into(),
+                token.clone(),
+            )),
+        }
+    }
+
+    /// What `catch` binds for `error`: the thrown value for `throw`, or an
+    /// `Error` holding the message and line of any other error.
+    pub(crate) fn caught(&self, error: RuntimeError) -> Value {
+        let (message, line) = match error {
+            RuntimeError::Thrown { value, .. } => return value,
+            RuntimeError::InSource { error, .. } => return self.caught(*error),
+            RuntimeError::InterpretError(message, token)
+            | RuntimeError::Traceback { message, token, .. } => (message, Some(token.line)),
+            error => (error.to_string().into(), self.line),
+        };
+
+        stdlib::error(&self.error_class, message, line)
+    }
+
+    /// A token standing in for whatever is running, for errors raised
+    /// without one of their own.
+    fn current_token(&self) -> Token {
+        Token::new(TokenValue::Eof, "", self.line.unwrap_or(0))
+    }
+
+    fn update(&mut self, operator: &Token, target: &Expr, prefix: bool) -> Result<Value> {
+        let delta = match operator.value {
+            TokenValue::PlusPlus => 1,
+            _ => -1,
+        };
+        let (old, new) = self.modify(target, |_, value| {
+            value.step(delta).map_err(|err| at_token(err, operator))
+        })?;
+
+        Ok(if prefix { new } else { old })
+    }
+
+    /// Replaces the value of `target`, a variable, property or index
+    /// expression, with what `change` makes of it, returning the old and new
+    /// values. The target's object and index are evaluated once, before
+    /// `change` runs.
+    fn modify(
+        &mut self,
+        target: &Expr,
+        change: impl FnOnce(&mut Self, Value) -> Result<Value>,
+    ) -> Result<(Value, Value)> {
+        match target {
+            Expr::Variable { name, depth } => {
+                let old = self.look_up_variable(name, depth)?;
+                let new = change(self, old.clone())?;
+                self.assign_variable(name, depth, new.clone())?;
+                Ok((old, new))
+            }
+            Expr::Get { name, object } => {
+                let Value::Instance(instance) = self.evaluate(object)? else {
+                    return Err(RuntimeError::InterpretError(
+                        Message::new(ErrorCode::UndefinedProperty, "Only instances have fields"),
+                        name.clone(),
+                    ));
+                };
+                let old = self.get_property(Value::Instance(instance.clone()), name)?;
+                let new = change(self, old.clone())?;
+                instance.borrow_mut().set(name, new.clone());
+                Ok((old, new))
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                let old = object
+                    .get_index(&index)
+                    .map_err(|err| at_token(err, bracket))?;
+                let new = change(self, old.clone())?;
+                object
+                    .set_index(&index, new.clone())
+                    .map_err(|err| at_token(err, bracket))?;
+                Ok((old, new))
+            }
+            _ => unreachable!("the parser only allows assignable targets"),
+        }
+    }
+
+    /// Assigns to a variable in the scope the resolver bound it to, falling
+    /// back to the globals like [`Self::look_up_variable`].
+    fn assign_variable(
+        &mut self,
+        name: &Token,
+        depth: &Cell<Option<usize>>,
+        value: Value,
+    ) -> Result<()> {
+        match depth.get() {
+            Some(distance) => Environment::assign_at(&self.environment, distance, name, value),
+            None => self.globals.borrow_mut().assign(name, value)?,
+        }
+        Ok(())
+    }
+
+    /// Reads a variable from the scope the resolver bound it to, falling back
+    /// to the globals for names it didn't find in any local scope.
+    fn look_up_variable(&self, name: &Token, depth: &Cell<Option<usize>>) -> Result<Value> {
+        match depth.get() {
+            Some(distance) => Environment::get_at(&self.environment, distance, name.symbol())
+                .ok_or_else(|| {
+                    RuntimeError::InterpretError(
+                        Message::new(
+                            ErrorCode::UndefinedVariable,
+                            format!("Undefined variable `{}`", name.lexeme),
+                        ),
+                        name.clone(),
+                    )
+                }),
+            None => self.globals.borrow().get(name),
+        }
+    }
+}
+
+/// An address in the caller's stack frame, to measure how far the stack has
+/// grown between two calls.
+#[inline(never)]
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+/// Gives an error without a location, such as one from a native function or
+/// a [`Value`] helper, the location of `token`.
+fn at_token(err: RuntimeError, token: &Token) -> RuntimeError {
+    match err {
+        RuntimeError::GeneralError(message) => RuntimeError::InterpretError(message, token.clone()),
+        err => err,
+    }
+}
+
+/// The `==` a match compares its value to a literal pattern with, located
+/// at the match's `keyword`.
+pub(crate) fn equals_token(keyword: &Token) -> Token {
+    Token {
+        value: TokenValue::EqualEqual,
+        lexeme: "==".into(),
+        ..keyword.clone()
+    }
+}
+
+/// The methods that overload a binary operator: the one called on the left
+/// operand, and the reflected one called on the right operand.
+fn operator_methods(operator: &TokenValue) -> Option<(&'static str, &'static str)> {
+    match operator {
+        TokenValue::Plus => Some(("__add__", "__radd__")),
+        TokenValue::Minus => Some(("__sub__", "__rsub__")),
+        TokenValue::Star => Some(("__mul__", "__rmul__")),
+        TokenValue::Slash => Some(("__div__", "__rdiv__")),
+        TokenValue::Percent => Some(("__mod__", "__rmod__")),
+        TokenValue::StarStar => Some(("__pow__", "__rpow__")),
+        TokenValue::Less => Some(("__lt__", "__gt__")),
+        TokenValue::LessEqual => Some(("__le__", "__ge__")),
+        TokenValue::Greater => Some(("__gt__", "__lt__")),
+        TokenValue::GreaterEqual => Some(("__ge__", "__le__")),
+        TokenValue::EqualEqual | TokenValue::BangEqual => Some(("__eq__", "__eq__")),
+        _ => None,
+    }
+}
+
+/// Looks up `name` on `value`, if it's an instance whose class defines it.
+fn special_method(value: &Value, name: &str) -> Option<Rc<dyn LoxCallable>> {
+    match value {
+        Value::Instance(instance) => LoxInstance::special_method(instance, name),
+        _ => None,
+    }
+}
+
+fn binary(left: Value, operator: &Token, right: Value) -> Result<Value> {
+    // An integer mixed with any other number is converted to one first.
+    let (left, right) = match (left, right) {
+        (Value::Int(l), Value::Int(r)) => return integer_binary(l, operator, r),
+        (Value::Int(l), right @ Value::Number(_)) => (Value::Number(l as f64), right),
+        (left @ Value::Number(_), Value::Int(r)) => (left, Value::Number(r as f64)),
+        operands => operands,
+    };
+
+    match (&operator.value, left, right) {
+        (TokenValue::EqualEqual, left, right) => Ok(Value::Bool(left == right)),
+        (TokenValue::BangEqual, left, right) => Ok(Value::Bool(left != right)),
+        (TokenValue::Plus, Value::Number(l), Value::Number(r)) => Ok(Value::Number(l + r)),
+        (TokenValue::Plus, Value::String(l), Value::String(r)) => Ok(Value::String(l + &r)),
+        (TokenValue::Plus, _, _) => Err(RuntimeError::InterpretError(
+            Message::new(
+                ErrorCode::OperandTypes,
+                "Operands must be two numbers or two strings",
+            ),
+            operator.clone(),
+        )),
+        (TokenValue::Minus, Value::Number(l), Value::Number(r)) => Ok(Value::Number(l - r)),
+        (TokenValue::Star, Value::Number(l), Value::Number(r)) => Ok(Value::Number(l * r)),
+        (TokenValue::Slash, Value::Number(l), Value::Number(r)) => Ok(Value::Number(l / r)),
+        (TokenValue::Percent, Value::Number(l), Value::Number(r)) => Ok(Value::Number(l % r)),
+        (TokenValue::StarStar, Value::Number(l), Value::Number(r)) => Ok(Value::Number(l.powf(r))),
+        (TokenValue::Greater, Value::Number(l), Value::Number(r)) => Ok(Value::Bool(l > r)),
+        (TokenValue::GreaterEqual, Value::Number(l), Value::Number(r)) => Ok(Value::Bool(l >= r)),
+        (TokenValue::Less, Value::Number(l), Value::Number(r)) => Ok(Value::Bool(l < r)),
+        (TokenValue::LessEqual, Value::Number(l), Value::Number(r)) => Ok(Value::Bool(l <= r)),
+        // Strings are ordered by comparing their characters in turn.
+        (TokenValue::Greater, Value::String(l), Value::String(r)) => Ok(Value::Bool(l > r)),
+        (TokenValue::GreaterEqual, Value::String(l), Value::String(r)) => Ok(Value::Bool(l >= r)),
+        (TokenValue::Less, Value::String(l), Value::String(r)) => Ok(Value::Bool(l < r)),
+        (TokenValue::LessEqual, Value::String(l), Value::String(r)) => Ok(Value::Bool(l <= r)),
+        (
+            TokenValue::Greater
+            | TokenValue::GreaterEqual
+            | TokenValue::Less
+            | TokenValue::LessEqual,
+            _,
+            _,
+        ) => Err(RuntimeError::InterpretError(
+            Message::new(
+                ErrorCode::OperandTypes,
+                "Operands must be two numbers or two strings",
+            ),
+            operator.clone(),
+        )),
+        _ => Err(RuntimeError::InterpretError(
+            Message::new(ErrorCode::OperandTypes, "Operands must be numbers"),
+            operator.clone(),
+        )),
+    }
+}
+
+/// Applies `operator` to two integers. Arithmetic gives an integer, or an
+/// error if it overflows, except that `**` with a negative exponent gives a
+/// floating-point number. `/` and `%` truncate towards zero.
+fn integer_binary(l: i64, operator: &Token, r: i64) -> Result<Value> {
+    let error = |message: &str| {
+        let message = Message::new(ErrorCode::IntegerArithmetic, message);
+        RuntimeError::InterpretError(message, operator.clone())
+    };
+    let checked = |result: Option<i64>| {
+        result
+            .map(Value::Int)
+            .ok_or_else(|| error("Integer overflow"))
+    };
+
+    match operator.value {
+        TokenValue::EqualEqual => Ok(Value::Bool(l == r)),
+        TokenValue::BangEqual => Ok(Value::Bool(l != r)),
+        TokenValue::Greater => Ok(Value::Bool(l > r)),
+        TokenValue::GreaterEqual => Ok(Value::Bool(l >= r)),
+        TokenValue::Less => Ok(Value::Bool(l < r)),
+        TokenValue::LessEqual => Ok(Value::Bool(l <= r)),
+        TokenValue::Plus => checked(l.checked_add(r)),
+        TokenValue::Minus => checked(l.checked_sub(r)),
+        TokenValue::Star => checked(l.checked_mul(r)),
+        TokenValue::Slash | TokenValue::Percent if r == 0 => Err(error("Division by zero")),
+        TokenValue::Slash => checked(l.checked_div(r)),
+        TokenValue::Percent => checked(l.checked_rem(r)),
+        TokenValue::StarStar if r < 0 => Ok(Value::Number((l as f64).powf(r as f64))),
+        TokenValue::StarStar => checked(u32::try_from(r).ok().and_then(|r| l.checked_pow(r))),
+        _ => binary(Value::Number(l as f64), operator, Value::Number(r as f64)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{
+        Backend, Capture, Hook, Interpreter, Lox, LoxOptions, Result, RuntimeError, Stmt, Value,
+    };
+
+    const VECTOR: &str = r#"
+        class Vector {
+            init(x, y) { this.x = x; this.y = y; }
+            __add__(other) { return Vector(this.x + other.x, this.y + other.y); }
+            __mul__(k) { return Vector(this.x * k, this.y * k); }
+            __rmul__(k) { return this * k; }
+            __eq__(other) { return type(other) == "instance" and this.x == other.x; }
+            __str__() { return "<" + str(this.x) + " " + str(this.y) + ">"; }
+        }
+        var v = Vector(1, 2);
+    "#;
+
+    fn eval(lox: &mut Lox, source: &str) -> String {
+        match lox.eval(source) {
+            Ok(value) => value.to_string(),
+            Err(err) => panic!("`{source}` failed: {err}"),
+        }
+    }
+
+    #[test]
+    fn expressions_follow_the_books_semantics() {
+        let mut lox = Lox::new();
+        let cases = [
+            ("1 + 2 * 3 - 4 / 2", "5"),
+            ("-(1 + 2) == -3", "true"),
+            ("1 < 2 and 2 <= 2 and !(3 > 4) and 4 >= 4", "true"),
+            ("\"con\" + \"cat\"", "concat"),
+            ("nil or \"default\"", "default"),
+            ("false and crash()", "false"),
+            ("!nil and !!0 and !!\"\"", "true"),
+            ("1 == \"1\"", "false"),
+            ("nil == nil", "true"),
+            ("0.1 + 0.2", "0.30000000000000004"),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(eval(&mut lox, source), expected, "`{source}`");
+        }
+    }
+
+    #[test]
+    fn closures_and_classes_keep_their_state() {
+        let mut lox = Lox::new();
+        eval(
+            &mut lox,
+            "fun counter() { var n = 0; fun next() { n = n + 1; return n; } return next; }\n\
+             var c = counter(); c();\n\
+             class Animal { init(name) { this.name = name; } speak() { return this.name + \" makes a sound\"; } }\n\
+             class Dog < Animal { speak() { return super.speak() + \", woof\"; } }",
+        );
+
+        assert_eq!(eval(&mut lox, "c();"), "2");
+        assert_eq!(
+            eval(&mut lox, "Dog(\"Rex\").speak();"),
+            "Rex makes a sound, woof"
+        );
+        assert_eq!(eval(&mut lox, "Dog(\"Rex\");"), "Dog instance");
+    }
+
+    #[test]
+    fn operators_dispatch_to_methods_on_either_operand() {
+        let mut lox = Lox::new();
+        eval(&mut lox, VECTOR);
+
+        assert_eq!(eval(&mut lox, "str(v + Vector(3, 4));"), "<4 6>");
+        assert_eq!(eval(&mut lox, "str(v * 2);"), "<2 4>");
+        assert_eq!(eval(&mut lox, "str(3 * v);"), "<3 6>");
+        assert_eq!(
+            eval(&mut lox, "str(v == nil) + str(nil != v);"),
+            "falsetrue"
+        );
+        assert!(lox.eval("v + 1;").is_err());
+        assert!(lox.eval("v - v;").is_err());
+    }
+
+    #[test]
+    fn compound_assignment_evaluates_its_object_once() {
+        let mut lox = Lox::new();
+        eval(
+            &mut lox,
+            "class Box {} var box = Box(); box.n = 1; var calls = 0;\n\
+             fun get() { calls = calls + 1; return box; }",
+        );
+
+        assert_eq!(eval(&mut lox, "get().n += 2;"), "3");
+        assert_eq!(eval(&mut lox, "box.n;"), "3");
+        assert_eq!(eval(&mut lox, "calls;"), "1");
+    }
+
+    #[test]
+    fn runtime_errors_show_the_failing_line() {
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            let err = Lox::new()
+                .with_backend(backend)
+                .run_source("var a = 1;\nprint a + \"b\";")
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "line 2:9 | Error[E0002]: Operands must be two numbers or two strings\n \
+                 2 | print a + \"b\";\n   |         ^",
+                "on {backend:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn hooks_see_statements_calls_returns_and_errors() {
+        #[derive(Clone, Default)]
+        struct Recorder(Rc<RefCell<Vec<String>>>);
+
+        impl Hook for Recorder {
+            fn on_statement(&mut self, interpreter: &Interpreter, stmt: &Stmt) -> Result<()> {
+                let line = stmt.span().start;
+                let depth = interpreter.call_depth();
+                self.0
+                    .borrow_mut()
+                    .push(format!("statement {line} at {depth}"));
+                Ok(())
+            }
+
+            fn on_call(&mut self, name: &str) {
+                self.0.borrow_mut().push(format!("call {name}"));
+            }
+
+            fn on_return(&mut self, value: Option<&Value>) {
+                let value = value.map_or("failed".into(), Value::to_string);
+                self.0.borrow_mut().push(format!("return {value}"));
+            }
+
+            fn on_error(&mut self, error: &RuntimeError) {
+                let message = error.to_string();
+                let message = message.lines().next().unwrap_or_default();
+                self.0.borrow_mut().push(format!("error {message}"));
+            }
+        }
+
+        let recorder = Recorder::default();
+        let mut lox = Lox::new()
+            .with_output(Capture::default())
+            .with_hook(recorder.clone());
+        let result = lox.run_source("fun f(n) { return n + 1; }\nf(1);\nf(nil);");
+
+        assert!(result.is_err());
+        assert_eq!(
+            *recorder.0.borrow(),
+            [
+                "statement 0 at 0",
+                "statement 27 at 0",
+                "call f",
+                "statement 11 at 1",
+                "return 2",
+                "statement 33 at 0",
+                "call f",
+                "statement 11 at 1",
+                "return failed",
+                "error line 1 | Error[E0002]: Operands must be two numbers or two strings",
+            ]
+        );
+    }
+
+    #[test]
+    fn filesystem_natives_can_be_disabled() {
+        let options = LoxOptions::default().with_filesystem(false);
+        let mut lox = Lox::new().with_options(options);
+
+        let err = lox.eval(r#"readFile("Cargo.toml");"#).unwrap_err();
+        assert!(err.to_string().contains("filesystem access isn't allowed"));
+    }
+
+    #[test]
+    fn coroutine_natives_need_the_vm() {
+        let mut lox = Lox::new();
+        for native in ["spawn(clock)", "yieldTo()", "recv(chanNew())"] {
+            let err = lox.eval(native).unwrap_err();
+            assert!(
+                err.to_string().contains("is not supported on this backend"),
+                "{native}: {err}"
+            );
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! The iterator protocol `for (item in iterable)` loops are built on.
+//!
+//! An iterator is an instance with a `hasNext()` method, saying whether
+//! there's another value, and a `next()` method returning it. A loop over a
+//! list, tuple, string or map gets a built-in `Iterator` over its elements,
+//! characters or keys. A loop over an instance with an `iterator()` method
+//! uses what that returns, and one over any other instance takes it to be
+//! an iterator itself, so user classes can take part either way.
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{
+    class::{LoxClass, LoxInstance, Method, NativeMethod},
+    error::{Result, RuntimeError},
+    gc,
+    interpreter::Interpreter,
+    symbol::Symbol,
+    value::Value,
+};
+
+/// The class of the iterators built-in collections are looped over with.
+/// One holds the list of values it produces and the index of the next.
+pub(crate) fn iterator_class() -> Rc<LoxClass> {
+    let mut methods: HashMap<Symbol, Rc<dyn Method>> = HashMap::new();
+    methods.insert(
+        Symbol::intern("hasNext"),
+        Rc::new(NativeMethod::new("hasNext", 0, |_, this, _| {
+            let (values, index) = state(this);
+            let more = index < values.borrow().len();
+            Ok(Value::Bool(more))
+        })),
+    );
+    methods.insert(
+        Symbol::intern("next"),
+        Rc::new(NativeMethod::new("next", 0, |_, this, _| {
+            let (values, index) = state(this);
+            let value = values.borrow().get(index).cloned().ok_or_else(|| {
+                RuntimeError::GeneralError("The iterator has no more values".into())
+            })?;
+            let index = Value::Number((index + 1) as f64);
+            this.borrow_mut().set_field(Symbol::intern("index"), index);
+            Ok(value)
+        })),
+    );
+
+    gc::alloc(LoxClass::new(
+        Symbol::intern("Iterator"),
+        None,
+        methods,
+        HashMap::new(),
+    ))
+}
+
+/// The list a built-in iterator goes through and the index of its next value.
+fn state(this: &Rc<RefCell<LoxInstance>>) -> (Rc<RefCell<Vec<Value>>>, usize) {
+    let iterator = this.borrow();
+    let Some(Value::List(values)) = iterator.field(Symbol::intern("values")) else {
+        unreachable!("a built-in iterator always has its values");
+    };
+    let index = match iterator.field(Symbol::intern("index")) {
+        Some(Value::Number(index)) => index as usize,
+        _ => 0,
+    };
+    (values, index)
+}
+
+/// The iterator a for-in loop with `names` variables goes through
+/// `iterable` with. A list is iterated as it is when each value is taken,
+/// so values pushed during the loop are reached; the other collections are
+/// copied first. A map produces its keys, or `(key, value)` tuples for a
+/// loop with two variables.
+pub(crate) fn iterator(
+    interpreter: &mut Interpreter,
+    iterable: Value,
+    names: usize,
+) -> Result<Value> {
+    let values = match iterable {
+        Value::List(list) => list,
+        Value::Tuple(elements) => gc::alloc(RefCell::new(elements.to_vec())),
+        Value::String(s) => gc::alloc(RefCell::new(
+            s.chars().map(|c| Value::String(c.to_string())).collect(),
+        )),
+        Value::Map(map) => gc::alloc(RefCell::new(
+            map.borrow()
+                .entries()
+                .map(|(key, value)| match names {
+                    2 => Value::tuple(vec![key.to_value(), value.clone()]),
+                    _ => key.to_value(),
+                })
+                .collect(),
+        )),
+        Value::Instance(instance) => {
+            return match LoxInstance::special_method(&instance, "iterator") {
+                Some(method) => method.call(interpreter, vec![]),
+                None => Ok(Value::Instance(instance)),
+            };
+        }
+        value => {
+            return Err(RuntimeError::GeneralError(
+                format!("Can't iterate over a {}", value.type_name()).into(),
+            ))
+        }
+    };
+
+    let mut iterator = LoxInstance::new(interpreter.iterator_class());
+    iterator.set_field(Symbol::intern("values"), Value::List(values));
+    iterator.set_field(Symbol::intern("index"), Value::Number(0.0));
+    Ok(Value::Instance(gc::alloc(RefCell::new(iterator))))
+}
@@ -0,0 +1,36 @@
+use crate::value::Value;
+
+/// Encodes `value` as JSON. Numbers, strings, booleans and `nil` map onto
+/// their JSON counterparts; anything else, and numbers JSON can't represent
+/// such as `NaN`, is written as the string Lox would print for it.
+pub(crate) fn value(value: &Value) -> String {
+    match value {
+        Value::Nil => "null".into(),
+        Value::Bool(b) => b.to_string(),
+        Value::Int(n) => n.to_string(),
+        Value::Number(n) if n.is_finite() => n.to_string(),
+        Value::String(s) => string(s),
+        value => string(&value.to_string()),
+    }
+}
+
+/// Encodes `s` as a quoted JSON string.
+pub(crate) fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
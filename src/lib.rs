@@ -0,0 +1,82 @@
+mod ast_printer;
+mod callable;
+mod chunk;
+mod class;
+mod compiler;
+mod coroutine;
+mod debugger;
+mod environment;
+mod error;
+mod explain;
+mod fmt;
+mod gc;
+mod import;
+mod incremental;
+mod interpreter;
+mod iterator;
+mod json;
+mod lox;
+mod map;
+mod memory;
+mod options;
+mod output;
+mod parser;
+mod profiler;
+mod repl;
+mod resolver;
+mod scanner;
+mod span;
+mod stdlib;
+mod symbol;
+mod symbols;
+mod teach;
+mod test_runner;
+mod text;
+mod token;
+#[cfg(feature = "tracing")]
+mod tracer;
+mod value;
+pub mod visit;
+mod vm;
+
+pub use ast_printer::{expr_sexpr, print_program, stmt_sexpr, AstFormat};
+pub use callable::{LoxCallable, NativeFunction};
+pub use error::{
+    Diagnostic, Diagnostics, ErrorCode, Message, Note, Result, RuntimeError, StackFrame,
+};
+pub use fmt::{format_source, format_source_with, LineEnding};
+pub use incremental::Document;
+pub use interpreter::{Hook, Interpreter};
+pub use lox::{install_panic_hook, Backend, FormatMode, Lox, TokenFormat};
+pub use options::LoxOptions;
+pub use output::{Capture, Output, Stdout, Writer};
+pub use parser::{
+    Catch, Expr, FunctionDecl, Literal, MatchArm, ParseOutput, Parser, Pattern, Stmt,
+};
+pub use resolver::Resolver;
+pub use scanner::{LoxDialect, ScanLimits, ScanOutput, Scanner};
+pub use span::Span;
+pub use symbol::Symbol;
+pub use token::{OwnedToken, Token, TokenValue};
+#[cfg(feature = "tracing")]
+pub use tracer::Tracer;
+pub use value::Value;
+
+/// The long explanation of the error with `code`, such as `E0001`, as
+/// `rlox --explain` shows it, or `None` if there's no such code.
+pub fn explain(code: &str) -> Option<&'static str> {
+    explain::find(code).map(|explanation| explanation.text)
+}
+
+/// Scans `source` into tokens without constructing a [`Lox`] instance.
+///
+/// This is meant for syntax highlighters and other external tools that only
+/// need the lexical structure of a program.
+pub fn tokenize(source: &str) -> core::result::Result<Vec<OwnedToken>, Vec<Diagnostic>> {
+    let output = Scanner::new(source).scan_tokens();
+
+    match output.errors.is_empty() {
+        true => Ok(output.tokens.into_iter().map(OwnedToken::from).collect()),
+        false => Err(output.errors.into_iter().map(Diagnostic::from).collect()),
+    }
+}
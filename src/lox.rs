@@ -1,23 +1,1338 @@
-use crate::{error::Result, scanner::Scanner};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet, VecDeque},
+    io::{BufRead, Write},
+    ops::ControlFlow,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
 
-pub struct Lox;
+use crate::{
+    ast_printer::{self, AstFormat},
+    compiler::Compiler,
+    debugger::Debugger,
+    environment::Environment,
+    error::{Diagnostics, Result, RuntimeError},
+    explain::LastError,
+    fmt::{self, LineEnding},
+    gc, import,
+    interpreter::{Hook, Interpreter},
+    json,
+    memory::MemoryStats,
+    options::LoxOptions,
+    output::{Capture, Output},
+    parser::{Expr, Parser, Stmt},
+    profiler::Profiler,
+    repl::{LineReader, ReadLine},
+    resolver::Resolver,
+    scanner::{error_token, LoxDialect, ScanLimits, Scanner, ScannerOptions},
+    symbol::Symbol,
+    symbols, teach, test_runner,
+    token::{Token, TokenValue},
+    value::Value,
+    visit::{self, Visitor},
+    vm::Vm,
+};
+
+thread_local! {
+    /// Where the last panic happened in rlox's own code, recorded by the hook
+    /// installed with [`install_panic_hook`].
+    static PANIC_LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+
+    /// How many calls to [`Lox::catch_internal_error`] are running on this
+    /// thread, so the hook knows whether a panic will be caught and reported.
+    static CATCHING: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Replaces Rust's default panic output, so an internal bug is reported as an
+/// rlox diagnostic (see [`RuntimeError::InternalError`]) instead of a raw
+/// backtrace. A panic that nothing in rlox will catch, such as one on another
+/// thread, still goes to the hook that was installed before. Embedders that
+/// want to keep their own hook can skip this; panics are still caught and
+/// converted either way.
+pub fn install_panic_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if CATCHING.with(Cell::get) == 0 {
+            return previous(info);
+        }
+        let location = info.location().map(|location| location.to_string());
+        PANIC_LOCATION.with(|cell| cell.replace(location));
+    }));
+}
+
+/// Which engine runs programs given to [`Lox::run_file`]. The tree-walking
+/// interpreter is the reference implementation; the bytecode VM is faster but
+/// doesn't support classes yet. The REPL and [`Lox::eval`] always use the
+/// interpreter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    TreeWalk,
+    Vm,
+}
+
+/// How [`Lox::print_tokens`] writes each token, and [`Lox::print_symbols`]
+/// each declaration. Either way there is one per line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TokenFormat {
+    /// `line:column kind lexeme`, with the lexeme quoted as a JSON string so
+    /// a multi-line string token still fits on one line.
+    #[default]
+    Text,
+    /// A JSON object with `kind`, `lexeme`, `line` and `column` fields.
+    Json,
+}
+
+/// What [`Lox::format_file`] does with the formatted source.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FormatMode {
+    /// Print it to stdout.
+    #[default]
+    Print,
+    /// Write it back to the file, if it differs.
+    Write,
+    /// Print a unified diff from the file to the formatted source.
+    Diff,
+}
+
+/// How often [`Lox::watch_file`] looks for changed scripts.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The REPL's `:help` text.
+const REPL_HELP: &str = "\
+:help           Show this message
+:env            List the globals defined so far
+:why            Explain the last error in more detail
+:ast <source>   Show the syntax tree of some source without running it
+:load <file>    Run a script in this session
+:reset          Forget everything defined so far
+:quit           Leave the REPL, as does Ctrl-D
+
+On a terminal, Up and Down recall earlier lines, which are kept in
+~/.rlox_history, and Ctrl-R searches them.";
+
+pub struct Lox {
+    interpreter: Interpreter,
+    backend: Backend,
+    scanner: ScannerOptions,
+    /// How many values the REPL has echoed, used to name `_1`, `_2`, ...
+    results: usize,
+    last_error: Option<LastError>,
+}
 
 impl Lox {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            interpreter: Interpreter::new(),
+            backend: Backend::default(),
+            scanner: ScannerOptions::default(),
+            results: 0,
+            last_error: None,
+        }
+    }
+
+    /// Rejects source that exceeds `limits` before it is parsed.
+    pub fn with_scan_limits(mut self, limits: ScanLimits) -> Self {
+        self.scanner.limits = limits;
+        self.interpreter.imports().scanner = self.scanner;
+        self
+    }
+
+    /// Sends what scripts print to `output` instead of stdout.
+    pub fn with_output(mut self, output: impl Output + 'static) -> Self {
+        self.interpreter = self.interpreter.with_output(output);
+        self
+    }
+
+    /// Reads scripts as `dialect` instead of the default, extended Lox.
+    pub fn with_dialect(mut self, dialect: LoxDialect) -> Self {
+        self.scanner.dialect = dialect;
+        self.interpreter.imports().scanner = self.scanner;
+        self
+    }
+
+    /// Stops programs that go past `options`; see [`LoxOptions`].
+    pub fn with_options(mut self, options: LoxOptions) -> Self {
+        self.interpreter = self.interpreter.with_options(options);
+        self
+    }
+
+    /// Has `hook` watch the scripts either backend runs; see [`Hook`].
+    pub fn with_hook(mut self, hook: impl Hook + 'static) -> Self {
+        self.interpreter.set_hook(Some(Box::new(hook)));
+        self
+    }
+
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Runs the cycle collector before every allocation instead of only
+    /// once enough objects have built up. This is very slow; it is meant for
+    /// flushing out objects that are freed while still in use.
+    pub fn with_gc_stress(self, stress: bool) -> Self {
+        gc::set_stress(stress);
+        self
+    }
+
+    /// A summary of the memory in use: the objects the cycle collector
+    /// tracks that are still live, the strings interned, the environments
+    /// created and the process's peak memory, the same as the script would
+    /// get from `memoryStats()`.
+    pub fn memory_report(&self) -> String {
+        MemoryStats::now().to_string()
+    }
+
+    /// Runs the script at `file_path`. Scripts it imports are found relative
+    /// to it.
+    pub fn run_file(&mut self, file_path: &str) -> Result<()> {
+        let source = std::fs::read_to_string(file_path)?;
+        let path = std::path::Path::new(file_path).canonicalize()?;
+
+        self.interpreter.imports().enter(path);
+        let result = self.run_source(&source);
+        self.interpreter.imports().exit();
+
+        result
+    }
+
+    /// Runs the script at `file_path`, then watches it and the scripts it
+    /// imports for changes until the process is stopped. A changed import
+    /// runs again on its own with [`Lox::reload_module`], keeping the rest of
+    /// the program's globals, while a change to the script itself starts it
+    /// over in a new session with the same backend, dialect and options.
+    /// Errors are reported on stderr and the watching carries on.
+    pub fn watch_file(&mut self, file_path: &str) -> Result<()> {
+        let main = Path::new(file_path).canonicalize()?;
+
+        loop {
+            if let Err(err) = self.run_file(file_path) {
+                eprintln!("{err}");
+            }
+
+            let mut modified = self.modification_times(&main);
+            loop {
+                std::thread::sleep(WATCH_INTERVAL);
+                let changed: Vec<PathBuf> = self
+                    .modification_times(&main)
+                    .into_iter()
+                    .filter(|(path, time)| modified.get(path) != Some(time))
+                    .map(|(path, _)| path)
+                    .collect();
+
+                if changed.contains(&main) {
+                    eprintln!("[{file_path} changed, restarting]");
+                    *self = self.fresh();
+                    break;
+                }
+                for path in changed {
+                    eprintln!("[{} changed, reloading it]", path.display());
+                    if let Err(err) = self.reload_module(&path) {
+                        eprintln!("{err}");
+                    }
+                }
+                modified = self.modification_times(&main);
+            }
+        }
+    }
+
+    /// Runs the script at `path`, which the program has imported, again in
+    /// this session. The globals it declares are replaced and the others
+    /// are left as they are, so functions and classes from it can be changed
+    /// without losing the state the rest of the program has built up.
+    pub fn reload_module(&mut self, path: &Path) -> Result<()> {
+        let path = path.canonicalize()?;
+        let backend = self.backend;
+        self.catch_internal_error(|lox| import::reload(&mut lox.interpreter, &path, backend))
+    }
+
+    /// When `main` and each script the program has imported were last
+    /// changed, for [`Lox::watch_file`].
+    fn modification_times(&mut self, main: &Path) -> HashMap<PathBuf, SystemTime> {
+        let imports = self.interpreter.imports();
+        std::iter::once(main)
+            .chain(imports.loaded().map(PathBuf::as_path))
+            .filter_map(|path| {
+                let modified = std::fs::metadata(path).and_then(|metadata| metadata.modified());
+                Some((path.to_path_buf(), modified.ok()?))
+            })
+            .collect()
+    }
+
+    /// A new session with the same backend, scanner settings and options.
+    fn fresh(&self) -> Lox {
+        Lox::new()
+            .with_backend(self.backend)
+            .with_scan_limits(self.scanner.limits)
+            .with_dialect(self.scanner.dialect)
+            .with_options(self.interpreter.options())
+    }
+
+    /// Runs the script at `file_path` under the debugger, which starts
+    /// paused before the first statement and reads its commands from stdin.
+    /// Only the tree-walking backend can be debugged.
+    pub fn debug_file(&mut self, file_path: &str) -> Result<()> {
+        let source = std::fs::read_to_string(file_path)?;
+        let path = std::path::Path::new(file_path).canonicalize()?;
+        let input = Box::new(std::io::stdin().lock());
+        let debugger = Debugger::new(&source, input, Box::new(std::io::stdout()));
+
+        self.interpreter.imports().enter(path);
+        let result = self.debug_source(&source, debugger);
+        self.interpreter.imports().exit();
+
+        result
+    }
+
+    pub(crate) fn debug_source(&mut self, source: &str, debugger: Debugger) -> Result<()> {
+        if self.backend == Backend::Vm {
+            return Err(RuntimeError::GeneralError(
+                "The debugger only works on the tree-walking backend".into(),
+            ));
+        }
+
+        let previous = self.interpreter.set_hook(Some(Box::new(debugger)));
+        let result = self.run_source(source);
+        self.interpreter.set_hook(previous);
+
+        result
+    }
+
+    /// Runs the script at `file_path` with the profiler, then reports the
+    /// time spent in each function and the busiest lines on stderr. With
+    /// `folded`, the stacks are also written to that file for flame graph
+    /// tools. Only the tree-walking backend can be profiled.
+    pub fn profile_file(&mut self, file_path: &str, folded: Option<&str>) -> Result<()> {
+        if self.backend == Backend::Vm {
+            return Err(RuntimeError::GeneralError(
+                "The profiler only works on the tree-walking backend".into(),
+            ));
+        }
+
+        let source = std::fs::read_to_string(file_path)?;
+        let path = std::path::Path::new(file_path).canonicalize()?;
+        let profiler = Profiler::new(&source);
+
+        let previous = self.interpreter.set_hook(Some(Box::new(profiler.clone())));
+        self.interpreter.imports().enter(path);
+        let result = self.run_source(&source);
+        self.interpreter.imports().exit();
+        self.interpreter.set_hook(previous);
+
+        let (report, stacks) = profiler.finish();
+        eprint!("{report}");
+        if let Some(folded) = folded {
+            std::fs::write(folded, stacks)?;
+        }
+
+        result
+    }
+
+    /// Reads a whole script from stdin and runs it, for `rlox -`.
+    pub fn run_stdin(&mut self) -> Result<()> {
+        let source = std::io::read_to_string(std::io::stdin())?;
+        self.run_source(&source)
+    }
+
+    /// Runs `source` as a script in this instance's global scope. Unlike
+    /// [`Lox::eval`], every statement needs its `;` and nothing is returned.
+    pub fn run_source(&mut self, source: &str) -> Result<()> {
+        self.catch_internal_error(|lox| lox.run_program(source))
+            .map_err(|err| err.in_source(source))
+    }
+
+    /// Checks the script at `file_path` for errors without running it. This
+    /// finds everything [`Lox::run_file`] would report before the script
+    /// starts.
+    pub fn check_file(&mut self, file_path: &str) -> Result<()> {
+        let source = std::fs::read_to_string(file_path)?;
+
+        self.catch_internal_error(|lox| lox.parse_program(&source, false).map(|_| ()))
+    }
+
+    /// Checks the scripts at `paths`, the `.lox` files in any directories
+    /// among them and every script they import, without running any of
+    /// them. The errors are returned together as [`RuntimeError::Files`],
+    /// sorted by file, line and column, and with the repeats that cascade
+    /// from an error left out.
+    pub fn check_files(&mut self, paths: &[String]) -> Result<()> {
+        let mut pending = VecDeque::new();
+        for path in paths.iter().map(PathBuf::from) {
+            match path.is_dir() {
+                true => {
+                    let mut scripts = vec![];
+                    test_runner::find_scripts(&path, &mut scripts)?;
+                    scripts.sort();
+                    pending.extend(scripts);
+                }
+                false => pending.push_back(path),
+            }
+        }
+
+        let mut checked = HashSet::new();
+        let mut files = vec![];
+        while let Some(path) = pending.pop_front() {
+            if !checked.insert(path.canonicalize()?) {
+                continue;
+            }
+
+            let source = std::fs::read_to_string(&path)?;
+            let file = path.display().to_string();
+            // The imports of a script with errors are still followed, from
+            // the statements that did parse.
+            let (program, mut diagnostics) =
+                match self.catch_internal_error(|lox| lox.parse_program(&source, false)) {
+                    Ok(program) => (program, Diagnostics::new(&source, vec![])),
+                    Err(RuntimeError::Diagnostics(diagnostics)) => {
+                        let tokens = Scanner::new(&source)
+                            .with_options(self.scanner)
+                            .filter_map(|token| token.ok());
+                        let parser = Parser::new(tokens).with_dialect(self.scanner.dialect);
+                        (parser.parse().program, diagnostics)
+                    }
+                    Err(err) => return Err(err),
+                };
+
+            // Imports are found relative to the importing script.
+            for token in imports(&program) {
+                let TokenValue::String(import) = &token.value else {
+                    unreachable!("the parser only accepts a string to import");
+                };
+                let imported = path.parent().unwrap_or(Path::new("")).join(&**import);
+                match imported.canonicalize() {
+                    Ok(_) => pending.push_back(imported),
+                    Err(err) => diagnostics.push(RuntimeError::InterpretError(
+                        format!("Can't import `{import}`: {err}").into(),
+                        token.clone(),
+                    )),
+                }
+            }
+            if !diagnostics.is_empty() {
+                files.push(diagnostics.in_file(&file).tidy());
+            }
+        }
+
+        files.sort_by(|a, b| a.file().cmp(&b.file()));
+        match files.is_empty() {
+            true => Ok(()),
+            false => Err(RuntimeError::Files(files)),
+        }
+    }
+
+    /// Prints the tokens of the script at `file_path` in `format`. Nothing is
+    /// printed if the scanner finds any errors; they are returned instead.
+    pub fn print_tokens(&mut self, file_path: &str, format: TokenFormat) -> Result<()> {
+        let source = std::fs::read_to_string(file_path)?;
+        let output = self.catch_internal_error(|lox| {
+            Ok(Scanner::new(&source)
+                .with_options(lox.scanner)
+                .scan_tokens())
+        })?;
+
+        if !output.errors.is_empty() {
+            return Err(RuntimeError::Diagnostics(Diagnostics::new(
+                &source,
+                output.errors,
+            )));
+        }
+
+        let mut stdout = std::io::stdout().lock();
+        for token in &output.tokens {
+            writeln!(stdout, "{}", token_line(token, format))?;
+        }
+
+        Ok(())
+    }
+
+    /// Prints the top-level classes, methods, functions and globals the
+    /// script at `file_path` declares, with their doc comments, in `format`.
+    /// Nothing is printed if the script doesn't parse; the errors are
+    /// returned instead.
+    pub fn print_symbols(&mut self, file_path: &str, format: TokenFormat) -> Result<()> {
+        let source = std::fs::read_to_string(file_path)?;
+        let declarations = self.catch_internal_error(|lox| {
+            let program = lox.parse_program(&source, false)?;
+            let comments: Vec<Token> = Scanner::new(&source)
+                .with_options(lox.scanner)
+                .with_comments(true)
+                .scan_tokens()
+                .tokens
+                .into_iter()
+                .filter(|token| token.value == TokenValue::Comment)
+                .collect();
+            Ok(symbols::declarations(&source, &program, &comments))
+        })?;
+
+        let mut stdout = std::io::stdout().lock();
+        for declaration in declarations {
+            match format {
+                TokenFormat::Text => writeln!(stdout, "{}", declaration.text())?,
+                TokenFormat::Json => writeln!(stdout, "{}", declaration.json())?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Prints the syntax tree of the script at `file_path` in `format`
+    /// without running it.
+    pub fn print_ast(&mut self, file_path: &str, format: AstFormat) -> Result<()> {
+        let source = std::fs::read_to_string(file_path)?;
+
+        self.catch_internal_error(|lox| {
+            let program = lox.parse_program(&source, false)?;
+            print!("{}", ast_printer::print_program(&program, format));
+            Ok(())
+        })
+    }
+
+    /// Formats the script at `file_path`, ending its lines as `line_ending`
+    /// says, and handles the result as `mode` says, returning whether the
+    /// file was already formatted. Nothing is formatted if the script
+    /// doesn't parse; the errors are returned instead.
+    pub fn format_file(
+        &mut self,
+        file_path: &str,
+        mode: FormatMode,
+        line_ending: LineEnding,
+    ) -> Result<bool> {
+        let source = std::fs::read_to_string(file_path)?;
+        let formatted =
+            self.catch_internal_error(|_| fmt::format_source_with(&source, line_ending))?;
+        let unchanged = formatted == source;
+
+        match mode {
+            FormatMode::Print => print!("{formatted}"),
+            FormatMode::Write if !unchanged => std::fs::write(file_path, &formatted)?,
+            FormatMode::Write => {}
+            FormatMode::Diff => print!("{}", fmt::diff(file_path, &source, &formatted)),
+        }
+
+        Ok(unchanged)
     }
 
-    pub fn run_file(&self, file_path: &str) -> Result<()> {
-        let contents = std::fs::read_to_string(file_path)?;
-        self.run(contents)?;
+    /// Runs the script at `file_path` `iterations` times, each in a fresh
+    /// instance with the same backend and limits, then reports how long the
+    /// runs took and how much work they gave the runtime on stderr, keeping
+    /// it apart from what the script prints.
+    pub fn bench_file(&mut self, file_path: &str, iterations: usize) -> Result<()> {
+        let source = std::fs::read_to_string(file_path)?;
+        let mut times = Vec::with_capacity(iterations);
+        let gc_before = gc::stats();
+        let environments_before = Environment::created();
+
+        for _ in 0..iterations {
+            let mut lox = self.fresh();
+            let start = Instant::now();
+            lox.run_source(&source)?;
+            times.push(start.elapsed());
+        }
+
+        let (Some(min), Some(max)) = (times.iter().min(), times.iter().max()) else {
+            return Ok(());
+        };
+        let average = times.iter().sum::<Duration>() / iterations as u32;
+        let gc = gc::stats();
+        let per_run = |total: usize| total / iterations;
+        let backend = match self.backend {
+            Backend::TreeWalk => "tree",
+            Backend::Vm => "vm",
+        };
+
+        eprintln!("{file_path}: {iterations} runs on the {backend} backend");
+        eprintln!("  time:         min {min:.2?}, avg {average:.2?}, max {max:.2?}");
+        eprintln!(
+            "  allocated:    {} objects per run",
+            per_run(gc.allocated - gc_before.allocated)
+        );
+        eprintln!(
+            "  environments: {} per run",
+            per_run(Environment::created() - environments_before)
+        );
+        eprintln!(
+            "  gc cycles:    {} per run",
+            per_run(gc.collections - gc_before.collections)
+        );
 
         Ok(())
     }
 
-    fn run(&self, source: String) -> Result<()> {
-        let tokens = Scanner::new(source).scan_tokens()?;
-        println!("{tokens:#?}");
+    /// Runs every script under `dir` as a test on this instance's backend
+    /// and dialect, returning whether they all passed. A script gives the
+    /// output and errors it expects in `// expect: ...` comments, as in the
+    /// *Crafting Interpreters* test suite. The scripts are run by the
+    /// executable that is running now, so this is meant for the `rlox` binary.
+    pub fn test_dir(&mut self, dir: &str) -> Result<bool> {
+        let rlox = std::env::current_exe()?;
+        let summary = self.catch_internal_error(|lox| {
+            test_runner::run(&rlox, dir.as_ref(), lox.backend, lox.scanner.dialect)
+        })?;
+
+        Ok(summary.failed == 0)
+    }
+
+    /// Runs the script at `file_path` on the tree-walking interpreter,
+    /// printing its tokens, syntax tree and variable bindings first and then
+    /// announcing each top-level statement as it runs. Meant for following
+    /// along with *Crafting Interpreters* on small programs.
+    pub fn teach_file(&mut self, file_path: &str) -> Result<()> {
+        let source = std::fs::read_to_string(file_path)?;
+
+        self.catch_internal_error(|lox| {
+            let program = lox.parse_program(&source, false)?;
+            teach::walkthrough(&mut lox.interpreter, &source, &program)
+        })
+    }
+
+    /// Runs `source` on the chosen backend in this instance's global scope
+    /// and returns the value of its last statement if that is an expression,
+    /// or `nil` otherwise. The final `;` may be left off, so
+    /// `lox.eval("1 + 2")` gives `3`.
+    pub fn eval(&mut self, source: &str) -> Result<Value> {
+        self.catch_internal_error(|lox| lox.eval_source(source))
+    }
+
+    /// Exposes a Rust function to scripts run by this instance. See
+    /// [`Interpreter::define_native`].
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&mut Interpreter, Vec<Value>) -> Result<Value> + 'static,
+    ) {
+        self.interpreter.define_native(name, arity, function);
+    }
+
+    /// Evaluates a single expression made only of literals and operators.
+    /// Variables, calls, property access and assignment are rejected before
+    /// anything runs, so the snippet cannot observe or affect any state. This
+    /// makes it safe for evaluating user-supplied formulas.
+    pub fn eval_const(&self, source: &str) -> Result<Value> {
+        let output = Scanner::new(source)
+            .with_options(self.scanner)
+            .scan_tokens();
+
+        if let Some(err) = output.errors.into_iter().next() {
+            return Err(err);
+        }
+
+        let expr = Parser::new(output.tokens)
+            .with_dialect(self.scanner.dialect)
+            .parse_expression()?;
+        check_const(&expr)?;
+
+        Interpreter::new().evaluate(&expr)
+    }
+
+    /// Starts an interactive session on stdin. State persists between lines,
+    /// and a line holding a single expression has its value echoed back. Each
+    /// echoed value is also bound to `_` and to `_1`, `_2`, ... in order, so
+    /// later lines can refer back to earlier results.
+    pub fn run_prompt(&mut self) -> Result<()> {
+        let mut reader = LineReader::new();
+        let mut input = String::new();
+
+        loop {
+            let prompt = match input.is_empty() {
+                true => "> ",
+                false => ".. ",
+            };
+
+            let line = match reader.read_line(prompt)? {
+                ReadLine::Line(line) => line,
+                ReadLine::Interrupted => {
+                    input.clear();
+                    continue;
+                }
+                ReadLine::Eof => {
+                    if !input.is_empty() {
+                        self.run_prompt_input(std::mem::take(&mut input))?;
+                    }
+                    println!();
+                    return Ok(());
+                }
+            };
+
+            if input.is_empty() && line.trim_start().starts_with(':') {
+                match self.run_command(line.trim()) {
+                    Ok(ControlFlow::Continue(())) => {}
+                    Ok(ControlFlow::Break(())) => return Ok(()),
+                    Err(err @ RuntimeError::Exit(_)) => return Err(err),
+                    Err(err) => {
+                        self.last_error = LastError::new(&err, line.trim());
+                        eprintln!("{err}");
+                    }
+                }
+                continue;
+            }
+
+            // Keep reading until the input is a complete statement. A blank
+            // line runs it anyway, as a way out of one that can't be
+            // finished.
+            input.push_str(&line);
+            input.push('\n');
+            if !line.trim().is_empty() && self.is_incomplete(&input) {
+                continue;
+            }
+
+            self.run_prompt_input(std::mem::take(&mut input))?;
+        }
+    }
+
+    /// Runs a REPL command such as `:load file.lox`, breaking if it ends the
+    /// session.
+    fn run_command(&mut self, command: &str) -> Result<ControlFlow<()>> {
+        let (name, argument) = command
+            .split_once(char::is_whitespace)
+            .map_or((command, ""), |(name, argument)| (name, argument.trim()));
+
+        match (name, argument) {
+            (":help", _) => println!("{REPL_HELP}"),
+            (":quit" | ":q", _) => return Ok(ControlFlow::Break(())),
+            (":env", _) => self.print_env(),
+            (":why", _) => print!("{}", self.why()),
+            (":ast", source) if !source.is_empty() => {
+                // Only parse errors matter here, so skip the resolver.
+                let output = Parser::new(Scanner::new(source).scan_tokens().tokens)
+                    .repl_mode()
+                    .parse();
+                if !output.errors.is_empty() {
+                    return Err(RuntimeError::Diagnostics(Diagnostics::new(
+                        source,
+                        output.errors,
+                    )));
+                }
+                print!(
+                    "{}",
+                    ast_printer::print_program(&output.program, AstFormat::Tree)
+                );
+            }
+            (":load", path) if !path.is_empty() => self.run_file(path)?,
+            (":reset", _) => {
+                self.interpreter.reset();
+                self.results = 0;
+            }
+            (":ast" | ":load", _) => {
+                return Err(RuntimeError::GeneralError(
+                    format!("{name} needs an argument, see :help").into(),
+                ))
+            }
+            _ => {
+                return Err(RuntimeError::GeneralError(
+                    format!("Unknown command `{name}`, see :help").into(),
+                ))
+            }
+        }
+
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Prints the globals defined in the session, leaving out the natives.
+    fn print_env(&self) {
+        let bindings = self.interpreter.user_globals();
+
+        if bindings.is_empty() {
+            println!("No globals defined");
+        }
+        for (name, value) in bindings {
+            println!("{name} = {value:#}");
+        }
+    }
 
+    /// Explains the last error the REPL reported, for `:why`.
+    fn why(&self) -> String {
+        let Some(last_error) = &self.last_error else {
+            return "No error to explain yet\n".into();
+        };
+        let globals = self.interpreter.globals();
+        let why = last_error.explain(|name| globals.borrow().lookup(name));
+        why
+    }
+
+    /// Runs a complete statement typed at the REPL, reporting any error
+    /// except a call to `exit`, which ends the session.
+    fn run_prompt_input(&mut self, input: String) -> Result<()> {
+        match self.run_line(input.clone()) {
+            Ok(Some(value)) => println!("{value:#}"),
+            Ok(None) => {}
+            Err(err @ RuntimeError::Exit(_)) => return Err(err),
+            Err(err) => {
+                self.last_error = LastError::new(&err, &input);
+                eprintln!("{err}");
+            }
+        }
         Ok(())
     }
+
+    /// Whether `source` stops partway through a statement, so the REPL
+    /// should read another line before running it. That's when every error
+    /// in it is about the source running out.
+    fn is_incomplete(&self, source: &str) -> bool {
+        let mut scanner = Scanner::new(source).with_options(self.scanner);
+        let mut errors = vec![];
+
+        let tokens = scanner.by_ref().map(|token| {
+            token.unwrap_or_else(|err| {
+                let token = error_token(&err);
+                errors.push(err);
+                token
+            })
+        });
+        let parsed = Parser::new(tokens)
+            .with_dialect(self.scanner.dialect)
+            .repl_mode()
+            .parse();
+        errors.extend(parsed.errors);
+
+        !errors.is_empty()
+            && errors
+                .iter()
+                .all(|err| matches!(err, RuntimeError::UnexpectedEof(..)))
+    }
+
+    /// Reads one snippet per line from stdin and writes one JSON object per
+    /// line to stdout: `{"value": ...}` on success or `{"error": "..."}`.
+    /// Each line runs in a fresh interpreter unless `shared` is set, in which
+    /// case definitions carry over to later lines. What a line writes with
+    /// `print` or `eprint` is added to its object as `"printed"` or
+    /// `"eprinted"`, so it can't break up the stream.
+    pub fn run_batch(&mut self, shared: bool) -> Result<()> {
+        self.batch(std::io::stdin().lock(), std::io::stdout(), shared)
+    }
+
+    fn batch(
+        &mut self,
+        mut input: impl BufRead,
+        mut output: impl Write,
+        shared: bool,
+    ) -> Result<()> {
+        let capture = Capture::default();
+        let previous = self.interpreter.replace_output(Box::new(capture.clone()));
+        let mut line = String::new();
+
+        let result = loop {
+            line.clear();
+            match input.read_line(&mut line) {
+                Ok(0) => break Ok(()),
+                Ok(_) => {}
+                Err(err) => break Err(err.into()),
+            }
+
+            if !shared {
+                self.interpreter.reset();
+            }
+
+            capture.clear();
+            let mut object = match self.eval(line.trim_end_matches(['\n', '\r'])) {
+                Ok(value) => format!("{{\"value\":{}", json::value(&value)),
+                Err(err) => format!("{{\"error\":{}", json::string(&err.to_string())),
+            };
+            for (key, text) in [
+                ("printed", capture.printed()),
+                ("eprinted", capture.eprinted()),
+            ] {
+                if !text.is_empty() {
+                    object.push_str(&format!(",\"{key}\":{}", json::string(&text)));
+                }
+            }
+
+            if let Err(err) = writeln!(output, "{object}}}").and_then(|_| output.flush()) {
+                break Err(err.into());
+            }
+        };
+
+        self.interpreter.replace_output(previous);
+        result
+    }
+
+    fn run_program(&mut self, source: &str) -> Result<()> {
+        let program = self.parse_program(source, false)?;
+        self.execute(&program, None).map(|_| ())
+    }
+
+    fn eval_source(&mut self, source: &str) -> Result<Value> {
+        let program = self.parse_program(source, true)?;
+
+        match program.split_last() {
+            Some((Stmt::Expression(expr), rest)) => self.execute(rest, Some(expr)),
+            _ => self.execute(&program, None),
+        }
+    }
+
+    /// Runs `program` on the chosen backend, then evaluates `value`, if
+    /// there is one, and gives it back.
+    fn execute(&mut self, program: &[Stmt], value: Option<&Expr>) -> Result<Value> {
+        match self.backend {
+            Backend::TreeWalk => {
+                self.interpreter.interpret(program)?;
+                match value {
+                    Some(expr) => self.interpreter.evaluate(expr),
+                    None => Ok(Value::Nil),
+                }
+            }
+            Backend::Vm => {
+                let compiler = Compiler::new().with_integers(self.interpreter.options().integers);
+                let module = match value {
+                    Some(expr) => compiler.compile_with_value(program, expr),
+                    None => compiler.compile(program),
+                };
+                Vm::new(&mut self.interpreter).run_script(module)
+            }
+        }
+    }
+
+    fn run_line(&mut self, source: String) -> Result<Option<Value>> {
+        self.catch_internal_error(|lox| lox.run_line_source(source))
+    }
+
+    fn run_line_source(&mut self, source: String) -> Result<Option<Value>> {
+        let program = self.parse_program(&source, true)?;
+
+        match program.as_slice() {
+            [Stmt::Expression(expr)] => {
+                let value = self.execute(&[], Some(expr))?;
+                self.record_result(value.clone());
+                Ok(Some(value))
+            }
+            program => self.execute(program, None).map(|_| None),
+        }
+    }
+
+    /// Binds `value` to `_` and the next `_N` as if the session had
+    /// declared them, so `:env` lists them and `:reset` clears them.
+    fn record_result(&mut self, value: Value) {
+        self.results += 1;
+        let globals = self.interpreter.globals();
+        let mut globals = globals.borrow_mut();
+        let name = Symbol::intern(&format!("_{}", self.results));
+        globals.define(name, value.clone());
+        globals.define(Symbol::intern("_"), value);
+    }
+
+    fn parse_program(&self, source: &str, repl: bool) -> Result<Vec<Stmt>> {
+        parse(source, self.scanner, repl)
+    }
+}
+
+/// The path tokens of the `import` statements in `program`, however deeply
+/// they're nested.
+fn imports(program: &[Stmt]) -> Vec<&Token> {
+    struct Imports<'ast>(Vec<&'ast Token>);
+
+    impl<'ast> Visitor<'ast> for Imports<'ast> {
+        fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+            if let Stmt::Import { path, .. } = stmt {
+                self.0.push(path);
+            }
+            visit::walk_stmt(self, stmt);
+        }
+    }
+
+    let mut imports = Imports(vec![]);
+    program.iter().for_each(|stmt| imports.visit_stmt(stmt));
+    imports.0
+}
+
+/// Scans, parses and resolves `source`, gathering every error found on the
+/// way into a single [`RuntimeError::Diagnostics`]. The resolver only runs
+/// on programs that parsed cleanly.
+/// How `token` is listed by [`Lox::print_tokens`] in `format`, without a
+/// line break.
+fn token_line(token: &Token, format: TokenFormat) -> String {
+    let kind = token.value.kind();
+    let lexeme = json::string(&token.lexeme);
+    match format {
+        TokenFormat::Text => format!("{}:{} {kind} {lexeme}", token.line, token.column),
+        TokenFormat::Json => format!(
+            "{{\"kind\":\"{kind}\",\"lexeme\":{lexeme},\"line\":{},\"column\":{}}}",
+            token.line, token.column
+        ),
+    }
+}
+
+pub(crate) fn parse(source: &str, options: ScannerOptions, repl: bool) -> Result<Vec<Stmt>> {
+    let mut scanner = Scanner::new(source).with_options(options);
+    let mut errors = vec![];
+
+    // The parser pulls tokens from the scanner as it goes, setting aside the
+    // scan errors to be reported first. Each leaves an error token behind,
+    // so the parser doesn't report the gap as well.
+    let tokens = scanner.by_ref().map(|token| {
+        token.unwrap_or_else(|err| {
+            let token = error_token(&err);
+            errors.push(err);
+            token
+        })
+    });
+    let parser = Parser::new(tokens).with_dialect(options.dialect);
+    let parsed = match repl {
+        true => parser.repl_mode().parse(),
+        false => parser.parse(),
+    };
+
+    // A program cut short by a scan limit would only give parse errors about
+    // where it was cut.
+    if !scanner.limit_exceeded() {
+        errors.extend(parsed.errors);
+    }
+
+    if errors.is_empty() {
+        errors = Resolver::new().resolve(&parsed.program);
+    }
+
+    match errors.is_empty() {
+        true => Ok(parsed.program),
+        false => Err(RuntimeError::Diagnostics(Diagnostics::new(source, errors))),
+    }
+}
+
+impl Lox {
+    /// Runs `f`, turning a panic anywhere in the pipeline into an
+    /// [`RuntimeError::InternalError`] that points at the Lox line being run.
+    fn catch_internal_error<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        CATCHING.with(|catching| catching.set(catching.get() + 1));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(self)));
+        CATCHING.with(|catching| catching.set(catching.get() - 1));
+
+        match result {
+            Ok(result) => result,
+            Err(payload) => {
+                let message = match (
+                    payload.downcast_ref::<&str>(),
+                    payload.downcast_ref::<String>(),
+                ) {
+                    (Some(message), _) => message.to_string(),
+                    (None, Some(message)) => message.clone(),
+                    (None, None) => "unknown panic".into(),
+                };
+
+                self.interpreter.reset_scope();
+
+                Err(RuntimeError::InternalError {
+                    message,
+                    location: PANIC_LOCATION.with(|cell| cell.take()),
+                    line: self.interpreter.current_line(),
+                })
+            }
+        }
+    }
+}
+
+fn check_const(expr: &Expr) -> Result<()> {
+    let token = match expr {
+        Expr::Literal { .. } => return Ok(()),
+        Expr::Grouping { group, .. } => return check_const(group),
+        Expr::Unary { right, .. } => return check_const(right),
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            check_const(left)?;
+            return check_const(right);
+        }
+        Expr::Conditional {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            check_const(condition)?;
+            check_const(then_branch)?;
+            return check_const(else_branch);
+        }
+        Expr::This { keyword, .. } | Expr::Super { keyword, .. } => keyword,
+        Expr::Variable { name, .. }
+        | Expr::Get { name, .. }
+        | Expr::Assign { name, .. }
+        | Expr::Set { name, .. } => name,
+        Expr::Call { paren, .. } => {
+            return Err(RuntimeError::InterpretError(
+                "Calls are not allowed in a constant expression".into(),
+                paren.clone(),
+            ))
+        }
+        Expr::List { elements, .. } | Expr::Tuple { elements, .. } => {
+            return elements.iter().try_for_each(check_const)
+        }
+        Expr::Match { keyword, .. } => keyword,
+        Expr::Map { entries, .. } => {
+            return entries
+                .iter()
+                .try_for_each(|(key, value)| check_const(key).and_then(|_| check_const(value)))
+        }
+        Expr::Index { object, index, .. } => {
+            check_const(object)?;
+            return check_const(index);
+        }
+        Expr::Slice {
+            object, start, end, ..
+        } => {
+            check_const(object)?;
+            return start
+                .iter()
+                .chain(end)
+                .try_for_each(|bound| check_const(bound));
+        }
+        Expr::SetIndex { bracket: token, .. }
+        | Expr::Update {
+            operator: token, ..
+        }
+        | Expr::Compound {
+            operator: token, ..
+        } => {
+            return Err(RuntimeError::InterpretError(
+                "Assignments are not allowed in a constant expression".into(),
+                token.clone(),
+            ))
+        }
+    };
+
+    Err(RuntimeError::InterpretError(
+        format!("`{}` is not allowed in a constant expression", token.lexeme).into(),
+        token.clone(),
+    ))
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn panics_become_internal_errors() {
+        let mut lox = Lox::new();
+        lox.define_native("crash", 0, |_, _| panic!("crashed"));
+
+        let err = lox.eval("1;\ncrash();").unwrap_err();
+        assert!(matches!(
+            err,
+            RuntimeError::InternalError { ref message, line: Some(2), .. } if message == "crashed"
+        ));
+        assert_eq!(CATCHING.with(Cell::get), 0);
+    }
+
+    #[test]
+    fn eval_runs_on_the_chosen_backend() {
+        let source = "var ch = chanNew();\nfun producer() { send(ch, 42); }\n\
+                      spawn(producer); yieldTo(); recv(ch)";
+
+        let mut lox = Lox::new().with_backend(Backend::Vm);
+        assert_eq!(lox.eval(source).ok(), Some(Value::Number(42.0)));
+        assert!(lox.run_prompt_input("spawn(producer);".into()).is_ok());
+        assert_eq!(lox.eval("recv(ch) + 1").ok(), Some(Value::Number(43.0)));
+
+        // Only the vm can spawn coroutines.
+        assert!(Lox::new().eval(source).is_err());
+    }
+
+    #[test]
+    fn the_env_lists_only_what_programs_define() {
+        let mut lox = Lox::new();
+        assert!(lox.eval("var answer = 42; PI = 3;").is_ok());
+
+        let names: Vec<_> = lox
+            .interpreter
+            .user_globals()
+            .into_iter()
+            .map(|(name, _)| name.to_string())
+            .collect();
+        assert_eq!(names, ["PI", "answer"]);
+    }
+
+    #[test]
+    fn batch_output_is_kept_inside_each_object() {
+        let input = "print \"hi\"; 1 + 2\nvar a = 1;\na\n";
+        let mut output = vec![];
+
+        let mut lox = Lox::new();
+        assert!(lox.batch(input.as_bytes(), &mut output, false).is_ok());
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"value\":3,\"printed\":\"hi\\n\"}\n{\"value\":null}\n\
+             {\"error\":\"line 1 | Error[E0001]: Undefined variable `a`\"}\n"
+        );
+    }
+
+    #[test]
+    fn batch_lines_keep_the_hosts_natives_and_hook() {
+        /// Counts the statements run.
+        struct Statements(Rc<Cell<usize>>);
+
+        impl Hook for Statements {
+            fn on_statement(&mut self, _: &Interpreter, _: &Stmt) -> Result<()> {
+                self.0.set(self.0.get() + 1);
+                Ok(())
+            }
+        }
+
+        let statements = Rc::new(Cell::new(0));
+        let mut lox = Lox::new().with_hook(Statements(statements.clone()));
+        lox.define_native("twice", 1, |_, arguments| {
+            Ok(Value::Number(2.0 * f64::try_from(arguments[0].clone())?))
+        });
+
+        let input = "var a = twice(1);\ntwice(2)\na\n";
+        let mut output = vec![];
+        assert!(lox.batch(input.as_bytes(), &mut output, false).is_ok());
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"value\":null}\n{\"value\":4}\n\
+             {\"error\":\"line 1 | Error[E0001]: Undefined variable `a`\"}\n"
+        );
+        assert_eq!(statements.get(), 1);
+    }
+
+    #[test]
+    fn repl_results_are_bound_to_numbered_names_until_a_reset() {
+        fn line(lox: &mut Lox, source: &str) -> String {
+            match lox.run_line(source.into()) {
+                Ok(value) => value.map_or("no value".into(), |value| value.to_string()),
+                Err(err) => err.to_string(),
+            }
+        }
+
+        let mut lox = Lox::new().with_output(Capture::default());
+        assert_eq!(line(&mut lox, "1 + 1"), "2");
+        assert_eq!(line(&mut lox, "\"two\""), "two");
+        assert_eq!(line(&mut lox, "[_1, _2, _]"), r#"[2, "two", "two"]"#);
+        assert_eq!(line(&mut lox, "_3 == _"), "true");
+
+        assert!(lox.run_command(":reset").is_ok());
+        assert!(lox.interpreter.user_globals().is_empty());
+        assert_eq!(
+            line(&mut lox, "_1"),
+            "line 1 | Error[E0001]: Undefined variable `_1`"
+        );
+        assert_eq!(line(&mut lox, "3"), "3");
+        assert_eq!(line(&mut lox, "_1"), "3");
+    }
+
+    #[test]
+    fn tokens_are_listed_one_per_line_as_text_or_json() {
+        let tokens = Scanner::new("print \"a\\tb\";\n-1").scan_tokens().tokens;
+        let lines = |format| {
+            let lines: Vec<_> = tokens
+                .iter()
+                .map(|token| token_line(token, format))
+                .collect();
+            lines.join("\n")
+        };
+
+        assert_eq!(
+            lines(TokenFormat::Text),
+            r#"1:1 Print "print"
+1:7 String "a\\tb"
+1:13 Semicolon ";"
+2:1 Minus "-"
+2:2 Number "1"
+2:3 Eof """#
+        );
+        assert_eq!(
+            lines(TokenFormat::Json),
+            r#"{"kind":"Print","lexeme":"print","line":1,"column":1}
+{"kind":"String","lexeme":"a\\tb","line":1,"column":7}
+{"kind":"Semicolon","lexeme":";","line":1,"column":13}
+{"kind":"Minus","lexeme":"-","line":2,"column":1}
+{"kind":"Number","lexeme":"1","line":2,"column":2}
+{"kind":"Eof","lexeme":"","line":2,"column":3}"#
+        );
+    }
+
+    #[test]
+    fn why_explains_the_last_error_with_the_values_on_its_line() {
+        let mut lox = Lox::new().with_output(Capture::default());
+        assert_eq!(lox.why(), "No error to explain yet\n");
+
+        assert!(lox.run_prompt_input("var a = \"x\";".into()).is_ok());
+        assert!(lox.run_prompt_input("print a - 1;".into()).is_ok());
+        let why = lox.why();
+        assert!(
+            why.starts_with("error[E0002]: Operands must be numbers\n"),
+            "{why}"
+        );
+        assert!(
+            why.ends_with("Values involved:\n  a: string = x\n"),
+            "{why}"
+        );
+    }
+
+    #[test]
+    fn checked_files_are_sorted_with_cascading_errors_left_out() {
+        let dir = std::env::temp_dir().join(format!("rlox-check-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("b.lox"), "print 1;").unwrap();
+        std::fs::write(
+            dir.join("main.lox"),
+            "import \"lib/util.lox\";\nimport \"gone.lox\";\nprint (1 + ;\nprint 1 +;",
+        )
+        .unwrap();
+        std::fs::write(dir.join("lib/util.lox"), "var a = ;\nvar b = 1 @ 2 @ 3;").unwrap();
+
+        let main = dir.join("main.lox").display().to_string();
+        let result = Lox::new().check_files(&[main]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let Err(RuntimeError::Files(files)) = result else {
+            panic!("expected errors in several files");
+        };
+        let found: Vec<(String, Vec<String>)> = files
+            .iter()
+            .map(|diagnostics| {
+                let file = diagnostics.file().unwrap_or_default();
+                let file = file.rsplit_once("rlox-check-").unwrap().1;
+                let messages = diagnostics
+                    .iter()
+                    .map(|diagnostic| format!("{}: {}", diagnostic.line, diagnostic.message))
+                    .collect();
+                (file.split_once('/').unwrap().1.to_string(), messages)
+            })
+            .collect();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].0, "lib/util.lox");
+        assert_eq!(
+            found[0].1,
+            [
+                "1: Expected expression, found: `;`",
+                "2: Unexpected token `@`"
+            ]
+        );
+        assert_eq!(found[1].0, "main.lox");
+        assert_eq!(found[1].1.len(), 3, "{:?}", found[1].1);
+        assert!(found[1].1[0].starts_with("2: Can't import `gone.lox`"));
+    }
+
+    #[test]
+    fn reloading_a_module_keeps_the_other_globals() {
+        let dir = std::env::temp_dir().join(format!("rlox-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let main = dir.join("main.lox");
+        let lib = dir.join("lib.lox");
+        std::fs::write(&main, "var kept = 1;\nimport \"lib.lox\";").unwrap();
+
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            std::fs::write(&lib, "var loads = 1;\nfun value() { return 1; }").unwrap();
+            let mut lox = Lox::new().with_backend(backend);
+            lox.run_file(&main.display().to_string()).ok().unwrap();
+            lox.eval("kept = kept + 1;").ok().unwrap();
+
+            std::fs::write(&lib, "var loads = 2;\nfun value() { return 2; }").unwrap();
+            lox.reload_module(&lib).ok().unwrap();
+            assert_eq!(
+                lox.eval("value() + loads").ok().unwrap(),
+                Value::Number(4.0)
+            );
+            assert_eq!(lox.eval("kept").ok().unwrap(), Value::Number(2.0));
+
+            std::fs::write(&lib, "fun value( {").unwrap();
+            assert!(lox.reload_module(&lib).is_err());
+            assert_eq!(lox.eval("value()").ok().unwrap(), Value::Number(2.0));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -1,54 +1,387 @@
-use error::RuntimeError;
-use lox::Lox;
+use rlox::{
+    install_panic_hook, AstFormat, Backend, FormatMode, LineEnding, Lox, LoxDialect, LoxOptions,
+    RuntimeError, TokenFormat,
+};
 
-mod error;
-mod lox;
-mod parser;
-mod scanner;
-mod token;
+// Exit codes from BSD's sysexits.h, following *Crafting Interpreters*.
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_SOFTWARE: i32 = 70;
+const EX_IOERR: i32 = 74;
+
+/// How many times `rlox bench` runs a script without `--iterations`.
+const DEFAULT_ITERATIONS: usize = 10;
+
+/// The stack for the thread scripts run on. The tree-walking backend
+/// recurses on it for every Lox call, so it needs room for the default call
+/// depth in [`LoxOptions`] even in a debug build, where frames are much
+/// larger. Scripts may use half of it, the rest being ample for the frames
+/// below and the deepest call's.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// The settings file read from the working directory when `--config` doesn't
+/// name one. It's fine for it not to exist.
+const CONFIG_FILE: &str = "rlox.conf";
+
+/// The settings a config file may give, and whether each is a switch, set
+/// with `true` or `false`, rather than an option taking a value.
+const SETTINGS: [(&str, bool); 8] = [
+    ("backend", false),
+    ("dialect", false),
+    ("line-endings", false),
+    ("gc-stress", true),
+    ("memory-report", true),
+    ("integers", true),
+    ("string-conversion", true),
+    ("reopen-classes", true),
+];
+
+const USAGE: &str = "\
+Usage: rlox [options] [script | - | -e <source>]
+       rlox [options] --check <script>
+       rlox [options] check <path>...
+       rlox [options] test <dir>
+       rlox [options] bench <script> [--iterations N]
+       rlox [options] watch <script>
+       rlox --tokens[=json] <script>
+       rlox --ast[=tree|sexpr] <script>
+       rlox parse --json <script>
+       rlox symbols [--json] <script>
+       rlox fmt [--write|--diff] <script>
+       rlox --teach <script>
+       rlox debug <script>
+       rlox --profile[=<folded file>] <script>
+       rlox --batch [--shared]
+       rlox --explain <code>
+
+With no script, rlox starts a REPL. `-` reads the script from stdin, and
+`-e` (or `--eval`) runs the source given after it.
+
+Options:
+  --backend=tree|vm  Run on the tree-walking interpreter (the default) or
+                     the bytecode vm
+  --config=<file>    Read settings from <file> instead of rlox.conf
+  --dialect=extended|book
+                     Read rlox's extended Lox (the default) or only the
+                     language of the book
+  --gc-stress        Run the cycle collector before every allocation
+  --memory-report    Print what's left in memory on stderr at exit
+  --integers         Keep number literals without a decimal point as
+                     integers, with integer arithmetic between them
+  --string-conversion
+                     Let `+` join a string with any other value
+  --reopen-classes   Let a second top-level `class` declaration add its
+                     methods to the class already declared
+  --line-endings=lf|crlf|keep
+                     End the lines `fmt` writes with `\\n` (the default),
+                     `\\r\\n`, or whichever the script's first line uses
+  -h, --help         Show this message
+
+A config file sets the options as `name = value` lines, such as
+`dialect = book` or `string-conversion = true`, with `#` starting a
+comment. Options given on the command line override it.";
+
+/// What rlox was asked to do, parsed from the command line.
+struct Cli {
+    backend: Backend,
+    dialect: LoxDialect,
+    options: LoxOptions,
+    gc_stress: bool,
+    memory_report: bool,
+    line_ending: LineEnding,
+    command: Command,
+}
+
+enum Command {
+    Help,
+    Prompt,
+    Batch {
+        shared: bool,
+    },
+    RunFile(String),
+    RunStdin,
+    Eval(String),
+    Check(String),
+    CheckAll(Vec<String>),
+    Tokens(String, TokenFormat),
+    Ast(String, AstFormat),
+    Symbols(String, TokenFormat),
+    Teach(String),
+    Debug(String),
+    Profile(String, Option<String>),
+    Format(String, FormatMode),
+    Test(String),
+    Bench(String, usize),
+    Watch(String),
+    /// The explanation of an error code, to print.
+    Explain(&'static str),
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    install_panic_hook();
 
-    match args.len() {
-        1 => todo!("Add REPL"),
-        2 => match Lox::new().run_file(&args[1]) {
-            Ok(_) => std::process::exit(0),
-            Err(err) => std::process::exit(handle_error(err)),
+    let runner = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run)
+        .expect("failed to start the interpreter thread");
+    if runner.join().is_err() {
+        std::process::exit(EX_SOFTWARE);
+    }
+}
+
+fn run() {
+    let cli = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(cli) => cli,
+        Err(message) => {
+            eprintln!("{message}\n\n{USAGE}");
+            std::process::exit(EX_USAGE);
+        }
+    };
+
+    let mut lox = Lox::new()
+        .with_backend(cli.backend)
+        .with_dialect(cli.dialect)
+        .with_options(cli.options)
+        .with_gc_stress(cli.gc_stress);
+
+    let result = match cli.command {
+        Command::Help => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        Command::Prompt => lox.run_prompt(),
+        Command::Batch { shared } => lox.run_batch(shared),
+        Command::RunFile(path) => lox.run_file(&path),
+        Command::RunStdin => lox.run_stdin(),
+        Command::Eval(source) => lox.run_source(&source),
+        Command::Check(path) => lox.check_file(&path),
+        Command::CheckAll(paths) => lox.check_files(&paths),
+        Command::Tokens(path, format) => lox.print_tokens(&path, format),
+        Command::Ast(path, format) => lox.print_ast(&path, format),
+        Command::Symbols(path, format) => lox.print_symbols(&path, format),
+        Command::Teach(path) => lox.teach_file(&path),
+        Command::Debug(path) => lox.debug_file(&path),
+        Command::Profile(path, folded) => lox.profile_file(&path, folded.as_deref()),
+        Command::Format(path, mode) => format(&mut lox, &path, mode, cli.line_ending),
+        Command::Test(dir) => match lox.test_dir(&dir) {
+            Ok(false) => std::process::exit(1),
+            result => result.map(|_| ()),
         },
-        _ => {
-            show_usage();
-            std::process::exit(64);
+        Command::Bench(path, iterations) => lox.bench_file(&path, iterations),
+        Command::Watch(path) => lox.watch_file(&path),
+        Command::Explain(text) => {
+            println!("{text}");
+            Ok(())
         }
+    };
+
+    if cli.memory_report {
+        eprint!("{}", lox.memory_report());
+    }
+
+    match result {
+        Ok(_) => std::process::exit(0),
+        Err(err) => std::process::exit(handle_error(err)),
     }
 }
 
-fn show_usage() {
-    println!("Usage: rlox [script]");
+/// Parses the arguments after the program name, after the options set in
+/// the config file. The options may come anywhere; what's left picks the
+/// command.
+fn parse_args(args: Vec<String>) -> Result<Cli, String> {
+    let mut backend = Backend::default();
+    let mut dialect = LoxDialect::default();
+    let mut options = LoxOptions::default().with_max_stack(STACK_SIZE / 2);
+    let mut gc_stress = false;
+    let mut memory_report = false;
+    let mut line_ending = LineEnding::default();
+    let mut rest = vec![];
+
+    let config = args.iter().find_map(|arg| arg.strip_prefix("--config="));
+    let settings = match config {
+        Some(path) => read_config(path, true)?,
+        None => read_config(CONFIG_FILE, false)?,
+    };
+
+    for arg in settings.into_iter().chain(args) {
+        if arg.starts_with("--config=") {
+            continue;
+        } else if let Some(name) = arg.strip_prefix("--backend=") {
+            backend = match name {
+                "tree" => Backend::TreeWalk,
+                "vm" => Backend::Vm,
+                _ => return Err(format!("Unknown backend `{name}`")),
+            };
+        } else if let Some(name) = arg.strip_prefix("--dialect=") {
+            dialect = match name {
+                "extended" => LoxDialect::EXTENDED,
+                "book" => LoxDialect::BOOK,
+                _ => return Err(format!("Unknown dialect `{name}`")),
+            };
+        } else if let Some(name) = arg.strip_prefix("--line-endings=") {
+            line_ending = match name {
+                "lf" => LineEnding::Lf,
+                "crlf" => LineEnding::Crlf,
+                "keep" => LineEnding::Keep,
+                _ => return Err(format!("Unknown line ending `{name}`")),
+            };
+        } else if arg == "--gc-stress" {
+            gc_stress = true;
+        } else if arg == "--memory-report" {
+            memory_report = true;
+        } else if arg == "--integers" {
+            options = options.with_integers(true);
+        } else if arg == "--string-conversion" {
+            options = options.with_string_conversion(true);
+        } else if arg == "--reopen-classes" {
+            options = options.with_class_reopening(true);
+        } else {
+            rest.push(arg);
+        }
+    }
+
+    let rest: Vec<&str> = rest.iter().map(String::as_str).collect();
+    let command = match rest.as_slice() {
+        [] => Command::Prompt,
+        ["-h" | "--help"] => Command::Help,
+        ["--batch"] => Command::Batch { shared: false },
+        ["--batch", "--shared"] => Command::Batch { shared: true },
+        ["-"] => Command::RunStdin,
+        ["-e" | "--eval", source] => Command::Eval(source.to_string()),
+        ["--check", path] => Command::Check(path.to_string()),
+        ["check", paths @ ..] if !paths.is_empty() => {
+            Command::CheckAll(paths.iter().map(|path| path.to_string()).collect())
+        }
+        ["--tokens", path] => Command::Tokens(path.to_string(), TokenFormat::Text),
+        ["--tokens=json", path] => Command::Tokens(path.to_string(), TokenFormat::Json),
+        ["--ast" | "--ast=tree", path] => Command::Ast(path.to_string(), AstFormat::Tree),
+        ["--ast=sexpr", path] => Command::Ast(path.to_string(), AstFormat::SExpr),
+        #[cfg(feature = "serde")]
+        ["parse", "--json", path] => Command::Ast(path.to_string(), AstFormat::Json),
+        #[cfg(not(feature = "serde"))]
+        ["parse", "--json", _] => {
+            return Err("`parse --json` needs rlox built with the `serde` feature".into())
+        }
+        ["symbols", path] => Command::Symbols(path.to_string(), TokenFormat::Text),
+        ["symbols", path, "--json"] | ["symbols", "--json", path] => {
+            Command::Symbols(path.to_string(), TokenFormat::Json)
+        }
+        ["--teach", path] => Command::Teach(path.to_string()),
+        ["--explain", code] => match rlox::explain(code) {
+            Some(text) => Command::Explain(text),
+            None => return Err(format!("Unknown error code `{code}`")),
+        },
+        ["debug", path] => Command::Debug(path.to_string()),
+        ["--profile", path] => Command::Profile(path.to_string(), None),
+        [profile, path] if profile.starts_with("--profile=") => {
+            let folded = profile.trim_start_matches("--profile=");
+            Command::Profile(path.to_string(), Some(folded.to_string()))
+        }
+        ["fmt", path] => Command::Format(path.to_string(), FormatMode::Print),
+        ["fmt", "--write", path] => Command::Format(path.to_string(), FormatMode::Write),
+        ["fmt", "--diff", path] => Command::Format(path.to_string(), FormatMode::Diff),
+        ["test", dir] => Command::Test(dir.to_string()),
+        ["bench", path] => Command::Bench(path.to_string(), DEFAULT_ITERATIONS),
+        ["bench", path, "--iterations", iterations] => match iterations.parse() {
+            Ok(iterations) if iterations > 0 => Command::Bench(path.to_string(), iterations),
+            _ => return Err(format!("Invalid number of iterations `{iterations}`")),
+        },
+        ["watch", path] => Command::Watch(path.to_string()),
+        [path] if !path.starts_with('-') => Command::RunFile(path.to_string()),
+        [arg, ..] if arg.starts_with('-') => return Err(format!("Unknown option `{arg}`")),
+        _ => return Err("Unexpected arguments".into()),
+    };
+
+    Ok(Cli {
+        backend,
+        dialect,
+        options,
+        gc_stress,
+        memory_report,
+        line_ending,
+        command,
+    })
+}
+
+/// Reads the options set in the config file at `path` as the command-line
+/// arguments that would set them. A missing file is an error only if it's
+/// `required`.
+fn read_config(path: &str, required: bool) -> Result<Vec<String>, String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound && !required => return Ok(vec![]),
+        Err(err) => return Err(format!("Couldn't read {path}: {err}")),
+    };
+
+    let mut args = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let invalid = |message: &str| format!("{path}:{}: {message}", index + 1);
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(invalid("Expected `name = value`"));
+        };
+        let (name, value) = (name.trim(), value.trim());
+        match SETTINGS.iter().find(|(setting, _)| *setting == name) {
+            Some((_, true)) => match value {
+                "true" => args.push(format!("--{name}")),
+                "false" => {}
+                _ => return Err(invalid(&format!("Expected `true` or `false` for `{name}`"))),
+            },
+            Some((_, false)) => args.push(format!("--{name}={value}")),
+            None => return Err(invalid(&format!("Unknown setting `{name}`"))),
+        }
+    }
+
+    Ok(args)
+}
+
+/// Runs `rlox fmt`. With `--diff`, a file that isn't formatted exits with 1,
+/// so the check can fail a CI job.
+fn format(
+    lox: &mut Lox,
+    path: &str,
+    mode: FormatMode,
+    line_ending: LineEnding,
+) -> Result<(), RuntimeError> {
+    let unchanged = lox.format_file(path, mode, line_ending)?;
+    if mode == FormatMode::Diff && !unchanged {
+        std::process::exit(1);
+    }
+    Ok(())
 }
 
 fn handle_error(error: RuntimeError) -> i32 {
+    if let RuntimeError::Exit(status) = error {
+        return status;
+    }
+    eprintln!("{error}");
+    exit_code(&error)
+}
+
+fn exit_code(error: &RuntimeError) -> i32 {
+    // Errors found before the program runs are problems with the input,
+    // while the rest happened in the running program or in rlox itself.
+    // Runtime errors are always located, so the only errors left without a
+    // location are failures to read the script.
     match error {
-        RuntimeError::GeneralError(msg) => {
-            eprintln!("{msg}");
-            1
-        }
-        RuntimeError::ScanError {
-            line,
-            column,
-            offset: _,
-            message,
-        } => {
-            eprintln!("line {line}:{column} | Error: {message}");
-            2
-        }
-        RuntimeError::ParseError(parse_err, _token) => {
-            eprintln!("{parse_err}");
-            3
-        }
-        RuntimeError::InvalidArgumentTarget(parse_err) => {
-            eprintln!("{parse_err}");
-            3
-        }
+        RuntimeError::ScanError { .. }
+        | RuntimeError::ParseError(..)
+        | RuntimeError::UnexpectedEof(..)
+        | RuntimeError::ResolveError(..)
+        | RuntimeError::DuplicateDeclaration { .. }
+        | RuntimeError::Diagnostics(_)
+        | RuntimeError::Files(_)
+        | RuntimeError::InvalidArgumentTarget(..) => EX_DATAERR,
+        RuntimeError::InterpretError(..)
+        | RuntimeError::Traceback { .. }
+        | RuntimeError::Thrown { .. }
+        | RuntimeError::InternalError { .. } => EX_SOFTWARE,
+        RuntimeError::GeneralError(_) => EX_IOERR,
+        RuntimeError::InSource { error, .. } => exit_code(error),
+        RuntimeError::Exit(_) => unreachable!("exits are returned above"),
     }
 }
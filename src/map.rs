@@ -0,0 +1,208 @@
+//! Lox maps. Iterating a map, whether by printing it or through `keys` and
+//! `values`, visits its entries in the order their keys were first
+//! inserted; setting an existing key keeps its place. Nothing about the
+//! order depends on hashing, so a script prints its maps the same way on
+//! every run and every platform.
+//!
+//! Keys are still hashed to find their entry, with 64-bit FNV-1a over a
+//! fixed encoding of the key: a tag byte (`0` for `nil`, `1` for a boolean,
+//! `2` for a number, `3` for a string), then the boolean as one byte, the
+//! number's bits as eight little-endian bytes, or the string's UTF-8 bytes
+//! followed by `0xff`. Unlike the standard library's randomly seeded hasher
+//! this gives every key the same hash everywhere.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    hash::{BuildHasherDefault, Hash, Hasher},
+};
+
+use crate::{
+    error::RuntimeError,
+    gc::{Trace, Visitor},
+    value::Value,
+};
+
+/// A Lox map. Entries are kept in the order their keys were first inserted,
+/// which is the order `keys`, `values` and printing use.
+#[derive(Debug, Default)]
+pub struct LoxMap {
+    entries: Vec<(MapKey, Value)>,
+    indexes: HashMap<MapKey, usize, BuildHasherDefault<KeyHasher>>,
+}
+
+/// The values that can be map keys. Only immutable values compare by
+/// content, so lists, maps, instances and functions are rejected rather than
+/// hashed by identity. Numbers are compared by their bits, after folding
+/// `-0` into `0` so the two find the same entry as they do with `==`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum MapKey {
+    Nil,
+    Bool(bool),
+    Number(u64),
+    String(String),
+}
+
+impl MapKey {
+    pub(crate) fn new(value: &Value) -> Result<MapKey, RuntimeError> {
+        match value {
+            Value::Nil => Ok(MapKey::Nil),
+            Value::Bool(b) => Ok(MapKey::Bool(*b)),
+            Value::Number(n) if n.is_nan() => Err(RuntimeError::GeneralError(
+                "NaN can't be used as a map key".into(),
+            )),
+            Value::Number(n) => Ok(MapKey::Number((n + 0.0).to_bits())),
+            // An integer is the same key as the floating-point number equal
+            // to it, as `1 == 1.0`.
+            Value::Int(n) => Ok(MapKey::Number((*n as f64 + 0.0).to_bits())),
+            Value::String(s) => Ok(MapKey::String(s.clone())),
+            value => Err(RuntimeError::GeneralError(
+                format!("A {} can't be used as a map key", value.type_name()).into(),
+            )),
+        }
+    }
+
+    pub(crate) fn to_value(&self) -> Value {
+        match self {
+            MapKey::Nil => Value::Nil,
+            MapKey::Bool(b) => Value::Bool(*b),
+            MapKey::Number(bits) => Value::Number(f64::from_bits(*bits)),
+            MapKey::String(s) => Value::String(s.clone()),
+        }
+    }
+}
+
+impl Hash for MapKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            MapKey::Nil => state.write_u8(0),
+            MapKey::Bool(b) => state.write(&[1, u8::from(*b)]),
+            MapKey::Number(bits) => {
+                state.write_u8(2);
+                state.write(&bits.to_le_bytes());
+            }
+            MapKey::String(s) => {
+                state.write_u8(3);
+                state.write(s.as_bytes());
+                state.write_u8(0xff);
+            }
+        }
+    }
+}
+
+/// 64-bit FNV-1a, which hashes the same bytes to the same value on every
+/// platform.
+pub(crate) struct KeyHasher(u64);
+
+impl KeyHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+}
+
+impl Default for KeyHasher {
+    fn default() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+}
+
+impl Hasher for KeyHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl LoxMap {
+    pub(crate) fn get(&self, key: &MapKey) -> Option<&Value> {
+        self.indexes.get(key).map(|&index| &self.entries[index].1)
+    }
+
+    /// Sets the value for `key`, keeping its original position if it was
+    /// already in the map.
+    pub(crate) fn insert(&mut self, key: MapKey, value: Value) {
+        match self.indexes.get(&key) {
+            Some(&index) => self.entries[index].1 = value,
+            None => {
+                self.indexes.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub(crate) fn contains(&self, key: &MapKey) -> bool {
+        self.indexes.contains_key(key)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&MapKey, &Value)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
+
+impl Trace for RefCell<LoxMap> {
+    fn trace(&self, visit: &mut Visitor) {
+        if let Ok(map) = self.try_borrow() {
+            map.entries.iter().for_each(|(_, value)| value.trace(visit));
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut map) = self.try_borrow_mut() {
+            map.entries.clear();
+            map.indexes.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(key: &MapKey) -> u64 {
+        let mut hasher = KeyHasher::default();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn entries_keep_the_order_keys_were_first_inserted() {
+        let mut map = LoxMap::default();
+        for (key, value) in [("b", 1.0), ("a", 2.0), ("b", 3.0)] {
+            map.insert(MapKey::String(key.into()), Value::Number(value));
+        }
+
+        let entries: Vec<_> = map
+            .entries()
+            .map(|(key, value)| format!("{} {value}", key.to_value()))
+            .collect();
+        assert_eq!(entries, ["b 3", "a 2"]);
+    }
+
+    #[test]
+    fn zero_and_negative_zero_are_one_key() {
+        assert!(matches!(
+            (MapKey::new(&Value::Number(-0.0)), MapKey::new(&Value::Number(0.0))),
+            (Ok(negative), Ok(positive)) if negative == positive
+        ));
+        assert!(MapKey::new(&Value::Number(f64::NAN)).is_err());
+    }
+
+    #[test]
+    fn keys_hash_to_fixed_values() {
+        assert_eq!(hash(&MapKey::Nil), 0xaf63_bd4c_8601_b7df);
+        assert_eq!(
+            hash(&MapKey::String("a".into())),
+            hash(&MapKey::String("a".into()))
+        );
+        assert_ne!(hash(&MapKey::String("".into())), hash(&MapKey::Nil));
+        assert_ne!(hash(&MapKey::Bool(false)), hash(&MapKey::Number(0)));
+    }
+}
@@ -0,0 +1,124 @@
+//! What a script has allocated, for `memoryStats()` and `--memory-report`.
+
+use std::fmt::Display;
+
+use crate::{
+    environment::Environment, error::Result, gc, interpreter::Interpreter, symbol::Symbol,
+    value::Value,
+};
+
+/// A snapshot of the memory in use on this thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct MemoryStats {
+    /// Lists, maps, instances, closures and the other objects the cycle
+    /// collector tracks that haven't been freed.
+    pub(crate) live_objects: usize,
+    /// Names and other strings interned as symbols, which are never freed.
+    pub(crate) interned_strings: usize,
+    /// Scopes the tree-walking interpreter has created, freed or not. The
+    /// vm keeps locals on its stack, so it creates few.
+    pub(crate) environments: usize,
+    /// The most memory the process has had resident at once, in bytes, where
+    /// the operating system reports it.
+    pub(crate) peak_memory: Option<usize>,
+}
+
+impl MemoryStats {
+    pub(crate) fn now() -> Self {
+        Self {
+            live_objects: gc::live(),
+            interned_strings: Symbol::interned(),
+            environments: Environment::created(),
+            peak_memory: peak_memory(),
+        }
+    }
+
+    /// The stats as a map from `liveObjects`, `internedStrings`,
+    /// `environments` and `peakMemory` to numbers, with `peakMemory` `nil`
+    /// where it isn't known.
+    pub(crate) fn to_map(self) -> Result<Value> {
+        let count = |count: usize| Value::Number(count as f64);
+        Value::map(vec![
+            (
+                Value::String("liveObjects".into()),
+                count(self.live_objects),
+            ),
+            (
+                Value::String("internedStrings".into()),
+                count(self.interned_strings),
+            ),
+            (
+                Value::String("environments".into()),
+                count(self.environments),
+            ),
+            (
+                "peakMemory".into(),
+                self.peak_memory.map_or(Value::Nil, count),
+            ),
+        ])
+    }
+}
+
+impl Display for MemoryStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "memory:")?;
+        writeln!(f, "  live objects:     {}", self.live_objects)?;
+        writeln!(f, "  interned strings: {}", self.interned_strings)?;
+        writeln!(f, "  environments:     {}", self.environments)?;
+        match self.peak_memory {
+            Some(bytes) => writeln!(
+                f,
+                "  peak memory:      {:.1} MiB",
+                bytes as f64 / (1024.0 * 1024.0)
+            ),
+            None => writeln!(f, "  peak memory:      unknown"),
+        }
+    }
+}
+
+/// `memoryStats()` returns a map of what's in memory; see [`MemoryStats`].
+pub(crate) fn memory_stats(_: &mut Interpreter, _: Vec<Value>) -> Result<Value> {
+    MemoryStats::now().to_map()
+}
+
+/// The peak resident set size, from the `VmHWM` line of
+/// `/proc/self/status` on Linux.
+fn peak_memory() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: usize = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, Capture, Lox};
+
+    #[test]
+    fn memory_stats_count_the_objects_a_script_keeps() {
+        let source = "
+            var before = memoryStats()[\"liveObjects\"];
+            var kept = [];
+            for (var i = 0; i < 10; i = i + 1) push(kept, [i]);
+            var stats = memoryStats();
+            print stats[\"liveObjects\"] - before >= 10;
+            print stats[\"internedStrings\"] > 0;
+            print type(stats[\"environments\"]);
+        ";
+
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            let capture = Capture::default();
+            let mut lox = Lox::new()
+                .with_backend(backend)
+                .with_output(capture.clone());
+            lox.run_source(source).unwrap_or_else(|err| panic!("{err}"));
+            assert_eq!(capture.printed(), "true\ntrue\nnumber\n", "{backend:?}");
+        }
+    }
+}
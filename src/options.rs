@@ -0,0 +1,204 @@
+use std::time::{Duration, Instant};
+
+use crate::error::{ErrorCode, Message};
+
+/// How deeply calls may nest by default before a program is stopped with a
+/// stack overflow.
+const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+
+/// How much of the Rust stack the tree-walking backend may use for nested
+/// calls by default: enough to leave room, on the 2 MiB stack Rust gives the
+/// threads it spawns, for the host's own frames and the deepest call's.
+const DEFAULT_MAX_STACK: usize = 1536 * 1024;
+
+/// How many steps go by between checks of the clock for
+/// [`LoxOptions::timeout`], which is cheap but not free.
+const CLOCK_CHECK_INTERVAL: u64 = 1024;
+
+/// How many more steps a program gets once it reaches its step or time
+/// limit, so a `catch` can handle the error. Past them every step fails
+/// again, which no handler can get around.
+const GRACE_STEPS: u64 = 1000;
+
+/// The error for calls nested deeper than [`LoxOptions::max_call_depth`].
+pub(crate) const STACK_OVERFLOW: &str = "Stack overflow";
+
+/// The `kind` of the `Error` a `catch` gets for an error raised with
+/// `code`: the limit it reports, or `runtime` for any other runtime error.
+pub(crate) fn error_kind(code: Option<ErrorCode>) -> &'static str {
+    match code {
+        Some(ErrorCode::StackOverflow) => "stackOverflow",
+        Some(ErrorCode::StepLimit) => "stepLimit",
+        Some(ErrorCode::Timeout) => "timeout",
+        _ => "runtime",
+    }
+}
+
+/// How programs are run: limits for embedding scripts that can't be
+/// trusted, and changes to the language that have to be opted into. A
+/// program that reaches a limit gets a runtime error it can catch, whose
+/// `kind` names the limit, though one out of steps or time only gets a
+/// little longer to handle it. `None` means no limit, which is the default
+/// for everything but the call depth and stack.
+///
+/// ```
+/// use std::time::Duration;
+/// use rlox::{Lox, LoxOptions};
+///
+/// let options = LoxOptions::default()
+///     .with_max_steps(10_000)
+///     .with_timeout(Duration::from_secs(1));
+/// let mut lox = Lox::new().with_options(options);
+///
+/// let err = lox.eval("while (true) {}").unwrap_err();
+/// assert!(err.to_string().contains("Exceeded the limit of 10000 steps"));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoxOptions {
+    /// Maximum number of calls in progress at once. On the tree-walking
+    /// backend, [`LoxOptions::max_stack`] may stop a recursion first.
+    pub max_call_depth: Option<usize>,
+    /// Maximum number of bytes of the Rust stack the tree-walking backend
+    /// may use from the start of the outermost call, checked on each call
+    /// and statement, so a deep recursion is a stack overflow error rather
+    /// than a crash. It has to be smaller than the stack of the thread
+    /// running the program, less what the host was already using; the
+    /// default suits any thread Rust spawns. The vm keeps its calls off the
+    /// Rust stack and isn't affected.
+    pub max_stack: Option<usize>,
+    /// Maximum number of steps in a single run: statements executed on the
+    /// tree-walking backend, or instructions on the vm.
+    pub max_steps: Option<u64>,
+    /// Maximum wall-clock time for a single run.
+    pub timeout: Option<Duration>,
+    /// Whether `readFile`, `writeFile` and `import` may touch the
+    /// filesystem. When they may not, calling either native or running an
+    /// `import` is a runtime error. The script the host runs itself is read
+    /// either way.
+    pub allow_filesystem: bool,
+    /// Keeps number literals written without a decimal point or exponent as
+    /// integers. Arithmetic on two integers gives an integer, and is a
+    /// runtime error if it overflows; `/` truncates. An integer mixed with
+    /// any other number is converted to a floating-point number first.
+    pub integers: bool,
+    /// Lets `+` join a string with any other value, converting the value as
+    /// `str` would, as in the challenge in the book's chapter on evaluating
+    /// expressions. Otherwise both operands must be strings.
+    pub string_conversion: bool,
+    /// Lets a second top-level `class` declaration with the name of an
+    /// existing class add its methods to that class, replacing any with the
+    /// same names, rather than declaring a new class. Instances made before
+    /// get the new methods too, and so do built-in classes like `Error`.
+    pub reopen_classes: bool,
+}
+
+impl Default for LoxOptions {
+    fn default() -> Self {
+        Self {
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            max_stack: Some(DEFAULT_MAX_STACK),
+            max_steps: None,
+            timeout: None,
+            allow_filesystem: true,
+            integers: false,
+            string_conversion: false,
+            reopen_classes: false,
+        }
+    }
+}
+
+impl LoxOptions {
+    pub fn with_max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = Some(depth);
+        self
+    }
+
+    pub fn with_max_stack(mut self, bytes: usize) -> Self {
+        self.max_stack = Some(bytes);
+        self
+    }
+
+    pub fn with_max_steps(mut self, steps: u64) -> Self {
+        self.max_steps = Some(steps);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_filesystem(mut self, allowed: bool) -> Self {
+        self.allow_filesystem = allowed;
+        self
+    }
+
+    pub fn with_integers(mut self, integers: bool) -> Self {
+        self.integers = integers;
+        self
+    }
+
+    pub fn with_string_conversion(mut self, enabled: bool) -> Self {
+        self.string_conversion = enabled;
+        self
+    }
+
+    pub fn with_class_reopening(mut self, enabled: bool) -> Self {
+        self.reopen_classes = enabled;
+        self
+    }
+}
+
+/// The steps a run has taken, checked against [`LoxOptions::max_steps`] and
+/// [`LoxOptions::timeout`].
+#[derive(Debug, Default)]
+pub(crate) struct Budget {
+    steps: u64,
+    deadline: Option<Instant>,
+    /// Once a limit is reached, why, and the step the grace after it ends
+    /// at.
+    exceeded: Option<(Message, u64)>,
+}
+
+impl Budget {
+    /// Starts counting a new run from zero.
+    pub(crate) fn start(&mut self, options: &LoxOptions) {
+        self.steps = 0;
+        self.deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+        self.exceeded = None;
+    }
+
+    /// Counts one step, or returns why the run must stop.
+    pub(crate) fn step(&mut self, options: &LoxOptions) -> Result<(), Message> {
+        self.steps += 1;
+
+        if let Some((message, grace_end)) = &self.exceeded {
+            return match self.steps > *grace_end {
+                true => Err(message.clone()),
+                false => Ok(()),
+            };
+        }
+
+        let mut exceeded = None;
+        if let Some(max) = options.max_steps {
+            if self.steps > max {
+                let message = format!("Exceeded the limit of {max} steps");
+                exceeded = Some(Message::new(ErrorCode::StepLimit, message));
+            }
+        }
+        if let (Some(deadline), Some(timeout)) = (self.deadline, options.timeout) {
+            if self.steps.is_multiple_of(CLOCK_CHECK_INTERVAL) && Instant::now() >= deadline {
+                let message = format!("Timed out after {timeout:?}");
+                exceeded = Some(Message::new(ErrorCode::Timeout, message));
+            }
+        }
+
+        match exceeded {
+            Some(message) => {
+                self.exceeded = Some((message.clone(), self.steps + GRACE_STEPS));
+                Err(message)
+            }
+            None => Ok(()),
+        }
+    }
+}
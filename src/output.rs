@@ -0,0 +1,145 @@
+//! Where the lines a program prints go. They go to stdout unless an
+//! embedder supplies somewhere else: a buffer a test reads back, a log
+//! file, or a web page when rlox is compiled to WebAssembly, where there is
+//! no stdout to write to.
+
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+};
+
+/// Receives each line a program prints, with `print` or the `println` and
+/// `eprint` natives. A failed write stops the program with a runtime error.
+///
+/// Any `FnMut(&str)` closure is an `Output` that takes the lines `print`
+/// writes, so collecting them takes no more than:
+///
+/// ```
+/// use std::{cell::RefCell, rc::Rc};
+/// use rlox::Lox;
+///
+/// let lines = Rc::new(RefCell::new(vec![]));
+/// let sink = lines.clone();
+/// let mut lox = Lox::new().with_output(move |line: &str| {
+///     sink.borrow_mut().push(line.to_string());
+/// });
+///
+/// assert!(lox.eval("print 1 + 2;").is_ok());
+/// assert_eq!(*lines.borrow(), ["3"]);
+/// ```
+pub trait Output {
+    /// Takes one line written by `print` or `println`, without its newline.
+    fn print(&mut self, line: &str) -> io::Result<()>;
+
+    /// Takes one line written by `eprint`, meant for diagnostics rather
+    /// than the program's results. By default it goes to stderr.
+    fn eprint(&mut self, line: &str) -> io::Result<()> {
+        writeln!(io::stderr().lock(), "{line}")
+    }
+}
+
+/// Prints each line to the process's stdout. This is the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stdout;
+
+impl Output for Stdout {
+    fn print(&mut self, line: &str) -> io::Result<()> {
+        writeln!(io::stdout().lock(), "{line}")
+    }
+}
+
+/// Writes each printed line to `W`, such as a log file.
+#[derive(Debug, Default)]
+pub struct Writer<W: Write>(pub W);
+
+impl<W: Write> Output for Writer<W> {
+    fn print(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.0, "{line}")
+    }
+}
+
+/// Keeps everything printed in memory. Clones share the same buffer, so
+/// one can be handed to [`Lox::with_output`](crate::Lox::with_output) and
+/// another kept to read back what the program printed.
+///
+/// ```
+/// use rlox::{Capture, Lox};
+///
+/// let capture = Capture::default();
+/// let mut lox = Lox::new().with_output(capture.clone());
+///
+/// assert!(lox.eval(r#"print "a", 1; eprint("b");"#).is_ok());
+/// assert_eq!(capture.printed(), "a 1\n");
+/// assert_eq!(capture.eprinted(), "b\n");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Capture {
+    printed: Rc<RefCell<String>>,
+    eprinted: Rc<RefCell<String>>,
+}
+
+impl Capture {
+    /// Everything written by `print` so far, one line per statement.
+    pub fn printed(&self) -> String {
+        self.printed.borrow().clone()
+    }
+
+    /// Everything written by `eprint` so far.
+    pub fn eprinted(&self) -> String {
+        self.eprinted.borrow().clone()
+    }
+
+    /// Forgets everything written so far.
+    pub fn clear(&self) {
+        self.printed.borrow_mut().clear();
+        self.eprinted.borrow_mut().clear();
+    }
+}
+
+impl Output for Capture {
+    fn print(&mut self, line: &str) -> io::Result<()> {
+        let mut printed = self.printed.borrow_mut();
+        printed.push_str(line);
+        printed.push('\n');
+        Ok(())
+    }
+
+    fn eprint(&mut self, line: &str) -> io::Result<()> {
+        let mut eprinted = self.eprinted.borrow_mut();
+        eprinted.push_str(line);
+        eprinted.push('\n');
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&str)> Output for F {
+    fn print(&mut self, line: &str) -> io::Result<()> {
+        self(line);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, Capture, Lox};
+
+    #[test]
+    fn printed_lines_go_to_the_output_on_both_backends() {
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            let capture = Capture::default();
+            let mut lox = Lox::new()
+                .with_backend(backend)
+                .with_output(capture.clone());
+
+            // `eval` always runs on the tree-walker, so go through
+            // `run_source` to reach the vm.
+            let source = "print 1, \"a\"; eprint(nil); print [true]; println(2, \"b\"); eprint(); var p = println; p();";
+            if let Err(err) = lox.run_source(source) {
+                panic!("{backend:?}: {err}");
+            }
+            assert_eq!(capture.printed(), "1 a\n[true]\n2 b\n\n");
+            assert_eq!(capture.eprinted(), "nil\n\n");
+        }
+    }
+}
@@ -1,12 +1,45 @@
+use std::{cell::Cell, fmt::Display, rc::Rc};
+
 use crate::{
-    error::{Result, RuntimeError},
+    error::{ErrorCode, Message, Result, RuntimeError},
+    scanner::LoxDialect,
+    span::Span,
+    symbol::Symbol,
     token::{Token, TokenValue},
 };
 
-pub(crate) struct Parser {
-    tokens: Vec<Token>,
+/// Everything the parser managed to produce from a token stream. Statements
+/// that failed to parse are left out of `program` and their errors are
+/// collected in `errors`, so tooling can still work with the rest of the file.
+pub struct ParseOutput {
+    pub program: Vec<Stmt>,
+    pub errors: Vec<RuntimeError>,
+}
+
+/// Builds the syntax tree for a program from the tokens produced by a
+/// [`Scanner`](crate::Scanner). The result must go through a
+/// [`Resolver`](crate::Resolver) before an [`Interpreter`](crate::Interpreter)
+/// can run it.
+///
+/// Tokens are pulled from `I` as they are needed, so they can come straight
+/// from a scanner without being collected first. If they run out before an
+/// end-of-file token, the parser acts as if one followed.
+pub struct Parser<I: Iterator<Item = Token>> {
+    tokens: I,
+    /// The next token, which hasn't been consumed yet. Grammar rules only
+    /// ever look one token ahead.
+    current: Token,
+    /// The last token consumed.
+    previous: Token,
     errors: Vec<RuntimeError>,
-    position: usize,
+    repl: bool,
+    /// Whether `"a" + "b"` is folded into one string literal.
+    fold: bool,
+    /// Whether the extensions in [`LoxDialect::extension_syntax`] are parsed.
+    extensions: bool,
+    /// How many loops enclose the statement being parsed, within the
+    /// current function. `break` and `continue` are only allowed inside one.
+    loop_depth: usize,
 }
 
 /// Syntax Grammar for Lox
@@ -16,79 +49,147 @@ pub(crate) struct Parser {
 /// A program is a series of declarations, which are the statements that bind
 /// new identifiers or any of the other statement types.
 ///
-/// ```
+/// ```text
 /// declaration → classDecl
+///             | deferDecl
 ///             | funDecl
+///             | importDecl
 ///             | varDecl
 ///             | statement ;
 ///
 /// classDecl   → "class" IDENTIFIER ( "<" IDENTIFIER )?
-///               "{" function* "}" ;
+///               "{" method* "}" ;
+/// method      → "class"? ( function | IDENTIFIER block ) ;
+/// deferDecl   → "defer" statement ;
 /// funDecl     → "fun" function ;
-/// varDecl     → "var" IDENTIFIER ( "=" expression )? ";" ;
+/// importDecl  → "import" STRING ";" ;
+/// varDecl     → "var" IDENTIFIER ( "=" expression )? ";"
+///             | "var" "(" parameters ")" "=" expression ";" ;
 /// ```
 ///
+/// A deferred statement runs when the block or function it's in exits, after
+/// any deferred later in it.
+///
 /// ## Statements
 /// The remaining statement rules produce side effects, but do not introduce bindings.
 ///
 /// Note that `block` is a statement rule, but is also used as a non-terminal in
 /// a couple of other rules for things like function bodies.
 ///
-/// ```
+/// ```text
 /// statement   → exprStmt
+///             | breakStmt
+///             | continueStmt
 ///             | forStmt
 ///             | ifStmt
 ///             | printStmt
 ///             | returnStmt
+///             | throwStmt
+///             | tryStmt
+///             | useStmt
 ///             | whileStmt
 ///             | block ;
 ///
 /// exprStmt    → expression ";" ;
+/// breakStmt   → "break" ";" ;
+/// continueStmt → "continue" ";" ;
 /// forStmt     → "for" "(" ( varDecl | exprStmt | ";" )
 ///                         expression? ";"
-///                         expression? ")" statement ;
+///                         expression? ")" statement
+///             | "for" "(" IDENTIFIER ( "," IDENTIFIER )* "in" expression ")"
+///                         statement ;
 /// ifStmt      → "if" "(" expression ")" statement
 ///               ( "else" statement )? ;
-/// printStmt   → "print" expression ";" ;
-/// returnStmt  → "return" expression? ";" ;
+/// printStmt   → "print" expression ( "," expression )* ";" ;
+/// returnStmt  → "return" ( expression ( "," expression )* )? ";" ;
+/// throwStmt   → "throw" expression ";" ;
+/// tryStmt     → "try" block ( "catch" "(" IDENTIFIER ")" block )?
+///               ( "finally" block )? ;
+/// useStmt     → "use" "(" "var" IDENTIFIER "=" expression ")" block ;
 /// whileStmt   → "while" "(" expression ")" statement ;
 /// block       → "{" declaration* "}" ;
 /// ```
 ///
+/// A `try` needs a `catch`, a `finally` or both. A `use` calls its
+/// variable's `close` method however its block exits, even by an error.
+///
 /// ## Expressions
 /// Expressions produce values. Lox has a number of unary and binary operators
 /// with different levels of precedence. Some grammars for languages do not
 /// directly encode the precedence relationships and specify that elsewhere.
 /// Here, we use a separate rule for each precedence level to make it explicit.
 ///
-/// ```
+/// ```text
 /// expression  → assignment ;
 ///
-/// assignment  → ( call "." )? IDENTIFIER "=" assignment
-///             | logic_or ;
+/// assignment  → ( call "." )? IDENTIFIER assignOp assignment
+///             | call "[" expression "]" assignOp assignment
+///             | conditional ;
+/// assignOp    → "=" | "+=" | "-=" | "*=" | "/=" ;
+///
+/// conditional → logic_or ( "?" expression ":" conditional )? ;
 ///
 /// logic_or    → logic_and ( "or" logic_and )* ;
 /// logic_and   → equality ( "and" equality )* ;
 /// equality    → comparison ( ( "!=" | "==" ) comparison )* ;
 /// comparison  → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
 /// term        → factor ( ( "-" | "+" ) factor )* ;
-/// factor      → unary ( ( "/" | "*" ) unary )* ;
+/// factor      → unary ( ( "/" | "*" | "%" ) unary )* ;
 ///
-/// unary       → ( "!" | "-" ) unary | call ;
-/// call        → primary ( "(" arguments? ")" | "." IDENTIFIER )* ;
+/// unary       → ( "!" | "-" ) unary | power ;
+/// power       → update ( "**" unary )? ;
+/// update      → ( "++" | "--" ) call | call ( "++" | "--" )? ;
+/// call        → primary ( "(" callArgs? ")" | "." IDENTIFIER
+///                       | "[" expression "]" )* ;
 /// primary     → "true" | "false" | "nil" | "this"
-///             | NUMBER | STRING | IDENTIFIER | "(" expression ")"
-///             | "super" "." IDENTIFIER ;
+///             | NUMBER | STRING+ | IDENTIFIER | "(" expression ")"
+///             | "(" expression ( "," expression )+ ")"
+///             | "[" ( arguments ","? )? "]"
+///             | "{" ( entry ( "," entry )* ","? )? "}"
+///             | "super" "." IDENTIFIER
+///             | match ;
+///
+/// match       → "match" "(" expression ")" "{" ( pattern "=>" expression "," )*
+///               "else" "=>" expression ","? "}" ;
+/// pattern     → NUMBER | STRING | "-" NUMBER | "true" | "false" | "nil"
+///             | IDENTIFIER
+///             | IDENTIFIER "(" ( IDENTIFIER ( "," IDENTIFIER )* ","? )? ")"
+///             | "(" pattern ( "," pattern )* ")" ;
 /// ```
 ///
+/// A compound assignment like `a.b += c` is shorthand for `a.b = a.b + c`,
+/// except that the target's object and index are evaluated once, before `c`.
+///
+/// The operand of `++` or `--` must be a variable, property or index, the same
+/// targets `=` accepts. Its object and index are also evaluated once.
+///
+/// `**` is right-associative and binds tighter than a unary operator on its
+/// left, so `-2 ** 2` is `-(2 ** 2)`.
+///
+/// A parenthesized list of two or more expressions is a tuple, as is the
+/// value of `return a, b;`. `var (a, b) = t;` unpacks a tuple or list, which
+/// must have as many elements as there are names.
+///
+/// A `match` is the value of the first arm whose pattern matches, or of its
+/// `else` arm. A literal matches an equal value and a name matches anything,
+/// binding it for the arm. `Point(x, y)` matches an instance of `Point`, or
+/// of a subclass, with the fields `x` and `y`, binding them. A tuple pattern
+/// matches a tuple of as many elements, each matching its pattern.
+///
+/// String literals joined by `+`, as in `"a" + "b" + c`, are folded into one
+/// literal as far as the chain is made of them, here `"ab" + c`.
+///
 /// ## Utility Rules
 /// In order to keep the above rules a little cleaner, some of the grammar is
 /// split out into a few reused helper rules.
 ///
-/// ```
+/// ```text
 /// function    → IDENTIFIER "(" parameters? ")" block ;
 /// parameters  → IDENTIFIER ( "," IDENTIFIER )* ;
 /// arguments   → expression ( "," expression )* ;
+/// callArgs    → arguments ( "," keywordArg )* | keywordArg ( "," keywordArg )* ;
+/// keywordArg  → IDENTIFIER ":" expression ;
+/// entry       → expression ":" expression ;
 /// ```
 ///
 /// Lexical Grammar of Lox
@@ -96,435 +197,2645 @@ pub(crate) struct Parser {
 /// The lexical grammar is used by the scanner to group characters into tokens. Where the syntax is context free, the
 /// lexical grammar is regular—note that there are no recursive rules.
 ///
-/// ```
-/// NUMBER      → DIGIT+ ( "." DIGIT+ )? ;
-/// STRING      → "\"" <any char except "\"">* "\"" ;
+/// ```text
+/// NUMBER      → DIGITS ( "." DIGITS )? ( ( "e" | "E" ) ( "+" | "-" )? DIGITS )? ;
+/// DIGITS      → DIGIT ( "_"? DIGIT )* ;
+/// STRING      → "\"" ( <any char except "\"" or "\\"> | ESCAPE )* "\"" ;
+/// ESCAPE      → "\\" ( "n" | "t" | "\"" | "\\" ) ;
 /// IDENTIFIER  → ALPHA ( ALPHA | DIGIT )* ;
 /// ALPHA       → "a" ... "z" | "A" ... "Z" | "_";
 /// DIGIT       → "0" ... "9" ;
 /// ```
 ///
-impl Parser {
-    pub(crate) fn new(tokens: Vec<Token>) -> Self {
+impl<I: Iterator<Item = Token>> Parser<I> {
+    pub fn new(tokens: impl IntoIterator<Item = Token, IntoIter = I>) -> Self {
+        let mut tokens = tokens.into_iter();
+        let current = tokens
+            .next()
+            .unwrap_or_else(|| Token::new(TokenValue::Eof, "", 1));
+
         Self {
             tokens,
+            previous: current.clone(),
+            current,
             errors: vec![],
-            position: 0,
+            repl: false,
+            fold: true,
+            extensions: true,
+            loop_depth: 0,
         }
     }
 
-    pub(crate) fn parse(&mut self) -> Result<Vec<Stmt>> {
-        let mut statements = vec![];
-        let mut errors = vec![];
+    /// Keeps concatenations of string literals as written rather than
+    /// folding them into one literal, for tools that reprint the tree.
+    pub fn without_folding(mut self) -> Self {
+        self.fold = false;
+        self
+    }
 
-        if self.tokens.is_empty() {
-            return Ok(statements);
-        }
+    /// Parses only the syntax `dialect` allows. Without the extensions, what
+    /// the book's grammar doesn't have is reported as jlox would report it,
+    /// such as `print 1, 2;` expecting a `;` after the `1`.
+    pub fn with_dialect(mut self, dialect: LoxDialect) -> Self {
+        self.extensions = dialect.extension_syntax;
+        self
+    }
 
-        loop {
-            match self.declaration() {
-                Ok(stmt) => statements.push(stmt),
-                Err(err) => errors.push(err),
-            };
+    /// Lets the final expression statement omit its `;`, so a REPL user can
+    /// type `1 + 2` and have it evaluated.
+    pub fn repl_mode(mut self) -> Self {
+        self.repl = true;
+        self
+    }
 
-            if self.is_at_end() {
-                break;
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "parse", skip_all))]
+    pub fn parse(mut self) -> ParseOutput {
+        let mut program = vec![];
+
+        while !self.is_at_end() {
+            if let Some(stmt) = self.recovering_declaration() {
+                program.push(stmt);
             }
+        }
 
-            self.advance();
+        ParseOutput {
+            program,
+            errors: self.errors,
+        }
+    }
+
+    /// The token the next declaration starts with, for callers that parse a
+    /// program one declaration at a time with
+    /// [`recovering_declaration`](Self::recovering_declaration).
+    pub(crate) fn next_token(&self) -> &Token {
+        &self.current
+    }
+
+    /// The errors reported so far.
+    pub(crate) fn into_errors(self) -> Vec<RuntimeError> {
+        self.errors
+    }
+
+    /// Parses the tokens as exactly one expression with nothing after it.
+    pub fn parse_expression(mut self) -> Result<Expr> {
+        let expr = self.expression()?;
+
+        if let Some(err) = self.errors.pop() {
+            return Err(err);
         }
 
-        if errors.is_empty() {
-            Ok(statements)
-        } else {
-            Err(RuntimeError::general_error("Errors occurred"))
+        if !self.is_at_end() {
+            return Err(RuntimeError::ParseError(
+                "Expected end of expression".into(),
+                self.peek(),
+            ));
         }
+
+        Ok(expr)
     }
 
     fn previous(&self) -> Token {
-        self.tokens[self.position - 1].clone()
+        self.previous.clone()
     }
 
-    fn current(&self) -> Token {
-        self.tokens[self.position].clone()
+    fn peek(&self) -> Token {
+        self.current.clone()
     }
 
     fn advance(&mut self) -> Token {
         if !self.is_at_end() {
-            self.position += 1;
+            let next = self.tokens.next().unwrap_or_else(|| {
+                let end = self.current.span.end;
+                let mut eof = Token::new(TokenValue::Eof, "", self.current.line);
+                eof.span = Span::new(end, end);
+                eof
+            });
+            self.previous = std::mem::replace(&mut self.current, next);
         }
 
-        self.current()
+        self.previous()
     }
 
-    fn is_match(&mut self, types: &[TokenValue]) -> bool {
-        match self.peek() {
-            Err(_) => false,
-            Ok(token) => types.contains(&token.value),
-        }
+    /// The span from `start` to the end of the last consumed token.
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous().span)
     }
 
-    fn is_at_end(&self) -> bool {
-        self.peek().is_err()
+    fn check(&self, expected: &TokenValue) -> bool {
+        &self.current.value == expected
     }
 
-    fn peek(&self) -> Result<Token> {
-        let offset = self.position + 1;
-        match offset >= self.tokens.len() {
-            false => Ok(self.tokens[offset].clone()),
-            true => Err(RuntimeError::general_error(
-                "Unexpected end of token stream",
-            )),
+    /// Consumes the next token if it is one of `types`.
+    fn is_match(&mut self, types: &[TokenValue]) -> bool {
+        if types.iter().any(|expected| self.check(expected)) {
+            self.advance();
+            true
+        } else {
+            false
         }
     }
 
-    fn consume(&mut self, expected: TokenValue, message: &str) -> Result<Token> {
-        let token = self.peek()?;
+    pub(crate) fn is_at_end(&self) -> bool {
+        self.check(&TokenValue::Eof)
+    }
 
-        if token.value == expected {
-            self.advance();
-            Ok(token)
-        } else {
-            Err(RuntimeError::ParseError(message.into(), token))
+    fn consume(&mut self, expected: TokenValue, message: &str) -> Result<Token> {
+        if self.check(&expected) {
+            return Ok(self.advance());
         }
+
+        let message = match expected {
+            TokenValue::Semicolon => Message::new(ErrorCode::MissingSemicolon, message),
+            _ => message.into(),
+        };
+        Err(parse_error(message, self.peek()))
     }
 
     fn consume_identifier(&mut self, message: &str) -> Result<Token> {
-        let token = self.peek()?;
+        let token = self.peek();
 
         match token.value {
-            TokenValue::Identifier(_) => {
+            TokenValue::Identifier(_) => Ok(self.advance()),
+            // Report the misuse but keep going as if it were a plain
+            // identifier, so the rest of the statement is still checked.
+            ref keyword if keyword.is_keyword() => {
+                self.report(RuntimeError::ParseError(
+                    format!(
+                        "`{}` is a reserved word and cannot be used as an identifier",
+                        token.lexeme
+                    )
+                    .into(),
+                    token.clone(),
+                ));
                 self.advance();
-                Ok(token)
+                Ok(Token {
+                    value: TokenValue::Identifier(token.symbol()),
+                    ..token
+                })
             }
-            _ => Err(RuntimeError::ParseError(message.into(), token)),
+            _ => Err(parse_error(message, token)),
         }
     }
 
-    fn declaration(&mut self) -> Result<Stmt> {
+    /// Parses a declaration, or records its error and skips ahead to where
+    /// the next statement probably starts so the rest of the source still
+    /// gets checked.
+    pub(crate) fn recovering_declaration(&mut self) -> Option<Stmt> {
+        match self.declaration() {
+            Ok(stmt) => Some(stmt),
+            Err(err) => {
+                self.report(err);
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    /// Records `err`, unless it's about an error token, whose scan error
+    /// already explains what went wrong.
+    fn report(&mut self, err: RuntimeError) {
+        if let RuntimeError::ParseError(_, token) | RuntimeError::UnexpectedEof(_, token) = &err {
+            if token.value == TokenValue::Error {
+                return;
+            }
+        }
+
+        self.errors.push(err);
+    }
+
+    /// Discards tokens until just after a `;` or just before a keyword that
+    /// starts a statement. Errors found between the bad token and that point
+    /// would only be consequences of the first one.
+    fn synchronize(&mut self) {
         self.advance();
-        let token = self.current();
 
-        match token.value {
-            TokenValue::Class => todo!(),
-            TokenValue::Fun => todo!(),
-            TokenValue::Var => todo!(),
-            _ => self.statement(),
+        while !self.is_at_end() {
+            if self.previous().value == TokenValue::Semicolon {
+                return;
+            }
+
+            match self.peek().value {
+                TokenValue::Class
+                | TokenValue::Fun
+                | TokenValue::Import
+                | TokenValue::Var
+                | TokenValue::For
+                | TokenValue::If
+                | TokenValue::While
+                | TokenValue::Print
+                | TokenValue::Return => return,
+                _ => self.advance(),
+            };
         }
     }
 
-    fn statement(&mut self) -> Result<Stmt> {
-        match self.current().value {
-            TokenValue::For => todo!(),
-            TokenValue::If => todo!(),
-            TokenValue::Print => todo!(),
-            TokenValue::Return => todo!(),
-            TokenValue::While => todo!(),
-            TokenValue::LeftBrace => self.block(),
-            _ => self.expression_statement(),
+    fn declaration(&mut self) -> Result<Stmt> {
+        match self.peek().value {
+            TokenValue::Class => {
+                self.advance();
+                self.class_declaration()
+            }
+            TokenValue::Defer => {
+                self.advance();
+                self.defer_declaration()
+            }
+            TokenValue::Fun => {
+                let start = self.advance().span;
+                Ok(Stmt::Function(Rc::new(self.function("function", start)?)))
+            }
+            TokenValue::Import => {
+                self.advance();
+                self.import_declaration()
+            }
+            TokenValue::Var => {
+                self.advance();
+                self.var_declaration()
+            }
+            _ => self.statement(),
         }
     }
 
-    // fn while_s
+    fn import_declaration(&mut self) -> Result<Stmt> {
+        let keyword = self.previous();
+        let path = match self.peek().value {
+            TokenValue::String(_) => self.advance(),
+            _ => return Err(parse_error("Expected import path", self.peek())),
+        };
 
-    fn block(&mut self) -> Result<Stmt> {
-        let mut statements = vec![];
+        self.consume(TokenValue::Semicolon, "Expected `;` after import path")?;
 
-        while !self.is_match(&[TokenValue::RightBrace]) {
-            statements.push(self.declaration()?);
-        }
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::Import {
+            keyword,
+            path,
+            span,
+        })
+    }
 
-        self.consume(TokenValue::RightBrace, "Expected `}` after block")?;
+    fn defer_declaration(&mut self) -> Result<Stmt> {
+        let keyword = self.previous();
+        let statement = Rc::new(self.statement()?);
 
-        Ok(Stmt::Block(statements))
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::Defer {
+            keyword,
+            statement,
+            span,
+        })
     }
 
-    fn expression_statement(&mut self) -> Result<Stmt> {
-        let expr = self.expression()?;
-        self.consume(TokenValue::Semicolon, "Expected `;` after expression")?;
-        Ok(Stmt::Expression(expr))
+    fn class_declaration(&mut self) -> Result<Stmt> {
+        let start = self.previous().span;
+        let name = self.consume_identifier("Expected class name")?;
+
+        let superclass = match self.is_match(&[TokenValue::Less]) {
+            true => {
+                let superclass = self.consume_identifier("Expected superclass name")?;
+
+                if superclass.lexeme == name.lexeme {
+                    return Err(RuntimeError::ParseError(
+                        "A class can't inherit from itself".into(),
+                        superclass,
+                    ));
+                }
+
+                Some(Expr::Variable {
+                    name: superclass,
+                    depth: Cell::default(),
+                })
+            }
+            false => None,
+        };
+
+        self.consume(TokenValue::LeftBrace, "Expected `{` before class body")?;
+
+        let mut methods = vec![];
+
+        while !self.check(&TokenValue::RightBrace) && !self.is_at_end() {
+            methods.push(Rc::new(self.method()?));
+        }
+
+        self.consume(TokenValue::RightBrace, "Expected `}` after class body")?;
+
+        Ok(Stmt::Class {
+            name,
+            superclass,
+            methods,
+            span: self.span_from(start),
+        })
     }
 
-    fn expression(&mut self) -> Result<Expr> {
-        self.assignment()
+    /// Parses the shared `function` rule. `kind` is only used to word errors,
+    /// and `start` is where the declaration began, including any `fun`.
+    fn function(&mut self, kind: &str, start: Span) -> Result<FunctionDecl> {
+        let name = self.consume_identifier(&format!("Expected {kind} name"))?;
+        let params = self.parameters(kind)?;
+        let body = self.function_body(kind)?;
+
+        Ok(FunctionDecl {
+            name,
+            params,
+            body,
+            is_getter: false,
+            is_class_method: false,
+            span: self.span_from(start),
+        })
     }
 
-    fn assignment(&mut self) -> Result<Expr> {
-        let mut expr = self.logic_or()?;
+    /// Parses a method in a class body. A method marked `class` is called on
+    /// the class itself, and one without a parameter list is a getter.
+    fn method(&mut self) -> Result<FunctionDecl> {
+        let start = self.peek().span;
+        let is_class_method = self.extensions && self.is_match(&[TokenValue::Class]);
+        let name = self.consume_identifier("Expected method name")?;
 
-        if self.is_match(&[TokenValue::Equal]) {
-            let value = self.assignment()?;
-            expr = match expr {
-                Expr::Variable { name } => Expr::Assign {
-                    name,
-                    value: Box::new(value),
-                },
-                Expr::Get { name, object } => Expr::Set {
-                    object,
-                    name,
-                    value: Box::new(value),
-                },
-                _ => return Err(RuntimeError::InvalidArgumentTarget("todo".into())),
-            };
-        }
+        let is_getter = self.extensions && self.check(&TokenValue::LeftBrace);
+        let params = match is_getter {
+            true => vec![],
+            false => self.parameters("method")?,
+        };
+        let body = self.function_body("method")?;
 
-        Ok(expr)
+        Ok(FunctionDecl {
+            name,
+            params,
+            body,
+            is_getter,
+            is_class_method,
+            span: self.span_from(start),
+        })
     }
 
-    fn logic_or(&mut self) -> Result<Expr> {
-        let mut expr = self.logic_and()?;
+    fn parameters(&mut self, kind: &str) -> Result<Vec<Token>> {
+        self.consume(
+            TokenValue::LeftParen,
+            &format!("Expected `(` after {kind} name"),
+        )?;
 
-        while self.is_match(&[TokenValue::Or]) {
-            self.advance();
-            let operator = self.current();
-            let and = self.logic_and()?;
-            expr = Expr::Logical {
-                right: Box::new(expr),
-                operator,
-                left: Box::new(and),
-            };
+        let mut params = vec![];
+
+        if !self.check(&TokenValue::RightParen) {
+            loop {
+                if params.len() >= MAX_ARGUMENTS {
+                    return Err(RuntimeError::ParseError(
+                        format!("Can't have more than {MAX_ARGUMENTS} parameters").into(),
+                        self.peek(),
+                    ));
+                }
+
+                params.push(self.consume_identifier("Expected parameter name")?);
+
+                if !self.is_match(&[TokenValue::Comma]) {
+                    break;
+                }
+            }
         }
 
-        Ok(expr)
+        self.consume(TokenValue::RightParen, "Expected `)` after parameters")?;
+        Ok(params)
     }
 
-    fn logic_and(&mut self) -> Result<Expr> {
-        let mut expr = self.equality()?;
+    fn function_body(&mut self, kind: &str) -> Result<Vec<Stmt>> {
+        self.consume(
+            TokenValue::LeftBrace,
+            &format!("Expected `{{` before {kind} body"),
+        )?;
 
-        while self.is_match(&[TokenValue::And]) {
-            self.advance();
-            let operator = self.current();
-            let equality = self.equality()?;
-            expr = Expr::Logical {
-                right: Box::new(expr),
-                operator,
-                left: Box::new(equality),
-            };
+        // A loop around the declaration doesn't reach into the body.
+        let enclosing_loops = std::mem::take(&mut self.loop_depth);
+        let body = self.block();
+        self.loop_depth = enclosing_loops;
+        body
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt> {
+        let start = self.previous().span;
+        if self.extensions && self.is_match(&[TokenValue::LeftParen]) {
+            return self.unpack_declaration(start);
         }
+        let name = self.consume_identifier("Expected variable name")?;
+        let initializer = match self.is_match(&[TokenValue::Equal]) {
+            true => Some(self.expression()?),
+            false => None,
+        };
 
-        Ok(expr)
-    }
+        self.consume(
+            TokenValue::Semicolon,
+            "Expected `;` after variable declaration",
+        )?;
 
-    fn equality(&mut self) -> Result<Expr> {
-        let mut expr = self.comparison()?;
+        Ok(Stmt::Var {
+            name,
+            initializer,
+            span: self.span_from(start),
+        })
+    }
 
-        while self.is_match(&[TokenValue::BangEqual, TokenValue::EqualEqual]) {
-            self.advance();
-            let operator = self.current();
-            let factor = self.comparison()?;
-            expr = Expr::Equality {
-                operator,
-                right: Box::new(factor),
-            };
+    /// Parses the rest of `var (a, b) = initializer;`.
+    fn unpack_declaration(&mut self, start: Span) -> Result<Stmt> {
+        let mut names = vec![self.consume_identifier("Expected variable name")?];
+        while self.is_match(&[TokenValue::Comma]) {
+            names.push(self.consume_identifier("Expected variable name")?);
         }
 
-        Ok(expr)
+        self.consume(
+            TokenValue::RightParen,
+            "Expected `)` after the variable names",
+        )?;
+        self.consume(TokenValue::Equal, "Expected `=` after the variable names")?;
+        let initializer = self.expression()?;
+        self.consume(
+            TokenValue::Semicolon,
+            "Expected `;` after variable declaration",
+        )?;
+
+        Ok(Stmt::Unpack {
+            names,
+            initializer,
+            span: self.span_from(start),
+        })
     }
 
-    fn comparison(&mut self) -> Result<Expr> {
-        let mut expr = self.term()?;
+    fn statement(&mut self) -> Result<Stmt> {
+        match self.peek().value {
+            TokenValue::For => {
+                self.advance();
+                self.for_statement()
+            }
+            TokenValue::If => {
+                self.advance();
+                self.if_statement()
+            }
+            TokenValue::Print => {
+                self.advance();
+                self.print_statement()
+            }
+            TokenValue::Return => {
+                self.advance();
+                self.return_statement()
+            }
+            TokenValue::Break | TokenValue::Continue => {
+                self.advance();
+                self.loop_control_statement()
+            }
+            TokenValue::While => {
+                self.advance();
+                self.while_statement()
+            }
+            TokenValue::Try => {
+                self.advance();
+                self.try_statement()
+            }
+            TokenValue::Throw => {
+                self.advance();
+                self.throw_statement()
+            }
+            TokenValue::Use => {
+                self.advance();
+                self.use_statement()
+            }
+            TokenValue::LeftBrace => {
+                let start = self.advance().span;
+                let statements = self.block()?;
+                Ok(Stmt::Block {
+                    statements,
+                    span: self.span_from(start),
+                })
+            }
+            _ => self.expression_statement(),
+        }
+    }
 
-        while self.is_match(&[
-            TokenValue::Greater,
-            TokenValue::GreaterEqual,
-            TokenValue::Less,
-            TokenValue::LessEqual,
-        ]) {
-            self.advance();
-            let operator = self.current();
-            let factor = self.term()?;
-            expr = Expr::Comparison {
-                operator,
-                right: Box::new(factor),
+    /// There is no dedicated node for a C-style for loop: it's desugared
+    /// into the equivalent block and while statement. A for-in loop, whose
+    /// names can only be told from an initializer once the expression is
+    /// parsed, is a [`Stmt::ForIn`].
+    fn for_statement(&mut self) -> Result<Stmt> {
+        let start = self.previous().span;
+        let keyword = self.previous();
+        self.consume(TokenValue::LeftParen, "Expected `(` after `for`")?;
+
+        let initializer = match self.peek().value {
+            TokenValue::Semicolon => {
+                self.advance();
+                None
+            }
+            TokenValue::Var => {
+                self.advance();
+                Some(self.var_declaration()?)
+            }
+            _ => {
+                let expr = self.expression()?;
+                match expr {
+                    Expr::Variable { name, .. }
+                        if self.check(&TokenValue::In) || self.check(&TokenValue::Comma) =>
+                    {
+                        return self.for_in_statement(keyword, name);
+                    }
+                    expr => {
+                        self.consume(TokenValue::Semicolon, "Expected `;` after expression")?;
+                        Some(Stmt::Expression(expr))
+                    }
+                }
+            }
+        };
+
+        let condition = match self.check(&TokenValue::Semicolon) {
+            true => Expr::Literal {
+                value: Literal::True,
+                span: Span::new(self.peek().span.start, self.peek().span.start),
+            },
+            false => self.expression()?,
+        };
+        self.consume(TokenValue::Semicolon, "Expected `;` after loop condition")?;
+
+        let increment = match self.check(&TokenValue::RightParen) {
+            true => None,
+            false => Some(self.expression()?),
+        };
+        self.consume(TokenValue::RightParen, "Expected `)` after for clauses")?;
+
+        let body = self.loop_body()?;
+        let span = self.span_from(start);
+
+        let mut body = Stmt::While {
+            condition,
+            body: Box::new(body),
+            increment,
+            span,
+        };
+
+        if let Some(initializer) = initializer {
+            body = Stmt::Block {
+                statements: vec![initializer, body],
+                span,
             };
         }
 
-        Ok(expr)
+        Ok(body)
     }
 
-    fn term(&mut self) -> Result<Expr> {
-        let mut expr = self.factor()?;
-
-        while self.is_match(&[TokenValue::Minus, TokenValue::Plus]) {
-            self.advance();
-            let operator = self.current();
-            let factor = self.factor()?;
-            expr = Expr::Term {
-                operator,
-                right: Box::new(factor),
-            };
+    /// Parses the rest of a for-in loop, whose first name was just parsed.
+    fn for_in_statement(&mut self, keyword: Token, first: Token) -> Result<Stmt> {
+        let mut names = vec![first];
+        while self.is_match(&[TokenValue::Comma]) {
+            names.push(self.consume_identifier("Expected variable name")?);
         }
 
-        Ok(expr)
+        self.consume(TokenValue::In, "Expected `in` after the loop's variables")?;
+        let iterable = self.expression()?;
+        self.consume(TokenValue::RightParen, "Expected `)` after for-in clause")?;
+        let body = Box::new(self.loop_body()?);
+
+        Ok(Stmt::ForIn {
+            span: self.span_from(keyword.span),
+            keyword,
+            names,
+            iterable,
+            body,
+        })
     }
 
-    fn factor(&mut self) -> Result<Expr> {
-        let mut expr = self.unary()?;
+    fn if_statement(&mut self) -> Result<Stmt> {
+        let start = self.previous().span;
+        self.consume(TokenValue::LeftParen, "Expected `(` after `if`")?;
+        let condition = self.expression()?;
+        self.consume(TokenValue::RightParen, "Expected `)` after if condition")?;
 
-        while self.is_match(&[TokenValue::Star, TokenValue::Slash]) {
-            self.advance();
-            let operator = self.current();
-            let unary = self.unary()?;
-            expr = Expr::Factor {
-                operator,
-                right: Box::new(unary),
-            };
-        }
+        let then_branch = Box::new(self.statement()?);
+        let else_branch = match self.is_match(&[TokenValue::Else]) {
+            true => Some(Box::new(self.statement()?)),
+            false => None,
+        };
 
-        Ok(expr)
+        Ok(Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            span: self.span_from(start),
+        })
     }
 
-    fn unary(&mut self) -> Result<Expr> {
-        if self.is_match(&[TokenValue::Bang, TokenValue::Minus]) {
-            self.advance();
-            let operator = self.current();
-            let unary = self.unary()?;
-            Ok(Expr::Unary {
-                operator,
-                right: Box::new(unary),
-            })
-        } else {
-            self.call()
-        }
+    fn while_statement(&mut self) -> Result<Stmt> {
+        let start = self.previous().span;
+        self.consume(TokenValue::LeftParen, "Expected `(` after `while`")?;
+        let condition = self.expression()?;
+        self.consume(TokenValue::RightParen, "Expected `)` after condition")?;
+        let body = Box::new(self.loop_body()?);
+
+        Ok(Stmt::While {
+            condition,
+            body,
+            increment: None,
+            span: self.span_from(start),
+        })
     }
 
-    fn call(&mut self) -> Result<Expr> {
-        let mut expr = self.primary()?;
+    fn loop_body(&mut self) -> Result<Stmt> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
 
-        loop {
-            if self.is_match(&[TokenValue::LeftParen]) {
-                self.advance();
-                let mut arguments = vec![];
+    /// Parses `break` or `continue`, whose keyword was just consumed. Using
+    /// one outside a loop is reported, but parsing carries on.
+    fn loop_control_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous();
 
-                loop {
-                    if arguments.len() > 255 {
-                        // todo - improve this error
-                        return Err(RuntimeError::general_error("Too many arguments"));
-                    }
+        if self.loop_depth == 0 {
+            self.report(RuntimeError::ParseError(
+                Message::new(
+                    ErrorCode::OutsideConstruct,
+                    format!("Can't use `{}` outside of a loop", keyword.lexeme),
+                ),
+                keyword.clone(),
+            ));
+        }
 
-                    arguments.push(self.assignment()?);
-                }
+        self.consume(
+            TokenValue::Semicolon,
+            &format!("Expected `;` after `{}`", keyword.lexeme),
+        )?;
 
-                todo!();
-            } else if self.is_match(&[TokenValue::Dot]) {
-                self.consume(TokenValue::Dot, "Expected `.`")?;
-                let name = self.consume_identifier("Expected property name after `.`")?;
-                expr = Expr::Get {
+        let span = self.span_from(keyword.span);
+        Ok(match keyword.value {
+            TokenValue::Break => Stmt::Break { keyword, span },
+            _ => Stmt::Continue { keyword, span },
+        })
+    }
+
+    /// Parses `try`, whose keyword was just consumed, followed by a `catch`
+    /// clause, a `finally` clause or both.
+    fn try_statement(&mut self) -> Result<Stmt> {
+        let start = self.previous().span;
+        let body_start = self
+            .consume(TokenValue::LeftBrace, "Expected `{` after `try`")?
+            .span;
+        let body = self.block()?;
+        let body_span = self.span_from(body_start);
+
+        let catch = match self.is_match(&[TokenValue::Catch]) {
+            true => {
+                let catch_start = self.previous().span;
+                self.consume(TokenValue::LeftParen, "Expected `(` after `catch`")?;
+                let name = self.consume_identifier("Expected a variable name in `catch`")?;
+                self.consume(TokenValue::RightParen, "Expected `)` after the variable")?;
+                self.consume(TokenValue::LeftBrace, "Expected `{` after `catch (...)`")?;
+                let body = self.block()?;
+                Some(Catch {
                     name,
-                    object: Box::new(expr),
-                }
-            } else {
-                break;
+                    body,
+                    span: self.span_from(catch_start),
+                })
             }
+            false => None,
+        };
+
+        let finally = match self.is_match(&[TokenValue::Finally]) {
+            true => {
+                self.consume(TokenValue::LeftBrace, "Expected `{` after `finally`")?;
+                Some(self.block()?.into())
+            }
+            false => None,
+        };
+
+        if catch.is_none() && finally.is_none() {
+            return Err(RuntimeError::ParseError(
+                "Expected `catch` or `finally` after the `try` block".into(),
+                self.peek(),
+            ));
         }
 
-        Ok(expr)
+        Ok(Stmt::Try {
+            body,
+            body_span,
+            catch,
+            finally,
+            span: self.span_from(start),
+        })
     }
 
-    fn primary(&mut self) -> Result<Expr> {
-        let token = self.current();
-        let res = match token.value.clone() {
-            TokenValue::True => Expr::Literal(Literal::True),
-            TokenValue::False => Expr::Literal(Literal::False),
-            TokenValue::Nil => Expr::Literal(Literal::Nil),
-            TokenValue::This => Expr::This { keyword: token },
-            TokenValue::Number(n) => Expr::Literal(Literal::Number(n)),
-            TokenValue::String(s) => Expr::Literal(Literal::String(s)),
-            TokenValue::Identifier(_) => Expr::Variable { name: token },
-            TokenValue::LeftParen => {
-                self.advance();
-                let expr = self.expression()?;
-                self.consume(TokenValue::RightParen, "Expected `)` after expression")?;
-                Expr::Grouping {
-                    group: Box::new(expr),
+    fn throw_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous();
+        let value = self.expression()?;
+        self.consume(TokenValue::Semicolon, "Expected `;` after the thrown value")?;
+
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::Throw {
+            keyword,
+            value,
+            span,
+        })
+    }
+
+    fn use_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous();
+        self.consume(TokenValue::LeftParen, "Expected `(` after `use`")?;
+        self.consume(TokenValue::Var, "Expected `var` to declare the resource")?;
+        let name = self.consume_identifier("Expected a variable name")?;
+        self.consume(TokenValue::Equal, "Expected `=` after the variable name")?;
+        let initializer = self.expression()?;
+        self.consume(TokenValue::RightParen, "Expected `)` after the resource")?;
+        let body_start = self
+            .consume(TokenValue::LeftBrace, "Expected `{` after `use (...)`")?
+            .span;
+        let body = self.block()?;
+        let body_span = self.span_from(body_start);
+
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::Use {
+            keyword,
+            name,
+            initializer,
+            body,
+            body_span,
+            span,
+        })
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt> {
+        let start = self.previous().span;
+        let mut values = vec![self.expression()?];
+
+        while self.extensions && self.is_match(&[TokenValue::Comma]) {
+            values.push(self.expression()?);
+        }
+
+        self.consume(TokenValue::Semicolon, "Expected `;` after value")?;
+        Ok(Stmt::Print {
+            values,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parses the rest of a tuple `(first, ...)` that started at `start`.
+    fn tuple(&mut self, first: Expr, start: Span) -> Result<Expr> {
+        let mut elements = vec![first];
+        while self.is_match(&[TokenValue::Comma]) {
+            elements.push(self.expression()?);
+        }
+        self.consume(
+            TokenValue::RightParen,
+            "Expected `)` after the tuple's elements",
+        )?;
+
+        Ok(Expr::Tuple {
+            elements,
+            span: self.span_from(start),
+        })
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous();
+        let value = match self.check(&TokenValue::Semicolon) {
+            true => None,
+            false => Some(self.expression()?),
+        };
+
+        // `return a, b;` returns a tuple.
+        let value = match value {
+            Some(first) if self.extensions && self.check(&TokenValue::Comma) => {
+                let mut elements = vec![first];
+                while self.is_match(&[TokenValue::Comma]) {
+                    elements.push(self.expression()?);
+                }
+                let span = elements[0].span().to(self.previous().span);
+                Some(Expr::Tuple { elements, span })
+            }
+            value => value,
+        };
+
+        self.consume(TokenValue::Semicolon, "Expected `;` after return value")?;
+
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::Return {
+            keyword,
+            value,
+            span,
+        })
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>> {
+        let mut statements = vec![];
+
+        while !self.check(&TokenValue::RightBrace) && !self.is_at_end() {
+            if let Some(stmt) = self.recovering_declaration() {
+                statements.push(stmt);
+            }
+        }
+
+        self.consume(TokenValue::RightBrace, "Expected `}` after block")?;
+
+        Ok(statements)
+    }
+
+    fn expression_statement(&mut self) -> Result<Stmt> {
+        let expr = self.expression()?;
+
+        if self.repl && self.is_at_end() {
+            return Ok(Stmt::Expression(expr));
+        }
+
+        self.consume(TokenValue::Semicolon, "Expected `;` after expression")?;
+        Ok(Stmt::Expression(expr))
+    }
+
+    fn expression(&mut self) -> Result<Expr> {
+        self.assignment()
+    }
+
+    fn assignment(&mut self) -> Result<Expr> {
+        let expr = self.conditional()?;
+
+        if self.is_match(&[
+            TokenValue::Equal,
+            TokenValue::PlusEqual,
+            TokenValue::MinusEqual,
+            TokenValue::StarEqual,
+            TokenValue::SlashEqual,
+        ]) {
+            let assign = self.previous();
+            let value = self.assignment()?;
+            if let Some(operator) = compound_operator(&assign) {
+                if !matches!(
+                    expr,
+                    Expr::Variable { .. } | Expr::Get { .. } | Expr::Index { .. }
+                ) {
+                    return Err(RuntimeError::InvalidArgumentTarget(
+                        "Invalid assignment target".into(),
+                        assign,
+                    ));
+                }
+                return Ok(Expr::Compound {
+                    target: Box::new(expr),
+                    operator,
+                    value: Box::new(value),
+                });
+            }
+            return match expr {
+                Expr::Variable { name, .. } => Ok(Expr::Assign {
+                    name,
+                    value: Box::new(value),
+                    depth: Cell::default(),
+                }),
+                Expr::Get { name, object } => Ok(Expr::Set {
+                    object,
+                    name,
+                    value: Box::new(value),
+                }),
+                Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                } => Ok(Expr::SetIndex {
+                    object,
+                    bracket,
+                    index,
+                    value: Box::new(value),
+                }),
+                _ => Err(RuntimeError::InvalidArgumentTarget(
+                    "Invalid assignment target".into(),
+                    assign,
+                )),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    /// Parses `condition ? then : else`. The else branch recurses, so
+    /// conditionals nest to the right.
+    fn conditional(&mut self) -> Result<Expr> {
+        let condition = self.logic_or()?;
+
+        if !self.is_match(&[TokenValue::Question]) {
+            return Ok(condition);
+        }
+
+        let then_branch = self.expression()?;
+        self.consume(
+            TokenValue::Colon,
+            "Expected `:` and an else branch after the then branch of `?`",
+        )?;
+
+        let else_branch = self.conditional()?;
+
+        Ok(Expr::Conditional {
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch: Box::new(else_branch),
+        })
+    }
+
+    fn logic_or(&mut self) -> Result<Expr> {
+        let mut expr = self.logic_and()?;
+
+        while self.is_match(&[TokenValue::Or]) {
+            let operator = self.previous();
+            let right = self.logic_and()?;
+            expr = Expr::Logical {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn logic_and(&mut self) -> Result<Expr> {
+        let mut expr = self.equality()?;
+
+        while self.is_match(&[TokenValue::And]) {
+            let operator = self.previous();
+            let right = self.equality()?;
+            expr = Expr::Logical {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn equality(&mut self) -> Result<Expr> {
+        self.binary(
+            &[TokenValue::BangEqual, TokenValue::EqualEqual],
+            Self::comparison,
+        )
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        self.binary(
+            &[
+                TokenValue::Greater,
+                TokenValue::GreaterEqual,
+                TokenValue::Less,
+                TokenValue::LessEqual,
+            ],
+            Self::term,
+        )
+    }
+
+    fn term(&mut self) -> Result<Expr> {
+        self.binary(&[TokenValue::Minus, TokenValue::Plus], Self::factor)
+    }
+
+    fn factor(&mut self) -> Result<Expr> {
+        self.binary(
+            &[TokenValue::Star, TokenValue::Slash, TokenValue::Percent],
+            Self::unary,
+        )
+    }
+
+    /// Parses a left-associative chain of `operand`s joined by any of
+    /// `operators`, the shape shared by every binary precedence level.
+    fn binary(
+        &mut self,
+        operators: &[TokenValue],
+        operand: fn(&mut Self) -> Result<Expr>,
+    ) -> Result<Expr> {
+        let mut expr = operand(self)?;
+
+        while self.is_match(operators) {
+            let operator = self.previous();
+            let right = operand(self)?;
+            expr = match (self.fold, expr, right) {
+                (
+                    true,
+                    Expr::Literal {
+                        value: Literal::String(left),
+                        span,
+                    },
+                    Expr::Literal {
+                        value: Literal::String(right),
+                        span: right_span,
+                    },
+                ) if operator.value == TokenValue::Plus => Expr::Literal {
+                    value: Literal::String(format!("{left}{right}").into()),
+                    span: span.to(right_span),
+                },
+                (_, left, right) => Expr::Binary {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                },
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.is_match(&[TokenValue::Bang, TokenValue::Minus]) {
+            let operator = self.previous();
+            let right = self.unary()?;
+            Ok(Expr::Unary {
+                operator,
+                right: Box::new(right),
+            })
+        } else {
+            self.power()
+        }
+    }
+
+    /// The exponent is parsed as a unary, so `2 ** -1` works and `2 ** 3 ** 2`
+    /// nests to the right.
+    fn power(&mut self) -> Result<Expr> {
+        let base = self.update()?;
+
+        if !self.is_match(&[TokenValue::StarStar]) {
+            return Ok(base);
+        }
+
+        let operator = self.previous();
+        let exponent = self.unary()?;
+        Ok(Expr::Binary {
+            left: Box::new(base),
+            operator,
+            right: Box::new(exponent),
+        })
+    }
+
+    fn update(&mut self) -> Result<Expr> {
+        let increments = [TokenValue::PlusPlus, TokenValue::MinusMinus];
+
+        if self.is_match(&increments) {
+            let operator = self.previous();
+            let target = self.call()?;
+            return Ok(self.update_target(operator, target, true));
+        }
+
+        let target = self.call()?;
+        match self.is_match(&increments) {
+            true => Ok(self.update_target(self.previous(), target, false)),
+            false => Ok(target),
+        }
+    }
+
+    /// Wraps `target` in an [`Expr::Update`], reporting an error if it can't
+    /// be assigned to.
+    fn update_target(&mut self, operator: Token, target: Expr, prefix: bool) -> Expr {
+        if !matches!(
+            target,
+            Expr::Variable { .. } | Expr::Get { .. } | Expr::Index { .. }
+        ) {
+            self.report(RuntimeError::ParseError(
+                format!(
+                    "The operand of `{}` must be a variable, property or index",
+                    operator.lexeme
+                )
+                .into(),
+                operator.clone(),
+            ));
+        }
+
+        Expr::Update {
+            operator,
+            target: Box::new(target),
+            prefix,
+        }
+    }
+
+    fn call(&mut self) -> Result<Expr> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.is_match(&[TokenValue::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.is_match(&[TokenValue::Dot]) {
+                let name = self.consume_identifier("Expected property name after `.`")?;
+                expr = Expr::Get {
+                    name,
+                    object: Box::new(expr),
+                }
+            } else if self.is_match(&[TokenValue::LeftBracket]) {
+                expr = self.index(expr)?;
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
+    /// Parses the arguments of a call. A variable followed by `:` names the
+    /// parameter the argument after it is for.
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr> {
+        let mut arguments = vec![];
+        let mut keywords: Vec<Token> = vec![];
+
+        if !self.check(&TokenValue::RightParen) {
+            loop {
+                if arguments.len() >= MAX_ARGUMENTS {
+                    return Err(RuntimeError::ParseError(
+                        format!("Can't have more than {MAX_ARGUMENTS} arguments").into(),
+                        self.peek(),
+                    ));
+                }
+
+                let start = self.peek();
+                let argument = self.expression()?;
+                match argument {
+                    Expr::Variable { name, .. } if self.is_match(&[TokenValue::Colon]) => {
+                        if keywords.iter().any(|keyword| keyword.lexeme == name.lexeme) {
+                            return Err(parse_error(
+                                Message::new(
+                                    ErrorCode::KeywordArguments,
+                                    format!("Argument `{}` is given twice", name.lexeme),
+                                ),
+                                name,
+                            ));
+                        }
+                        arguments.push(self.expression()?);
+                        keywords.push(name);
+                    }
+                    _ if !keywords.is_empty() => {
+                        return Err(parse_error(
+                            "Positional arguments must come before keyword arguments",
+                            start,
+                        ))
+                    }
+                    argument => arguments.push(argument),
+                }
+
+                if !self.is_match(&[TokenValue::Comma]) {
+                    break;
+                }
+            }
+        }
+
+        let paren = self.consume(TokenValue::RightParen, "Expected `)` after arguments")?;
+
+        Ok(Expr::Call {
+            callee: Box::new(callee),
+            paren,
+            arguments,
+            keywords,
+        })
+    }
+
+    /// Parses the rest of an index `object[index]` or a slice
+    /// `object[start:end]`, where either bound can be left out.
+    fn index(&mut self, object: Expr) -> Result<Expr> {
+        let start = match self.check(&TokenValue::Colon) {
+            true => None,
+            false => Some(Box::new(self.expression()?)),
+        };
+
+        if !self.is_match(&[TokenValue::Colon]) {
+            let bracket = self.consume(TokenValue::RightBracket, "Expected `]` after index")?;
+            return Ok(Expr::Index {
+                object: Box::new(object),
+                bracket,
+                index: start.expect("an index is parsed unless a `:` comes first"),
+            });
+        }
+
+        let end = match self.check(&TokenValue::RightBracket) {
+            true => None,
+            false => Some(Box::new(self.expression()?)),
+        };
+        let bracket = self.consume(TokenValue::RightBracket, "Expected `]` after slice")?;
+
+        Ok(Expr::Slice {
+            object: Box::new(object),
+            bracket,
+            start,
+            end,
+        })
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let token = self.peek();
+        let span = token.span;
+        let literal = |value| Expr::Literal { value, span };
+        let res = match token.value.clone() {
+            TokenValue::True => literal(Literal::True),
+            TokenValue::False => literal(Literal::False),
+            TokenValue::Nil => literal(Literal::Nil),
+            TokenValue::This => Expr::This {
+                keyword: token,
+                depth: Cell::default(),
+            },
+            TokenValue::Number(n) => literal(match integer(&token.lexeme) {
+                Some(n) => Literal::Integer(n),
+                None => Literal::Number(n),
+            }),
+            TokenValue::String(s) => {
+                self.advance();
+                let value = Literal::String(self.adjacent_strings(s));
+                return Ok(Expr::Literal {
+                    value,
+                    span: self.span_from(token.span),
+                });
+            }
+            TokenValue::Identifier(_) => Expr::Variable {
+                name: token,
+                depth: Cell::default(),
+            },
+            TokenValue::LeftParen => {
+                self.advance();
+                let expr = self.expression()?;
+                if self.extensions && self.check(&TokenValue::Comma) {
+                    return self.tuple(expr, token.span);
                 }
+                self.consume(TokenValue::RightParen, "Expected `)` after expression")?;
+                return Ok(Expr::Grouping {
+                    group: Box::new(expr),
+                    span: self.span_from(token.span),
+                });
+            }
+            TokenValue::LeftBracket => {
+                self.advance();
+                return self.list(token.span);
+            }
+            TokenValue::Match => {
+                self.advance();
+                return self.match_expression(token);
+            }
+            // A `{` starting a statement is a block, so this is only reached
+            // where an expression is expected.
+            TokenValue::LeftBrace if self.extensions => {
+                self.advance();
+                return self.map(token);
             }
             TokenValue::Super => {
+                self.advance();
                 self.consume(TokenValue::Dot, "Expected `.` after `super`")?;
                 let method = self.consume_identifier("Expected superclass method name")?;
-                Expr::Super {
+                return Ok(Expr::Super {
                     keyword: token,
                     method,
-                }
+                    depth: Cell::default(),
+                });
+            }
+            TokenValue::Eof => {
+                return Err(parse_error("Expected expression, found end of file", token))
             }
             t => {
-                return Err(RuntimeError::ParseError(
+                return Err(parse_error(
                     format!("Expected expression, found: `{t}`"),
                     token,
                 ))
             }
         };
 
+        self.advance();
         Ok(res)
     }
+
+    /// Parses the elements of a list literal after its `[`. A trailing comma
+    /// is allowed, for lists written one element per line.
+    fn list(&mut self, start: Span) -> Result<Expr> {
+        let mut elements = vec![];
+
+        while !self.check(&TokenValue::RightBracket) {
+            elements.push(self.expression()?);
+
+            if !self.is_match(&[TokenValue::Comma]) {
+                break;
+            }
+        }
+
+        self.consume(TokenValue::RightBracket, "Expected `]` after list elements")?;
+
+        Ok(Expr::List {
+            elements,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parses the rest of `match (subject) { pattern => body, ... else =>
+    /// body }`.
+    fn match_expression(&mut self, keyword: Token) -> Result<Expr> {
+        self.consume(TokenValue::LeftParen, "Expected `(` after `match`")?;
+        let subject = self.expression()?;
+        self.consume(
+            TokenValue::RightParen,
+            "Expected `)` after the matched value",
+        )?;
+        self.consume(TokenValue::LeftBrace, "Expected `{` before the match arms")?;
+
+        let mut arms = vec![];
+        while !self.is_match(&[TokenValue::Else]) {
+            let pattern = self.pattern()?;
+            self.consume(TokenValue::EqualGreater, "Expected `=>` after the pattern")?;
+            let body = self.expression()?;
+            arms.push(MatchArm { pattern, body });
+            self.consume(
+                TokenValue::Comma,
+                "Expected `,` after the match arm, and an `else` arm last",
+            )?;
+        }
+
+        self.consume(TokenValue::EqualGreater, "Expected `=>` after `else`")?;
+        let otherwise = Box::new(self.expression()?);
+        self.is_match(&[TokenValue::Comma]);
+        self.consume(TokenValue::RightBrace, "Expected `}` after the `else` arm")?;
+
+        let span = self.span_from(keyword.span);
+        Ok(Expr::Match {
+            keyword,
+            subject: Box::new(subject),
+            arms,
+            otherwise,
+            span,
+        })
+    }
+
+    fn pattern(&mut self) -> Result<Pattern> {
+        let token = self.peek();
+        match token.value {
+            TokenValue::Number(_)
+            | TokenValue::String(_)
+            | TokenValue::True
+            | TokenValue::False
+            | TokenValue::Nil => Ok(Pattern::Literal(self.primary()?)),
+            TokenValue::Minus => Ok(Pattern::Literal(self.unary()?)),
+            TokenValue::LeftParen => {
+                self.advance();
+                let mut elements = vec![self.pattern()?];
+                while self.is_match(&[TokenValue::Comma]) {
+                    elements.push(self.pattern()?);
+                }
+                self.consume(
+                    TokenValue::RightParen,
+                    "Expected `)` after the tuple pattern",
+                )?;
+
+                Ok(Pattern::Tuple {
+                    elements,
+                    span: self.span_from(token.span),
+                })
+            }
+            TokenValue::Identifier(_) => {
+                self.advance();
+                if !self.is_match(&[TokenValue::LeftParen]) {
+                    return Ok(Pattern::Binding(token));
+                }
+
+                let mut fields = vec![];
+                while !self.check(&TokenValue::RightParen) {
+                    fields.push(self.consume_identifier("Expected a field name")?);
+                    if !self.is_match(&[TokenValue::Comma]) {
+                        break;
+                    }
+                }
+                self.consume(TokenValue::RightParen, "Expected `)` after the field names")?;
+
+                Ok(Pattern::Instance {
+                    span: self.span_from(token.span),
+                    class: Expr::Variable {
+                        name: token,
+                        depth: Cell::default(),
+                    },
+                    fields,
+                })
+            }
+            _ => Err(parse_error("Expected a pattern", token)),
+        }
+    }
+
+    /// Parses the entries of a map literal after its `{`.
+    fn map(&mut self, brace: Token) -> Result<Expr> {
+        let mut entries = vec![];
+
+        while !self.check(&TokenValue::RightBrace) {
+            let key = self.expression()?;
+            self.consume(TokenValue::Colon, "Expected `:` after map key")?;
+            entries.push((key, self.expression()?));
+
+            if !self.is_match(&[TokenValue::Comma]) {
+                break;
+            }
+        }
+
+        self.consume(TokenValue::RightBrace, "Expected `}` after map entries")?;
+
+        let span = self.span_from(brace.span);
+        Ok(Expr::Map {
+            brace,
+            entries,
+            span,
+        })
+    }
+
+    /// Merges string literals written next to each other (`"foo" "bar"`) into
+    /// a single constant so the concatenation never happens at runtime.
+    fn adjacent_strings(&mut self, first: Rc<str>) -> Rc<str> {
+        let (true, TokenValue::String(_)) = (self.extensions, &self.peek().value) else {
+            return first;
+        };
+
+        let mut value = first.to_string();
+        while let TokenValue::String(next) = &self.peek().value {
+            value.push_str(next);
+            self.advance();
+        }
+
+        value.into()
+    }
+}
+
+/// An error at `token`, which is an [`UnexpectedEof`](RuntimeError::UnexpectedEof)
+/// when the source has run out there.
+fn parse_error(message: impl Into<Message>, token: Token) -> RuntimeError {
+    match token.value {
+        TokenValue::Eof => RuntimeError::UnexpectedEof(message.into(), token),
+        _ => RuntimeError::ParseError(message.into(), token),
+    }
 }
 
+/// The binary operator a compound assignment like `+=` applies, as a token
+/// at the same place, or `None` for a plain `=`.
+fn compound_operator(assign: &Token) -> Option<Token> {
+    let value = match assign.value {
+        TokenValue::PlusEqual => TokenValue::Plus,
+        TokenValue::MinusEqual => TokenValue::Minus,
+        TokenValue::StarEqual => TokenValue::Star,
+        TokenValue::SlashEqual => TokenValue::Slash,
+        _ => return None,
+    };
+
+    Some(Token {
+        lexeme: value.to_string().into(),
+        value,
+        ..assign.clone()
+    })
+}
+
+/// The most arguments a call (or parameters a function) may have.
+const MAX_ARGUMENTS: usize = 255;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FunctionDecl {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
+    /// A method declared without a parameter list, which runs as soon as
+    /// the property is read.
+    pub is_getter: bool,
+    /// A method declared with `class`, which belongs to the class itself
+    /// rather than its instances.
+    pub is_class_method: bool,
+    pub span: Span,
+}
+
+/// `span` covers a statement from its first token to its last, including
+/// the closing `;` or `}`. Loops desugared from `for` all share the span of
+/// the original statement.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Stmt {
     Expression(Expr),
-    Block(Vec<Stmt>),
+    Function(Rc<FunctionDecl>),
+    Class {
+        name: Token,
+        superclass: Option<Expr>,
+        methods: Vec<Rc<FunctionDecl>>,
+        span: Span,
+    },
+    /// Runs the script at `path`, a string token, unless the program has
+    /// already imported it.
+    Import {
+        keyword: Token,
+        path: Token,
+        span: Span,
+    },
+    Print {
+        values: Vec<Expr>,
+        span: Span,
+    },
+    Var {
+        name: Token,
+        initializer: Option<Expr>,
+        span: Span,
+    },
+    /// `var (a, b) = initializer;`, declaring a variable for each element
+    /// of the tuple or list `initializer` gives.
+    Unpack {
+        names: Vec<Token>,
+        initializer: Expr,
+        span: Span,
+    },
+    If {
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+        span: Span,
+    },
+    /// `increment` is only set for loops desugared from `for`, and runs
+    /// after each iteration even when the body ends with `continue`.
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+        increment: Option<Expr>,
+        span: Span,
+    },
+    Return {
+        keyword: Token,
+        value: Option<Expr>,
+        span: Span,
+    },
+    Block {
+        statements: Vec<Stmt>,
+        span: Span,
+    },
+    Break {
+        keyword: Token,
+        span: Span,
+    },
+    Continue {
+        keyword: Token,
+        span: Span,
+    },
+    /// Runs `body`, then `finally` however `body` ends. An error raised in
+    /// `body` is caught by `catch` if there is one. `finally` is shared so
+    /// the compiler can copy it into each way out of the statement.
+    Try {
+        body: Vec<Stmt>,
+        /// The braces around `body`.
+        body_span: Span,
+        catch: Option<Catch>,
+        finally: Option<Rc<[Stmt]>>,
+        span: Span,
+    },
+    /// Raises `value` as an error, which `catch` binds as it is.
+    Throw {
+        keyword: Token,
+        value: Expr,
+        span: Span,
+    },
+    /// Runs `statement` when the block or function it's in ends, however
+    /// it ends, after the statements that follow it and the `defer`s among
+    /// them. Like `finally`, `statement` is shared with the compiler.
+    Defer {
+        keyword: Token,
+        statement: Rc<Stmt>,
+        span: Span,
+    },
+    /// `for (names in iterable) body`: runs `body` once for each value the
+    /// iterator for `iterable` produces, bound to the one name or, with
+    /// more, unpacked from a tuple of that many values.
+    ForIn {
+        keyword: Token,
+        names: Vec<Token>,
+        iterable: Expr,
+        body: Box<Stmt>,
+        span: Span,
+    },
+    /// `use (var name = initializer) { body }`: runs `body` with `name`
+    /// bound to the resource, then calls `name.close()` however it ends, as
+    /// if that call were deferred.
+    Use {
+        keyword: Token,
+        name: Token,
+        initializer: Expr,
+        body: Vec<Stmt>,
+        /// The braces around `body`.
+        body_span: Span,
+        span: Span,
+    },
+}
+
+impl Stmt {
+    /// The `name.close()` call a [`Stmt::Use`] ends with, located at its
+    /// `keyword`. `name` is found in the innermost scope.
+    pub(crate) fn close_call(keyword: &Token, name: &Token) -> Expr {
+        let close = Symbol::intern("close");
+        Expr::Call {
+            callee: Box::new(Expr::Get {
+                object: Box::new(Expr::Variable {
+                    name: name.clone(),
+                    depth: Cell::new(Some(0)),
+                }),
+                name: Token {
+                    value: TokenValue::Identifier(close),
+                    lexeme: close.as_str().into(),
+                    ..keyword.clone()
+                },
+            }),
+            paren: keyword.clone(),
+            arguments: vec![],
+            keywords: vec![],
+        }
+    }
+}
+
+/// One `pattern => body` arm of an [`Expr::Match`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Expr,
+}
+
+/// What a [`MatchArm`] checks the matched value against.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Pattern {
+    /// A literal, or a negated number, that the value must equal.
+    Literal(Expr),
+    /// A name, which matches anything and binds it.
+    Binding(Token),
+    /// `(a, b)`: a tuple of as many elements, each matching its pattern.
+    Tuple { elements: Vec<Pattern>, span: Span },
+    /// `Point(x, y)`: an instance of the class `class` names, or of a
+    /// subclass, with the fields `x` and `y`, which it binds.
+    Instance {
+        class: Expr,
+        fields: Vec<Token>,
+        span: Span,
+    },
+}
+
+impl Pattern {
+    pub fn span(&self) -> Span {
+        match self {
+            Pattern::Literal(literal) => literal.span(),
+            Pattern::Binding(name) => name.span,
+            Pattern::Tuple { span, .. } | Pattern::Instance { span, .. } => *span,
+        }
+    }
+
+    /// The names the pattern binds, in order.
+    pub fn bindings(&self) -> Vec<&Token> {
+        match self {
+            Pattern::Literal(_) => vec![],
+            Pattern::Binding(name) => vec![name],
+            Pattern::Tuple { elements, .. } => {
+                elements.iter().flat_map(Pattern::bindings).collect()
+            }
+            Pattern::Instance { fields, .. } => fields.iter().collect(),
+        }
+    }
+
+    /// The expressions in the pattern: literals and class names.
+    pub fn exprs(&self) -> Vec<&Expr> {
+        match self {
+            Pattern::Literal(expr) | Pattern::Instance { class: expr, .. } => vec![expr],
+            Pattern::Binding(_) => vec![],
+            Pattern::Tuple { elements, .. } => elements.iter().flat_map(Pattern::exprs).collect(),
+        }
+    }
+}
+
+/// The `catch (name) { body }` clause of a [`Stmt::Try`].
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Catch {
+    pub name: Token,
+    pub body: Vec<Stmt>,
+    pub span: Span,
 }
 
+/// `depth` on the nodes that read or write a variable is filled in by the
+/// resolver: how many scopes out from the current one the variable lives, or
+/// `None` for a global.
+///
+/// Only the nodes without a token at each end store a `span`; the rest are
+/// worked out from their tokens and children by [`Expr::span`].
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Expr {
-    Literal(Literal),
+    Literal {
+        value: Literal,
+        span: Span,
+    },
     This {
         keyword: Token,
+        depth: Cell<Option<usize>>,
     },
     Variable {
         name: Token,
+        depth: Cell<Option<usize>>,
     },
     Grouping {
         group: Box<Expr>,
+        span: Span,
     },
     Super {
         keyword: Token,
         method: Token,
+        depth: Cell<Option<usize>>,
     },
     Get {
         name: Token,
         object: Box<Expr>,
     },
+    Call {
+        callee: Box<Expr>,
+        paren: Token,
+        arguments: Vec<Expr>,
+        /// The names of the last `keywords.len()` arguments, which are for
+        /// the parameters with those names. The ones before them are
+        /// positional.
+        keywords: Vec<Token>,
+    },
     Unary {
         operator: Token,
         right: Box<Expr>,
     },
-    Factor {
+    /// `++` or `--` before or after `target`, which is a variable, property
+    /// or index expression.
+    Update {
         operator: Token,
-        right: Box<Expr>,
+        target: Box<Expr>,
+        prefix: bool,
     },
-    Term {
+    Binary {
+        left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
     },
-    Comparison {
+    /// A compound assignment such as `target += value`, where `target` is a
+    /// variable, property or index expression. `operator` is the binary
+    /// operator it applies, spanning the `+=` in the source.
+    Compound {
+        target: Box<Expr>,
         operator: Token,
-        right: Box<Expr>,
+        value: Box<Expr>,
     },
-    Equality {
+    Logical {
+        left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
     },
-    Logical {
-        right: Box<Expr>,
-        operator: Token,
-        left: Box<Expr>,
+    Conditional {
+        condition: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
     },
     Assign {
         name: Token,
         value: Box<Expr>,
+        depth: Cell<Option<usize>>,
     },
     Set {
         object: Box<Expr>,
         name: Token,
         value: Box<Expr>,
     },
+    List {
+        elements: Vec<Expr>,
+        span: Span,
+    },
+    /// `(a, b)`, or the values of `return a, b;`.
+    Tuple {
+        elements: Vec<Expr>,
+        span: Span,
+    },
+    /// `brace` is the opening `{`, where errors are reported.
+    Map {
+        brace: Token,
+        entries: Vec<(Expr, Expr)>,
+        span: Span,
+    },
+    /// `bracket` is the closing `]`, where errors are reported.
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    /// `object[start:end]`, where a missing bound runs to that end of the
+    /// string or list.
+    Slice {
+        object: Box<Expr>,
+        bracket: Token,
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+    },
+    /// `match (subject) { pattern => body, ... else => otherwise }`, which
+    /// gives the body of the first arm whose pattern matches, or
+    /// `otherwise`.
+    Match {
+        keyword: Token,
+        subject: Box<Expr>,
+        arms: Vec<MatchArm>,
+        otherwise: Box<Expr>,
+        span: Span,
+    },
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Literal {
     False,
     True,
     Nil,
+    /// A number written without a decimal point or exponent that fits in an
+    /// `i64`. Whether it stays an integer at runtime depends on
+    /// [`LoxOptions::integers`](crate::LoxOptions::integers).
+    Integer(i64),
     Number(f64),
-    String(String),
+    String(Rc<str>),
+}
+
+impl Expr {
+    /// The part of the source this expression was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Literal { span, .. }
+            | Expr::Grouping { span, .. }
+            | Expr::List { span, .. }
+            | Expr::Tuple { span, .. }
+            | Expr::Map { span, .. }
+            | Expr::Match { span, .. } => *span,
+            Expr::This { keyword, .. } => keyword.span,
+            Expr::Variable { name, .. } => name.span,
+            Expr::Super {
+                keyword, method, ..
+            } => keyword.span.to(method.span),
+            Expr::Get { name, object } => object.span().to(name.span),
+            Expr::Call { callee, paren, .. } => callee.span().to(paren.span),
+            Expr::Unary { operator, right } => operator.span.to(right.span()),
+            Expr::Update {
+                operator,
+                target,
+                prefix: true,
+            } => operator.span.to(target.span()),
+            Expr::Update {
+                operator, target, ..
+            } => target.span().to(operator.span),
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                left.span().to(right.span())
+            }
+            Expr::Conditional {
+                condition,
+                else_branch,
+                ..
+            } => condition.span().to(else_branch.span()),
+            Expr::Assign { name, value, .. } => name.span.to(value.span()),
+            Expr::Compound { target, value, .. } => target.span().to(value.span()),
+            Expr::Set { object, value, .. } | Expr::SetIndex { object, value, .. } => {
+                object.span().to(value.span())
+            }
+            Expr::Index {
+                object, bracket, ..
+            }
+            | Expr::Slice {
+                object, bracket, ..
+            } => object.span().to(bracket.span),
+        }
+    }
+
+    /// The token most closely associated with this node, for nodes that
+    /// hold one.
+    pub(crate) fn token(&self) -> Option<&Token> {
+        match self {
+            Expr::Literal { .. }
+            | Expr::Grouping { .. }
+            | Expr::Conditional { .. }
+            | Expr::List { .. }
+            | Expr::Tuple { .. } => None,
+            Expr::Map { brace, .. } => Some(brace),
+            Expr::Match { keyword, .. } => Some(keyword),
+            Expr::Index { bracket, .. }
+            | Expr::SetIndex { bracket, .. }
+            | Expr::Slice { bracket, .. } => Some(bracket),
+            Expr::This { keyword, .. } | Expr::Super { keyword, .. } => Some(keyword),
+            Expr::Variable { name, .. }
+            | Expr::Get { name, .. }
+            | Expr::Assign { name, .. }
+            | Expr::Set { name, .. } => Some(name),
+            Expr::Call { paren, .. } => Some(paren),
+            Expr::Unary { operator, .. }
+            | Expr::Update { operator, .. }
+            | Expr::Binary { operator, .. }
+            | Expr::Compound { operator, .. }
+            | Expr::Logical { operator, .. } => Some(operator),
+        }
+    }
+}
+
+/// How deep `Display` descends into a tree before eliding the rest with `...`.
+/// Use the precision flag to pick a different cutoff, e.g. `{stmt:.3}`.
+const DEFAULT_MAX_DEPTH: usize = 16;
+
+impl Display for Stmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let max_depth = f.precision().unwrap_or(DEFAULT_MAX_DEPTH);
+        self.write_tree(f, 0, max_depth)
+    }
+}
+
+impl Stmt {
+    /// The part of the source this statement was parsed from. An expression
+    /// statement's span leaves out its `;`.
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Expression(expr) => expr.span(),
+            Stmt::Function(function) => function.span,
+            Stmt::Class { span, .. }
+            | Stmt::Import { span, .. }
+            | Stmt::Print { span, .. }
+            | Stmt::Var { span, .. }
+            | Stmt::Unpack { span, .. }
+            | Stmt::If { span, .. }
+            | Stmt::While { span, .. }
+            | Stmt::Return { span, .. }
+            | Stmt::Block { span, .. }
+            | Stmt::Break { span, .. }
+            | Stmt::Continue { span, .. }
+            | Stmt::Try { span, .. }
+            | Stmt::Throw { span, .. }
+            | Stmt::Defer { span, .. }
+            | Stmt::ForIn { span, .. }
+            | Stmt::Use { span, .. } => *span,
+        }
+    }
+
+    fn write_tree(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        depth: usize,
+        max_depth: usize,
+    ) -> std::fmt::Result {
+        let indent = "  ".repeat(depth);
+
+        if depth >= max_depth {
+            return writeln!(f, "{indent}...");
+        }
+
+        match self {
+            Stmt::Expression(expr) => {
+                writeln!(f, "{indent}Expression")?;
+                expr.write_tree(f, depth + 1, max_depth)
+            }
+            Stmt::Function(function) => {
+                let params = function
+                    .params
+                    .iter()
+                    .map(|param| &*param.lexeme)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let name = &function.name.lexeme;
+                match (function.is_class_method, function.is_getter) {
+                    (false, false) => writeln!(f, "{indent}Function {name}({params})")?,
+                    (false, true) => writeln!(f, "{indent}Getter {name}")?,
+                    (true, false) => writeln!(f, "{indent}ClassMethod {name}({params})")?,
+                    (true, true) => writeln!(f, "{indent}ClassGetter {name}")?,
+                }
+                function
+                    .body
+                    .iter()
+                    .try_for_each(|stmt| stmt.write_tree(f, depth + 1, max_depth))
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                match superclass {
+                    Some(superclass) => {
+                        writeln!(f, "{indent}Class {} <", name.lexeme)?;
+                        superclass.write_tree(f, depth + 1, max_depth)?;
+                    }
+                    None => writeln!(f, "{indent}Class {}", name.lexeme)?,
+                }
+                methods.iter().try_for_each(|method| {
+                    Stmt::Function(method.clone()).write_tree(f, depth + 1, max_depth)
+                })
+            }
+            Stmt::Import { path, .. } => writeln!(f, "{indent}Import \"{}\"", path.lexeme),
+            Stmt::Print { values, .. } => {
+                writeln!(f, "{indent}Print")?;
+                values
+                    .iter()
+                    .try_for_each(|expr| expr.write_tree(f, depth + 1, max_depth))
+            }
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                writeln!(f, "{indent}Var {}", name.lexeme)?;
+                initializer
+                    .iter()
+                    .try_for_each(|expr| expr.write_tree(f, depth + 1, max_depth))
+            }
+            Stmt::Unpack {
+                names, initializer, ..
+            } => {
+                let names: Vec<_> = names.iter().map(|name| name.lexeme.as_ref()).collect();
+                writeln!(f, "{indent}Var ({})", names.join(", "))?;
+                initializer.write_tree(f, depth + 1, max_depth)
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                writeln!(f, "{indent}If")?;
+                condition.write_tree(f, depth + 1, max_depth)?;
+                then_branch.write_tree(f, depth + 1, max_depth)?;
+                else_branch
+                    .iter()
+                    .try_for_each(|stmt| stmt.write_tree(f, depth + 1, max_depth))
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                writeln!(f, "{indent}While")?;
+                condition.write_tree(f, depth + 1, max_depth)?;
+                body.write_tree(f, depth + 1, max_depth)?;
+                increment
+                    .iter()
+                    .try_for_each(|expr| expr.write_tree(f, depth + 1, max_depth))
+            }
+            Stmt::Return { value, .. } => {
+                writeln!(f, "{indent}Return")?;
+                value
+                    .iter()
+                    .try_for_each(|expr| expr.write_tree(f, depth + 1, max_depth))
+            }
+            Stmt::Block { statements, .. } => {
+                writeln!(f, "{indent}Block")?;
+                statements
+                    .iter()
+                    .try_for_each(|stmt| stmt.write_tree(f, depth + 1, max_depth))
+            }
+            Stmt::Break { .. } => writeln!(f, "{indent}Break"),
+            Stmt::Continue { .. } => writeln!(f, "{indent}Continue"),
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                writeln!(f, "{indent}Try")?;
+                body.iter()
+                    .try_for_each(|stmt| stmt.write_tree(f, depth + 1, max_depth))?;
+                if let Some(catch) = catch {
+                    writeln!(f, "{indent}Catch {}", catch.name.lexeme)?;
+                    catch
+                        .body
+                        .iter()
+                        .try_for_each(|stmt| stmt.write_tree(f, depth + 1, max_depth))?;
+                }
+                if let Some(finally) = finally {
+                    writeln!(f, "{indent}Finally")?;
+                    finally
+                        .iter()
+                        .try_for_each(|stmt| stmt.write_tree(f, depth + 1, max_depth))?;
+                }
+                Ok(())
+            }
+            Stmt::Throw { value, .. } => {
+                writeln!(f, "{indent}Throw")?;
+                value.write_tree(f, depth + 1, max_depth)
+            }
+            Stmt::Defer { statement, .. } => {
+                writeln!(f, "{indent}Defer")?;
+                statement.write_tree(f, depth + 1, max_depth)
+            }
+            Stmt::ForIn {
+                names,
+                iterable,
+                body,
+                ..
+            } => {
+                let names: Vec<_> = names.iter().map(|name| name.lexeme.as_ref()).collect();
+                writeln!(f, "{indent}ForIn {}", names.join(", "))?;
+                iterable.write_tree(f, depth + 1, max_depth)?;
+                body.write_tree(f, depth + 1, max_depth)
+            }
+            Stmt::Use {
+                name,
+                initializer,
+                body,
+                ..
+            } => {
+                writeln!(f, "{indent}Use {}", name.lexeme)?;
+                initializer.write_tree(f, depth + 1, max_depth)?;
+                body.iter()
+                    .try_for_each(|stmt| stmt.write_tree(f, depth + 1, max_depth))
+            }
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let max_depth = f.precision().unwrap_or(DEFAULT_MAX_DEPTH);
+        self.write_tree(f, 0, max_depth)
+    }
+}
+
+impl Expr {
+    fn write_tree(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        depth: usize,
+        max_depth: usize,
+    ) -> std::fmt::Result {
+        let indent = "  ".repeat(depth);
+
+        if depth >= max_depth {
+            return writeln!(f, "{indent}...");
+        }
+
+        let (label, children): (String, Vec<&Expr>) = match self {
+            Expr::Literal { value: literal, .. } => (format!("Literal {literal}"), vec![]),
+            Expr::This { .. } => ("This".into(), vec![]),
+            Expr::Variable { name, .. } => (format!("Variable {}", name.lexeme), vec![]),
+            Expr::Grouping { group, .. } => ("Grouping".into(), vec![group]),
+            Expr::Super { method, .. } => (format!("Super {}", method.lexeme), vec![]),
+            Expr::Get { name, object } => (format!("Get {}", name.lexeme), vec![object]),
+            Expr::Call {
+                callee, arguments, ..
+            } => (
+                "Call".into(),
+                std::iter::once(callee.as_ref()).chain(arguments).collect(),
+            ),
+            Expr::Unary { operator, right } => (format!("Unary {}", operator.lexeme), vec![right]),
+            Expr::Update {
+                operator,
+                target,
+                prefix,
+            } => {
+                let position = if *prefix { "Prefix" } else { "Postfix" };
+                (format!("{position} {}", operator.lexeme), vec![target])
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => (format!("Binary {}", operator.lexeme), vec![left, right]),
+            Expr::Compound {
+                target,
+                operator,
+                value,
+            } => (
+                format!("Compound {}=", operator.lexeme),
+                vec![target, value],
+            ),
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => (format!("Logical {}", operator.lexeme), vec![left, right]),
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => (
+                "Conditional".into(),
+                vec![condition, then_branch, else_branch],
+            ),
+            Expr::Assign { name, value, .. } => (format!("Assign {}", name.lexeme), vec![value]),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => (format!("Set {}", name.lexeme), vec![object, value]),
+            Expr::List { elements, .. } => ("List".into(), elements.iter().collect()),
+            Expr::Tuple { elements, .. } => ("Tuple".into(), elements.iter().collect()),
+            Expr::Match {
+                subject,
+                arms,
+                otherwise,
+                ..
+            } => (
+                "Match".into(),
+                std::iter::once(subject.as_ref())
+                    .chain(arms.iter().flat_map(|arm| {
+                        let mut children = arm.pattern.exprs();
+                        children.push(&arm.body);
+                        children
+                    }))
+                    .chain([otherwise.as_ref()])
+                    .collect(),
+            ),
+            Expr::Map { entries, .. } => (
+                "Map".into(),
+                entries
+                    .iter()
+                    .flat_map(|(key, value)| [key, value])
+                    .collect(),
+            ),
+            Expr::Index { object, index, .. } => ("Index".into(), vec![object, index]),
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => ("SetIndex".into(), vec![object, index, value]),
+            Expr::Slice {
+                object, start, end, ..
+            } => {
+                let label = match (start, end) {
+                    (Some(_), Some(_)) => "Slice start:end",
+                    (Some(_), None) => "Slice start:",
+                    (None, Some(_)) => "Slice :end",
+                    (None, None) => "Slice :",
+                };
+                let bounds = start.iter().chain(end).map(|bound| bound.as_ref());
+                (
+                    label.into(),
+                    [object.as_ref()].into_iter().chain(bounds).collect(),
+                )
+            }
+        };
+
+        writeln!(f, "{indent}{label}")?;
+        children
+            .into_iter()
+            .try_for_each(|child| child.write_tree(f, depth + 1, max_depth))
+    }
+}
+
+/// The value of a number literal's `lexeme` if it's written as an integer.
+fn integer(lexeme: &str) -> Option<i64> {
+    lexeme
+        .chars()
+        .all(|c| c.is_ascii_digit() || c == '_')
+        .then(|| lexeme.replace('_', "").parse().ok())
+        .flatten()
+}
+
+impl Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Literal::False => f.write_str("false"),
+            Literal::True => f.write_str("true"),
+            Literal::Nil => f.write_str("nil"),
+            Literal::Integer(n) => n.fmt(f),
+            Literal::Number(n) => n.fmt(f),
+            Literal::String(s) => write!(f, "{s:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    fn parse_expression(source: &str) -> Expr {
+        let tokens = Scanner::new(source).scan_tokens().tokens;
+        Parser::new(tokens)
+            .parse_expression()
+            .unwrap_or_else(|err| panic!("failed to parse `{source}`: {err}"))
+    }
+
+    fn tree(source: &str) -> String {
+        parse_expression(source).to_string()
+    }
+
+    fn parse_program(source: &str) -> ParseOutput {
+        Parser::new(Scanner::new(source).scan_tokens().tokens).parse()
+    }
+
+    #[test]
+    fn binary_keeps_both_operands() {
+        let Expr::Binary {
+            left,
+            operator,
+            right,
+        } = parse_expression("1 + 2")
+        else {
+            panic!("expected a binary expression");
+        };
+
+        assert!(matches!(
+            *left,
+            Expr::Literal {
+                value: Literal::Integer(1),
+                ..
+            }
+        ));
+        assert_eq!(operator.value, TokenValue::Plus);
+        assert!(matches!(
+            *right,
+            Expr::Literal {
+                value: Literal::Integer(2),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn binary_operators_are_left_associative() {
+        assert_eq!(
+            tree("1 - 2 - 3"),
+            "Binary -\n  Binary -\n    Literal 1\n    Literal 2\n  Literal 3\n"
+        );
+    }
+
+    #[test]
+    fn binary_operators_follow_precedence() {
+        assert_eq!(
+            tree("1 + 2 * 3 == 7"),
+            "Binary ==\n  Binary +\n    Literal 1\n    Binary *\n      Literal 2\n      Literal 3\n  Literal 7\n"
+        );
+        assert_eq!(
+            tree("1 < 2 != 3 >= 4"),
+            "Binary !=\n  Binary <\n    Literal 1\n    Literal 2\n  Binary >=\n    Literal 3\n    Literal 4\n"
+        );
+    }
+
+    #[test]
+    fn grouping_overrides_precedence() {
+        assert_eq!(
+            tree("(1 + 2) * 3"),
+            "Binary *\n  Grouping\n    Binary +\n      Literal 1\n      Literal 2\n  Literal 3\n"
+        );
+    }
+
+    #[test]
+    fn logical_keeps_operands_in_order() {
+        assert_eq!(
+            tree("a or b and c"),
+            "Logical or\n  Variable a\n  Logical and\n    Variable b\n    Variable c\n"
+        );
+    }
+
+    #[test]
+    fn unary_binds_tighter_than_binary() {
+        assert_eq!(
+            tree("-1 * !a"),
+            "Binary *\n  Unary -\n    Literal 1\n  Unary !\n    Variable a\n"
+        );
+    }
+
+    #[test]
+    fn binary_span_covers_both_operands() {
+        let source = "x + 10 * y";
+        assert_eq!(parse_expression(source).span(), Span::new(0, source.len()));
+    }
+
+    #[test]
+    fn for_loop_keeps_increment_on_while() {
+        let output = parse_program("for (var i = 0; i < 3; i = i + 1) continue;");
+
+        assert!(output.errors.is_empty());
+        assert_eq!(
+            output.program[0].to_string(),
+            "Block\n  Var i\n    Literal 0\n  While\n    Binary <\n      Variable i\n      Literal 3\n    Continue\n    Assign i\n      Binary +\n        Variable i\n        Literal 1\n"
+        );
+    }
+
+    #[test]
+    fn loop_control_outside_loop_is_an_error() {
+        let output = parse_program("break; while (true) { fun f() { continue; } break; }");
+        let messages: Vec<String> = output.errors.iter().map(|err| err.to_string()).collect();
+
+        assert_eq!(
+            messages,
+            [
+                "line 1 | Error[E0009]: Can't use `break` outside of a loop",
+                "line 1 | Error[E0009]: Can't use `continue` outside of a loop",
+            ]
+        );
+    }
+
+    #[test]
+    fn conditional_is_right_associative() {
+        assert_eq!(
+            tree("a ? 1 : b ? 2 : 3"),
+            "Conditional\n  Variable a\n  Literal 1\n  Conditional\n    Variable b\n    Literal 2\n    Literal 3\n"
+        );
+    }
+
+    #[test]
+    fn conditional_binds_looser_than_logic_or() {
+        assert_eq!(
+            tree("a or b ? 1 : 2"),
+            "Conditional\n  Logical or\n    Variable a\n    Variable b\n  Literal 1\n  Literal 2\n"
+        );
+    }
+
+    #[test]
+    fn index_assignment_targets_the_indexed_list() {
+        assert_eq!(
+            tree("xs[0][i] = [1, 2][1]"),
+            "SetIndex\n  Index\n    Variable xs\n    Literal 0\n  Variable i\n  Index\n    List\n      Literal 1\n      Literal 2\n    Literal 1\n"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn syntax_trees_serialize_to_json() {
+        let program = parse_program("print -x;").program;
+        assert_eq!(
+            serde_json::to_string(&program).unwrap(),
+            concat!(
+                r#"[{"Print":{"values":[{"Unary":{"#,
+                r#""operator":{"value":"Minus","lexeme":"-","line":1,"column":7,"span":{"start":6,"end":7}},"#,
+                r#""right":{"Variable":{"name":{"value":{"Identifier":"x"},"lexeme":"x","line":1,"column":8,"span":{"start":7,"end":8}},"depth":null}}}}],"#,
+                r#""span":{"start":0,"end":9}}}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn slices_may_leave_out_either_bound() {
+        assert_eq!(
+            tree("s[1:n][:2]"),
+            "Slice :end\n  Slice start:end\n    Variable s\n    Literal 1\n    Variable n\n  Literal 2\n"
+        );
+        assert_eq!(tree("s[:]"), "Slice :\n  Variable s\n");
+    }
+
+    #[test]
+    fn map_literal_keeps_entry_order() {
+        assert_eq!(
+            tree(r#"{"b": 1, 2: x,}"#),
+            "Map\n  Literal \"b\"\n  Literal 1\n  Literal 2\n  Variable x\n"
+        );
+    }
+
+    #[test]
+    fn power_is_right_associative_and_binds_tighter_than_unary() {
+        assert_eq!(
+            tree("-2 ** 3 ** 2 % 5"),
+            "Binary %\n  Unary -\n    Binary **\n      Literal 2\n      Binary **\n        Literal 3\n        Literal 2\n  Literal 5\n"
+        );
+    }
+
+    #[test]
+    fn compound_assignment_keeps_its_target_once() {
+        assert_eq!(
+            tree("a.b -= 1"),
+            "Compound -=\n  Get b\n    Variable a\n  Literal 1\n"
+        );
+    }
+
+    #[test]
+    fn string_literal_chains_are_folded() {
+        assert_eq!(tree(r#""a" + "b" "c" + "d""#), "Literal \"abcd\"\n");
+        assert_eq!(
+            tree(r#""a" + "b" + c + "d""#),
+            "Binary +\n  Binary +\n    Literal \"ab\"\n    Variable c\n  Literal \"d\"\n"
+        );
+        assert_eq!(
+            tree(r#""a" - "b""#),
+            "Binary -\n  Literal \"a\"\n  Literal \"b\"\n"
+        );
+    }
+
+    #[test]
+    fn updates_bind_tighter_than_unary_and_power() {
+        assert_eq!(
+            tree("-xs[i]++ ** --a.b"),
+            "Unary -\n  Binary **\n    Postfix ++\n      Index\n        Variable xs\n        Variable i\n    Prefix --\n      Get b\n        Variable a\n"
+        );
+    }
+
+    #[test]
+    fn update_target_must_be_assignable() {
+        let output = parse_program("1++; --f(); a--;");
+        let messages: Vec<String> = output.errors.iter().map(|err| err.to_string()).collect();
+
+        assert_eq!(
+            messages,
+            [
+                "line 1 | Error: The operand of `++` must be a variable, property or index",
+                "line 1 | Error: The operand of `--` must be a variable, property or index",
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_tokens_pulled_from_the_scanner() {
+        // Leave off the end-of-file token, which the parser supplies itself.
+        let tokens = Scanner::new("var a = 1;\nprint a + 2;")
+            .filter_map(|token| token.ok())
+            .filter(|token| token.value != TokenValue::Eof);
+        let output = Parser::new(tokens).parse();
+
+        assert!(output.errors.is_empty());
+        assert_eq!(
+            output
+                .program
+                .iter()
+                .map(|stmt| stmt.to_string())
+                .collect::<String>(),
+            parse_program("var a = 1;\nprint a + 2;")
+                .program
+                .iter()
+                .map(|stmt| stmt.to_string())
+                .collect::<String>()
+        );
+    }
+
+    #[test]
+    fn errors_at_error_tokens_are_left_to_the_scanner() {
+        for source in ["print \"abc", "var a = 1 @ 2;", "f(@);"] {
+            let tokens = Scanner::new(source)
+                .map(|token| token.unwrap_or_else(|err| crate::scanner::error_token(&err)));
+            let errors = Parser::new(tokens).parse().errors;
+            assert!(errors.is_empty(), "`{source}` gave parse errors");
+        }
+    }
+
+    #[test]
+    fn invalid_assignment_targets_are_reported_at_the_equals() {
+        let errors = parse_program("1 + a = 3;").errors;
+        assert!(matches!(
+            errors.as_slice(),
+            [RuntimeError::InvalidArgumentTarget(_, token)] if token.column == 7
+        ));
+    }
+
+    #[test]
+    fn the_end_of_file_is_named_in_errors() {
+        let errors = parse_program("print").errors;
+        assert_eq!(
+            errors[0].to_string(),
+            "line 1 | Error: Expected expression, found end of file"
+        );
+    }
+
+    #[test]
+    fn running_out_of_tokens_is_unexpected_eof() {
+        for source in ["fun f() {", "print (1 +", "var a ="] {
+            let errors = parse_program(source).errors;
+            assert!(
+                matches!(errors.as_slice(), [RuntimeError::UnexpectedEof(..)]),
+                "`{source}` should end unexpectedly"
+            );
+        }
+
+        let errors = parse_program("print 1 +;").errors;
+        assert!(matches!(errors.as_slice(), [RuntimeError::ParseError(..)]));
+    }
+
+    #[test]
+    fn import_takes_a_string_path() {
+        let output = parse_program("import \"lib/helpers.lox\";");
+        assert!(output.errors.is_empty());
+        assert_eq!(
+            output.program[0].to_string(),
+            "Import \"lib/helpers.lox\"\n"
+        );
+
+        let errors = parse_program("import helpers;").errors;
+        assert!(matches!(errors.as_slice(), [RuntimeError::ParseError(..)]));
+    }
+
+    #[test]
+    fn class_bodies_take_getters_and_class_methods() {
+        let output = parse_program(
+            "class Circle { area { return this.r; } class unit() { return this(1); } }",
+        );
+        assert!(output.errors.is_empty());
+
+        let Stmt::Class { methods, .. } = &output.program[0] else {
+            panic!("expected a class declaration");
+        };
+        assert!(methods[0].is_getter && !methods[0].is_class_method);
+        assert!(!methods[1].is_getter && methods[1].is_class_method);
+    }
+
+    #[test]
+    fn the_book_dialect_rejects_each_syntax_extension() {
+        let errors = |source: &str, dialect| {
+            let tokens = Scanner::new(source).with_dialect(dialect).scan_tokens();
+            let parsed = Parser::new(tokens.tokens).with_dialect(dialect).parse();
+            let errors: Vec<_> = parsed.errors.iter().map(|err| err.to_string()).collect();
+            errors
+        };
+
+        for (source, error) in [
+            ("print 1, 2;", "Expected `;` after value"),
+            ("print \"a\" \"b\";", "Expected `;` after value"),
+            (
+                "fun f() { return 1, 2; }",
+                "Expected `;` after return value",
+            ),
+            ("var (a, b) = (1, 2);", "Expected variable name"),
+            ("print (1, 2);", "Expected `)` after expression"),
+            ("print {};", "Expected expression"),
+            (
+                "class A { area { return 1; } }",
+                "Expected `(` after method name",
+            ),
+            (
+                "class A { class make() { return A(); } }",
+                "`class` is a reserved word",
+            ),
+        ] {
+            assert_eq!(errors(source, LoxDialect::EXTENDED), [] as [String; 0]);
+            let book = errors(source, LoxDialect::BOOK);
+            assert!(
+                book.first().is_some_and(|err| err.contains(error)),
+                "`{source}` gave {book:?}"
+            );
+        }
+    }
 }
@@ -0,0 +1,225 @@
+//! `rlox --profile`: times each Lox function and class the tree-walking
+//! interpreter calls and counts the statements run on each line, then
+//! reports where the time went. The time spent outside any call is
+//! credited to `<script>`.
+//!
+//! The stacks can also be written in the folded format flame graph tools
+//! read, one `<script>;outer;inner microseconds` line per stack.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::Result,
+    interpreter::{Hook, Interpreter},
+    parser::Stmt,
+    value::Value,
+};
+
+/// How many of the busiest lines the report lists.
+const REPORT_LINES: usize = 20;
+
+/// The name the time outside any call is reported under.
+const SCRIPT: &str = "<script>";
+
+/// How long one function ran, over all its calls.
+#[derive(Debug, Default)]
+struct FunctionTime {
+    calls: u64,
+    /// Including the functions it called. A recursive function's nested
+    /// calls aren't counted twice.
+    inclusive: Duration,
+    /// Excluding the functions it called.
+    exclusive: Duration,
+}
+
+/// A call in progress.
+struct Frame {
+    name: String,
+    start: Instant,
+    /// How long the calls it made ran, to take off its exclusive time.
+    callees: Duration,
+}
+
+struct Profile {
+    source: String,
+    /// The calls in progress, with the script at the bottom.
+    stack: Vec<Frame>,
+    functions: HashMap<String, FunctionTime>,
+    /// How many statements ran on each line.
+    lines: BTreeMap<usize, u64>,
+    /// The exclusive time of each stack, keyed by its folded names.
+    stacks: HashMap<String, Duration>,
+    /// How many scripts were running when the main script started, so
+    /// statements from the scripts it imports can be told apart.
+    main_script: Option<usize>,
+}
+
+/// Collects a profile while installed as the interpreter's hook. Clones
+/// share the profile, so one can be kept to report on it after the run.
+#[derive(Clone)]
+pub(crate) struct Profiler(Rc<RefCell<Profile>>);
+
+impl Profiler {
+    /// Profiles a run of the script in `source`, which starts now.
+    pub(crate) fn new(source: &str) -> Self {
+        Self(Rc::new(RefCell::new(Profile {
+            source: source.to_string(),
+            stack: vec![Frame::new(SCRIPT)],
+            functions: HashMap::new(),
+            lines: BTreeMap::new(),
+            stacks: HashMap::new(),
+            main_script: None,
+        })))
+    }
+
+    /// Ends the run, returning the report and the folded stacks.
+    pub(crate) fn finish(&self) -> (String, String) {
+        let mut profile = self.0.borrow_mut();
+        while !profile.stack.is_empty() {
+            profile.exit();
+        }
+
+        (profile.report(), profile.folded())
+    }
+}
+
+impl Frame {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            start: Instant::now(),
+            callees: Duration::ZERO,
+        }
+    }
+}
+
+impl Profile {
+    fn exit(&mut self) {
+        let Some(frame) = self.stack.pop() else {
+            return;
+        };
+        let elapsed = frame.start.elapsed();
+        let exclusive = elapsed.saturating_sub(frame.callees);
+        let recursive = self.stack.iter().any(|outer| outer.name == frame.name);
+
+        let mut path: Vec<_> = self.stack.iter().map(|outer| outer.name.as_str()).collect();
+        path.push(&frame.name);
+        *self.stacks.entry(path.join(";")).or_default() += exclusive;
+
+        let time = self.functions.entry(frame.name).or_default();
+        time.calls += 1;
+        time.exclusive += exclusive;
+        if !recursive {
+            time.inclusive += elapsed;
+        }
+
+        if let Some(caller) = self.stack.last_mut() {
+            caller.callees += elapsed;
+        }
+    }
+
+    fn report(&self) -> String {
+        let mut report = String::new();
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by(|(a, a_time), (b, b_time)| {
+            b_time.exclusive.cmp(&a_time.exclusive).then(a.cmp(b))
+        });
+
+        writeln!(
+            report,
+            "{:>10} {:>12} {:>12}  function",
+            "calls", "inclusive", "exclusive"
+        )
+        .expect("writing to a String can't fail");
+        for (name, time) in functions {
+            writeln!(
+                report,
+                "{:>10} {:>12} {:>12}  {name}",
+                time.calls,
+                format!("{:.2?}", time.inclusive),
+                format!("{:.2?}", time.exclusive),
+            )
+            .expect("writing to a String can't fail");
+        }
+
+        let mut lines: Vec<_> = self.lines.iter().collect();
+        lines.sort_by(|(a, a_hits), (b, b_hits)| b_hits.cmp(a_hits).then(a.cmp(b)));
+
+        writeln!(report, "\n{:>10} {:>6}", "hits", "line").expect("writing to a String can't fail");
+        for (line, hits) in lines.into_iter().take(REPORT_LINES) {
+            let text = self.source.lines().nth(line - 1).unwrap_or_default();
+            writeln!(report, "{hits:>10} {line:>6} | {}", text.trim())
+                .expect("writing to a String can't fail");
+        }
+
+        report
+    }
+
+    fn folded(&self) -> String {
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort();
+
+        stacks
+            .into_iter()
+            .map(|(stack, time)| format!("{stack} {}\n", time.as_micros()))
+            .collect()
+    }
+}
+
+impl Hook for Profiler {
+    fn on_statement(&mut self, interpreter: &Interpreter, stmt: &Stmt) -> Result<()> {
+        let mut profile = self.0.borrow_mut();
+        let scripts = interpreter.running_scripts();
+        if scripts > *profile.main_script.get_or_insert(scripts) {
+            return Ok(());
+        }
+
+        let line = stmt.span().line_in(&profile.source);
+        *profile.lines.entry(line).or_default() += 1;
+        Ok(())
+    }
+
+    fn on_call(&mut self, name: &str) {
+        self.0.borrow_mut().stack.push(Frame::new(name));
+    }
+
+    fn on_return(&mut self, _value: Option<&Value>) {
+        self.0.borrow_mut().exit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Capture, Lox};
+
+    #[test]
+    fn profiler_writes_each_stack_folded() {
+        let dir = std::env::temp_dir().join(format!("rlox-profile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("script.lox");
+        let folded = dir.join("stacks.folded");
+        std::fs::write(&script, "fun f(n) { if (n > 0) f(n - 1); }\nf(2);").unwrap();
+
+        let mut lox = Lox::new().with_output(Capture::default());
+        let result = lox.profile_file(script.to_str().unwrap(), folded.to_str());
+        let stacks = std::fs::read_to_string(&folded).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_ok());
+        let names: Vec<_> = stacks
+            .lines()
+            .filter_map(|line| line.rsplit_once(' '))
+            .map(|(stack, _)| stack)
+            .collect();
+        assert_eq!(
+            names,
+            ["<script>", "<script>;f", "<script>;f;f", "<script>;f;f;f"]
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Reading input for the REPL. On a terminal, lines are read with a small
+//! line editor and kept in a history file between sessions; otherwise they
+//! are read from stdin as they are.
+
+mod editor;
+
+use std::{
+    fs::OpenOptions,
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+};
+
+use editor::Editor;
+
+/// The most lines of history kept between sessions.
+const MAX_HISTORY: usize = 1000;
+
+/// What reading a line of input got.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ReadLine {
+    /// A line, without its line break.
+    Line(String),
+    /// Ctrl-C, which abandons whatever was being typed.
+    Interrupted,
+    /// The end of input, from Ctrl-D or a closed stdin.
+    Eof,
+}
+
+pub(crate) struct LineReader {
+    /// `None` when stdin or stdout isn't a terminal, or the terminal can't
+    /// be switched to raw mode.
+    editor: Option<Editor>,
+    /// Where history is saved, which is `~/.rlox_history`.
+    history_file: Option<PathBuf>,
+}
+
+impl LineReader {
+    pub(crate) fn new() -> Self {
+        let interactive = cfg!(unix) && io::stdin().is_terminal() && io::stdout().is_terminal();
+        if !interactive {
+            return Self {
+                editor: None,
+                history_file: None,
+            };
+        }
+
+        let history_file =
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rlox_history"));
+        let history = history_file
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|history| {
+                let lines: Vec<String> = history.lines().map(String::from).collect();
+                lines[lines.len().saturating_sub(MAX_HISTORY)..].to_vec()
+            })
+            .unwrap_or_default();
+
+        Self {
+            editor: Some(Editor::new(history)),
+            history_file,
+        }
+    }
+
+    /// Shows `prompt` and reads a line.
+    pub(crate) fn read_line(&mut self, prompt: &str) -> io::Result<ReadLine> {
+        if let Some(editor) = &mut self.editor {
+            match editor.read_line(prompt) {
+                Ok(ReadLine::Line(line)) => {
+                    self.remember(&line);
+                    return Ok(ReadLine::Line(line));
+                }
+                Ok(read) => return Ok(read),
+                // Most likely there's no `stty` to switch modes with, so
+                // carry on without editing.
+                Err(_) => self.editor = None,
+            }
+        }
+
+        print!("{prompt}");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line)? {
+            0 => Ok(ReadLine::Eof),
+            _ => Ok(ReadLine::Line(
+                line.trim_end_matches(['\n', '\r']).to_string(),
+            )),
+        }
+    }
+
+    /// Adds `line` to the history, unless it's blank or the same as the last
+    /// one. Failing to save it isn't worth interrupting the session for.
+    fn remember(&mut self, line: &str) {
+        let Some(editor) = &mut self.editor else {
+            return;
+        };
+        if line.trim().is_empty() || editor.history().last().is_some_and(|last| last == line) {
+            return;
+        }
+
+        editor.add_history(line.to_string());
+        if let Some(path) = &self.history_file {
+            let _ = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{line}"));
+        }
+    }
+}
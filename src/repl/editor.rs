@@ -0,0 +1,544 @@
+//! A minimal line editor, enough to make the REPL pleasant without pulling
+//! in a readline library. It supports moving and deleting within the line,
+//! recalling history with the arrow keys and searching it with Ctrl-R.
+//!
+//! The terminal is switched into raw mode with `stty` while a line is being
+//! read, so this only works on Unix terminals. Decoding keys and editing the
+//! line are kept apart from the terminal, so they can be tested with bytes.
+
+use std::{
+    io::{self, Read, Write},
+    panic,
+    process::{Command, Stdio},
+    sync::{Mutex, Once},
+};
+
+use super::ReadLine;
+
+/// A key press, decoded from the bytes the terminal sends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    /// A letter pressed with Ctrl, as its lowercase letter.
+    Ctrl(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    /// An escape sequence this editor doesn't handle.
+    Unknown,
+}
+
+/// The terminal settings to go back to while it's in raw mode, for the
+/// panic hook to restore before the panic is reported.
+static SAVED: Mutex<Option<String>> = Mutex::new(None);
+
+/// Puts the terminal in raw mode until dropped, so keys arrive one at a
+/// time and aren't echoed. A panic restores it before its message is shown,
+/// rather than once unwinding drops this, if it unwinds at all.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        static HOOK: Once = Once::new();
+        HOOK.call_once(|| {
+            let previous = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                restore();
+                previous(info);
+            }));
+        });
+
+        let saved = stty(&["-g"])?.trim().to_string();
+        *SAVED.lock().unwrap_or_else(|err| err.into_inner()) = Some(saved);
+        // Made before switching, so the terminal is restored even if
+        // switching only partly worked.
+        let raw = Self;
+        stty(&["raw", "-echo"])?;
+        Ok(raw)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Puts back the settings saved when raw mode was enabled, if it still is.
+fn restore() {
+    let saved = SAVED.lock().unwrap_or_else(|err| err.into_inner()).take();
+    if let Some(saved) = saved {
+        let _ = stty(&[&saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()?;
+
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        false => Err(io::Error::other("stty failed")),
+    }
+}
+
+/// The line being edited.
+#[derive(Default)]
+struct Buffer {
+    chars: Vec<char>,
+    /// Where the next character typed goes, from 0 to `chars.len()`.
+    cursor: usize,
+}
+
+impl Buffer {
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    /// Replaces the line with `text`, with the cursor at its end.
+    fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    /// Deletes the word before the cursor, along with any spaces after it.
+    fn delete_word(&mut self) {
+        let mut start = self.cursor;
+        while start > 0 && self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.chars.drain(start..self.cursor);
+        self.cursor = start;
+    }
+}
+
+/// What the editor does after a key press while a line is being read.
+#[derive(Debug, PartialEq, Eq)]
+enum Step {
+    /// Redraw the line and read another key.
+    Edit,
+    Finish(ReadLine),
+    ClearScreen,
+    /// Start searching the history with Ctrl-R.
+    Search,
+}
+
+/// A line being read: what's been typed and which history entry is shown.
+struct Line<'a> {
+    buffer: Buffer,
+    history: &'a [String],
+    /// The history entry being shown, where `history.len()` is the new
+    /// line, which is kept in `draft` while browsing.
+    shown: usize,
+    draft: String,
+}
+
+impl<'a> Line<'a> {
+    fn new(history: &'a [String]) -> Self {
+        Self {
+            buffer: Buffer::default(),
+            history,
+            shown: history.len(),
+            draft: String::new(),
+        }
+    }
+
+    fn press(&mut self, key: Key) -> Step {
+        let buffer = &mut self.buffer;
+        match key {
+            Key::Enter => return Step::Finish(ReadLine::Line(buffer.text())),
+            Key::Ctrl('c') => return Step::Finish(ReadLine::Interrupted),
+            Key::Ctrl('d') if buffer.chars.is_empty() => return Step::Finish(ReadLine::Eof),
+            Key::Ctrl('d') | Key::Delete => buffer.delete(),
+            Key::Backspace => buffer.backspace(),
+            Key::Left | Key::Ctrl('b') => buffer.cursor = buffer.cursor.saturating_sub(1),
+            Key::Right | Key::Ctrl('f') => {
+                buffer.cursor = (buffer.cursor + 1).min(buffer.chars.len())
+            }
+            Key::Home | Key::Ctrl('a') => buffer.cursor = 0,
+            Key::End | Key::Ctrl('e') => buffer.cursor = buffer.chars.len(),
+            Key::Ctrl('k') => buffer.chars.truncate(buffer.cursor),
+            Key::Ctrl('u') => {
+                buffer.chars.drain(..buffer.cursor);
+                buffer.cursor = 0;
+            }
+            Key::Ctrl('w') => buffer.delete_word(),
+            Key::Ctrl('l') => return Step::ClearScreen,
+            Key::Ctrl('r') => return Step::Search,
+            Key::Up | Key::Ctrl('p') if self.shown > 0 => {
+                if self.shown == self.history.len() {
+                    self.draft = buffer.text();
+                }
+                self.shown -= 1;
+                buffer.set(&self.history[self.shown]);
+            }
+            Key::Down | Key::Ctrl('n') if self.shown < self.history.len() => {
+                self.shown += 1;
+                match self.history.get(self.shown) {
+                    Some(entry) => buffer.set(entry),
+                    None => buffer.set(&self.draft),
+                }
+            }
+            Key::Char(c) => buffer.insert(c),
+            _ => {}
+        }
+        Step::Edit
+    }
+}
+
+/// A Ctrl-R search of the history, backwards for lines containing what's
+/// typed.
+struct Search<'a> {
+    history: &'a [String],
+    query: String,
+    /// The index of the entry matched, if any.
+    found: Option<usize>,
+}
+
+impl<'a> Search<'a> {
+    fn new(history: &'a [String]) -> Self {
+        Self {
+            history,
+            query: String::new(),
+            found: None,
+        }
+    }
+
+    /// The latest entry before `before` that contains the query.
+    fn find(&self, before: usize) -> Option<usize> {
+        (0..before)
+            .rev()
+            .find(|&index| self.history[index].contains(&self.query))
+    }
+
+    /// Applies `key`, giving back how the search ended, if it did: with
+    /// the entry matched, or `None` if it was given up.
+    fn press(&mut self, key: Key) -> Option<Option<Found>> {
+        match key {
+            Key::Char(c) => {
+                self.query.push(c);
+                self.found = self.find(self.found.map_or(self.history.len(), |index| index + 1));
+            }
+            Key::Backspace => {
+                self.query.pop();
+                self.found = self.find(self.history.len());
+            }
+            Key::Ctrl('r') => {
+                if let Some(index) = self.found {
+                    self.found = self.find(index).or(self.found);
+                }
+            }
+            Key::Ctrl('g') | Key::Ctrl('c') => return Some(None),
+            key => {
+                return Some(self.found.map(|index| Found {
+                    line: self.history[index].clone(),
+                    submit: key == Key::Enter,
+                }))
+            }
+        }
+        None
+    }
+}
+
+pub(crate) struct Editor {
+    history: Vec<String>,
+}
+
+impl Editor {
+    pub(crate) fn new(history: Vec<String>) -> Self {
+        Self { history }
+    }
+
+    pub(crate) fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    pub(crate) fn add_history(&mut self, line: String) {
+        self.history.push(line);
+    }
+
+    /// Shows `prompt` and reads a line with editing. Fails without showing
+    /// anything if the terminal can't be put in raw mode.
+    pub(crate) fn read_line(&mut self, prompt: &str) -> io::Result<ReadLine> {
+        let _raw = RawMode::enable()?;
+        self.edit(prompt, &mut io::stdin().lock(), &mut io::stdout().lock())
+    }
+
+    /// Reads a line from the keys pressed on `input`, drawing it on
+    /// `output` as it's edited.
+    fn edit(
+        &self,
+        prompt: &str,
+        input: &mut impl Read,
+        output: &mut impl Write,
+    ) -> io::Result<ReadLine> {
+        let mut line = Line::new(&self.history);
+
+        render(output, prompt, &line.buffer)?;
+        loop {
+            let Some(key) = read_key(input)? else {
+                return Ok(ReadLine::Eof);
+            };
+
+            match line.press(key) {
+                Step::Edit => {}
+                Step::Finish(ReadLine::Line(text)) => {
+                    output.write_all(b"\r\n")?;
+                    return Ok(ReadLine::Line(text));
+                }
+                Step::Finish(ReadLine::Interrupted) => {
+                    output.write_all(b"^C\r\n")?;
+                    return Ok(ReadLine::Interrupted);
+                }
+                Step::Finish(ReadLine::Eof) => return Ok(ReadLine::Eof),
+                Step::ClearScreen => output.write_all(b"\x1b[H\x1b[2J")?,
+                Step::Search => {
+                    if let Some(found) = self.search(input, output, &line.buffer)? {
+                        line.buffer.set(&found.line);
+                        if found.submit {
+                            render(output, prompt, &line.buffer)?;
+                            output.write_all(b"\r\n")?;
+                            return Ok(ReadLine::Line(found.line));
+                        }
+                    }
+                }
+            }
+
+            render(output, prompt, &line.buffer)?;
+        }
+    }
+
+    /// Searches the history backwards for lines containing what's typed,
+    /// starting from the line being edited. Enter runs the match, Ctrl-R
+    /// finds an older one, Ctrl-G or Ctrl-C gives up, and any other key
+    /// leaves the match to be edited.
+    fn search(
+        &self,
+        input: &mut impl Read,
+        output: &mut impl Write,
+        buffer: &Buffer,
+    ) -> io::Result<Option<Found>> {
+        let mut search = Search::new(&self.history);
+
+        loop {
+            let shown = search
+                .found
+                .map_or_else(|| buffer.text(), |index| self.history[index].clone());
+            write!(
+                output,
+                "\r(reverse-i-search)`{}': {shown}\x1b[K",
+                search.query
+            )?;
+            output.flush()?;
+
+            let Some(key) = read_key(input)? else {
+                return Ok(None);
+            };
+            if let Some(found) = search.press(key) {
+                return Ok(found);
+            }
+        }
+    }
+}
+
+/// A line picked from the history with Ctrl-R.
+#[derive(Debug, PartialEq, Eq)]
+struct Found {
+    line: String,
+    /// Whether it was picked with Enter, to be run straight away.
+    submit: bool,
+}
+
+/// Redraws the prompt and line, and puts the cursor back where it belongs.
+fn render(output: &mut impl Write, prompt: &str, buffer: &Buffer) -> io::Result<()> {
+    write!(output, "\r{prompt}{}\x1b[K", buffer.text())?;
+
+    let back = buffer.chars.len() - buffer.cursor;
+    if back > 0 {
+        write!(output, "\x1b[{back}D")?;
+    }
+
+    output.flush()
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// Reads the next key press, or `None` if stdin has closed.
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        // Insert spaces for a tab, which is how Lox code is usually indented.
+        b'\t' => Key::Char(' '),
+        127 | 8 => Key::Backspace,
+        1..=26 => Key::Ctrl((b'a' + byte - 1) as char),
+        27 => read_escape(input)?,
+        byte if byte < 0x80 => Key::Char(byte as char),
+        first => {
+            // The rest of a multibyte UTF-8 character.
+            let length = match first {
+                0xf0.. => 4,
+                0xe0.. => 3,
+                _ => 2,
+            };
+            let mut bytes = vec![first];
+            for _ in 1..length {
+                bytes.extend(read_byte(input)?);
+            }
+            String::from_utf8(bytes)
+                .ok()
+                .and_then(|text| text.chars().next())
+                .map_or(Key::Unknown, Key::Char)
+        }
+    };
+
+    Ok(Some(key))
+}
+
+/// Decodes the rest of an escape sequence, as sent for the arrow keys and
+/// the like, after its leading escape.
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    let Some(b'[' | b'O') = read_byte(input)? else {
+        return Ok(Key::Unknown);
+    };
+
+    let mut parameter = String::new();
+    loop {
+        let Some(byte) = read_byte(input)? else {
+            return Ok(Key::Unknown);
+        };
+
+        return Ok(match (byte, parameter.as_str()) {
+            (b'0'..=b'9' | b';', _) => {
+                parameter.push(byte as char);
+                continue;
+            }
+            (b'A', _) => Key::Up,
+            (b'B', _) => Key::Down,
+            (b'C', _) => Key::Right,
+            (b'D', _) => Key::Left,
+            (b'H', _) | (b'~', "1" | "7") => Key::Home,
+            (b'F', _) | (b'~', "4" | "8") => Key::End,
+            (b'~', "3") => Key::Delete,
+            _ => Key::Unknown,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_key, Buffer, Editor, Key, ReadLine};
+
+    fn keys(mut bytes: &[u8]) -> Vec<Key> {
+        std::iter::from_fn(|| read_key(&mut bytes).unwrap()).collect()
+    }
+
+    /// Reads a line from `bytes` typed with `history` to recall.
+    fn edit(history: &[&str], bytes: &[u8]) -> ReadLine {
+        let editor = Editor::new(history.iter().map(|line| line.to_string()).collect());
+        editor.edit("> ", &mut &bytes[..], &mut vec![]).unwrap()
+    }
+
+    fn line(text: &str) -> ReadLine {
+        ReadLine::Line(text.to_string())
+    }
+
+    #[test]
+    fn keys_are_decoded_from_the_bytes_terminals_send() {
+        assert_eq!(
+            keys(b"a\t\x7f\x01\r\x1b[A\x1bOB\x1b[3~\x1b[1~\x1b[F\x1b[9Z\xc3\xa9"),
+            [
+                Key::Char('a'),
+                Key::Char(' '),
+                Key::Backspace,
+                Key::Ctrl('a'),
+                Key::Enter,
+                Key::Up,
+                Key::Down,
+                Key::Delete,
+                Key::Home,
+                Key::End,
+                Key::Unknown,
+                Key::Char('é'),
+            ]
+        );
+    }
+
+    #[test]
+    fn deleting_a_word_takes_the_spaces_after_it() {
+        let mut buffer = Buffer::default();
+        buffer.set("print one  two  ");
+        buffer.delete_word();
+        assert_eq!(buffer.text(), "print one  ");
+        buffer.cursor = 5;
+        buffer.delete_word();
+        assert_eq!((buffer.text().as_str(), buffer.cursor), (" one  ", 0));
+    }
+
+    #[test]
+    fn keys_move_around_and_edit_the_line() {
+        assert_eq!(edit(&[], b"ac\x1b[Db\r"), line("abc"));
+        assert_eq!(edit(&[], b"abc\x01\x1b[3~\x05d\r"), line("bcd"));
+        assert_eq!(edit(&[], b"one two\x02\x02\x0bx\r"), line("one tx"));
+        assert_eq!(edit(&[], b"one two\x02\x02\x15x\r"), line("xwo"));
+        assert_eq!(edit(&[], b"ab\x03"), ReadLine::Interrupted);
+        assert_eq!(edit(&[], b"\x04"), ReadLine::Eof);
+        assert_eq!(edit(&[], b"ab"), ReadLine::Eof);
+    }
+
+    #[test]
+    fn the_arrows_browse_history_and_keep_the_new_line() {
+        let history = ["first", "second"];
+        assert_eq!(edit(&history, b"\x1b[A\x1b[A\r"), line("first"));
+        assert_eq!(edit(&history, b"new\x1b[A\x1b[B\r"), line("new"));
+        assert_eq!(edit(&history, b"\x1b[A\x1b[A\x1b[A!\r"), line("first!"));
+    }
+
+    #[test]
+    fn ctrl_r_finds_and_runs_or_edits_a_history_line() {
+        let history = ["var a = 1;", "print a;", "var b = 2;"];
+        assert_eq!(edit(&history, b"\x12var\r"), line("var b = 2;"));
+        assert_eq!(edit(&history, b"\x12var\x12\r"), line("var a = 1;"));
+        assert_eq!(edit(&history, b"\x12pr\x1b[C;\r"), line("print a;;"));
+        assert_eq!(edit(&history, b"x\x12pr\x07\r"), line("x"));
+    }
+}
@@ -0,0 +1,591 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::{ErrorCode, Message, RuntimeError},
+    parser::{Expr, FunctionDecl, Stmt},
+    symbol::Symbol,
+    token::Token,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassKind {
+    None,
+    Class,
+    Subclass,
+}
+
+/// A name declared in a local scope.
+struct Local {
+    /// `false` while the variable's initializer is being resolved.
+    defined: bool,
+    /// Where the name was declared, or `None` for the implicit `this` and
+    /// `super`.
+    declaration: Option<Token>,
+}
+
+/// A static pass run between parsing and interpreting. It works out which
+/// scope every local variable reference refers to, storing the distance on
+/// the AST node for the interpreter, and reports mistakes that can be caught
+/// without running the program.
+///
+/// The scopes pushed here must mirror the environments the interpreter
+/// creates at runtime, or the recorded distances will point at the wrong
+/// scope. Anything not found in a local scope is assumed to be a global.
+pub struct Resolver {
+    /// One map per enclosing local scope, innermost last.
+    scopes: Vec<HashMap<Symbol, Local>>,
+    function: FunctionKind,
+    class: ClassKind,
+    errors: Vec<RuntimeError>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![],
+            function: FunctionKind::None,
+            class: ClassKind::None,
+            errors: vec![],
+        }
+    }
+
+    /// Resolves `statements` as a whole program, returning every error found.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "resolve", skip_all))]
+    pub fn resolve(mut self, statements: &[Stmt]) -> Vec<RuntimeError> {
+        self.resolve_statements(statements);
+        self.errors
+    }
+
+    fn resolve_statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.resolve_statement(statement);
+        }
+    }
+
+    fn resolve_statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) => self.resolve_expression(expr),
+            Stmt::Function(declaration) => {
+                self.declare(&declaration.name);
+                self.define(&declaration.name);
+                self.resolve_function(declaration, FunctionKind::Function);
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                let kind = match superclass {
+                    Some(_) => ClassKind::Subclass,
+                    None => ClassKind::Class,
+                };
+                let enclosing_class = std::mem::replace(&mut self.class, kind);
+
+                self.declare(name);
+                self.define(name);
+
+                if let Some(superclass) = superclass {
+                    self.resolve_expression(superclass);
+                    self.begin_scope();
+                    self.define_name(Symbol::SUPER);
+                }
+
+                self.begin_scope();
+                self.define_name(Symbol::THIS);
+
+                // Class methods and instance methods don't share names.
+                let mut declared: HashMap<(bool, Symbol), &Token> = HashMap::new();
+                for method in methods {
+                    let key = (method.is_class_method, method.name.symbol());
+                    if let Some(first) = declared.insert(key, &method.name) {
+                        self.duplicate(
+                            "Already a method with this name in this class",
+                            &method.name,
+                            first,
+                        );
+                    }
+
+                    let kind = match method.name.symbol() == Symbol::INIT && !method.is_class_method
+                    {
+                        true => FunctionKind::Initializer,
+                        false => FunctionKind::Method,
+                    };
+                    self.resolve_function(method, kind);
+                }
+
+                self.end_scope();
+
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+
+                self.class = enclosing_class;
+            }
+            Stmt::Import { keyword, .. } => {
+                if !self.scopes.is_empty() {
+                    self.error("Can only import at the top level", keyword);
+                }
+            }
+            Stmt::Print { values, .. } => {
+                for value in values {
+                    self.resolve_expression(value);
+                }
+            }
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.resolve_expression(initializer);
+                }
+                self.define(name);
+            }
+            Stmt::Unpack {
+                names, initializer, ..
+            } => {
+                names.iter().for_each(|name| self.declare(name));
+                self.resolve_expression(initializer);
+                names.iter().for_each(|name| self.define(name));
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.resolve_expression(condition);
+                self.resolve_statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_statement(else_branch);
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                self.resolve_expression(condition);
+                self.resolve_statement(body);
+                if let Some(increment) = increment {
+                    self.resolve_expression(increment);
+                }
+            }
+            Stmt::Return { keyword, value, .. } => {
+                if self.function == FunctionKind::None {
+                    self.error(
+                        Message::new(ErrorCode::InvalidReturn, "Can't return from top-level code"),
+                        keyword,
+                    );
+                }
+
+                if let Some(value) = value {
+                    if self.function == FunctionKind::Initializer {
+                        self.error(
+                            Message::new(
+                                ErrorCode::InvalidReturn,
+                                "Can't return a value from an initializer",
+                            ),
+                            keyword,
+                        );
+                    }
+                    self.resolve_expression(value);
+                }
+            }
+            Stmt::Block { statements, .. } => {
+                self.begin_scope();
+                self.resolve_statements(statements);
+                self.end_scope();
+            }
+            Stmt::Break { .. } | Stmt::Continue { .. } => {}
+            // The caught error and the catch body share a scope, as a
+            // function's parameters and body do.
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.begin_scope();
+                self.resolve_statements(body);
+                self.end_scope();
+
+                if let Some(catch) = catch {
+                    self.begin_scope();
+                    self.declare(&catch.name);
+                    self.define(&catch.name);
+                    self.resolve_statements(&catch.body);
+                    self.end_scope();
+                }
+
+                if let Some(finally) = finally {
+                    self.begin_scope();
+                    self.resolve_statements(finally);
+                    self.end_scope();
+                }
+            }
+            Stmt::Throw { value, .. } => self.resolve_expression(value),
+            Stmt::Defer {
+                keyword, statement, ..
+            } => {
+                if self.scopes.is_empty() {
+                    self.error("Can't defer outside a block or function", keyword);
+                }
+                self.resolve_statement(statement);
+            }
+            Stmt::ForIn {
+                names,
+                iterable,
+                body,
+                ..
+            } => {
+                self.resolve_expression(iterable);
+                self.begin_scope();
+                for name in names {
+                    self.declare(name);
+                    self.define(name);
+                }
+                self.resolve_statement(body);
+                self.end_scope();
+            }
+            Stmt::Use {
+                name,
+                initializer,
+                body,
+                ..
+            } => {
+                self.resolve_expression(initializer);
+                self.begin_scope();
+                self.declare(name);
+                self.define(name);
+                self.begin_scope();
+                self.resolve_statements(body);
+                self.end_scope();
+                self.end_scope();
+            }
+        }
+    }
+
+    /// Parameters and the body share a single scope, matching the one
+    /// environment `LoxFunction::call` runs the body in.
+    fn resolve_function(&mut self, declaration: &FunctionDecl, kind: FunctionKind) {
+        let enclosing_function = std::mem::replace(&mut self.function, kind);
+
+        self.begin_scope();
+        for param in &declaration.params {
+            self.declare_as(param, "Already a parameter with this name");
+            self.define(param);
+        }
+        self.resolve_statements(&declaration.body);
+        self.end_scope();
+
+        self.function = enclosing_function;
+    }
+
+    fn resolve_expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal { .. } => {}
+            Expr::This { keyword, depth } => {
+                if self.class == ClassKind::None {
+                    self.error(
+                        Message::new(
+                            ErrorCode::OutsideConstruct,
+                            "Can't use `this` outside of a class",
+                        ),
+                        keyword,
+                    );
+                    return;
+                }
+                depth.set(self.resolve_local(keyword.symbol()));
+            }
+            Expr::Super { keyword, depth, .. } => {
+                match self.class {
+                    ClassKind::None => {
+                        self.error(
+                            Message::new(
+                                ErrorCode::OutsideConstruct,
+                                "Can't use `super` outside of a class",
+                            ),
+                            keyword,
+                        );
+                        return;
+                    }
+                    ClassKind::Class => {
+                        self.error(
+                            Message::new(
+                                ErrorCode::OutsideConstruct,
+                                "Can't use `super` in a class with no superclass",
+                            ),
+                            keyword,
+                        );
+                        return;
+                    }
+                    ClassKind::Subclass => {}
+                }
+                depth.set(self.resolve_local(keyword.symbol()));
+            }
+            Expr::Variable { name, depth } => {
+                let local = self
+                    .scopes
+                    .last()
+                    .and_then(|scope| scope.get(&name.symbol()));
+                if let Some(Local { defined: false, .. }) = local {
+                    self.error(
+                        Message::new(
+                            ErrorCode::OwnInitializer,
+                            "Can't read local variable in its own initializer",
+                        ),
+                        name,
+                    );
+                }
+                depth.set(self.resolve_local(name.symbol()));
+            }
+            Expr::Assign { name, value, depth } => {
+                self.resolve_expression(value);
+                depth.set(self.resolve_local(name.symbol()));
+            }
+            Expr::Grouping { group, .. } => self.resolve_expression(group),
+            Expr::Get { object, .. } => self.resolve_expression(object),
+            Expr::Set { object, value, .. } => {
+                self.resolve_expression(value);
+                self.resolve_expression(object);
+            }
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.resolve_expression(key);
+                    self.resolve_expression(value);
+                }
+            }
+            Expr::List { elements, .. } | Expr::Tuple { elements, .. } => {
+                for element in elements {
+                    self.resolve_expression(element);
+                }
+            }
+            Expr::Match {
+                subject,
+                arms,
+                otherwise,
+                ..
+            } => {
+                self.resolve_expression(subject);
+                for arm in arms {
+                    for expr in arm.pattern.exprs() {
+                        self.resolve_expression(expr);
+                    }
+                    self.begin_scope();
+                    for name in arm.pattern.bindings() {
+                        self.declare_as(name, "Already a variable with this name in this pattern");
+                        self.define(name);
+                    }
+                    self.resolve_expression(&arm.body);
+                    self.end_scope();
+                }
+                self.resolve_expression(otherwise);
+            }
+            Expr::Index { object, index, .. } => {
+                self.resolve_expression(object);
+                self.resolve_expression(index);
+            }
+            Expr::Slice {
+                object, start, end, ..
+            } => {
+                self.resolve_expression(object);
+                for bound in start.iter().chain(end) {
+                    self.resolve_expression(bound);
+                }
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.resolve_expression(object);
+                self.resolve_expression(index);
+                self.resolve_expression(value);
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.resolve_expression(callee);
+                for argument in arguments {
+                    self.resolve_expression(argument);
+                }
+            }
+            Expr::Unary { right, .. } => self.resolve_expression(right),
+            Expr::Update { target, .. } => self.resolve_expression(target),
+            Expr::Compound { target, value, .. } => {
+                self.resolve_expression(target);
+                self.resolve_expression(value);
+            }
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.resolve_expression(left);
+                self.resolve_expression(right);
+            }
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_expression(condition);
+                self.resolve_expression(then_branch);
+                self.resolve_expression(else_branch);
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Adds `name` to the innermost scope as not yet usable. Globals are not
+    /// tracked, so redeclaring one is allowed, as it is in the REPL.
+    fn declare(&mut self, name: &Token) {
+        self.declare_as(name, "Already a variable with this name in this scope");
+    }
+
+    /// Declares `name`, reporting `message` alongside the first declaration
+    /// if the innermost scope already has one.
+    fn declare_as(&mut self, name: &Token, message: &str) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+
+        let local = Local {
+            defined: false,
+            declaration: Some(name.clone()),
+        };
+        if let Some(previous) = scope.insert(name.symbol(), local) {
+            match previous.declaration {
+                Some(first) => self.duplicate(message, name, &first),
+                None => self.error(Message::new(ErrorCode::AlreadyDeclared, message), name),
+            }
+        }
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(local) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(&name.symbol()))
+        {
+            local.defined = true;
+        }
+    }
+
+    fn define_name(&mut self, name: Symbol) {
+        if let Some(scope) = self.scopes.last_mut() {
+            let local = Local {
+                defined: true,
+                declaration: None,
+            };
+            scope.insert(name, local);
+        }
+    }
+
+    /// How many scopes out from the innermost one `name` is declared, or
+    /// `None` if it isn't local and so must be a global.
+    fn resolve_local(&self, name: Symbol) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains_key(&name))
+    }
+
+    fn error(&mut self, message: impl Into<Message>, token: &Token) {
+        self.errors
+            .push(RuntimeError::ResolveError(message.into(), token.clone()));
+    }
+
+    fn duplicate(&mut self, message: &str, name: &Token, first: &Token) {
+        self.errors.push(RuntimeError::DuplicateDeclaration {
+            message: Message::new(ErrorCode::AlreadyDeclared, message),
+            name: name.clone(),
+            first: Box::new(first.clone()),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner};
+
+    fn resolve(source: &str) -> (Vec<Stmt>, Vec<String>) {
+        let program = Parser::new(Scanner::new(source).scan_tokens().tokens)
+            .parse()
+            .program;
+        let errors = Resolver::new().resolve(&program);
+        (program, errors.iter().map(ToString::to_string).collect())
+    }
+
+    #[test]
+    fn locals_are_bound_to_the_scope_that_declares_them() {
+        let (program, errors) = resolve("var g; { var a; { a; g; } }");
+        assert!(errors.is_empty(), "{errors:?}");
+
+        let Stmt::Block { statements, .. } = &program[1] else {
+            panic!("expected a block");
+        };
+        let Stmt::Block { statements, .. } = &statements[1] else {
+            panic!("expected a nested block");
+        };
+        let depths: Vec<_> = statements
+            .iter()
+            .map(|stmt| match stmt {
+                Stmt::Expression(Expr::Variable { depth, .. }) => depth.get(),
+                stmt => panic!("expected a variable, found {stmt}"),
+            })
+            .collect();
+        assert_eq!(depths, [Some(1), None]);
+    }
+
+    #[test]
+    fn misplaced_statements_are_reported() {
+        let (_, errors) = resolve(
+            "return 1;\n\
+             print this;\n\
+             { var a = a; }\n\
+             class A { init() { return 1; } }\n\
+             class B { f() { super.f(); } }\n\
+             defer print 1;",
+        );
+        assert_eq!(
+            errors,
+            [
+                "line 1 | Error[E0008]: Can't return from top-level code",
+                "line 2 | Error[E0009]: Can't use `this` outside of a class",
+                "line 3 | Error[E0007]: Can't read local variable in its own initializer",
+                "line 4 | Error[E0008]: Can't return a value from an initializer",
+                "line 5 | Error[E0009]: Can't use `super` in a class with no superclass",
+                "line 6 | Error: Can't defer outside a block or function",
+            ]
+        );
+    }
+
+    #[test]
+    fn locals_can_only_be_declared_once_per_scope() {
+        let (_, errors) = resolve("var a; var a; { var b; var b; }");
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].contains("Already a variable"), "{errors:?}");
+    }
+}
@@ -1,168 +1,511 @@
+use std::borrow::Cow;
+
 use crate::{
     error::{Result, RuntimeError},
+    span::Span,
+    symbol::Symbol,
     token::{Token, TokenValue},
 };
 
+/// Upper bounds enforced while scanning, so hosts running untrusted scripts
+/// can reject oversized input before it reaches the parser. `None` means no
+/// limit, which is the default for every field.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ScanLimits {
+    /// Maximum size of the whole source, in bytes.
+    pub max_source_bytes: Option<usize>,
+    /// Maximum number of tokens, not counting the final end-of-file token.
+    pub max_tokens: Option<usize>,
+    /// Maximum length of a single string literal, in characters.
+    pub max_string_length: Option<usize>,
+}
+
+/// Which Lox a [`Scanner`] reads: the language of *Crafting Interpreters*,
+/// rlox's extended one, or something in between. An extension that's turned
+/// off scans the way jlox would, so `break` is an ordinary identifier and
+/// `[` is an unexpected character.
+///
+/// ```
+/// use rlox::{LoxDialect, Scanner};
+///
+/// let output = Scanner::new("xs[0];").with_dialect(LoxDialect::BOOK).scan_tokens();
+/// assert_eq!(output.errors[0].to_string(), "line 1:3 | Error: Unexpected token `[`");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoxDialect {
+    /// Matches keywords whatever their case, so `CLASS` is `class`.
+    pub case_insensitive_keywords: bool,
+    /// Scans `break`, `continue`, `defer`, `import`, `in`, `match`, `try`,
+    /// `catch`, `finally`, `throw` and `use` as keywords.
+    pub extension_keywords: bool,
+    /// Allows `/* ... */` comments.
+    pub block_comments: bool,
+    /// Scans rlox's operators: `%`, `**`, `++`, `--`, compound assignments
+    /// such as `+=`, `?` and `:`, `[` and `]`, and `=>`.
+    pub extension_operators: bool,
+    /// Reads `\n`, `\t`, `\"` and `\\` in a string as escape sequences.
+    /// Without them a backslash is an ordinary character, so `"c:\dir"` is
+    /// a string.
+    pub string_escapes: bool,
+    /// Allows exponents in numbers, as in `1.5e3`, and underscores between
+    /// digits, as in `1_000`.
+    pub number_extensions: bool,
+    /// Allows any Unicode letters and digits in identifiers, following
+    /// UAX #31. Without it identifiers are ASCII letters, digits and `_`.
+    pub unicode_identifiers: bool,
+    /// Lets a [`Parser`](crate::Parser) set up with the dialect accept the
+    /// extensions made of tokens the book's Lox already has: `print` with
+    /// several values, string literals written next to each other, tuples,
+    /// `return a, b;`, `var (a, b) = ...;`, map literals, getters and class
+    /// methods.
+    pub extension_syntax: bool,
+}
+
+impl LoxDialect {
+    /// Only what jlox, the book's interpreter, scans.
+    pub const BOOK: LoxDialect = LoxDialect {
+        case_insensitive_keywords: false,
+        extension_keywords: false,
+        block_comments: false,
+        extension_operators: false,
+        string_escapes: false,
+        number_extensions: false,
+        unicode_identifiers: false,
+        extension_syntax: false,
+    };
+
+    /// Every rlox extension, with keywords matched case-sensitively as in
+    /// the book. This is the default.
+    pub const EXTENDED: LoxDialect = LoxDialect {
+        case_insensitive_keywords: false,
+        extension_keywords: true,
+        block_comments: true,
+        extension_operators: true,
+        string_escapes: true,
+        number_extensions: true,
+        unicode_identifiers: true,
+        extension_syntax: true,
+    };
+}
+
+impl Default for LoxDialect {
+    fn default() -> Self {
+        Self::EXTENDED
+    }
+}
+
+/// Everything a [`Scanner`] is set up with, kept together so scripts a
+/// program imports are scanned the same way as the program.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct ScannerOptions {
+    pub(crate) limits: ScanLimits,
+    pub(crate) dialect: LoxDialect,
+}
+
+/// The tokens scanned from a source along with every lexical error found.
+/// Characters that couldn't be scanned are left out of `tokens`, which always
+/// ends with an end-of-file token.
+pub struct ScanOutput {
+    pub tokens: Vec<Token>,
+    pub errors: Vec<RuntimeError>,
+}
+
+/// How far a [`Scanner`] has got through its source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ScanState {
+    /// Nothing has been scanned, and the source hasn't been checked against
+    /// the limits yet.
+    Start,
+    Scanning,
+    /// The source is used up, or a limit stopped the scan. Only the
+    /// end-of-file token is left.
+    Ending,
+    Done,
+}
+
+/// Turns source text into [`Token`]s, the first step of running a program.
+/// The source is borrowed rather than copied, and lexemes are sliced out of
+/// it directly.
 #[derive(Debug)]
-pub(crate) struct Scanner {
-    chars: Vec<char>,
+pub struct Scanner<'a> {
+    source: &'a str,
+    limits: ScanLimits,
+    dialect: LoxDialect,
+    keep_comments: bool,
+    state: ScanState,
+    tokens_scanned: usize,
+    limit_exceeded: bool,
+    /// The byte offset of the character being scanned.
     position: usize,
     line: usize,
+    /// Counted in characters, so multibyte characters take one column.
     column: usize,
 }
 
-impl Scanner {
-    pub(crate) fn new(source: String) -> Self {
-        let chars = source.clone().chars().into_iter().collect();
-
+impl<'a> Scanner<'a> {
+    pub fn new(source: &'a str) -> Self {
         Self {
-            chars,
+            source,
+            limits: ScanLimits::default(),
+            dialect: LoxDialect::default(),
+            keep_comments: false,
+            state: ScanState::Start,
+            tokens_scanned: 0,
+            limit_exceeded: false,
             position: 0,
             line: 1,
-            column: 0,
+            column: 1,
         }
     }
 
-    pub(crate) fn scan_tokens(mut self) -> Result<Vec<Token>> {
+    /// Rejects source that exceeds `limits`; see [`ScanLimits`].
+    pub fn with_limits(mut self, limits: ScanLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Scans `dialect` instead of the default, extended Lox.
+    pub fn with_dialect(mut self, dialect: LoxDialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    pub(crate) fn with_options(self, options: ScannerOptions) -> Self {
+        self.with_limits(options.limits)
+            .with_dialect(options.dialect)
+    }
+
+    /// Emits a [`TokenValue::Comment`] for each comment instead of skipping
+    /// it, for tools like the formatter that need to put comments back. The
+    /// parser doesn't accept comment tokens, so they must be filtered out
+    /// before parsing.
+    pub fn with_comments(mut self, keep: bool) -> Self {
+        self.keep_comments = keep;
+        self
+    }
+
+    /// Starts scanning at the byte offset `position`, which is on `line` and
+    /// `column`, as though everything before it had been scanned already.
+    /// `position` has to be where a token or the space before one starts.
+    pub(crate) fn starting_at(mut self, position: usize, line: usize, column: usize) -> Self {
+        self.position = position;
+        self.line = line;
+        self.column = column;
+        self
+    }
+
+    /// Scans the whole source at once. Iterating over the scanner instead
+    /// gives the same tokens and errors one at a time, in source order.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "scan", skip_all, fields(bytes = self.source.len()))
+    )]
+    pub fn scan_tokens(mut self) -> ScanOutput {
         let mut tokens = vec![];
+        let mut errors = vec![];
 
-        if self.chars.is_empty() {
-            tokens.push(Token::new(TokenValue::Eof, "".into(), self.line));
-            return Ok(tokens);
+        for result in self.by_ref() {
+            match result {
+                Ok(token) => tokens.push(token),
+                Err(err) => errors.push(err),
+            }
         }
 
-        loop {
-            if let Some(token) = self.scan_token()? {
-                tokens.push(token);
+        // Scanning stopped by a limit passes no tokens but the end-of-file
+        // one, so the parser doesn't report errors for a truncated program
+        // on top of the limit being exceeded.
+        if self.limit_exceeded() {
+            tokens.retain(|token| token.value == TokenValue::Eof);
+        }
+
+        ScanOutput { tokens, errors }
+    }
+
+    /// Whether scanning stopped early because the source exceeded one of the
+    /// [`ScanLimits`].
+    pub fn limit_exceeded(&self) -> bool {
+        self.limit_exceeded
+    }
+
+    /// Scans the token at the current position, or returns `None` once the
+    /// whole source has been scanned.
+    fn next_token(&mut self) -> Option<Result<Token>> {
+        if self.state == ScanState::Start {
+            self.state = ScanState::Scanning;
+
+            if let Some(max) = self.limits.max_source_bytes {
+                if self.source.len() > max {
+                    return Some(Err(self.exceed_limit(format!(
+                        "Source is {} bytes, which exceeds the limit of {max} bytes",
+                        self.source.len()
+                    ))));
+                }
             }
+            if self.source.is_empty() {
+                self.state = ScanState::Ending;
+            }
+        }
 
-            if self.at_end() {
-                break;
+        while self.state == ScanState::Scanning {
+            let column = self.column;
+            let start = self.position;
+            let result = self.scan_token();
+            let end = self.position + self.current().len_utf8();
+
+            if let Ok(Some(_)) = result {
+                self.tokens_scanned += 1;
+                if let Some(max) = self.limits.max_tokens {
+                    if self.tokens_scanned > max {
+                        return Some(Err(
+                            self.exceed_limit(format!("Source exceeds the limit of {max} tokens"))
+                        ));
+                    }
+                }
             }
 
-            self.advance();
+            // Stay on the last character, which the end-of-file token is
+            // placed after.
+            match self.at_end() {
+                true => self.state = ScanState::Ending,
+                false => self.advance(),
+            }
+
+            match result {
+                Ok(Some(mut token)) => {
+                    token.column = column;
+                    // A line comment ends before the line break the scanner
+                    // has already moved past, so it sets its own span.
+                    if token.span.is_empty() {
+                        token.span = Span::new(start, end);
+                    }
+                    return Some(Ok(token));
+                }
+                Ok(None) => {}
+                // Skip the offending character and carry on, so every
+                // lexical error in the source is reported.
+                Err(err) => return Some(Err(err)),
+            }
         }
 
-        tokens.push(Token::new(TokenValue::Eof, "".into(), self.line));
+        None
+    }
 
-        Ok(tokens)
+    /// Stops scanning with `message`, reported at the current position.
+    fn exceed_limit(&mut self, message: String) -> RuntimeError {
+        self.limit_exceeded = true;
+        self.state = ScanState::Ending;
+        RuntimeError::scan_error(message, self.line, self.column, self.here())
     }
 
+    /// The token that ends every scan.
+    fn eof(&self) -> Token {
+        let mut eof = Token::new(TokenValue::Eof, "", self.line);
+        eof.column = match self.source.is_empty() {
+            true => 1,
+            false => self.column + 1,
+        };
+        eof.span = Span::new(self.source.len(), self.source.len());
+        eof
+    }
+
+    /// Whether the current character is the last one in the source.
     fn at_end(&self) -> bool {
-        self.peek().is_err()
+        self.peek().is_none()
     }
 
     fn current(&self) -> char {
-        self.chars[self.position]
+        self.source[self.position..]
+            .chars()
+            .next()
+            .expect("scanner position is past the end of the source")
     }
 
     fn advance(&mut self) {
-        self.position += 1;
+        self.position += self.current().len_utf8();
         self.column += 1;
     }
 
+    /// Moves to the next line. The column is reset to zero rather than one
+    /// because the scan loop advances past the line terminator afterwards.
     fn next_line(&mut self) {
         self.line += 1;
         self.column = 0;
     }
 
-    fn peek(&self) -> Result<char> {
-        let offset = self.position + 1;
-        match offset >= self.chars.len() {
-            false => Ok(self.chars[offset]),
-            true => Err(RuntimeError::scan_error(
-                format!("Attempt to read source at invalid offset `{offset}``"),
-                self.line,
-                self.column,
-                self.position,
-            )),
+    /// Consumes a line terminator at the current position. `\r\n` and a lone
+    /// `\r` are treated the same as `\n`, so files with Windows or classic Mac
+    /// line endings still get correct line numbers.
+    fn line_ending(&mut self) {
+        if self.current() == '\r' && self.next_eq('\n') {
+            self.advance();
         }
+
+        self.next_line();
+    }
+
+    /// The span of the current character, or an empty span at the end of
+    /// the source.
+    fn here(&self) -> Span {
+        let end = self.source[self.position..]
+            .chars()
+            .next()
+            .map_or(self.position, |c| self.position + c.len_utf8());
+        Span::new(self.position, end)
     }
 
-    fn next_eq(&self, expected: &str) -> bool {
-        match self.peek() {
-            Ok(actual) => actual.to_string() == expected,
-            Err(_) => false,
+    /// The digits following the current one, which must be a digit. An
+    /// underscore is only allowed between two digits.
+    fn scan_digits(&mut self) -> Result<()> {
+        while let Some(char) = self.peek() {
+            match char {
+                '0'..='9' => self.advance(),
+                '_' if !self.dialect.number_extensions => break,
+                '_' if self.peek_nth(2).is_some_and(|c| c.is_ascii_digit()) => self.advance(),
+                '_' => {
+                    self.advance();
+                    return Err(RuntimeError::scan_error(
+                        "An underscore in a number must be between two digits".into(),
+                        self.line,
+                        self.column,
+                        self.here(),
+                    ));
+                }
+                _ => break,
+            }
         }
+
+        Ok(())
+    }
+
+    /// The character `n` places after the current one.
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.source[self.position..].chars().nth(n)
+    }
+
+    /// The character after the current one.
+    fn peek(&self) -> Option<char> {
+        let mut chars = self.source[self.position..].chars();
+        chars.next();
+        chars.next()
+    }
+
+    fn next_eq(&self, expected: char) -> bool {
+        self.peek() == Some(expected)
+    }
+
+    /// The source from byte offset `start` up to and including the current
+    /// character.
+    fn lexeme_from(&self, start: usize) -> &'a str {
+        &self.source[start..self.position + self.current().len_utf8()]
+    }
+
+    /// Consumes the second character of an operator like `!=` and returns
+    /// its token.
+    fn two_char_token(&mut self, value: TokenValue) -> Token {
+        let start = self.position;
+        self.advance();
+        Token::new(value, self.lexeme_from(start), self.line)
     }
 
     fn scan_token(&mut self) -> Result<Option<Token>> {
         let next_char = self.current();
-        let lexeme = next_char.to_string();
+        let mut buffer = [0; 4];
+        let lexeme: &str = next_char.encode_utf8(&mut buffer);
 
+        let extended = self.dialect.extension_operators;
         match next_char {
-            ' ' | '\r' | '\t' => Ok(None),
-            '\n' => {
-                self.next_line();
+            ' ' | '\t' => Ok(None),
+            '[' | ']' | '%' | '?' | ':' if !extended => Err(self.unexpected(next_char)),
+            '\n' | '\r' => {
+                self.line_ending();
                 Ok(None)
             }
             '(' => Ok(Some(Token::new(TokenValue::LeftParen, lexeme, self.line))),
             ')' => Ok(Some(Token::new(TokenValue::RightParen, lexeme, self.line))),
             '{' => Ok(Some(Token::new(TokenValue::LeftBrace, lexeme, self.line))),
             '}' => Ok(Some(Token::new(TokenValue::RightBrace, lexeme, self.line))),
+            '[' => Ok(Some(Token::new(TokenValue::LeftBracket, lexeme, self.line))),
+            ']' => Ok(Some(Token::new(
+                TokenValue::RightBracket,
+                lexeme,
+                self.line,
+            ))),
             ',' => Ok(Some(Token::new(TokenValue::Comma, lexeme, self.line))),
             '.' => Ok(Some(Token::new(TokenValue::Dot, lexeme, self.line))),
-            '+' => Ok(Some(Token::new(TokenValue::Plus, lexeme, self.line))),
-            '-' => Ok(Some(Token::new(TokenValue::Minus, lexeme, self.line))),
+            '%' => Ok(Some(Token::new(TokenValue::Percent, lexeme, self.line))),
             ';' => Ok(Some(Token::new(TokenValue::Semicolon, lexeme, self.line))),
-            '*' => Ok(Some(Token::new(TokenValue::Star, lexeme, self.line))),
-            '!' => match self.next_eq("=") {
+            '+' => match self.peek() {
+                Some('+') if extended => Ok(Some(self.two_char_token(TokenValue::PlusPlus))),
+                Some('=') if extended => Ok(Some(self.two_char_token(TokenValue::PlusEqual))),
+                _ => Ok(Some(Token::new(TokenValue::Plus, lexeme, self.line))),
+            },
+            '-' => match self.peek() {
+                Some('-') if extended => Ok(Some(self.two_char_token(TokenValue::MinusMinus))),
+                Some('=') if extended => Ok(Some(self.two_char_token(TokenValue::MinusEqual))),
+                _ => Ok(Some(Token::new(TokenValue::Minus, lexeme, self.line))),
+            },
+            '*' => match self.peek() {
+                Some('*') if extended => Ok(Some(self.two_char_token(TokenValue::StarStar))),
+                Some('=') if extended => Ok(Some(self.two_char_token(TokenValue::StarEqual))),
+                _ => Ok(Some(Token::new(TokenValue::Star, lexeme, self.line))),
+            },
+            '?' => Ok(Some(Token::new(TokenValue::Question, lexeme, self.line))),
+            ':' => Ok(Some(Token::new(TokenValue::Colon, lexeme, self.line))),
+            '!' => match self.next_eq('=') {
                 false => Ok(Some(Token::new(TokenValue::Bang, lexeme, self.line))),
-                true => {
-                    self.advance();
-                    Ok(Some(Token::new(TokenValue::BangEqual, lexeme, self.line)))
-                }
+                true => Ok(Some(self.two_char_token(TokenValue::BangEqual))),
             },
-            '=' => match self.next_eq("=") {
-                false => Ok(Some(Token::new(TokenValue::Equal, lexeme, self.line))),
-                true => {
-                    self.advance();
-                    Ok(Some(Token::new(TokenValue::EqualEqual, lexeme, self.line)))
-                }
+            '=' => match self.peek() {
+                Some('=') => Ok(Some(self.two_char_token(TokenValue::EqualEqual))),
+                Some('>') if extended => Ok(Some(self.two_char_token(TokenValue::EqualGreater))),
+                _ => Ok(Some(Token::new(TokenValue::Equal, lexeme, self.line))),
             },
-            '>' => match self.next_eq("=") {
+            '>' => match self.next_eq('=') {
                 false => Ok(Some(Token::new(TokenValue::Greater, lexeme, self.line))),
-                true => {
-                    self.advance();
-                    Ok(Some(Token::new(
-                        TokenValue::GreaterEqual,
-                        lexeme,
-                        self.line,
-                    )))
-                }
+                true => Ok(Some(self.two_char_token(TokenValue::GreaterEqual))),
             },
-            '<' => match self.next_eq("=") {
+            '<' => match self.next_eq('=') {
                 false => Ok(Some(Token::new(TokenValue::Less, lexeme, self.line))),
-                true => {
-                    self.advance();
-                    Ok(Some(Token::new(TokenValue::LessEqual, lexeme, self.line)))
-                }
+                true => Ok(Some(self.two_char_token(TokenValue::LessEqual))),
             },
-            '/' => match self.next_eq("/") {
-                false => Ok(Some(Token::new(TokenValue::Slash, lexeme, self.line))),
-                true => {
+            '/' => match self.peek() {
+                Some('/') => {
+                    let (line, start) = (self.line, self.position);
                     self.skip_inline_comment();
-                    Ok(None)
+                    let text = self.source[start..].lines().next().unwrap_or_default();
+                    Ok(self.comment(line, Span::new(start, start + text.len())))
                 }
+                Some('*') if self.dialect.block_comments => {
+                    let (line, start) = (self.line, self.position);
+                    self.skip_block_comment()?;
+                    let end = self.position + self.current().len_utf8();
+                    Ok(self.comment(line, Span::new(start, end)))
+                }
+                Some('=') if extended => Ok(Some(self.two_char_token(TokenValue::SlashEqual))),
+                _ => Ok(Some(Token::new(TokenValue::Slash, lexeme, self.line))),
             },
             '"' => self.scan_string(),
             character => {
-                if character.is_digit(10) {
+                if character.is_ascii_digit() {
                     self.scan_number()
-                } else if character.is_alphabetic() || character == '_' {
+                } else if is_identifier_start(character, self.dialect) {
                     self.scan_identifier()
                 } else {
-                    Err(RuntimeError::scan_error(
-                        format!("Unexpected token `{character}`"),
-                        self.line,
-                        self.column,
-                        self.position,
-                    ))
+                    Err(self.unexpected(character))
                 }
             }
         }
     }
 
+    fn unexpected(&self, character: char) -> RuntimeError {
+        RuntimeError::scan_error(
+            format!("Unexpected token `{character}`"),
+            self.line,
+            self.column,
+            self.here(),
+        )
+    }
+
     fn skip_inline_comment(&mut self) {
         if self.at_end() {
             return;
@@ -175,144 +518,582 @@ impl Scanner {
                 return;
             }
 
-            if self.current() == '\n' {
-                self.next_line();
+            if matches!(self.current(), '\n' | '\r') {
+                self.line_ending();
                 return;
             }
         }
     }
 
-    fn scan_string(&mut self) -> Result<Option<Token>> {
-        let mut lexeme = String::from("");
+    /// A token for the comment at `span`, which started on `line`, if
+    /// comments are being kept.
+    fn comment(&self, line: usize, span: Span) -> Option<Token> {
+        if !self.keep_comments {
+            return None;
+        }
+
+        let lexeme = &self.source[span.start..span.end];
+        let mut token = Token::new(TokenValue::Comment, lexeme, line);
+        token.span = span;
+        Some(token)
+    }
+
+    /// Skips a `/* ... */` comment. Block comments nest, so each `/*` inside
+    /// one needs its own `*/`.
+    fn skip_block_comment(&mut self) -> Result<()> {
+        let (line, column, start) = (self.line, self.column, self.position);
+        let mut depth = 1;
+
         self.advance();
 
-        loop {
+        while depth > 0 {
             if self.at_end() {
-                return Err(RuntimeError::scan_error(
-                    "Unterminated string".into(),
-                    self.line,
-                    self.column,
-                    self.position,
+                return Err(unexpected_eof(
+                    "Unterminated block comment",
+                    line,
+                    column,
+                    Span::new(start, start + 2),
                 ));
             }
 
+            self.advance();
+
+            match self.current() {
+                '/' if self.next_eq('*') => {
+                    self.advance();
+                    depth += 1;
+                }
+                '*' if self.next_eq('/') => {
+                    self.advance();
+                    depth -= 1;
+                }
+                // A `\r\n` pair is counted once, when the `\n` is reached.
+                '\n' => self.next_line(),
+                '\r' if !self.next_eq('\n') => self.next_line(),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn scan_string(&mut self) -> Result<Option<Token>> {
+        let mut length = 0;
+        // The string's value, once an escape sequence means it differs from
+        // the source between the quotes.
+        let mut unescaped: Option<String> = None;
+        let mut error = None;
+        // An unterminated string is reported where it opens, since the end
+        // of the file is no help in finding it.
+        let (line, column, quote) = (self.line, self.column, self.position);
+        let unterminated = move || {
+            unexpected_eof(
+                "Unterminated string",
+                line,
+                column,
+                Span::new(quote, quote + 1),
+            )
+        };
+
+        if self.at_end() {
+            return Err(unterminated());
+        }
+
+        self.advance();
+        let start = self.position;
+
+        loop {
             let char = self.current();
 
             if char == '"' {
                 break;
             }
 
-            if char == '\n' {
-                self.next_line();
+            if char == '\\' && self.dialect.string_escapes {
+                if self.at_end() {
+                    return Err(unterminated());
+                }
+
+                let value =
+                    unescaped.get_or_insert_with(|| self.source[start..self.position].to_string());
+                let backslash = self.position;
+                self.advance();
+
+                match self.current() {
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    '"' => value.push('"'),
+                    '\\' => value.push('\\'),
+                    other => {
+                        // Report the first bad escape once the whole string
+                        // is consumed, so scanning resumes after it.
+                        error.get_or_insert_with(|| {
+                            RuntimeError::scan_error(
+                                format!("Unknown escape sequence `\\{other}`"),
+                                self.line,
+                                self.column - 1,
+                                Span::new(backslash, self.position + other.len_utf8()),
+                            )
+                        });
+                        if other == '\n' || (other == '\r' && !self.next_eq('\n')) {
+                            self.next_line();
+                        }
+                    }
+                }
+            } else {
+                // A `\r\n` pair is counted once, when the `\n` is reached.
+                if char == '\n' || (char == '\r' && !self.next_eq('\n')) {
+                    self.next_line();
+                }
+
+                if let Some(value) = &mut unescaped {
+                    value.push(char);
+                }
+            }
+
+            length += 1;
+
+            // Checked after the character an escape consumed as well, so a
+            // source ending in `\"` is unterminated rather than read past.
+            if self.at_end() {
+                return Err(unterminated());
             }
 
-            lexeme.push(char);
             self.advance();
         }
 
+        // Checked once the closing quote is reached, so scanning resumes
+        // after the string rather than in the middle of it.
+        if let Some(max) = self.limits.max_string_length {
+            if length > max {
+                return Err(RuntimeError::scan_error(
+                    format!("String literal exceeds the limit of {max} characters"),
+                    self.line,
+                    self.column,
+                    self.here(),
+                ));
+            }
+        }
+
+        if let Some(error) = error {
+            return Err(error);
+        }
+
+        let lexeme = &self.source[start..self.position];
+        let value = match unescaped {
+            Some(value) => value.into(),
+            None => lexeme.into(),
+        };
         Ok(Some(Token::new(
-            TokenValue::String(lexeme.clone()),
+            TokenValue::String(value),
             lexeme,
             self.line,
         )))
     }
 
+    /// Scans a number literal: digits with an optional fraction and
+    /// exponent, where underscores may separate digits (`1_000.5e-3`). A `.`
+    /// or `e` not followed by digits is left for the next token.
     fn scan_number(&mut self) -> Result<Option<Token>> {
-        let mut notation_std = true;
-        let mut decimal_allowed = true;
-        let mut lexeme = String::from("");
-        let current = self.current();
-        lexeme.push(current);
+        let start = self.position;
 
-        loop {
-            if self.at_end() {
-                break;
-            }
+        self.scan_digits()?;
+
+        if self.peek() == Some('.') && self.peek_nth(2).is_some_and(|c| c.is_ascii_digit()) {
+            self.advance();
+            self.advance();
+            self.scan_digits()?;
+        }
 
-            if let Ok(char) = self.peek() {
-                if char == '_' {
+        if self.dialect.number_extensions && matches!(self.peek(), Some('e' | 'E')) {
+            let digit_at = match self.peek_nth(2) {
+                Some('+' | '-') => 3,
+                _ => 2,
+            };
+
+            if self.peek_nth(digit_at).is_some_and(|c| c.is_ascii_digit()) {
+                for _ in 0..digit_at {
                     self.advance();
-                    continue;
-                } else if char == '.' {
-                    if decimal_allowed {
-                        decimal_allowed = false;
-                    } else {
-                        break;
-                    }
-                } else if char == 'e' {
-                    if notation_std {
-                        notation_std = false;
-                        decimal_allowed = false;
-                    } else {
-                        break;
-                    }
-                } else if !char.is_digit(10) {
-                    break;
                 }
-
-                lexeme.push(char);
-                self.advance();
+                self.scan_digits()?;
             }
         }
 
-        let number = lexeme.parse::<f64>().map_err(|_| {
+        let lexeme = self.lexeme_from(start);
+        let digits = match lexeme.contains('_') {
+            true => Cow::Owned(lexeme.replace('_', "")),
+            false => Cow::Borrowed(lexeme),
+        };
+        let number = digits.parse::<f64>().map_err(|_| {
             RuntimeError::scan_error(
-                format!("Could not parse number: `{lexeme}`"),
+                format!("Could not parse number: `{digits}`"),
                 self.line,
                 self.column,
-                self.position,
+                self.here(),
             )
         })?;
 
-        return Ok(Some(Token::new(
+        Ok(Some(Token::new(
             TokenValue::Number(number),
             lexeme,
             self.line,
-        )));
+        )))
     }
 
     fn scan_identifier(&mut self) -> Result<Option<Token>> {
-        let mut lexeme = String::from("");
-        let char = self.current();
-        lexeme.push(char);
+        let start = self.position;
 
-        loop {
-            if self.at_end() {
-                break;
-            }
-
-            if let Ok(char) = self.peek() {
-                if !char.is_alphabetic() && char != '_' {
-                    break;
-                }
-
-                lexeme.push(char);
-                self.advance();
-            }
+        let continues = match self.dialect.unicode_identifiers {
+            true => unicode_ident::is_xid_continue,
+            false => |c: char| c.is_ascii_alphanumeric() || c == '_',
+        };
+        while self.peek().is_some_and(continues) {
+            self.advance();
         }
 
-        match lexeme.to_lowercase().as_str() {
+        let lexeme = self.lexeme_from(start);
+        let keyword = match self.dialect.case_insensitive_keywords {
+            true => Cow::Owned(lexeme.to_lowercase()),
+            false => Cow::Borrowed(lexeme),
+        };
+        let extended = self.dialect.extension_keywords;
+        match keyword.as_ref() {
             "and" => Ok(Some(Token::new(TokenValue::And, lexeme, self.line))),
+            "break" if extended => Ok(Some(Token::new(TokenValue::Break, lexeme, self.line))),
+            "catch" if extended => Ok(Some(Token::new(TokenValue::Catch, lexeme, self.line))),
             "class" => Ok(Some(Token::new(TokenValue::Class, lexeme, self.line))),
+            "continue" if extended => Ok(Some(Token::new(TokenValue::Continue, lexeme, self.line))),
+            "defer" if extended => Ok(Some(Token::new(TokenValue::Defer, lexeme, self.line))),
             "else" => Ok(Some(Token::new(TokenValue::Else, lexeme, self.line))),
             "false" => Ok(Some(Token::new(TokenValue::False, lexeme, self.line))),
+            "finally" if extended => Ok(Some(Token::new(TokenValue::Finally, lexeme, self.line))),
             "for" => Ok(Some(Token::new(TokenValue::For, lexeme, self.line))),
             "fun" => Ok(Some(Token::new(TokenValue::Fun, lexeme, self.line))),
             "if" => Ok(Some(Token::new(TokenValue::If, lexeme, self.line))),
+            "import" if extended => Ok(Some(Token::new(TokenValue::Import, lexeme, self.line))),
+            "in" if extended => Ok(Some(Token::new(TokenValue::In, lexeme, self.line))),
+            "match" if extended => Ok(Some(Token::new(TokenValue::Match, lexeme, self.line))),
             "nil" => Ok(Some(Token::new(TokenValue::Nil, lexeme, self.line))),
             "or" => Ok(Some(Token::new(TokenValue::Or, lexeme, self.line))),
             "print" => Ok(Some(Token::new(TokenValue::Print, lexeme, self.line))),
             "return" => Ok(Some(Token::new(TokenValue::Return, lexeme, self.line))),
             "super" => Ok(Some(Token::new(TokenValue::Super, lexeme, self.line))),
             "this" => Ok(Some(Token::new(TokenValue::This, lexeme, self.line))),
+            "throw" if extended => Ok(Some(Token::new(TokenValue::Throw, lexeme, self.line))),
             "true" => Ok(Some(Token::new(TokenValue::True, lexeme, self.line))),
+            "try" if extended => Ok(Some(Token::new(TokenValue::Try, lexeme, self.line))),
+            "use" if extended => Ok(Some(Token::new(TokenValue::Use, lexeme, self.line))),
             "var" => Ok(Some(Token::new(TokenValue::Var, lexeme, self.line))),
             "while" => Ok(Some(Token::new(TokenValue::While, lexeme, self.line))),
-            _ => Ok(Some(Token::new(
-                TokenValue::Identifier(lexeme.clone()),
-                lexeme,
-                self.line,
-            ))),
+            _ => match Symbol::try_intern(lexeme) {
+                Some(symbol) => Ok(Some(Token::new(
+                    TokenValue::Identifier(symbol),
+                    lexeme,
+                    self.line,
+                ))),
+                None => Err(self.exceed_limit(
+                    "Too many distinct identifiers have been scanned in this process".into(),
+                )),
+            },
         }
     }
 }
+
+/// Yields each token and lexical error in source order, ending with the
+/// end-of-file token. Unlike [`Scanner::scan_tokens`], this doesn't hold on
+/// to the tokens, so a huge source can be tokenized a piece at a time.
+impl Iterator for Scanner<'_> {
+    type Item = Result<Token>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.state {
+            ScanState::Done => None,
+            _ => self.next_token().or_else(|| {
+                self.state = ScanState::Done;
+                Some(Ok(self.eof()))
+            }),
+        }
+    }
+}
+
+/// Identifiers follow Unicode's rules for them (UAX #31), as Rust's do: a
+/// letter or `_`, then any letters, digits, combining marks and connectors.
+/// The book's Lox only has the ASCII ones.
+fn is_identifier_start(character: char, dialect: LoxDialect) -> bool {
+    match dialect.unicode_identifiers {
+        true => unicode_ident::is_xid_start(character) || character == '_',
+        false => character.is_ascii_alphabetic() || character == '_',
+    }
+}
+
+/// A [`TokenValue::Error`] token where `error`, a lexical error from a
+/// [`Scanner`], was reported, to hand the parser in place of the source that
+/// couldn't be scanned.
+pub(crate) fn error_token(error: &RuntimeError) -> Token {
+    match error {
+        RuntimeError::ScanError {
+            line, column, span, ..
+        } => {
+            let mut token = Token::new(TokenValue::Error, "", *line);
+            token.column = *column;
+            token.span = *span;
+            token
+        }
+        RuntimeError::UnexpectedEof(_, token) => Token {
+            value: TokenValue::Error,
+            ..token.clone()
+        },
+        _ => unreachable!("the scanner only reports scan errors and unexpected ends"),
+    }
+}
+
+/// A lexical error for source that ends inside a token, reported at `line`,
+/// `column` and `span` like any other scan error.
+fn unexpected_eof(message: &str, line: usize, column: usize, span: Span) -> RuntimeError {
+    let mut token = Token::new(TokenValue::Eof, "", line);
+    token.column = column;
+    token.span = span;
+    RuntimeError::UnexpectedEof(message.into(), token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string_value(source: &str) -> String {
+        let output = Scanner::new(source).scan_tokens();
+        assert!(output.errors.is_empty(), "`{source}` failed to scan");
+        match &output.tokens[0].value {
+            TokenValue::String(value) => value.to_string(),
+            value => panic!("`{source}` scanned as {value:?}"),
+        }
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        let output = Scanner::new(source).scan_tokens();
+        output.errors.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn escapes_are_unescaped() {
+        assert_eq!(string_value(r#""a\nb""#), "a\nb");
+        assert_eq!(string_value(r#""a\tb""#), "a\tb");
+        assert_eq!(string_value(r#""say \"hi\"""#), "say \"hi\"");
+        assert_eq!(string_value(r#""c:\\dir""#), "c:\\dir");
+    }
+
+    #[test]
+    fn unknown_escapes_are_reported() {
+        assert_eq!(
+            errors(r#""a\qb" 1"#),
+            ["line 1:3 | Error: Unknown escape sequence `\\q`"]
+        );
+    }
+
+    #[test]
+    fn a_backslash_at_the_end_is_unterminated() {
+        for source in [r#""\"#, r#""a\"#, r#""a\""#, r#""a\\"#] {
+            let errors = errors(source);
+            assert!(
+                matches!(errors.as_slice(), [error] if error.ends_with("Unterminated string")),
+                "`{source}` gave {errors:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn an_unterminated_string_is_reported_where_it_opens() {
+        let source = "print \"one\ntwo\nthree\n";
+        let output = Scanner::new(source).scan_tokens();
+        let diagnostics = crate::Diagnostics::new(source, output.errors);
+
+        assert_eq!(
+            diagnostics.to_string(),
+            "line 1:7 | Error: Unterminated string\n \
+             1 | print \"one\n   \
+             |       ^"
+        );
+    }
+
+    fn values(source: &str, dialect: LoxDialect) -> Vec<TokenValue> {
+        let output = Scanner::new(source).with_dialect(dialect).scan_tokens();
+        output.tokens.into_iter().map(|token| token.value).collect()
+    }
+
+    #[test]
+    fn keywords_are_case_sensitive() {
+        for source in ["CLASS", "Nil"] {
+            let values = values(source, LoxDialect::default());
+            assert!(
+                matches!(
+                    values.as_slice(),
+                    [TokenValue::Identifier(_), TokenValue::Eof]
+                ),
+                "`{source}` scanned as {values:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn dialects_choose_keywords() {
+        assert!(matches!(
+            values("break", LoxDialect::BOOK).as_slice(),
+            [TokenValue::Identifier(_), TokenValue::Eof]
+        ));
+        let loose = LoxDialect {
+            case_insensitive_keywords: true,
+            ..LoxDialect::BOOK
+        };
+        assert_eq!(values("CLASS", loose), [TokenValue::Class, TokenValue::Eof]);
+    }
+
+    #[test]
+    fn identifiers_follow_unicode_rules() {
+        // `é` is written as `e` and a combining acute accent.
+        for name in ["café", "cafe\u{301}", "名前", "_x٣"] {
+            let values = values(name, LoxDialect::default());
+            assert!(
+                matches!(values.as_slice(), [TokenValue::Identifier(id), TokenValue::Eof] if id.as_str() == name),
+                "`{name}` scanned as {values:?}"
+            );
+        }
+
+        let errors = Scanner::new("🎉").scan_tokens().errors;
+        assert!(matches!(
+            errors.as_slice(),
+            [RuntimeError::ScanError { .. }]
+        ));
+    }
+
+    #[test]
+    fn the_book_dialect_keeps_to_the_books_tokens() {
+        let output = Scanner::new(r#"print "c:\dir";"#)
+            .with_dialect(LoxDialect::BOOK)
+            .scan_tokens();
+        assert!(output.errors.is_empty());
+        assert!(matches!(
+            &output.tokens[1].value,
+            TokenValue::String(value) if &**value == "c:\\dir"
+        ));
+
+        let numbers = values("1e3 1_000", LoxDialect::BOOK);
+        assert!(
+            matches!(
+                numbers.as_slice(),
+                [
+                    TokenValue::Number(_),
+                    TokenValue::Identifier(_),
+                    TokenValue::Number(_),
+                    TokenValue::Identifier(_),
+                    TokenValue::Eof
+                ]
+            ),
+            "scanned as {numbers:?}"
+        );
+
+        let errors = Scanner::new("café")
+            .with_dialect(LoxDialect::BOOK)
+            .scan_tokens()
+            .errors;
+        assert!(matches!(
+            errors.as_slice(),
+            [RuntimeError::ScanError { .. }]
+        ));
+    }
+
+    #[test]
+    fn crlf_and_lone_cr_end_lines() {
+        let source = "a\r\nb\rc\n\r\nd /* x\r\ny\r */ e \"s\r\nt\" f";
+        let tokens = Scanner::new(source).scan_tokens().tokens;
+        let lines: Vec<_> = tokens.iter().map(|token| token.line).collect();
+        // A string spanning lines is on the line it ends on, as in jlox.
+        assert_eq!(lines, [1, 2, 3, 5, 7, 8, 8, 8]);
+
+        let columns: Vec<_> = tokens.iter().map(|token| token.column).collect();
+        assert_eq!(columns[..4], [1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn the_iterator_scans_as_it_is_pulled() {
+        let mut scanner = Scanner::new("var a = 1; @ a");
+
+        let lexemes: Vec<_> = scanner
+            .by_ref()
+            .take(5)
+            .map(|token| {
+                token
+                    .map(|token| token.lexeme.to_string())
+                    .unwrap_or_default()
+            })
+            .collect();
+        assert_eq!(lexemes, ["var", "a", "=", "1", ";"]);
+        assert!(matches!(
+            scanner.next(),
+            Some(Err(RuntimeError::ScanError { .. }))
+        ));
+        assert!(matches!(
+            scanner.next(),
+            Some(Ok(Token {
+                value: TokenValue::Identifier(_),
+                ..
+            }))
+        ));
+        assert!(matches!(
+            scanner.next(),
+            Some(Ok(Token {
+                value: TokenValue::Eof,
+                ..
+            }))
+        ));
+        assert!(scanner.next().is_none());
+    }
+
+    #[test]
+    fn block_comments_nest_and_count_lines() {
+        let tokens = Scanner::new("/* a /* b */\n c */ x /**/ y")
+            .scan_tokens()
+            .tokens;
+        let found: Vec<_> = tokens
+            .iter()
+            .map(|token| (token.lexeme.to_string(), token.line))
+            .collect();
+        assert_eq!(found, [("x".into(), 2), ("y".into(), 2), ("".into(), 2)]);
+
+        let comments = Scanner::new("x /* note */")
+            .with_comments(true)
+            .scan_tokens();
+        assert_eq!(comments.tokens[1].value, TokenValue::Comment);
+        assert_eq!(&*comments.tokens[1].lexeme, "/* note */");
+
+        assert_eq!(
+            errors("x /* a /* b */"),
+            ["line 1 | Error: Unterminated block comment"]
+        );
+    }
+
+    #[test]
+    fn block_comments_are_an_extension() {
+        let values = values("/* a */", LoxDialect::BOOK);
+        assert!(
+            matches!(
+                values.as_slice(),
+                [
+                    TokenValue::Slash,
+                    TokenValue::Star,
+                    TokenValue::Identifier(_),
+                    TokenValue::Star,
+                    TokenValue::Slash,
+                    TokenValue::Eof
+                ]
+            ),
+            "scanned as {values:?}"
+        );
+    }
+
+    #[test]
+    fn columns_count_chars() {
+        let tokens = Scanner::new("\"naïve\" + 名前").scan_tokens().tokens;
+        let columns: Vec<_> = tokens.iter().map(|token| token.column).collect();
+        assert_eq!(columns, [1, 9, 11, 13]);
+    }
+}
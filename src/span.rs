@@ -0,0 +1,33 @@
+/// A range of the source, as byte offsets from its start. `end` is
+/// exclusive, so an empty span marks a position between two characters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// The 1-based line of `source` this span starts on.
+    pub(crate) fn line_in(&self, source: &str) -> usize {
+        let start = self.start.min(source.len());
+        source[..start].matches('\n').count() + 1
+    }
+}
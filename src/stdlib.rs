@@ -0,0 +1,564 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    callable::LoxCallable,
+    class::{LoxClass, LoxInstance, Method, NativeMethod},
+    coroutine,
+    error::{Message, Result, RuntimeError},
+    gc,
+    interpreter::Interpreter,
+    map::{LoxMap, MapKey},
+    memory, options,
+    symbol::Symbol,
+    text,
+    value::{out_of_range, whole_number, Value},
+};
+
+/// Defines the built-in functions every program starts with.
+pub(crate) fn define_globals(interpreter: &mut Interpreter) {
+    interpreter.define_native("clock", 0, clock);
+    interpreter.define_native("type", 1, type_of);
+    interpreter.define_native("str", 1, to_str);
+    interpreter.define_native("num", 1, to_num);
+    interpreter.define_native("len", 1, len);
+    interpreter.define_native("push", 2, push);
+    interpreter.define_native("pop", 1, pop);
+    interpreter.define_native("keys", 1, keys);
+    interpreter.define_native("values", 1, values);
+    interpreter.define_native("has", 2, has);
+    interpreter.define_native("at", 2, at);
+    interpreter.define_native("substring", 3, substring);
+    interpreter.define_native("reverse", 1, reverse);
+    interpreter.define_native("indexOf", 2, index_of);
+    interpreter.define_native("split", 2, split);
+    interpreter.define_native("replace", 3, replace);
+    interpreter.define_native("trim", 1, trim);
+    interpreter.define_native("toUpperCase", 1, to_upper_case);
+    interpreter.define_native("toLowerCase", 1, to_lower_case);
+    interpreter.define_native("byteLength", 1, byte_length);
+    interpreter.define_native("byteAt", 2, byte_at);
+    interpreter.define_native("byteSubstring", 3, byte_substring);
+    interpreter.define_global("PI", Value::Number(std::f64::consts::PI));
+    interpreter.define_native("sqrt", 1, |_, arguments| unary_math(arguments, f64::sqrt));
+    interpreter.define_native("abs", 1, |_, arguments| unary_math(arguments, f64::abs));
+    interpreter.define_native("floor", 1, |_, arguments| unary_math(arguments, f64::floor));
+    interpreter.define_native("ceil", 1, |_, arguments| unary_math(arguments, f64::ceil));
+    interpreter.define_native("min", 2, |_, arguments| binary_math(arguments, f64::min));
+    interpreter.define_native("max", 2, |_, arguments| binary_math(arguments, f64::max));
+    interpreter.define_native("pow", 2, |_, arguments| binary_math(arguments, f64::powf));
+    interpreter.define_native("toFixed", 2, to_fixed);
+    interpreter.define_native("toPrecision", 2, to_precision);
+    interpreter.define_native("random", 0, random);
+    interpreter.define_native("readLine", 0, read_line);
+    interpreter.define_native("readFile", 1, read_file);
+    interpreter.define_native("writeFile", 2, write_file);
+    interpreter.define_variadic_native("println", 0, println);
+    interpreter.define_variadic_native("eprint", 0, eprint);
+    interpreter.define_native("exit", 1, exit);
+    interpreter.define_native("collect", 0, collect);
+    interpreter.define_native("memoryStats", 0, memory::memory_stats);
+    interpreter.define_native("__bench", 2, bench);
+    interpreter.define_global("Error", Value::Class(interpreter.error_class()));
+    coroutine::define_globals(interpreter);
+}
+
+/// The class of the errors `catch` gets for runtime errors, which scripts can
+/// also construct, throw and subclass. `Error(message)` has a `message`
+/// field, and `line` and `kind` fields that are `nil` unless the runtime
+/// raised it. `kind` is `stackOverflow`, `stepLimit` or `timeout` for the
+/// limits in [`LoxOptions`](crate::LoxOptions), and `runtime` otherwise.
+pub(crate) fn error_class() -> Rc<LoxClass> {
+    let mut methods: HashMap<Symbol, Rc<dyn Method>> = HashMap::new();
+    methods.insert(
+        Symbol::INIT,
+        Rc::new(NativeMethod::new("init", 1, |_, this, arguments| {
+            let mut error = this.borrow_mut();
+            error.set_field(Symbol::intern("message"), arguments[0].clone());
+            error.set_field(Symbol::intern("line"), Value::Nil);
+            error.set_field(Symbol::intern("kind"), Value::Nil);
+            Ok(Value::Instance(this.clone()))
+        })),
+    );
+    methods.insert(
+        Symbol::intern("__str__"),
+        Rc::new(NativeMethod::new("__str__", 0, |_, this, _| {
+            let error = this.borrow();
+            let message = error.field(Symbol::intern("message")).unwrap_or(Value::Nil);
+            Ok(format!("{}: {message}", error.class()).into())
+        })),
+    );
+
+    gc::alloc(LoxClass::new(
+        Symbol::intern("Error"),
+        None,
+        methods,
+        HashMap::new(),
+    ))
+}
+
+/// An instance of `class`, the `Error` class, for a runtime error raised on
+/// `line`.
+pub(crate) fn error(class: &Rc<LoxClass>, message: Message, line: Option<usize>) -> Value {
+    let mut error = LoxInstance::new(class.clone());
+    let kind = options::error_kind(message.code());
+    error.set_field(Symbol::intern("kind"), kind.into());
+    error.set_field(Symbol::intern("message"), String::from(message).into());
+    error.set_field(Symbol::intern("line"), line.map(|line| line as f64).into());
+    Value::Instance(gc::alloc(RefCell::new(error)))
+}
+
+/// `clock()` returns the seconds since the Unix epoch, with sub-second
+/// precision, for timing scripts.
+fn clock(_: &mut Interpreter, _: Vec<Value>) -> Result<Value> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|err| RuntimeError::GeneralError(err.to_string().into()))?;
+
+    Ok(Value::Number(now.as_secs_f64()))
+}
+
+/// `type(value)` returns the name of the value's type, e.g. `"number"`.
+fn type_of(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    Ok(arguments[0].type_name().into())
+}
+
+/// `str(value)` returns the value as `print` would show it.
+fn to_str(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    Ok(interpreter.stringify(&arguments[0])?.into())
+}
+
+/// `num(value)` parses a string as a number, ignoring surrounding
+/// whitespace. Numbers are returned unchanged.
+fn to_num(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    match &arguments[0] {
+        n @ (Value::Int(_) | Value::Number(_)) => Ok(n.clone()),
+        Value::String(s) => s.trim().parse::<f64>().map(Value::Number).map_err(|_| {
+            RuntimeError::GeneralError(format!("Can't convert \"{s}\" to a number").into())
+        }),
+        value => Err(RuntimeError::GeneralError(
+            format!("Can't convert a {} to a number", value.type_name()).into(),
+        )),
+    }
+}
+
+// The string natives below count and index by character, which means an
+// extended grapheme cluster such as an emoji with a skin tone modifier (see
+// `text::characters`). The `byte_` variants work on the UTF-8 bytes instead.
+
+/// `len(value)` returns the number of characters in a string, elements in a
+/// list or tuple, or entries in a map.
+fn len(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    match &arguments[0] {
+        Value::List(list) => Ok(Value::Number(list.borrow().len() as f64)),
+        Value::Map(map) => Ok(Value::Number(map.borrow().len() as f64)),
+        Value::Tuple(elements) => Ok(Value::Number(elements.len() as f64)),
+        value => {
+            let s = String::try_from(value.clone())?;
+            Ok(Value::Number(text::characters(&s).count() as f64))
+        }
+    }
+}
+
+/// `push(list, value)` appends `value` to the end of a list.
+fn push(_: &mut Interpreter, mut arguments: Vec<Value>) -> Result<Value> {
+    let value = arguments.pop().expect("push takes two arguments");
+    expect_list(&arguments[0], "push")?.borrow_mut().push(value);
+    Ok(Value::Nil)
+}
+
+/// `pop(list)` removes and returns the last element of a list.
+fn pop(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    expect_list(&arguments[0], "pop")?
+        .borrow_mut()
+        .pop()
+        .ok_or_else(|| RuntimeError::GeneralError("Can't pop from an empty list".into()))
+}
+
+fn expect_list<'a>(value: &'a Value, name: &str) -> Result<&'a Rc<RefCell<Vec<Value>>>> {
+    match value {
+        Value::List(list) => Ok(list),
+        value => Err(RuntimeError::GeneralError(
+            format!("{name} expects a list but got a {}", value.type_name()).into(),
+        )),
+    }
+}
+
+/// `keys(map)` returns a list of a map's keys, in insertion order.
+fn keys(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let map = expect_map(&arguments[0], "keys")?.borrow();
+    Ok(Value::list(
+        map.entries().map(|(key, _)| key.to_value()).collect(),
+    ))
+}
+
+/// `values(map)` returns a list of a map's values, in insertion order.
+fn values(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let map = expect_map(&arguments[0], "values")?.borrow();
+    Ok(Value::list(
+        map.entries().map(|(_, value)| value.clone()).collect(),
+    ))
+}
+
+/// `has(map, key)` returns whether a map has an entry for `key`.
+fn has(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let map = expect_map(&arguments[0], "has")?.borrow();
+    Ok(Value::Bool(map.contains(&MapKey::new(&arguments[1])?)))
+}
+
+fn expect_map<'a>(value: &'a Value, name: &str) -> Result<&'a Rc<RefCell<LoxMap>>> {
+    match value {
+        Value::Map(map) => Ok(map),
+        value => Err(RuntimeError::GeneralError(
+            format!("{name} expects a map but got a {}", value.type_name()).into(),
+        )),
+    }
+}
+
+/// `at(string, index)` returns the character at a zero-based index.
+fn at(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let s = String::try_from(arguments[0].clone())?;
+    let index = whole_number(&arguments[1], "index")?;
+
+    let character = text::characters(&s).nth(index);
+    character
+        .map(Value::from)
+        .ok_or_else(|| out_of_range(index))
+}
+
+/// `substring(string, start, end)` returns the characters from `start` up
+/// to but not including `end`, like `string[start:end]`. A range running
+/// past the end is cut short.
+fn substring(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let s = String::try_from(arguments[0].clone())?;
+    let start = whole_number(&arguments[1], "start")?;
+    let end = whole_number(&arguments[2], "end")?;
+
+    let substring: String = text::characters(&s)
+        .skip(start)
+        .take(end.saturating_sub(start))
+        .collect();
+    Ok(substring.into())
+}
+
+/// `reverse(string)` reverses the order of the characters, keeping each
+/// character's combining marks attached to it.
+fn reverse(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let s = String::try_from(arguments[0].clone())?;
+    let reversed: String = text::characters(&s).rev().collect();
+    Ok(reversed.into())
+}
+
+/// `indexOf(string, search)` returns the index of the character where the
+/// first occurrence of `search` starts, or `nil` if there isn't one.
+fn index_of(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let s = String::try_from(arguments[0].clone())?;
+    let search = String::try_from(arguments[1].clone())?;
+
+    Ok(match s.find(search.as_str()) {
+        Some(offset) => Value::Number(text::characters(&s[..offset]).count() as f64),
+        None => Value::Nil,
+    })
+}
+
+/// `split(string, separator)` returns a list of the parts of a string
+/// between each occurrence of `separator`, or of its characters if the
+/// separator is empty.
+fn split(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let s = String::try_from(arguments[0].clone())?;
+    let separator = String::try_from(arguments[1].clone())?;
+
+    let parts = match separator.is_empty() {
+        true => text::characters(&s).map(Value::from).collect(),
+        false => s.split(separator.as_str()).map(Value::from).collect(),
+    };
+    Ok(Value::list(parts))
+}
+
+/// `replace(string, from, to)` replaces every occurrence of `from` with `to`.
+fn replace(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let s = String::try_from(arguments[0].clone())?;
+    let from = String::try_from(arguments[1].clone())?;
+    let to = String::try_from(arguments[2].clone())?;
+
+    if from.is_empty() {
+        return Err(RuntimeError::GeneralError(
+            "replace expects a non-empty string to replace".into(),
+        ));
+    }
+    Ok(s.replace(from.as_str(), &to).into())
+}
+
+/// `trim(string)` removes whitespace from both ends of a string.
+fn trim(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let s = String::try_from(arguments[0].clone())?;
+    Ok(s.trim().into())
+}
+
+/// `toUpperCase(string)` returns the string in upper case.
+fn to_upper_case(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let s = String::try_from(arguments[0].clone())?;
+    Ok(s.to_uppercase().into())
+}
+
+/// `toLowerCase(string)` returns the string in lower case.
+fn to_lower_case(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let s = String::try_from(arguments[0].clone())?;
+    Ok(s.to_lowercase().into())
+}
+
+/// `byteLength(string)` returns the length of a string's UTF-8 encoding.
+fn byte_length(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let s = String::try_from(arguments[0].clone())?;
+    Ok(Value::Number(s.len() as f64))
+}
+
+/// `byteAt(string, index)` returns the UTF-8 byte at `index` as a number.
+fn byte_at(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let s = String::try_from(arguments[0].clone())?;
+    let index = whole_number(&arguments[1], "index")?;
+
+    s.as_bytes()
+        .get(index)
+        .map(|&byte| Value::Number(byte as f64))
+        .ok_or_else(|| out_of_range(index))
+}
+
+/// `byteSubstring(string, start, end)` slices by byte offsets, which must
+/// not fall inside a multi-byte character.
+fn byte_substring(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let s = String::try_from(arguments[0].clone())?;
+    let start = whole_number(&arguments[1], "start")?.min(s.len());
+    let end = whole_number(&arguments[2], "end")?.clamp(start, s.len());
+
+    s.get(start..end).map(Value::from).ok_or_else(|| {
+        RuntimeError::GeneralError(format!("Byte range {start}..{end} splits a character").into())
+    })
+}
+
+/// Applies `f` to a native's one number argument, e.g. for `sqrt(x)`.
+fn unary_math(arguments: Vec<Value>, f: fn(f64) -> f64) -> Result<Value> {
+    let [x] = numbers(arguments)?;
+    Ok(Value::Number(f(x)))
+}
+
+/// Applies `f` to a native's two number arguments, e.g. for `pow(x, y)`.
+fn binary_math(arguments: Vec<Value>, f: fn(f64, f64) -> f64) -> Result<Value> {
+    let [x, y] = numbers(arguments)?;
+    Ok(Value::Number(f(x, y)))
+}
+
+fn numbers<const N: usize>(arguments: Vec<Value>) -> Result<[f64; N]> {
+    let numbers = arguments
+        .into_iter()
+        .map(f64::try_from)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(numbers
+        .try_into()
+        .expect("arity is checked before a native runs"))
+}
+
+/// The most digits `toFixed` and `toPrecision` will write, as in JavaScript.
+const MAX_DIGITS: usize = 100;
+
+/// `toFixed(number, digits)` writes a number with exactly `digits` digits
+/// after the decimal point, rounding if it has more, so `toFixed(2.5, 2)` is
+/// `"2.50"`.
+fn to_fixed(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let n = f64::try_from(arguments[0].clone())?;
+    let digits = format_digits(&arguments[1], 0)?;
+    if !n.is_finite() {
+        return Ok(Value::Number(n).to_string().into());
+    }
+
+    Ok(format!("{n:.digits$}").into())
+}
+
+/// `toPrecision(number, digits)` writes a number rounded to `digits`
+/// significant digits, switching to exponent notation like `1.2e+21` for
+/// numbers too large or small to write out, as JavaScript does.
+fn to_precision(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let n = f64::try_from(arguments[0].clone())?;
+    let precision = format_digits(&arguments[1], 1)?;
+    if !n.is_finite() {
+        return Ok(Value::Number(n).to_string().into());
+    }
+
+    // Rounding in exponent notation first finds the exponent of the rounded
+    // number, which decides between the two notations.
+    let scientific = format!("{n:.*e}", precision - 1);
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("exponent notation has an `e`");
+    let exponent: i32 = exponent.parse().expect("the exponent is a number");
+
+    if exponent < -6 || exponent >= precision as i32 {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        return Ok(format!("{mantissa}e{sign}{}", exponent.abs()).into());
+    }
+
+    let decimals = (precision as i32 - 1 - exponent) as usize;
+    Ok(format!("{n:.decimals$}").into())
+}
+
+/// The digit count given to `toFixed` or `toPrecision`, which must be a
+/// whole number from `min` to [`MAX_DIGITS`].
+fn format_digits(digits: &Value, min: usize) -> Result<usize> {
+    match whole_number(digits, "number of digits")? {
+        digits if (min..=MAX_DIGITS).contains(&digits) => Ok(digits),
+        _ => Err(RuntimeError::GeneralError(
+            format!("The number of digits must be from {min} to {MAX_DIGITS}").into(),
+        )),
+    }
+}
+
+/// `random()` returns a number from 0 up to but not including 1. The
+/// numbers come from an xorshift generator seeded from the clock, which is
+/// fine for games and simulations but not for anything secret.
+fn random(_: &mut Interpreter, _: Vec<Value>) -> Result<Value> {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_nanos() as u64)
+                | 1,
+        );
+    }
+
+    let bits = STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        x
+    });
+    // The top 53 bits fill a double's mantissa exactly.
+    Ok(Value::Number((bits >> 11) as f64 / (1u64 << 53) as f64))
+}
+
+// The natives below do I/O. `readFile` and `writeFile` are refused when
+// the interpreter's options don't allow filesystem access.
+
+/// `readLine()` reads a line from stdin, without its line ending, or
+/// returns `nil` at the end of the input.
+fn read_line(_: &mut Interpreter, _: Vec<Value>) -> Result<Value> {
+    let mut line = String::new();
+    if std::io::stdin().read_line(&mut line)? == 0 {
+        return Ok(Value::Nil);
+    }
+
+    Ok(line.trim_end_matches(['\n', '\r']).into())
+}
+
+/// `readFile(path)` returns the contents of a UTF-8 file.
+fn read_file(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let path = filesystem_path(interpreter, &arguments[0], "readFile")?;
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|err| RuntimeError::GeneralError(format!("Can't read `{path}`: {err}").into()))?;
+    Ok(contents.into())
+}
+
+/// `writeFile(path, contents)` replaces the contents of a file, creating it
+/// if it doesn't exist.
+fn write_file(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let path = filesystem_path(interpreter, &arguments[0], "writeFile")?;
+    let contents = String::try_from(arguments[1].clone())?;
+    std::fs::write(&path, contents)
+        .map_err(|err| RuntimeError::GeneralError(format!("Can't write `{path}`: {err}").into()))?;
+    Ok(Value::Nil)
+}
+
+fn filesystem_path(interpreter: &Interpreter, path: &Value, name: &str) -> Result<String> {
+    if !interpreter.options().allow_filesystem {
+        return Err(RuntimeError::GeneralError(
+            format!("{name} is disabled, as filesystem access isn't allowed").into(),
+        ));
+    }
+    String::try_from(path.clone())
+}
+
+/// `println(a, b, ...)` prints its arguments on one line separated by
+/// spaces, as `print a, b;` does, but can be passed around and called like
+/// any other function.
+fn println(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let line = print_line(interpreter, &arguments)?;
+    interpreter.print(&line)?;
+    Ok(Value::Nil)
+}
+
+/// `eprint(a, b, ...)` prints its arguments to stderr, as `println` would to
+/// stdout.
+fn eprint(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let line = print_line(interpreter, &arguments)?;
+    interpreter.eprint(&line)?;
+    Ok(Value::Nil)
+}
+
+fn print_line(interpreter: &mut Interpreter, values: &[Value]) -> Result<String> {
+    let values = values
+        .iter()
+        .map(|value| interpreter.stringify(value))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(values.join(" "))
+}
+
+/// `exit(status)` stops the program, which exits with `status`.
+fn exit(_: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    match whole_number(&arguments[0], "status")? {
+        status @ 0..=255 => Err(RuntimeError::Exit(status as i32)),
+        _ => Err(RuntimeError::GeneralError(
+            "The status must be from 0 to 255".into(),
+        )),
+    }
+}
+
+/// `collect()` frees objects kept alive only by reference cycles, such as a
+/// closure stored in the scope it closes over, and returns how many there
+/// were. Collection also happens on its own as objects are allocated.
+fn collect(_: &mut Interpreter, _: Vec<Value>) -> Result<Value> {
+    Ok(Value::Number(gc::collect() as f64))
+}
+
+/// `__bench(fn, iterations)` calls a zero-argument function `iterations`
+/// times after a short warmup and returns the mean time per call in seconds.
+/// Timing inside the interpreter keeps process start-up and parsing out of
+/// the measurement when comparing algorithm variants.
+fn bench(interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+    let function: Rc<dyn LoxCallable> = match &arguments[0] {
+        Value::Callable(function) if function.arity() == 0 => function.clone(),
+        Value::Class(class) if class.arity() == 0 => class.clone(),
+        _ => {
+            return Err(RuntimeError::GeneralError(
+                "__bench expects a function that takes no arguments".into(),
+            ))
+        }
+    };
+
+    let iterations = match arguments[1] {
+        Value::Int(n) if n >= 1 => n as usize,
+        Value::Number(n) if n >= 1.0 && n.fract() == 0.0 => n as usize,
+        _ => {
+            return Err(RuntimeError::GeneralError(
+                "__bench expects a positive whole number of iterations".into(),
+            ))
+        }
+    };
+
+    for _ in 0..(iterations / 10).max(1) {
+        function.clone().call(interpreter, vec![])?;
+    }
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        function.clone().call(interpreter, vec![])?;
+    }
+
+    Ok(Value::Number(
+        start.elapsed().as_secs_f64() / iterations as f64,
+    ))
+}
@@ -0,0 +1,224 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Display},
+    ops::Deref,
+    sync::{Mutex, OnceLock},
+};
+
+/// An interned name. Identifiers are interned as they are scanned, so
+/// copying a name or comparing and hashing one only touches a `u32`.
+///
+/// Interned strings are leaked: they're shared by every [`Lox`](crate::Lox)
+/// on every thread and live until the process exits, even once the
+/// instance that scanned them is dropped. Only names are interned, never
+/// string literals or values built at runtime, so what is kept is bounded
+/// by the distinct identifiers in the programs that were scanned. A host
+/// that scans untrusted programs for a long time is protected by a cap:
+/// once 64 MiB of names are interned, the scanner reports any identifier
+/// it hasn't seen before as an error rather than interning it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// How many bytes of names the scanner may intern in all, since they're
+/// never freed.
+const MAX_INTERNED_BYTES: usize = 64 * 1024 * 1024;
+
+/// Names the runtime looks up itself, interned up front so they can be
+/// compared against without interning them again.
+const PREDEFINED: [&str; 4] = ["", "this", "super", "init"];
+
+impl Symbol {
+    pub const EMPTY: Symbol = Symbol(0);
+    pub const THIS: Symbol = Symbol(1);
+    pub const SUPER: Symbol = Symbol(2);
+    pub const INIT: Symbol = Symbol(3);
+
+    /// Returns the symbol for `string`, interning it if it hasn't been seen.
+    /// The string is never freed, and unlike the scanner this interns past
+    /// the cap, so it's meant for names the host chooses rather than ones
+    /// from untrusted input.
+    pub fn intern(string: &str) -> Symbol {
+        Self::intern_within(string, usize::MAX).expect("no cap to reach")
+    }
+
+    /// Returns the symbol for `string`, or `None` if it hasn't been seen and
+    /// interning it would take the interned names past the cap.
+    pub(crate) fn try_intern(string: &str) -> Option<Symbol> {
+        Self::intern_within(string, MAX_INTERNED_BYTES)
+    }
+
+    fn intern_within(string: &str, max_bytes: usize) -> Option<Symbol> {
+        if let Some(symbol) = LOCAL.with(|local| local.borrow().symbols.get(string).copied()) {
+            return Some(symbol);
+        }
+
+        let symbol = {
+            let mut shared = shared().lock().expect("interner lock poisoned");
+            match shared.symbols.get(string) {
+                Some(&symbol) => symbol,
+                None if shared.bytes + string.len() > max_bytes => return None,
+                None => {
+                    shared.bytes += string.len();
+                    shared.insert(Box::leak(string.into()))
+                }
+            }
+        };
+        LOCAL.with(|local| local.borrow_mut().catch_up());
+        Some(symbol)
+    }
+
+    /// How many strings have been interned, on any thread.
+    pub(crate) fn interned() -> usize {
+        shared()
+            .lock()
+            .expect("interner lock poisoned")
+            .strings
+            .len()
+    }
+
+    pub fn as_str(self) -> &'static str {
+        let index = self.0 as usize;
+        LOCAL.with(|local| {
+            if let Some(&string) = local.borrow().strings.get(index) {
+                return string;
+            }
+
+            let mut local = local.borrow_mut();
+            local.catch_up();
+            local.strings[index]
+        })
+    }
+}
+
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>,
+    /// The length of all the strings leaked to intern, only kept up to date
+    /// in the shared interner.
+    bytes: usize,
+}
+
+impl Interner {
+    fn insert(&mut self, string: &'static str) -> Symbol {
+        let symbol = Symbol(self.strings.len() as u32);
+        self.strings.push(string);
+        self.symbols.insert(string, symbol);
+        symbol
+    }
+
+    /// Copies in the symbols interned on other threads since this copy was
+    /// last brought up to date.
+    fn catch_up(&mut self) {
+        let shared = shared().lock().expect("interner lock poisoned");
+        for &string in &shared.strings[self.strings.len()..] {
+            self.insert(string);
+        }
+    }
+}
+
+/// Every symbol interned on any thread. Symbols are only added, so each
+/// thread keeps a copy it reads without locking and only takes the lock to
+/// intern a string it hasn't seen or read a symbol interned elsewhere.
+fn shared() -> &'static Mutex<Interner> {
+    static SHARED: OnceLock<Mutex<Interner>> = OnceLock::new();
+
+    SHARED.get_or_init(|| {
+        let mut interner = Interner::default();
+        for string in PREDEFINED {
+            interner.insert(string);
+        }
+        Mutex::new(interner)
+    })
+}
+
+thread_local! {
+    static LOCAL: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(value: &str) -> Self {
+        Symbol::intern(value)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.as_str(), f)
+    }
+}
+
+/// Serialized as the string it stands for, since the number is only
+/// meaningful to this process's interner.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Symbol {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_intern_to_the_same_symbol() {
+        let symbol = Symbol::intern("a_name_for_this_test");
+        assert_eq!(Symbol::intern("a_name_for_this_test"), symbol);
+        assert_eq!(symbol.as_str(), "a_name_for_this_test");
+        assert_eq!(Symbol::intern("init"), Symbol::INIT);
+    }
+
+    #[test]
+    fn symbols_read_back_on_other_threads() {
+        let symbol = std::thread::spawn(|| Symbol::intern("interned_on_another_thread"))
+            .join()
+            .unwrap();
+
+        assert_eq!(symbol.as_str(), "interned_on_another_thread");
+        assert_eq!(Symbol::intern("interned_on_another_thread"), symbol);
+    }
+
+    #[test]
+    fn names_past_the_cap_are_not_interned() {
+        let symbol = Symbol::intern("interned_before_the_cap");
+
+        assert_eq!(
+            Symbol::intern_within("interned_before_the_cap", 0),
+            Some(symbol)
+        );
+        assert_eq!(Symbol::intern_within("not_interned_past_the_cap", 0), None);
+        assert!(!shared()
+            .lock()
+            .unwrap()
+            .symbols
+            .contains_key("not_interned_past_the_cap"));
+    }
+}
@@ -0,0 +1,176 @@
+//! `rlox symbols`: lists what a script declares at its top level, its
+//! classes and their methods, functions and global variables, for editors
+//! to build outline views and quick-open lists from.
+//!
+//! A declaration's doc comment is the run of `//` comments on the lines
+//! right above it, each alone on its line.
+
+use crate::{json, parser::Stmt, span::Span, token::Token};
+
+/// One declaration a script makes.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Declaration {
+    pub(crate) name: String,
+    /// `class`, `method`, `function` or `variable`.
+    pub(crate) kind: &'static str,
+    /// The class a method belongs to.
+    pub(crate) container: Option<String>,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+    /// The whole declaration, not just its name.
+    pub(crate) span: Span,
+    pub(crate) doc: Option<String>,
+}
+
+impl Declaration {
+    fn new(kind: &'static str, name: &Token, span: Span, doc: Option<String>) -> Self {
+        Self {
+            name: name.lexeme.to_string(),
+            kind,
+            container: None,
+            line: name.line,
+            column: name.column,
+            span,
+            doc,
+        }
+    }
+
+    /// `line:column kind name`, with a method's name qualified by its class.
+    pub(crate) fn text(&self) -> String {
+        let name = match &self.container {
+            Some(container) => format!("{container}.{}", self.name),
+            None => self.name.clone(),
+        };
+        format!("{}:{} {} {name}", self.line, self.column, self.kind)
+    }
+
+    /// The declaration as a JSON object on one line.
+    pub(crate) fn json(&self) -> String {
+        let optional = |value: &Option<String>| match value {
+            Some(value) => json::string(value),
+            None => "null".into(),
+        };
+        format!(
+            "{{\"name\":{},\"kind\":\"{}\",\"container\":{},\"line\":{},\"column\":{},\
+             \"span\":{{\"start\":{},\"end\":{}}},\"doc\":{}}}",
+            json::string(&self.name),
+            self.kind,
+            optional(&self.container),
+            self.line,
+            self.column,
+            self.span.start,
+            self.span.end,
+            optional(&self.doc),
+        )
+    }
+}
+
+/// The top-level declarations in `program`, parsed from `source`, in source
+/// order. `comments` are the source's comment tokens.
+pub(crate) fn declarations(source: &str, program: &[Stmt], comments: &[Token]) -> Vec<Declaration> {
+    let doc = |span: Span| doc_comment(source, comments, span);
+    let mut declarations = vec![];
+
+    for stmt in program {
+        match stmt {
+            Stmt::Function(function) => declarations.push(Declaration::new(
+                "function",
+                &function.name,
+                function.span,
+                doc(function.span),
+            )),
+            Stmt::Class {
+                name,
+                methods,
+                span,
+                ..
+            } => {
+                declarations.push(Declaration::new("class", name, *span, doc(*span)));
+                for method in methods {
+                    let mut declaration =
+                        Declaration::new("method", &method.name, method.span, doc(method.span));
+                    declaration.container = Some(name.lexeme.to_string());
+                    declarations.push(declaration);
+                }
+            }
+            Stmt::Var { name, span, .. } => {
+                declarations.push(Declaration::new("variable", name, *span, doc(*span)))
+            }
+            Stmt::Unpack { names, span, .. } => {
+                for name in names {
+                    declarations.push(Declaration::new("variable", name, *span, doc(*span)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    declarations
+}
+
+/// The `//` comments on the lines right above the code at `span`, without
+/// their slashes.
+fn doc_comment(source: &str, comments: &[Token], span: Span) -> Option<String> {
+    let mut line = span.line_in(source);
+    let mut lines = vec![];
+
+    for comment in comments
+        .iter()
+        .rev()
+        .skip_while(|comment| comment.span.start >= span.start)
+    {
+        let line_start = source[..comment.span.start]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let alone = source[line_start..comment.span.start].trim().is_empty();
+        if comment.line + 1 != line || !alone || !comment.lexeme.starts_with("//") {
+            break;
+        }
+
+        let text = comment.lexeme.trim_start_matches('/');
+        lines.push(text.strip_prefix(' ').unwrap_or(text).trim_end());
+        line = comment.line;
+    }
+
+    lines.reverse();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lox, scanner::Scanner, token::TokenValue};
+
+    #[test]
+    fn top_level_declarations_are_listed_with_their_doc_comments() {
+        let source = "// The shape.\n// With sides.\nclass Shape {\n  // Its area.\n  area() { return 0; }\n}\n\n\
+                      // Not a doc comment.\n\nfun make() { var inner = 1; }\nvar (a, b) = (1, 2); // trailing\nvar c;";
+        let program = lox::parse(source, Default::default(), false).ok().unwrap();
+        let comments: Vec<Token> = Scanner::new(source)
+            .with_comments(true)
+            .scan_tokens()
+            .tokens
+            .into_iter()
+            .filter(|token| token.value == TokenValue::Comment)
+            .collect();
+
+        let listed: Vec<_> = declarations(source, &program, &comments)
+            .iter()
+            .map(|declaration| (declaration.text(), declaration.doc.clone()))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                (
+                    "3:7 class Shape".into(),
+                    Some("The shape.\nWith sides.".into())
+                ),
+                ("5:3 method Shape.area".into(), Some("Its area.".into())),
+                ("10:5 function make".into(), None),
+                ("11:6 variable a".into(), None),
+                ("11:9 variable b".into(), None),
+                ("12:5 variable c".into(), None),
+            ]
+        );
+    }
+}
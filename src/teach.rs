@@ -0,0 +1,98 @@
+//! The `--teach` walkthrough: shows each stage of running a program in turn,
+//! headed with the chapters of *Crafting Interpreters* that cover it.
+
+use crate::{
+    error::Result,
+    interpreter::Interpreter,
+    parser::{Expr, Stmt},
+    scanner::Scanner,
+    token::Token,
+    visit::{self, Visitor},
+};
+
+/// Prints the tokens, syntax tree and variable bindings of an already
+/// resolved `program`, then runs it one top-level statement at a time.
+pub(crate) fn walkthrough(
+    interpreter: &mut Interpreter,
+    source: &str,
+    program: &[Stmt],
+) -> Result<()> {
+    heading("Scanning (chapter 4)");
+    println!("The scanner splits the source into tokens:\n");
+    for token in Scanner::new(source).scan_tokens().tokens {
+        let position = format!("{}:{}", token.line, token.column);
+        let row = format!("  {position:<8}{:<14}{}", token.value.kind(), token.lexeme);
+        println!("{}", row.trim_end());
+    }
+
+    heading("Parsing (chapters 5 and 6)");
+    println!("The parser arranges the tokens into a tree of statements:\n");
+    for statement in program {
+        print!("{statement}");
+    }
+
+    heading("Resolving (chapter 11)");
+    println!("The resolver works out which declaration each variable refers to:\n");
+    let mut bindings = Bindings::default();
+    program
+        .iter()
+        .for_each(|statement| bindings.visit_stmt(statement));
+    let Bindings(bindings) = bindings;
+    if bindings.is_empty() {
+        println!("  (no variables are used)");
+    }
+    for (token, depth) in bindings {
+        let position = format!("{}:{}", token.line, token.column);
+        let binding = match depth {
+            None => "global".to_string(),
+            Some(0) => "local, declared in the same scope".to_string(),
+            Some(1) => "local, declared 1 scope out".to_string(),
+            Some(depth) => format!("local, declared {depth} scopes out"),
+        };
+        println!("  {position:<8}{:<14}{binding}", token.lexeme);
+    }
+
+    heading("Evaluating (chapters 7 to 10)");
+    println!("The interpreter runs each statement in turn:");
+    for (index, statement) in program.iter().enumerate() {
+        let tree = format!("{statement:.1}");
+        let summary = tree.lines().next().unwrap_or_default();
+        println!("\n-- step {}: {summary}", index + 1);
+
+        match statement {
+            Stmt::Expression(expr) => {
+                let value = interpreter.evaluate(expr)?;
+                println!("=> {value}");
+            }
+            statement => interpreter.interpret(std::slice::from_ref(statement))?,
+        }
+    }
+
+    Ok(())
+}
+
+fn heading(title: &str) {
+    println!("\n== {title} ==\n");
+}
+
+/// Collects every variable reference in a program along with the scope
+/// distance the resolver stored on it.
+#[derive(Default)]
+struct Bindings<'a>(Vec<(&'a Token, Option<usize>)>);
+
+impl<'a> Visitor<'a> for Bindings<'a> {
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        match expr {
+            Expr::Variable { name, depth } => self.0.push((name, depth.get())),
+            Expr::This { keyword, depth } | Expr::Super { keyword, depth, .. } => {
+                self.0.push((keyword, depth.get()))
+            }
+            // The assigned value is evaluated before the variable is set.
+            Expr::Assign { name, depth, .. } => {
+                visit::walk_expr(self, expr);
+                self.0.push((name, depth.get()));
+            }
+            expr => visit::walk_expr(self, expr),
+        }
+    }
+}
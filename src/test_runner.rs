@@ -0,0 +1,189 @@
+//! `rlox test`: runs every script under a directory and checks it against
+//! the expectations written in its comments, following the convention of the
+//! *Crafting Interpreters* test suite:
+//!
+//! - `// expect: <text>` is the next line the script prints.
+//! - `// expect runtime error: <message>` is the runtime error that stops
+//!   the script, raised on the comment's line.
+//! - `// error: <message>` is an error reported on the comment's line before
+//!   the script runs. A script with any of these isn't run at all.
+//!
+//! Each script runs in a new rlox process, so a crash or a stack overflow
+//! only fails its own test.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{error::Result, lox::Backend, scanner::LoxDialect};
+
+const EXPECT: &str = "// expect: ";
+const EXPECT_RUNTIME_ERROR: &str = "// expect runtime error: ";
+const EXPECT_ERROR: &str = "// error: ";
+
+/// How many of the scripts [`run`] found passed and failed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TestSummary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+/// What a script says it should do, each part with the line it was written
+/// on.
+#[derive(Default)]
+struct Expectations {
+    output: Vec<(usize, String)>,
+    runtime_error: Option<(usize, String)>,
+    errors: Vec<(usize, String)>,
+}
+
+impl Expectations {
+    fn parse(source: &str) -> Self {
+        let mut expectations = Expectations::default();
+
+        for (index, text) in source.lines().enumerate() {
+            let line = index + 1;
+            if let Some((_, expected)) = text.split_once(EXPECT) {
+                expectations.output.push((line, expected.into()));
+            } else if let Some((_, message)) = text.split_once(EXPECT_RUNTIME_ERROR) {
+                expectations.runtime_error = Some((line, message.into()));
+            } else if let Some((_, message)) = text.split_once(EXPECT_ERROR) {
+                expectations.errors.push((line, message.into()));
+            }
+        }
+
+        expectations
+    }
+
+    /// The exit code rlox should finish with, from `main`.
+    fn exit_code(&self) -> i32 {
+        match (self.errors.is_empty(), &self.runtime_error) {
+            (false, _) => 65,
+            (true, Some(_)) => 70,
+            (true, None) => 0,
+        }
+    }
+}
+
+/// Runs every `.lox` file under `dir` with `rlox` on `backend`, printing
+/// each failure and then the totals. The scripts are read as the book's Lox
+/// if `dialect` is [`LoxDialect::BOOK`], and as rlox's otherwise, since
+/// those are the dialects `rlox` can be asked for.
+pub fn run(rlox: &Path, dir: &Path, backend: Backend, dialect: LoxDialect) -> Result<TestSummary> {
+    let mut scripts = vec![];
+    find_scripts(dir, &mut scripts)?;
+    scripts.sort();
+
+    let mut summary = TestSummary::default();
+    for script in &scripts {
+        let failures = run_script(rlox, script, backend, dialect)?;
+        if failures.is_empty() {
+            summary.passed += 1;
+            continue;
+        }
+
+        summary.failed += 1;
+        println!("FAIL {}", script.display());
+        for failure in failures {
+            println!("    {failure}");
+        }
+    }
+
+    println!("{} passed, {} failed", summary.passed, summary.failed);
+    Ok(summary)
+}
+
+/// Adds the `.lox` files in `dir` and the directories within it to `scripts`.
+pub(crate) fn find_scripts(dir: &Path, scripts: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_scripts(&path, scripts)?;
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            scripts.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs one script, returning how it went wrong, if it did.
+fn run_script(
+    rlox: &Path,
+    script: &Path,
+    backend: Backend,
+    dialect: LoxDialect,
+) -> Result<Vec<String>> {
+    let expectations = Expectations::parse(&std::fs::read_to_string(script)?);
+    let backend = match backend {
+        Backend::TreeWalk => "--backend=tree",
+        Backend::Vm => "--backend=vm",
+    };
+    let dialect = match dialect == LoxDialect::BOOK {
+        true => "--dialect=book",
+        false => "--dialect=extended",
+    };
+    let output = Command::new(rlox)
+        .args([backend, dialect])
+        .arg(script)
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    let mut failures = vec![];
+
+    let mut printed = stdout.lines();
+    for (line, expected) in &expectations.output {
+        match printed.next() {
+            Some(actual) if actual == expected => {}
+            Some(actual) => failures.push(format!(
+                "line {line}: expected output `{expected}`, got `{actual}`"
+            )),
+            None => failures.push(format!(
+                "line {line}: expected output `{expected}`, got nothing"
+            )),
+        }
+    }
+    for actual in printed {
+        failures.push(format!("unexpected output `{actual}`"));
+    }
+
+    let reported = |line: usize, message: &str| {
+        stderr.lines().any(|text| {
+            let located = text.starts_with(&format!("line {line} "))
+                || text.starts_with(&format!("line {line}:"));
+            // The error may be shown with its code, as `Error[E0001]: ...`.
+            let kind = text.strip_suffix(&format!(": {message}"));
+            let kind = kind.map(|kind| kind.split_once('[').map_or(kind, |(kind, _)| kind));
+            located && kind.is_some_and(|kind| kind.ends_with("Error"))
+        })
+    };
+    for (line, message) in expectations
+        .errors
+        .iter()
+        .chain(&expectations.runtime_error)
+    {
+        if !reported(*line, message) {
+            failures.push(format!("line {line}: expected error `{message}`"));
+        }
+    }
+
+    match output.status.code() {
+        Some(code) if code == expectations.exit_code() => {}
+        code => {
+            let code = code.map_or("no exit code".into(), |code| format!("exit code {code}"));
+            failures.push(format!(
+                "expected exit code {}, got {code}",
+                expectations.exit_code()
+            ));
+        }
+    }
+
+    // Show the errors rlox reported, since they usually explain the failure.
+    if !failures.is_empty() {
+        failures.extend(stderr.lines().map(|text| format!("| {text}")));
+    }
+
+    Ok(failures)
+}
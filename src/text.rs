@@ -0,0 +1,13 @@
+/// Splits `s` into the characters the string natives count and index by:
+/// extended grapheme clusters, or Unicode scalar values without the
+/// `graphemes` feature.
+#[cfg(feature = "graphemes")]
+pub(crate) fn characters(s: &str) -> impl DoubleEndedIterator<Item = &str> {
+    unicode_segmentation::UnicodeSegmentation::graphemes(s, true)
+}
+
+#[cfg(not(feature = "graphemes"))]
+pub(crate) fn characters(s: &str) -> impl DoubleEndedIterator<Item = &str> {
+    s.char_indices()
+        .map(move |(start, c)| &s[start..start + c.len_utf8()])
+}
@@ -1,73 +1,271 @@
-use std::fmt::Display;
+use std::{fmt::Display, rc::Rc};
+
+use crate::{span::Span, symbol::Symbol};
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Token {
     pub value: TokenValue,
-    pub lexeme: String,
+    pub lexeme: Rc<str>,
     pub line: usize,
+    /// The 1-based column the token starts at, filled in by the scanner once
+    /// the whole token has been read. Zero if unknown.
+    pub column: usize,
+    /// Where the token's lexeme is in the source, also filled in by the
+    /// scanner. Empty for tokens that weren't scanned.
+    pub span: Span,
 }
 
 impl Token {
-    pub fn new(value: TokenValue, lexeme: String, line: usize) -> Self {
+    pub fn new(value: TokenValue, lexeme: impl Into<Rc<str>>, line: usize) -> Self {
         Self {
             value,
-            lexeme,
+            lexeme: lexeme.into(),
             line,
+            column: 0,
+            span: Span::default(),
+        }
+    }
+
+    /// The name an identifier, `this` or `super` token stands for. Only
+    /// identifiers are interned as they are scanned; any other token's
+    /// lexeme is interned now.
+    pub fn symbol(&self) -> Symbol {
+        match self.value {
+            TokenValue::Identifier(symbol) => symbol,
+            TokenValue::This => Symbol::THIS,
+            TokenValue::Super => Symbol::SUPER,
+            _ => Symbol::intern(&self.lexeme),
+        }
+    }
+
+    /// An identifier for `name` standing where `at` is, for the names the
+    /// runtime looks up on its own, such as the methods a for-in loop calls.
+    pub(crate) fn identifier_at(at: &Token, name: &str) -> Self {
+        Self {
+            value: TokenValue::Identifier(Symbol::intern(name)),
+            lexeme: name.into(),
+            ..at.clone()
         }
     }
 }
 
+/// A token detached from the scanner's internal representation, with its kind
+/// as a plain string so it can be handed to external tools.
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct OwnedToken {
+    pub kind: String,
+    pub lexeme: String,
+    pub line: usize,
+    pub column: usize,
+    pub span: Span,
+}
+
+impl From<Token> for OwnedToken {
+    fn from(value: Token) -> Self {
+        Self {
+            kind: value.value.kind().into(),
+            lexeme: value.lexeme.to_string(),
+            line: value.line,
+            column: value.column,
+            span: value.span,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum TokenValue {
     // Single-character tokens.
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
+    Percent,
     Plus,
     Semicolon,
     Slash,
     Star,
+    Question,
+    Colon,
 
     // One or two character tokens.
     Bang,
     BangEqual,
     Equal,
     EqualEqual,
+    EqualGreater,
     Greater,
     GreaterEqual,
     Less,
     LessEqual,
+    MinusEqual,
+    MinusMinus,
+    PlusEqual,
+    PlusPlus,
+    SlashEqual,
+    StarEqual,
+    StarStar,
+
+    /// A `//` or `/* */` comment, only produced by a scanner that keeps
+    /// them. The lexeme holds the whole comment.
+    Comment,
 
     // Literals.
-    Identifier(String),
-    String(String),
+    Identifier(Symbol),
+    String(Rc<str>),
     Number(f64),
 
     // keywords.
     And,
+    Break,
+    Catch,
     Class,
+    Continue,
+    Defer,
     Else,
     False,
+    Finally,
     Fun,
     For,
     If,
+    Import,
+    In,
+    Match,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
+    Use,
     Var,
     While,
 
+    /// Stands in for source the scanner couldn't read, such as a stray
+    /// character or an unterminated string, after reporting why. The parser
+    /// reports nothing about it, since any error there would only be a
+    /// consequence of the scan error.
+    Error,
+
     Eof,
 }
 
+impl TokenValue {
+    pub fn is_keyword(&self) -> bool {
+        matches!(
+            self,
+            TokenValue::And
+                | TokenValue::Break
+                | TokenValue::Catch
+                | TokenValue::Class
+                | TokenValue::Continue
+                | TokenValue::Defer
+                | TokenValue::Else
+                | TokenValue::False
+                | TokenValue::Finally
+                | TokenValue::Fun
+                | TokenValue::For
+                | TokenValue::If
+                | TokenValue::Import
+                | TokenValue::In
+                | TokenValue::Match
+                | TokenValue::Nil
+                | TokenValue::Or
+                | TokenValue::Print
+                | TokenValue::Return
+                | TokenValue::Super
+                | TokenValue::This
+                | TokenValue::Throw
+                | TokenValue::True
+                | TokenValue::Try
+                | TokenValue::Use
+                | TokenValue::Var
+                | TokenValue::While
+        )
+    }
+
+    /// The name of the token's kind, independent of any literal value it holds.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TokenValue::LeftParen => "LeftParen",
+            TokenValue::RightParen => "RightParen",
+            TokenValue::LeftBrace => "LeftBrace",
+            TokenValue::RightBrace => "RightBrace",
+            TokenValue::LeftBracket => "LeftBracket",
+            TokenValue::RightBracket => "RightBracket",
+            TokenValue::Comma => "Comma",
+            TokenValue::Dot => "Dot",
+            TokenValue::Minus => "Minus",
+            TokenValue::Percent => "Percent",
+            TokenValue::Plus => "Plus",
+            TokenValue::Semicolon => "Semicolon",
+            TokenValue::Slash => "Slash",
+            TokenValue::Star => "Star",
+            TokenValue::Question => "Question",
+            TokenValue::Colon => "Colon",
+            TokenValue::Bang => "Bang",
+            TokenValue::BangEqual => "BangEqual",
+            TokenValue::Equal => "Equal",
+            TokenValue::EqualEqual => "EqualEqual",
+            TokenValue::EqualGreater => "EqualGreater",
+            TokenValue::Greater => "Greater",
+            TokenValue::GreaterEqual => "GreaterEqual",
+            TokenValue::Less => "Less",
+            TokenValue::LessEqual => "LessEqual",
+            TokenValue::MinusEqual => "MinusEqual",
+            TokenValue::MinusMinus => "MinusMinus",
+            TokenValue::PlusEqual => "PlusEqual",
+            TokenValue::PlusPlus => "PlusPlus",
+            TokenValue::SlashEqual => "SlashEqual",
+            TokenValue::StarEqual => "StarEqual",
+            TokenValue::StarStar => "StarStar",
+            TokenValue::Comment => "Comment",
+            TokenValue::Identifier(_) => "Identifier",
+            TokenValue::String(_) => "String",
+            TokenValue::Number(_) => "Number",
+            TokenValue::And => "And",
+            TokenValue::Break => "Break",
+            TokenValue::Catch => "Catch",
+            TokenValue::Class => "Class",
+            TokenValue::Continue => "Continue",
+            TokenValue::Defer => "Defer",
+            TokenValue::Else => "Else",
+            TokenValue::False => "False",
+            TokenValue::Finally => "Finally",
+            TokenValue::Fun => "Fun",
+            TokenValue::For => "For",
+            TokenValue::If => "If",
+            TokenValue::Import => "Import",
+            TokenValue::In => "In",
+            TokenValue::Match => "Match",
+            TokenValue::Nil => "Nil",
+            TokenValue::Or => "Or",
+            TokenValue::Print => "Print",
+            TokenValue::Return => "Return",
+            TokenValue::Super => "Super",
+            TokenValue::This => "This",
+            TokenValue::Throw => "Throw",
+            TokenValue::True => "True",
+            TokenValue::Try => "Try",
+            TokenValue::Use => "Use",
+            TokenValue::Var => "Var",
+            TokenValue::While => "While",
+            TokenValue::Error => "Error",
+            TokenValue::Eof => "Eof",
+        }
+    }
+}
+
 impl Display for TokenValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self {
@@ -75,41 +273,67 @@ impl Display for TokenValue {
             TokenValue::RightParen => f.write_str(")"),
             TokenValue::LeftBrace => f.write_str("{"),
             TokenValue::RightBrace => f.write_str("}"),
+            TokenValue::LeftBracket => f.write_str("["),
+            TokenValue::RightBracket => f.write_str("]"),
             TokenValue::Comma => f.write_str(","),
             TokenValue::Dot => f.write_str("."),
             TokenValue::Minus => f.write_str("-"),
+            TokenValue::Percent => f.write_str("%"),
             TokenValue::Plus => f.write_str("+"),
             TokenValue::Semicolon => f.write_str(";"),
             TokenValue::Slash => f.write_str("/"),
             TokenValue::Star => f.write_str("*"),
+            TokenValue::Question => f.write_str("?"),
+            TokenValue::Colon => f.write_str(":"),
             TokenValue::Bang => f.write_str("!"),
             TokenValue::BangEqual => f.write_str("!="),
             TokenValue::Equal => f.write_str("="),
             TokenValue::EqualEqual => f.write_str("=="),
+            TokenValue::EqualGreater => f.write_str("=>"),
             TokenValue::Greater => f.write_str(">"),
             TokenValue::GreaterEqual => f.write_str(">="),
             TokenValue::Less => f.write_str("<"),
             TokenValue::LessEqual => f.write_str("<="),
+            TokenValue::MinusEqual => f.write_str("-="),
+            TokenValue::MinusMinus => f.write_str("--"),
+            TokenValue::PlusEqual => f.write_str("+="),
+            TokenValue::PlusPlus => f.write_str("++"),
+            TokenValue::SlashEqual => f.write_str("/="),
+            TokenValue::StarEqual => f.write_str("*="),
+            TokenValue::StarStar => f.write_str("**"),
+            TokenValue::Comment => f.write_str("comment"),
             TokenValue::Identifier(s) => f.write_str(s),
             TokenValue::String(s) => s.fmt(f),
             TokenValue::Number(n) => n.fmt(f),
             TokenValue::And => f.write_str("and"),
+            TokenValue::Break => f.write_str("break"),
+            TokenValue::Catch => f.write_str("catch"),
             TokenValue::Class => f.write_str("class"),
+            TokenValue::Continue => f.write_str("continue"),
+            TokenValue::Defer => f.write_str("defer"),
             TokenValue::Else => f.write_str("else"),
             TokenValue::False => f.write_str("false"),
+            TokenValue::Finally => f.write_str("finally"),
             TokenValue::Fun => f.write_str("fun"),
             TokenValue::For => f.write_str("for"),
             TokenValue::If => f.write_str("if"),
+            TokenValue::Import => f.write_str("import"),
+            TokenValue::In => f.write_str("in"),
+            TokenValue::Match => f.write_str("match"),
             TokenValue::Nil => f.write_str("nil"),
             TokenValue::Or => f.write_str("or"),
             TokenValue::Print => f.write_str("print"),
             TokenValue::Return => f.write_str("return"),
             TokenValue::Super => f.write_str("super"),
             TokenValue::This => f.write_str("this"),
+            TokenValue::Throw => f.write_str("throw"),
             TokenValue::True => f.write_str("true"),
+            TokenValue::Try => f.write_str("try"),
+            TokenValue::Use => f.write_str("use"),
             TokenValue::Var => f.write_str("var"),
             TokenValue::While => f.write_str("while"),
-            TokenValue::Eof => f.write_str("\\d"),
+            TokenValue::Error => f.write_str("error"),
+            TokenValue::Eof => f.write_str("end of file"),
         }
     }
 }
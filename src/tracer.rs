@@ -0,0 +1,179 @@
+//! A [`Hook`] that reports a program's run to `tracing`: a `call` span for
+//! each Lox function, method or class called, entered while it runs, and
+//! events for the statements run, the values returned and the error that
+//! stopped the program. Install it with
+//! [`Lox::with_hook`](crate::Lox::with_hook) under a subscriber of your own.
+
+use tracing::span::EnteredSpan;
+
+use crate::{
+    error::{Result, RuntimeError},
+    interpreter::{Hook, Interpreter},
+    parser::Stmt,
+    value::Value,
+};
+
+/// Traces the calls, statements, returns and errors of the programs it
+/// watches. The vm runs no statements one at a time, so only the tree-walker
+/// traces them.
+#[derive(Default)]
+pub struct Tracer {
+    /// The spans of the calls in progress, innermost last.
+    calls: Vec<EnteredSpan>,
+}
+
+impl Tracer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Hook for Tracer {
+    fn on_statement(&mut self, interpreter: &Interpreter, stmt: &Stmt) -> Result<()> {
+        let span = stmt.span();
+        tracing::trace!(
+            start = span.start,
+            end = span.end,
+            depth = interpreter.call_depth(),
+            "statement"
+        );
+        Ok(())
+    }
+
+    fn on_call(&mut self, name: &str) {
+        self.calls
+            .push(tracing::trace_span!("call", function = name).entered());
+    }
+
+    fn on_return(&mut self, value: Option<&Value>) {
+        match value {
+            Some(value) => tracing::trace!(%value, "return"),
+            None => tracing::trace!("failed"),
+        }
+        self.calls.pop();
+    }
+
+    fn on_error(&mut self, error: &RuntimeError) {
+        tracing::error!(%error, "error");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Event, Metadata, Subscriber,
+    };
+
+    use super::Tracer;
+    use crate::{Backend, Capture, Lox};
+
+    /// Writes down the `call` spans entered and left and the messages of
+    /// the events recorded, leaving out the phase spans.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        log: Arc<Mutex<Vec<String>>>,
+        /// The function of each span, by id, or `None` for a phase.
+        functions: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    /// Finds the `function` of a `call` span or the `message` of an event.
+    #[derive(Default)]
+    struct Fields {
+        function: String,
+        message: String,
+    }
+
+    impl Visit for Fields {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "function" {
+                self.function = value.to_string();
+            }
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.message = format!("{value:?}");
+            }
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut fields = Fields::default();
+            span.record(&mut fields);
+            let call = span.metadata().name() == "call";
+            let mut functions = self.functions.lock().unwrap();
+            functions.push(call.then_some(fields.function));
+            Id::from_u64(functions.len() as u64)
+        }
+
+        fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            if !fields.message.is_empty() {
+                self.log.lock().unwrap().push(fields.message);
+            }
+        }
+
+        fn enter(&self, span: &Id) {
+            if let Some(function) = self.function(span) {
+                self.log.lock().unwrap().push(format!("enter {function}"));
+            }
+        }
+
+        fn exit(&self, span: &Id) {
+            if self.function(span).is_some() {
+                self.log.lock().unwrap().push("exit".to_string());
+            }
+        }
+    }
+
+    impl Recorder {
+        fn function(&self, span: &Id) -> Option<String> {
+            self.functions.lock().unwrap()[span.into_u64() as usize - 1].clone()
+        }
+    }
+
+    #[test]
+    fn calls_returns_and_errors_are_traced_on_both_backends() {
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            let recorder = Recorder::default();
+            let mut lox = Lox::new()
+                .with_backend(backend)
+                .with_output(Capture::default())
+                .with_hook(Tracer::new());
+            let result = tracing::subscriber::with_default(recorder.clone(), || {
+                lox.run_source(
+                    "class P { init(n) { this.n = n; } }\n\
+                     fun f(n) { return P(n).n + 1; }\n\
+                     f(1);\n\
+                     f(nil);",
+                )
+            });
+
+            assert!(result.is_err());
+            let log = recorder.log.lock().unwrap();
+            let log: Vec<_> = log.iter().filter(|entry| *entry != "statement").collect();
+            assert_eq!(
+                log,
+                [
+                    "enter f", "enter P", "return", "exit", "return", "exit", "enter f", "enter P",
+                    "return", "exit", "failed", "exit", "error",
+                ],
+                "on {backend:?}"
+            );
+        }
+    }
+}
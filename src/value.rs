@@ -0,0 +1,591 @@
+use std::{cell::RefCell, fmt::Display, rc::Rc};
+
+use crate::{
+    callable::LoxCallable,
+    class::{LoxClass, LoxInstance},
+    coroutine::Channel,
+    error::{ErrorCode, Message, RuntimeError},
+    gc::{self, Trace, Visitor},
+    map::{LoxMap, MapKey},
+    parser::Literal,
+    text,
+};
+
+/// A Lox value produced at runtime by the interpreter.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    /// An integer, only made when [`LoxOptions::integers`] is on.
+    ///
+    /// [`LoxOptions::integers`]: crate::LoxOptions::integers
+    Int(i64),
+    Number(f64),
+    String(String),
+    Callable(Rc<dyn LoxCallable>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<LoxMap>>),
+    /// A fixed sequence of values, as made by `(a, b)` or `return a, b;`.
+    /// Unlike a list it can't be changed, and equals another tuple with
+    /// equal elements.
+    Tuple(Rc<Vec<Value>>),
+    /// A channel made by `chanNew()`, for coroutines to `send` values to
+    /// each other over.
+    Channel(Rc<Channel>),
+}
+
+impl Value {
+    /// Lox follows Ruby's rule: `false` and `nil` are falsey, everything else
+    /// is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    /// The name of this value's type, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Int(_) | Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Callable(_) => "function",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Tuple(_) => "tuple",
+            Value::Channel(_) => "channel",
+        }
+    }
+
+    /// The value of `literal`, keeping an integer literal an [`Value::Int`]
+    /// if `integers` is on.
+    pub(crate) fn from_literal(literal: &Literal, integers: bool) -> Self {
+        match literal {
+            Literal::False => Value::Bool(false),
+            Literal::True => Value::Bool(true),
+            Literal::Nil => Value::Nil,
+            Literal::Integer(n) if integers => Value::Int(*n),
+            Literal::Integer(n) => Value::Number(*n as f64),
+            Literal::Number(n) => Value::Number(*n),
+            Literal::String(s) => Value::String(s.to_string()),
+        }
+    }
+
+    /// Adds `delta` to a number for `++` and `--`.
+    pub(crate) fn step(&self, delta: i64) -> Result<Value, RuntimeError> {
+        match self {
+            Value::Int(n) => n.checked_add(delta).map(Value::Int).ok_or_else(|| {
+                RuntimeError::GeneralError(Message::new(
+                    ErrorCode::IntegerArithmetic,
+                    "Integer overflow",
+                ))
+            }),
+            Value::Number(n) => Ok(Value::Number(n + delta as f64)),
+            _ => Err(RuntimeError::GeneralError(Message::new(
+                ErrorCode::OperandTypes,
+                "Operand must be a number",
+            ))),
+        }
+    }
+
+    /// Creates a list holding `elements`.
+    pub fn list(elements: Vec<Value>) -> Value {
+        Value::List(gc::alloc(RefCell::new(elements)))
+    }
+
+    /// Creates a tuple of `elements`.
+    pub fn tuple(elements: Vec<Value>) -> Value {
+        Value::Tuple(gc::alloc(elements))
+    }
+
+    /// Creates a map holding `entries`. Later entries replace earlier ones
+    /// with the same key.
+    pub fn map(entries: Vec<(Value, Value)>) -> Result<Value, RuntimeError> {
+        let mut map = LoxMap::default();
+        for (key, value) in entries {
+            map.insert(MapKey::new(&key)?, value);
+        }
+        Ok(Value::Map(gc::alloc(RefCell::new(map))))
+    }
+
+    /// Evaluates `self[index]`.
+    pub(crate) fn get_index(&self, index: &Value) -> Result<Value, RuntimeError> {
+        match self {
+            Value::List(list) => {
+                let list = list.borrow();
+                let index = list_index(index, list.len())?;
+                Ok(list[index].clone())
+            }
+            Value::Tuple(elements) => Ok(elements[list_index(index, elements.len())?].clone()),
+            Value::String(s) => {
+                let index = whole_number(index, "index")?;
+                let character = text::characters(s).nth(index);
+                character
+                    .map(Value::from)
+                    .ok_or_else(|| out_of_range(index))
+            }
+            Value::Map(map) => map
+                .borrow()
+                .get(&MapKey::new(index)?)
+                .cloned()
+                .ok_or_else(|| {
+                    let key = match index {
+                        Value::String(s) => format!("{s:?}"),
+                        index => index.to_string(),
+                    };
+                    RuntimeError::GeneralError(format!("Undefined key {key}").into())
+                }),
+            value => Err(not_indexable(value)),
+        }
+    }
+
+    /// Performs `self[index] = value`.
+    pub(crate) fn set_index(&self, index: &Value, value: Value) -> Result<(), RuntimeError> {
+        match self {
+            Value::List(list) => {
+                let mut list = list.borrow_mut();
+                let index = list_index(index, list.len())?;
+                list[index] = value;
+                Ok(())
+            }
+            Value::Map(map) => {
+                map.borrow_mut().insert(MapKey::new(index)?, value);
+                Ok(())
+            }
+            Value::String(_) => Err(RuntimeError::GeneralError(
+                "Strings can't be changed, build a new one instead".into(),
+            )),
+            Value::Tuple(_) => Err(RuntimeError::GeneralError(
+                "Tuples can't be changed, build a new one instead".into(),
+            )),
+            value => Err(not_indexable(value)),
+        }
+    }
+
+    /// Evaluates `self[start:end]` for a string or list, where a `nil` bound
+    /// runs to that end of it. A range running past the end is cut short.
+    pub(crate) fn get_slice(&self, start: &Value, end: &Value) -> Result<Value, RuntimeError> {
+        let bound = |bound: &Value, name: &str, default: usize| match bound {
+            Value::Nil => Ok(default),
+            bound => whole_number(bound, name),
+        };
+
+        match self {
+            Value::String(s) => {
+                let start = bound(start, "start", 0)?;
+                let end = bound(end, "end", usize::MAX)?;
+                let slice: String = text::characters(s)
+                    .skip(start)
+                    .take(end.saturating_sub(start))
+                    .collect();
+                Ok(slice.into())
+            }
+            Value::List(list) => {
+                let list = list.borrow();
+                let end = bound(end, "end", list.len())?.min(list.len());
+                let start = bound(start, "start", 0)?.min(end);
+                Ok(Value::list(list[start..end].to_vec()))
+            }
+            value => Err(RuntimeError::GeneralError(
+                format!("Can't slice a {}", value.type_name()).into(),
+            )),
+        }
+    }
+
+    /// Splits a tuple or list into its `count` elements, for
+    /// `var (a, b) = value;`.
+    pub(crate) fn unpack(&self, count: usize) -> Result<Vec<Value>, RuntimeError> {
+        let elements = match self {
+            Value::Tuple(elements) => elements.to_vec(),
+            Value::List(list) => list.borrow().clone(),
+            value => {
+                return Err(RuntimeError::GeneralError(
+                    format!("Can't unpack a {}", value.type_name()).into(),
+                ))
+            }
+        };
+
+        match elements.len() == count {
+            true => Ok(elements),
+            false => Err(RuntimeError::GeneralError(
+                format!(
+                    "Expected {count} values to unpack but got {}",
+                    elements.len()
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+fn list_index(index: &Value, len: usize) -> Result<usize, RuntimeError> {
+    match whole_number(index, "index")? {
+        index if index < len => Ok(index),
+        index => Err(out_of_range(index)),
+    }
+}
+
+fn not_indexable(value: &Value) -> RuntimeError {
+    RuntimeError::GeneralError(format!("Can't index into a {}", value.type_name()).into())
+}
+
+/// Converts `value` to an index or length, naming it `name` in the error if
+/// it isn't a non-negative whole number.
+pub(crate) fn whole_number(value: &Value, name: &str) -> Result<usize, RuntimeError> {
+    match value {
+        Value::Int(n) if *n >= 0 => Ok(*n as usize),
+        Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Ok(*n as usize),
+        _ => Err(RuntimeError::GeneralError(
+            format!("The {name} must be a non-negative whole number").into(),
+        )),
+    }
+}
+
+pub(crate) fn out_of_range(index: usize) -> RuntimeError {
+    RuntimeError::GeneralError(format!("Index {index} is out of range").into())
+}
+
+impl Trace for Value {
+    fn trace(&self, visit: &mut Visitor) {
+        match self {
+            Value::Callable(callable) => visit(gc::address(callable)),
+            Value::Class(class) => visit(gc::address(class)),
+            Value::Instance(instance) => visit(gc::address(instance)),
+            Value::List(list) => visit(gc::address(list)),
+            Value::Map(map) => visit(gc::address(map)),
+            Value::Tuple(elements) => visit(gc::address(elements)),
+            Value::Channel(channel) => visit(gc::address(channel)),
+            Value::Nil | Value::Bool(_) | Value::Int(_) | Value::Number(_) | Value::String(_) => {}
+        }
+    }
+}
+
+/// A tuple's elements, which can't be cleared, but a cycle through a tuple
+/// also runs through the list, map or instance that holds it.
+impl Trace for Vec<Value> {
+    fn trace(&self, visit: &mut Visitor) {
+        self.iter().for_each(|element| element.trace(visit));
+    }
+}
+
+impl Trace for RefCell<Vec<Value>> {
+    fn trace(&self, visit: &mut Visitor) {
+        if let Ok(elements) = self.try_borrow() {
+            elements.iter().for_each(|element| element.trace(visit));
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut elements) = self.try_borrow_mut() {
+            // Not `elements.clear()`, which would be `Trace::clear`.
+            Vec::clear(&mut elements);
+        }
+    }
+}
+
+// Conversions between Rust types and `Value`, for passing arguments to and
+// returning results from native functions. A failed conversion is an error
+// naming both types, ready to be returned from the native.
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Value::Nil
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.into())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Nil, Into::into)
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = RuntimeError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            value => Err(mismatch("boolean", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = RuntimeError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Int(n) => Ok(n as f64),
+            Value::Number(n) => Ok(n),
+            value => Err(mismatch("number", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = RuntimeError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(s),
+            value => Err(mismatch("string", &value)),
+        }
+    }
+}
+
+fn mismatch(expected: &str, value: &Value) -> RuntimeError {
+    RuntimeError::GeneralError(
+        format!("Expected a {expected} but got a {}", value.type_name()).into(),
+    )
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(l), Value::Bool(r)) => l == r,
+            (Value::Int(l), Value::Int(r)) => l == r,
+            (Value::Number(l), Value::Number(r)) => l == r,
+            (Value::Int(l), Value::Number(r)) | (Value::Number(r), Value::Int(l)) => {
+                *l as f64 == *r
+            }
+            (Value::String(l), Value::String(r)) => l == r,
+            (Value::Callable(l), Value::Callable(r)) => Rc::ptr_eq(l, r),
+            (Value::Class(l), Value::Class(r)) => Rc::ptr_eq(l, r),
+            (Value::Instance(l), Value::Instance(r)) => Rc::ptr_eq(l, r),
+            (Value::List(l), Value::List(r)) => Rc::ptr_eq(l, r),
+            (Value::Map(l), Value::Map(r)) => Rc::ptr_eq(l, r),
+            (Value::Tuple(l), Value::Tuple(r)) => l == r,
+            (Value::Channel(l), Value::Channel(r)) => Rc::ptr_eq(l, r),
+            _ => false,
+        }
+    }
+}
+
+impl From<&Literal> for Value {
+    fn from(value: &Literal) -> Self {
+        Value::from_literal(value, false)
+    }
+}
+
+/// Values display as `print` shows them, with lists and maps on one line.
+/// The alternate flag, `{value:#}`, lays out a list or map too wide for
+/// one line with an element per line, and elides those nested more than
+/// eight deep as `[...]` or `{...}`. The precision flag picks a different
+/// cutoff, e.g. `{value:.2}`.
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Nil => f.write_str("nil"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Int(n) => write!(f, "{n}"),
+            Value::Number(n) => fmt_number(*n, f),
+            Value::String(s) => f.write_str(s),
+            Value::Callable(callable) => callable.fmt(f),
+            Value::Class(class) => class.fmt(f),
+            Value::Instance(instance) => instance.borrow().fmt(f),
+            Value::List(list) => {
+                let elements = list
+                    .borrow()
+                    .iter()
+                    .map(|value| (None, value.clone()))
+                    .collect();
+                fmt_container(gc::address(list), ("[", "]"), elements, f)
+            }
+            Value::Map(map) => {
+                let entries = map
+                    .borrow()
+                    .entries()
+                    .map(|(key, value)| (Some(key.to_value()), value.clone()))
+                    .collect();
+                fmt_container(gc::address(map), ("{", "}"), entries, f)
+            }
+            Value::Tuple(tuple) => {
+                let elements = tuple.iter().map(|value| (None, value.clone())).collect();
+                fmt_container(gc::address(tuple), ("(", ")"), elements, f)
+            }
+            Value::Channel(_) => f.write_str("<channel>"),
+        }
+    }
+}
+
+/// How deep `{value:#}` descends into nested lists and maps before eliding
+/// the rest.
+pub(crate) const DEFAULT_MAX_DEPTH: usize = 8;
+
+/// How wide a list or map may be written on one line by `{value:#}`,
+/// counting its indentation.
+const PRETTY_WIDTH: usize = 80;
+
+const INDENT: &str = "  ";
+
+thread_local! {
+    /// The lists and maps currently being displayed, so one that contains
+    /// itself prints `[...]` or `{...}` instead of recursing forever. Its
+    /// length is how deep the one being displayed is nested.
+    static DISPLAYING: RefCell<Vec<*const ()>> = const { RefCell::new(vec![]) };
+}
+
+/// A list's elements, or a map's keys and values in insertion order.
+type Entries = Vec<(Option<Value>, Value)>;
+
+/// Writes `entries` between `open` and `close`, or just `open...close` if
+/// the container at `address` is already being written further up or is
+/// past the depth cutoff.
+fn fmt_container(
+    address: *const (),
+    (open, close): (&str, &str),
+    entries: Entries,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    let depth = DISPLAYING.with(|displaying| displaying.borrow().len());
+    let max_depth = match f.alternate() {
+        true => Some(f.precision().unwrap_or(DEFAULT_MAX_DEPTH)),
+        false => f.precision(),
+    };
+    let cycle = DISPLAYING.with(|displaying| displaying.borrow().contains(&address));
+    if cycle || max_depth.is_some_and(|max_depth| depth >= max_depth) {
+        return write!(f, "{open}...{close}");
+    }
+
+    DISPLAYING.with(|displaying| displaying.borrow_mut().push(address));
+    let result = match f.alternate() {
+        true => fmt_pretty(&entries, (open, close), depth, max_depth, f),
+        false => f
+            .write_str(open)
+            .and_then(|_| fmt_entries(&entries, ", ", f))
+            .and_then(|_| f.write_str(close)),
+    };
+    DISPLAYING.with(|displaying| displaying.borrow_mut().pop());
+
+    result
+}
+
+/// Writes `entries` on one line if they fit, or else one to a line, each
+/// indented a step further than the container at `depth`.
+fn fmt_pretty(
+    entries: &Entries,
+    (open, close): (&str, &str),
+    depth: usize,
+    max_depth: Option<usize>,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    struct Inline<'a>(&'a Entries);
+
+    impl Display for Inline<'_> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            fmt_entries(self.0, ", ", f)
+        }
+    }
+
+    let inline = match max_depth {
+        Some(max_depth) => format!("{:.max_depth$}", Inline(entries)),
+        None => Inline(entries).to_string(),
+    };
+    let width = INDENT.len() * depth + open.len() + inline.len() + close.len();
+    if entries.is_empty() || (width <= PRETTY_WIDTH && !inline.contains('\n')) {
+        return write!(f, "{open}{inline}{close}");
+    }
+
+    let indent = INDENT.repeat(depth + 1);
+    write!(f, "{open}\n{indent}")?;
+    fmt_entries(entries, &format!(",\n{indent}"), f)?;
+    write!(f, "\n{}{close}", INDENT.repeat(depth))
+}
+
+/// Writes `entries` separated by `separator`, each as `key: value` in a map.
+fn fmt_entries(
+    entries: &Entries,
+    separator: &str,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    for (index, (key, value)) in entries.iter().enumerate() {
+        if index > 0 {
+            f.write_str(separator)?;
+        }
+        if let Some(key) = key {
+            fmt_element(key, f)?;
+            f.write_str(": ")?;
+        }
+        fmt_element(value, f)?;
+    }
+    Ok(())
+}
+
+/// Formats a value inside a list or map, quoting strings so they can be told
+/// apart from other values.
+fn fmt_element(value: &Value, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match value {
+        Value::String(s) => write!(f, "{s:?}"),
+        value => value.fmt(f),
+    }
+}
+
+/// Formats numbers the way jlox does: integral values have no trailing `.0`,
+/// negative zero keeps its sign, and infinities and NaN are spelled out as in
+/// Java. `tests/lox/conformance` checks this against jlox's output.
+fn fmt_number(n: f64, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match n {
+        f64::INFINITY => f.write_str("Infinity"),
+        f64::NEG_INFINITY => f.write_str("-Infinity"),
+        n => write!(f, "{n}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(elements: impl IntoIterator<Item = Value>) -> Value {
+        Value::list(elements.into_iter().collect())
+    }
+
+    #[test]
+    fn alternate_display_breaks_wide_containers_over_lines() {
+        let row = |n: f64| list((0..12).map(|i| Value::Number(n * 1000.0 + i as f64)));
+        let table = list([row(1.0), list([]), Value::String("end".into())]);
+
+        assert_eq!(
+            format!("{table:#}"),
+            "[\n  [1000, 1001, 1002, 1003, 1004, 1005, 1006, 1007, 1008, 1009, 1010, 1011],\n  [],\n  \"end\"\n]"
+        );
+        assert!(!table.to_string().contains('\n'));
+    }
+
+    #[test]
+    fn nesting_past_the_cutoff_is_elided() {
+        let nested = list([list([list([Value::Number(1.5)])])]);
+
+        assert_eq!(nested.to_string(), "[[[1.5]]]");
+        assert_eq!(format!("{nested:.2}"), "[[[...]]]");
+        assert_eq!(format!("{nested:#.1}"), "[[...]]");
+        assert_eq!(format!("{:.1}", Value::Number(1.5)), "1.5");
+    }
+}
@@ -0,0 +1,232 @@
+//! Read-only traversal of the syntax tree.
+//!
+//! A pass implements [`Visitor`] and overrides only the methods for the
+//! nodes it cares about. Every method defaults to the matching `walk_`
+//! function, which visits the node's children in source order, so an
+//! override calls `walk_*` itself when it wants to keep descending.
+//!
+//! ```
+//! use rlox::{visit::{self, Visitor}, Expr, Parser, Scanner};
+//!
+//! /// Counts the calls in an expression, however deeply they are nested.
+//! #[derive(Default)]
+//! struct CallCounter(usize);
+//!
+//! impl Visitor<'_> for CallCounter {
+//!     fn visit_expr(&mut self, expr: &Expr) {
+//!         if let Expr::Call { .. } = expr {
+//!             self.0 += 1;
+//!         }
+//!         visit::walk_expr(self, expr);
+//!     }
+//! }
+//!
+//! let tokens = Scanner::new("f(g(1), [h()])").scan_tokens().tokens;
+//! let expr = Parser::new(tokens).parse_expression().ok().unwrap();
+//! let mut counter = CallCounter::default();
+//! counter.visit_expr(&expr);
+//! assert_eq!(counter.0, 3);
+//! ```
+
+use crate::parser::{Expr, FunctionDecl, Stmt};
+
+/// A pass over the syntax tree. `'ast` is the lifetime of the tree, so a
+/// visitor can keep references to the nodes it sees.
+pub trait Visitor<'ast> {
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        walk_expr(self, expr);
+    }
+
+    /// Visits a function declaration or a method of a class.
+    fn visit_function(&mut self, function: &'ast FunctionDecl) {
+        walk_function(self, function);
+    }
+}
+
+/// Visits the statements and expressions directly inside `stmt`.
+pub fn walk_stmt<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, stmt: &'ast Stmt) {
+    match stmt {
+        Stmt::Expression(expr) => visitor.visit_expr(expr),
+        Stmt::Function(function) => visitor.visit_function(function),
+        Stmt::Class {
+            superclass,
+            methods,
+            ..
+        } => {
+            if let Some(superclass) = superclass {
+                visitor.visit_expr(superclass);
+            }
+            for method in methods {
+                visitor.visit_function(method);
+            }
+        }
+        Stmt::Print { values, .. } => values.iter().for_each(|expr| visitor.visit_expr(expr)),
+        Stmt::Var { initializer, .. } => {
+            if let Some(initializer) = initializer {
+                visitor.visit_expr(initializer);
+            }
+        }
+        Stmt::Unpack { initializer, .. } => visitor.visit_expr(initializer),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt(else_branch);
+            }
+        }
+        Stmt::While {
+            condition,
+            body,
+            increment,
+            ..
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(body);
+            if let Some(increment) = increment {
+                visitor.visit_expr(increment);
+            }
+        }
+        Stmt::Return { value, .. } => {
+            if let Some(value) = value {
+                visitor.visit_expr(value);
+            }
+        }
+        Stmt::Block { statements, .. } => {
+            statements.iter().for_each(|stmt| visitor.visit_stmt(stmt))
+        }
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+            ..
+        } => {
+            body.iter().for_each(|stmt| visitor.visit_stmt(stmt));
+            if let Some(catch) = catch {
+                catch.body.iter().for_each(|stmt| visitor.visit_stmt(stmt));
+            }
+            if let Some(finally) = finally {
+                finally.iter().for_each(|stmt| visitor.visit_stmt(stmt));
+            }
+        }
+        Stmt::Throw { value, .. } => visitor.visit_expr(value),
+        Stmt::Defer { statement, .. } => visitor.visit_stmt(statement),
+        Stmt::ForIn { iterable, body, .. } => {
+            visitor.visit_expr(iterable);
+            visitor.visit_stmt(body);
+        }
+        Stmt::Use {
+            initializer, body, ..
+        } => {
+            visitor.visit_expr(initializer);
+            body.iter().for_each(|stmt| visitor.visit_stmt(stmt));
+        }
+        Stmt::Import { .. } | Stmt::Break { .. } | Stmt::Continue { .. } => {}
+    }
+}
+
+/// Visits the expressions directly inside `expr`, in the order they are
+/// evaluated.
+pub fn walk_expr<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, expr: &'ast Expr) {
+    match expr {
+        Expr::Literal { .. } | Expr::This { .. } | Expr::Variable { .. } | Expr::Super { .. } => {}
+        Expr::Grouping { group, .. } => visitor.visit_expr(group),
+        Expr::Get { object, .. } => visitor.visit_expr(object),
+        Expr::Call {
+            callee, arguments, ..
+        } => {
+            visitor.visit_expr(callee);
+            arguments.iter().for_each(|expr| visitor.visit_expr(expr));
+        }
+        Expr::Unary { right, .. } => visitor.visit_expr(right),
+        Expr::Update { target, .. } => visitor.visit_expr(target),
+        Expr::Compound { target, value, .. } => {
+            visitor.visit_expr(target);
+            visitor.visit_expr(value);
+        }
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        Expr::Conditional {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_expr(then_branch);
+            visitor.visit_expr(else_branch);
+        }
+        Expr::Assign { value, .. } => visitor.visit_expr(value),
+        Expr::Set { object, value, .. } => {
+            visitor.visit_expr(object);
+            visitor.visit_expr(value);
+        }
+        Expr::List { elements, .. } | Expr::Tuple { elements, .. } => {
+            elements.iter().for_each(|expr| visitor.visit_expr(expr))
+        }
+        Expr::Match {
+            subject,
+            arms,
+            otherwise,
+            ..
+        } => {
+            visitor.visit_expr(subject);
+            for arm in arms {
+                arm.pattern
+                    .exprs()
+                    .into_iter()
+                    .for_each(|expr| visitor.visit_expr(expr));
+                visitor.visit_expr(&arm.body);
+            }
+            visitor.visit_expr(otherwise);
+        }
+        Expr::Map { entries, .. } => {
+            for (key, value) in entries {
+                visitor.visit_expr(key);
+                visitor.visit_expr(value);
+            }
+        }
+        Expr::Index { object, index, .. } => {
+            visitor.visit_expr(object);
+            visitor.visit_expr(index);
+        }
+        Expr::Slice {
+            object, start, end, ..
+        } => {
+            visitor.visit_expr(object);
+            for bound in start.iter().chain(end) {
+                visitor.visit_expr(bound);
+            }
+        }
+        Expr::SetIndex {
+            object,
+            index,
+            value,
+            ..
+        } => {
+            visitor.visit_expr(object);
+            visitor.visit_expr(index);
+            visitor.visit_expr(value);
+        }
+    }
+}
+
+/// Visits the statements in a function's body.
+pub fn walk_function<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    function: &'ast FunctionDecl,
+) {
+    function
+        .body
+        .iter()
+        .for_each(|stmt| visitor.visit_stmt(stmt));
+}
@@ -0,0 +1,1637 @@
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    rc::Rc,
+};
+
+use crate::{
+    callable::{arity_mismatch, arrange_arguments, LoxCallable},
+    chunk::{Chunk, Function, Module, Op},
+    class::{LoxClass, LoxInstance, Method, Property},
+    coroutine::{self, Channel, Scheduling, SchedulingNative, DEADLOCK},
+    environment::Environment,
+    error::{ErrorCode, Message, Result, RuntimeError},
+    gc::{self, Trace, Visitor},
+    import,
+    interpreter::Interpreter,
+    iterator,
+    lox::Backend,
+    options,
+    symbol::Symbol,
+    token::{Token, TokenValue},
+    value::Value,
+};
+
+/// A variable captured by a closure. While the scope that declared it is
+/// still running it is `Open` and refers to the variable's stack slot; when
+/// that scope ends the value moves into the upvalue itself.
+#[derive(Debug)]
+enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// A compiled function together with the variables it captured. Closures are
+/// ordinary callables, so natives can call them too, which runs them on a
+/// nested [`Vm`].
+#[derive(Debug)]
+pub(crate) struct Closure {
+    function: Rc<Function>,
+    upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// The constant pool of the module the function was compiled in.
+    constants: Rc<[Value]>,
+}
+
+impl LoxCallable for Closure {
+    fn arity(&self) -> usize {
+        self.function.arity
+    }
+
+    fn parameters(&self) -> Option<Vec<Symbol>> {
+        Some(self.function.parameters.clone())
+    }
+
+    fn call(self: Rc<Self>, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+        let function = Value::Callable(self.clone());
+        Vm::new(interpreter).call_closure(self, function, arguments)
+    }
+}
+
+impl Method for Closure {
+    fn bind(self: Rc<Self>, this: Value) -> Rc<dyn LoxCallable> {
+        gc::alloc(BoundMethod {
+            receiver: this,
+            method: self,
+        })
+    }
+
+    fn is_getter(&self) -> bool {
+        self.function.is_getter
+    }
+}
+
+impl Trace for Closure {
+    fn trace(&self, visit: &mut Visitor) {
+        for upvalue in &self.upvalues {
+            visit(gc::address(upvalue));
+        }
+    }
+}
+
+impl Trace for RefCell<Upvalue> {
+    fn trace(&self, visit: &mut Visitor) {
+        if let Ok(upvalue) = self.try_borrow() {
+            if let Upvalue::Closed(value) = &*upvalue {
+                value.trace(visit);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut upvalue) = self.try_borrow_mut() {
+            *upvalue = Upvalue::Closed(Value::Nil);
+        }
+    }
+}
+
+impl Display for Closure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fn {}>", self.function.name)
+    }
+}
+
+/// A method of a class the vm declared, bound to the instance it was read
+/// from, or to the class for a class method. Calling it puts `receiver` in
+/// slot zero, where the method's body finds `this`.
+#[derive(Debug)]
+pub(crate) struct BoundMethod {
+    receiver: Value,
+    method: Rc<Closure>,
+}
+
+impl LoxCallable for BoundMethod {
+    fn arity(&self) -> usize {
+        self.method.function.arity
+    }
+
+    fn frame_name(&self) -> Option<String> {
+        Some(self.method.function.name.clone())
+    }
+
+    fn parameters(&self) -> Option<Vec<Symbol>> {
+        self.method.parameters()
+    }
+
+    fn call(self: Rc<Self>, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<Value> {
+        Vm::new(interpreter).call_closure(self.method.clone(), self.receiver.clone(), arguments)
+    }
+}
+
+impl Trace for BoundMethod {
+    fn trace(&self, visit: &mut Visitor) {
+        self.receiver.trace(visit);
+        visit(gc::address(&self.method));
+    }
+}
+
+impl Display for BoundMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.method.fmt(f)
+    }
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// The stack index of slot zero, which holds the closure being called.
+    base: usize,
+    /// Whether the hook was told this call started, so it's told when the
+    /// call ends.
+    called: bool,
+}
+
+/// A `try` block being run, which an error unwinds to.
+struct Handler {
+    /// How many frames and stack slots there were when it started.
+    frames: usize,
+    stack: usize,
+    ip: usize,
+    rethrow: bool,
+}
+
+/// A coroutine that isn't running, with its own stack, calls and `try`
+/// blocks.
+struct Coroutine {
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    handlers: Vec<Handler>,
+    caught: Vec<RuntimeError>,
+    /// The upvalues open into its stack, with their slots. They're closed
+    /// while it's suspended, so the other coroutines read and write the
+    /// variables' values, and reopened when it resumes.
+    upvalues: Vec<(usize, Rc<RefCell<Upvalue>>)>,
+    /// The channel it's waiting to receive from.
+    waiting: Option<Rc<Channel>>,
+    is_main: bool,
+}
+
+impl Coroutine {
+    fn can_run(&self) -> bool {
+        self.waiting
+            .as_ref()
+            .is_none_or(|channel| !channel.is_empty())
+    }
+}
+
+/// A stack-based virtual machine that runs code produced by the
+/// [`Compiler`](crate::compiler::Compiler). It shares globals, including the
+/// native functions, with the tree-walking interpreter it is created from.
+pub(crate) struct Vm<'a> {
+    interpreter: &'a mut Interpreter,
+    globals: Rc<RefCell<Environment>>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    /// Upvalues still pointing into the stack, shared by every closure that
+    /// captures the same variable.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    handlers: Vec<Handler>,
+    /// Errors caught by handlers that raise them again after `finally`.
+    caught: Vec<RuntimeError>,
+    /// The coroutines `spawn` started that aren't running, in the order
+    /// they get to run.
+    suspended: VecDeque<Coroutine>,
+    /// Whether the coroutine running is the one the vm started with, whose
+    /// result it returns.
+    in_main: bool,
+    /// What the main coroutine returned, once it has.
+    result: Option<Value>,
+}
+
+impl<'a> Vm<'a> {
+    pub(crate) fn new(interpreter: &'a mut Interpreter) -> Self {
+        Self {
+            globals: interpreter.globals(),
+            interpreter,
+            stack: vec![],
+            frames: vec![],
+            open_upvalues: vec![],
+            handlers: vec![],
+            caught: vec![],
+            suspended: VecDeque::new(),
+            in_main: true,
+            result: None,
+        }
+    }
+
+    /// Runs a compiled script to completion, giving back what it returns:
+    /// `nil`, unless it was compiled with a value.
+    pub(crate) fn run_script(mut self, module: Module) -> Result<Value> {
+        self.interpreter.start_budget();
+        let result = self.call_script(module);
+        if let Err(err) = &result {
+            self.interpreter.hook_error(err);
+        }
+        result
+    }
+
+    /// Runs a compiled script as part of the run already in progress, for
+    /// `import`.
+    pub(crate) fn run_module(mut self, module: Module) -> Result<()> {
+        self.call_script(module).map(|_| ())
+    }
+
+    fn call_script(&mut self, module: Module) -> Result<Value> {
+        let closure = gc::alloc(Closure {
+            function: module.script,
+            upvalues: vec![],
+            constants: module.constants,
+        });
+
+        let script = Value::Callable(closure.clone());
+        self.call_closure(closure, script, vec![])
+    }
+
+    /// Calls `closure` with `receiver` in slot zero: the closure itself, or
+    /// the object a method is bound to.
+    fn call_closure(
+        &mut self,
+        closure: Rc<Closure>,
+        receiver: Value,
+        arguments: Vec<Value>,
+    ) -> Result<Value> {
+        let count = arguments.len();
+        self.stack.push(receiver);
+        self.stack.extend(arguments);
+        self.push_frame(closure, count)?;
+        self.run()
+    }
+
+    /// Executes instructions until the outermost frame returns, giving back
+    /// its return value. An error that `catch` can handle goes to the
+    /// innermost `try` block, if one is running.
+    fn run(&mut self) -> Result<Value> {
+        loop {
+            match self.execute() {
+                Err(err) if err.is_catchable() && !self.handlers.is_empty() => self.catch(err),
+                Err(err) => {
+                    self.unwind_frames(0);
+                    return Err(err);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Drops the frames past the first `len`, innermost first, telling the
+    /// hook the calls among them failed.
+    fn unwind_frames(&mut self, len: usize) {
+        while self.frames.len() > len {
+            let frame = self.frames.pop().expect("unwinding with no frames");
+            if frame.called {
+                self.interpreter.hook_return(None);
+            }
+        }
+    }
+
+    /// Unwinds to the innermost `try` block and jumps to its handler.
+    fn catch(&mut self, err: RuntimeError) {
+        let handler = self.handlers.pop().expect("catching with no handlers");
+        self.unwind_frames(handler.frames);
+        self.close_upvalues(handler.stack);
+        self.stack.truncate(handler.stack);
+        self.frame_mut().ip = handler.ip;
+
+        if handler.rethrow {
+            self.stack.push(Value::Nil);
+            self.caught.push(err);
+        } else {
+            let value = self.interpreter.caught(err);
+            self.stack.push(value);
+        }
+    }
+
+    fn execute(&mut self) -> Result<Value> {
+        loop {
+            let frame = self.frames.last_mut().expect("run with no frames");
+            let op = frame.closure.function.chunk.code[frame.ip];
+            frame.ip += 1;
+
+            if let Err(message) = self.interpreter.step() {
+                return Err(self.error(message));
+            }
+
+            match op {
+                Op::Constant(index) => {
+                    let value = self.frame().closure.constants[index].clone();
+                    self.stack.push(value);
+                }
+                Op::Nil => self.stack.push(Value::Nil),
+                Op::True => self.stack.push(Value::Bool(true)),
+                Op::False => self.stack.push(Value::Bool(false)),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Dup => self.stack.push(self.peek().clone()),
+                Op::DupPair => {
+                    let pair = self.stack[self.stack.len() - 2..].to_vec();
+                    self.stack.extend(pair);
+                }
+                Op::Swap => {
+                    let top = self.stack.len() - 1;
+                    self.stack.swap(top, top - 1);
+                }
+                Op::GetLocal(slot) => {
+                    let value = self.stack[self.frame().base + slot].clone();
+                    self.stack.push(value);
+                }
+                Op::SetLocal(slot) => {
+                    let index = self.frame().base + slot;
+                    self.stack[index] = self.peek().clone();
+                }
+                Op::GetGlobal(name) => {
+                    let value = self.globals.borrow().lookup(name);
+                    match value {
+                        Some(value) => self.stack.push(value),
+                        None => return Err(self.undefined_variable(name)),
+                    }
+                }
+                Op::DefineGlobal(name) => {
+                    let value = self.pop();
+                    self.globals.borrow_mut().define(name, value);
+                }
+                Op::DefineClass(name) => {
+                    let class = self.pop();
+                    let existing = match self.interpreter.options().reopen_classes {
+                        true => self.globals.borrow().lookup(name),
+                        false => None,
+                    };
+                    match (existing, &class) {
+                        (Some(Value::Class(existing)), Value::Class(class)) => existing
+                            .reopen(class)
+                            .map_err(|message| self.error(message))?,
+                        _ => self.globals.borrow_mut().define(name, class),
+                    }
+                }
+                Op::SetGlobal(name) => {
+                    if self.globals.borrow().lookup(name).is_none() {
+                        return Err(self.undefined_variable(name));
+                    }
+                    let value = self.peek().clone();
+                    self.globals.borrow_mut().define(name, value);
+                }
+                Op::GetUpvalue(index) => {
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                Op::SetUpvalue(index) => {
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = self.peek().clone();
+                    let mut upvalue = upvalue.borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    };
+                }
+                Op::Equal
+                | Op::NotEqual
+                | Op::Add
+                | Op::Subtract
+                | Op::Multiply
+                | Op::Divide
+                | Op::Modulo
+                | Op::Power
+                | Op::Greater
+                | Op::GreaterEqual
+                | Op::Less
+                | Op::LessEqual
+                    if self.stack[self.stack.len() - 2..]
+                        .iter()
+                        .any(|operand| matches!(operand, Value::Instance(_) | Value::Int(_))) =>
+                {
+                    let (left, right) = self.pop_pair();
+                    let value = self.overload(left, right)?;
+                    self.stack.push(value);
+                }
+                Op::Equal => {
+                    let (left, right) = self.pop_pair();
+                    self.stack.push(Value::Bool(left == right));
+                }
+                Op::NotEqual => {
+                    let (left, right) = self.pop_pair();
+                    self.stack.push(Value::Bool(left != right));
+                }
+                Op::Add => {
+                    let value = match self.pop_pair() {
+                        (Value::Number(l), Value::Number(r)) => Value::Number(l + r),
+                        (Value::String(l), Value::String(r)) => Value::String(l + &r),
+                        (left, right)
+                            if self.interpreter.options().string_conversion
+                                && [&left, &right]
+                                    .iter()
+                                    .any(|operand| matches!(operand, Value::String(_))) =>
+                        {
+                            self.overload(left, right)?
+                        }
+                        _ => {
+                            return Err(self.error(Message::new(
+                                ErrorCode::OperandTypes,
+                                "Operands must be two numbers or two strings",
+                            )))
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                Op::Subtract | Op::Multiply | Op::Divide | Op::Modulo | Op::Power => {
+                    let (Value::Number(l), Value::Number(r)) = self.pop_pair() else {
+                        return Err(self.error(Message::new(
+                            ErrorCode::OperandTypes,
+                            "Operands must be numbers",
+                        )));
+                    };
+                    self.stack.push(Value::Number(match op {
+                        Op::Subtract => l - r,
+                        Op::Multiply => l * r,
+                        Op::Divide => l / r,
+                        Op::Modulo => l % r,
+                        _ => l.powf(r),
+                    }));
+                }
+                Op::Greater | Op::GreaterEqual | Op::Less | Op::LessEqual => {
+                    let ordering = match self.pop_pair() {
+                        (Value::Number(l), Value::Number(r)) => l.partial_cmp(&r),
+                        (Value::String(l), Value::String(r)) => Some(l.cmp(&r)),
+                        _ => {
+                            return Err(self.error(Message::new(
+                                ErrorCode::OperandTypes,
+                                "Operands must be two numbers or two strings",
+                            )))
+                        }
+                    };
+                    // A comparison with NaN has no ordering and is false.
+                    self.stack
+                        .push(Value::Bool(ordering.is_some_and(|ordering| match op {
+                            Op::Greater => ordering.is_gt(),
+                            Op::GreaterEqual => ordering.is_ge(),
+                            Op::Less => ordering.is_lt(),
+                            _ => ordering.is_le(),
+                        })));
+                }
+                Op::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Bool(!value.is_truthy()));
+                }
+                Op::Negate => match self.pop() {
+                    Value::Int(n) => match n.checked_neg() {
+                        Some(n) => self.stack.push(Value::Int(n)),
+                        None => {
+                            return Err(self.error(Message::new(
+                                ErrorCode::IntegerArithmetic,
+                                "Integer overflow",
+                            )))
+                        }
+                    },
+                    Value::Number(n) => self.stack.push(Value::Number(-n)),
+                    Value::Instance(instance) => {
+                        let Some(method) = LoxInstance::special_method(&instance, "__neg__") else {
+                            return Err(self.error(Message::new(
+                                ErrorCode::OperandTypes,
+                                "Operand must be a number",
+                            )));
+                        };
+                        let method = Value::Callable(method);
+                        self.stack.push(method.clone());
+                        self.call_value(method, 0)?;
+                    }
+                    _ => {
+                        return Err(self.error(Message::new(
+                            ErrorCode::OperandTypes,
+                            "Operand must be a number",
+                        )))
+                    }
+                },
+                Op::Step(delta) => {
+                    let value = self.pop().step(delta).map_err(|err| self.located(err))?;
+                    self.stack.push(value);
+                }
+                Op::StepIndex(delta, prefix) => {
+                    let (object, index) = self.pop_pair();
+                    let old = object.get_index(&index).map_err(|err| self.located(err))?;
+                    let new = old.step(delta).map_err(|err| self.located(err))?;
+                    object
+                        .set_index(&index, new.clone())
+                        .map_err(|err| self.located(err))?;
+                    self.stack.push(if prefix { new } else { old });
+                }
+                Op::Import(index) => {
+                    let Value::String(path) = self.frame().closure.constants[index].clone() else {
+                        unreachable!("import paths are compiled to string constants");
+                    };
+                    let line = self.line(self.frame());
+                    import::run(self.interpreter, &path, line, Backend::Vm)
+                        .map_err(|err| self.located(err))?;
+                }
+                Op::Print(count) => {
+                    let values = self.stack.split_off(self.stack.len() - count);
+                    let values = values
+                        .iter()
+                        .map(|value| self.interpreter.stringify(value))
+                        .collect::<Result<Vec<_>>>()
+                        .map_err(|err| self.located(err))?;
+                    self.interpreter
+                        .print(&values.join(" "))
+                        .map_err(|err| self.located(err))?;
+                }
+                Op::List(count) => {
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    self.stack.push(Value::list(elements));
+                }
+                Op::Tuple(count) => {
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    self.stack.push(Value::tuple(elements));
+                }
+                Op::Unpack(count) => {
+                    let elements = self.pop().unpack(count).map_err(|err| self.located(err))?;
+                    self.stack.extend(elements);
+                }
+                Op::IsTuple(count) => {
+                    let is_tuple =
+                        matches!(self.pop(), Value::Tuple(tuple) if tuple.len() == count);
+                    self.stack.push(Value::Bool(is_tuple));
+                }
+                Op::IsInstance => {
+                    let class = match self.pop() {
+                        Value::Class(class) => class,
+                        other => {
+                            let message = format!(
+                                "Expected a class to match but got a {}",
+                                other.type_name()
+                            );
+                            return Err(self.error(message));
+                        }
+                    };
+                    let is_instance = match self.pop() {
+                        Value::Instance(instance) => instance.borrow().class().inherits(&class),
+                        _ => false,
+                    };
+                    self.stack.push(Value::Bool(is_instance));
+                }
+                Op::HasField(name) => {
+                    let has_field = match self.pop() {
+                        Value::Instance(instance) => instance.borrow().field(name).is_some(),
+                        _ => false,
+                    };
+                    self.stack.push(Value::Bool(has_field));
+                }
+                Op::Map(count) => {
+                    let values = self.stack.split_off(self.stack.len() - count * 2);
+                    let mut values = values.into_iter();
+                    let entries = std::iter::from_fn(|| Some((values.next()?, values.next()?)));
+                    let map = Value::map(entries.collect()).map_err(|err| self.located(err))?;
+                    self.stack.push(map);
+                }
+                Op::GetProperty(_) => {
+                    let object = self.pop();
+                    let name = self.token(self.frame()).clone();
+                    let property = match object {
+                        Value::Instance(instance) => LoxInstance::get(&instance, &name),
+                        Value::Class(class) => LoxClass::get(&class, &name),
+                        _ => {
+                            return Err(self.error(Message::new(
+                                ErrorCode::UndefinedProperty,
+                                "Only instances have properties",
+                            )))
+                        }
+                    };
+                    let property = property.map_err(|err| self.relocated(err))?;
+                    self.push_property(property)?;
+                }
+                Op::SetProperty(_) => {
+                    let value = self.pop();
+                    let Value::Instance(instance) = self.pop() else {
+                        return Err(self.error(Message::new(
+                            ErrorCode::UndefinedProperty,
+                            "Only instances have fields",
+                        )));
+                    };
+                    let name = self.token(self.frame()).clone();
+                    instance.borrow_mut().set(&name, value.clone());
+                    self.stack.push(value);
+                }
+                Op::GetSuper(name) => {
+                    let Value::Class(superclass) = self.pop() else {
+                        unreachable!("`super` is only bound to a class");
+                    };
+                    // In a class method `this` is the class, so `super` looks
+                    // in the superclass's class methods instead.
+                    let method = self.token(self.frame()).clone();
+                    let property = match self.pop() {
+                        Value::Class(_) => LoxClass::get(&superclass, &method)
+                            .map_err(|err| self.relocated(err))?,
+                        this @ Value::Instance(_) => match superclass.find_method(name) {
+                            Some(found) => Property::method(&found, this),
+                            None => {
+                                return Err(self.error(Message::new(
+                                    ErrorCode::UndefinedProperty,
+                                    format!("Undefined property `{}`", method.lexeme),
+                                )))
+                            }
+                        },
+                        _ => unreachable!("`this` is always an instance or a class"),
+                    };
+                    self.push_property(property)?;
+                }
+                Op::Class {
+                    name,
+                    inherits,
+                    methods,
+                } => {
+                    let closures = self.stack.split_off(self.stack.len() - methods);
+                    let superclass = match inherits {
+                        true => match self.pop() {
+                            Value::Class(superclass) => Some(superclass),
+                            _ => return Err(self.error("Superclass must be a class")),
+                        },
+                        false => None,
+                    };
+
+                    let mut instance_methods = HashMap::new();
+                    let mut class_methods = HashMap::new();
+                    for closure in closures {
+                        let Value::Callable(callable) = closure else {
+                            unreachable!("methods are compiled to closures");
+                        };
+                        let any: Rc<dyn Any> = callable;
+                        let closure = any
+                            .downcast::<Closure>()
+                            .expect("methods are compiled to closures");
+                        let methods = match closure.function.is_class_method {
+                            true => &mut class_methods,
+                            false => &mut instance_methods,
+                        };
+                        let method_name = Symbol::intern(&closure.function.name);
+                        methods.insert(method_name, closure as Rc<dyn Method>);
+                    }
+
+                    let class = LoxClass::new(name, superclass, instance_methods, class_methods);
+                    self.stack.push(Value::Class(gc::alloc(class)));
+                }
+                Op::GetIndex => {
+                    let (object, index) = self.pop_pair();
+                    let value = object.get_index(&index).map_err(|err| self.located(err))?;
+                    self.stack.push(value);
+                }
+                Op::Slice => {
+                    let (start, end) = self.pop_pair();
+                    let object = self.pop();
+                    let value = object
+                        .get_slice(&start, &end)
+                        .map_err(|err| self.located(err))?;
+                    self.stack.push(value);
+                }
+                Op::SetIndex => {
+                    let value = self.pop();
+                    let (object, index) = self.pop_pair();
+                    object
+                        .set_index(&index, value.clone())
+                        .map_err(|err| self.located(err))?;
+                    self.stack.push(value);
+                }
+                Op::Jump(target) => self.frame_mut().ip = target,
+                Op::JumpIfFalse(target) => {
+                    if !self.peek().is_truthy() {
+                        self.frame_mut().ip = target;
+                    }
+                }
+                Op::Iterate(names) => {
+                    let iterable = self.pop();
+                    let iterator = iterator::iterator(self.interpreter, iterable, names)
+                        .map_err(|err| self.located(err))?;
+                    self.stack.push(iterator);
+                }
+                Op::Call(count) | Op::CallWithKeywords(count, _) => {
+                    let callee = self.stack[self.stack.len() - count - 1].clone();
+                    if let Op::CallWithKeywords(_, keywords) = op {
+                        self.arrange_arguments(&callee, count, keywords)?;
+                    }
+                    match scheduling(&callee) {
+                        Some(scheduling) => {
+                            if let Some(result) = self.schedule(scheduling, count)? {
+                                return Ok(result);
+                            }
+                        }
+                        None => self.call_value(callee, count)?,
+                    }
+                }
+                Op::Closure(index) => {
+                    let function = self.chunk().functions[index].clone();
+                    let base = self.frame().base;
+
+                    let upvalues = function
+                        .upvalues
+                        .iter()
+                        .map(|source| match source.is_local {
+                            true => self.capture_upvalue(base + source.index),
+                            false => self.frame().closure.upvalues[source.index].clone(),
+                        })
+                        .collect();
+
+                    let closure = Closure {
+                        function,
+                        upvalues,
+                        constants: self.frame().closure.constants.clone(),
+                    };
+                    self.stack.push(Value::Callable(gc::alloc(closure)));
+                }
+                Op::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                Op::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("returning with no frames");
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    if frame.called {
+                        self.interpreter.hook_return(Some(&result));
+                    }
+
+                    if self.frames.is_empty() {
+                        if self.in_main {
+                            self.result = Some(result);
+                        }
+                        match self.finish_coroutine() {
+                            Some(result) => return Ok(result),
+                            None => continue,
+                        }
+                    }
+                    self.stack.push(result);
+                }
+                Op::Try(ip, rethrow) => self.handlers.push(Handler {
+                    frames: self.frames.len(),
+                    stack: self.stack.len(),
+                    ip,
+                    rethrow,
+                }),
+                Op::EndTry => {
+                    self.handlers.pop();
+                }
+                Op::Throw => {
+                    let value = self.pop();
+                    let message = self
+                        .interpreter
+                        .stringify(&value)
+                        .map_err(|err| self.relocated(err))?;
+                    let error = Box::new(self.error(format!("Uncaught {message}")));
+                    return Err(RuntimeError::Thrown { value, error });
+                }
+                Op::Rethrow => {
+                    self.pop();
+                    return Err(self.caught.pop().expect("rethrowing with nothing caught"));
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, count: usize) -> Result<()> {
+        let callable: Rc<dyn LoxCallable> = match callee {
+            Value::Callable(callable) => callable,
+            Value::Class(class) => return self.construct(class, count),
+            _ => {
+                return Err(self.error(Message::new(
+                    ErrorCode::NotCallable,
+                    "Can only call functions and classes",
+                )))
+            }
+        };
+
+        let any: Rc<dyn Any> = callable.clone();
+        let any = match any.downcast::<Closure>() {
+            Ok(closure) => {
+                let name = closure.function.name.clone();
+                return self.push_call(closure, count, &name);
+            }
+            Err(any) => any,
+        };
+        if let Ok(bound) = any.downcast::<BoundMethod>() {
+            let slot = self.stack.len() - count - 1;
+            self.stack[slot] = bound.receiver.clone();
+            let name = bound.method.function.name.clone();
+            return self.push_call(bound.method.clone(), count, &name);
+        }
+
+        self.check_arity(callable.arity(), callable.variadic(), count)?;
+
+        let arguments = self.stack.split_off(self.stack.len() - count);
+        self.pop();
+
+        // Natives have no source location of their own, so their errors are
+        // reported at the call site.
+        let result = callable
+            .call(self.interpreter, arguments)
+            .map_err(|err| self.located(err))?;
+        self.stack.push(result);
+
+        Ok(())
+    }
+
+    /// Puts the top `count` arguments, the last of which were passed by the
+    /// names in `keywords[index]` of the chunk, in the order of `callee`'s
+    /// parameters.
+    fn arrange_arguments(&mut self, callee: &Value, count: usize, index: usize) -> Result<()> {
+        let callable: Rc<dyn LoxCallable> = match callee {
+            Value::Callable(callable) => callable.clone(),
+            Value::Class(class) => class.clone(),
+            _ => {
+                return Err(self.error(Message::new(
+                    ErrorCode::NotCallable,
+                    "Can only call functions and classes",
+                )))
+            }
+        };
+
+        let keywords = self.chunk().keywords[index].clone();
+        let arguments = self.stack.split_off(self.stack.len() - count);
+        let arguments =
+            arrange_arguments(&*callable, arguments, &keywords).map_err(|err| self.located(err))?;
+        self.stack.extend(arguments);
+        Ok(())
+    }
+
+    /// Runs a call to one of the natives that can switch coroutines, giving
+    /// back the main coroutine's result if nothing is left to run.
+    fn schedule(&mut self, scheduling: Scheduling, count: usize) -> Result<Option<Value>> {
+        let arity = SchedulingNative(scheduling).arity();
+        self.check_arity(arity, false, count)?;
+
+        match scheduling {
+            Scheduling::Spawn => {
+                let function = self.pop();
+                self.pop();
+                self.spawn(function)?;
+                self.stack.push(Value::Nil);
+            }
+            Scheduling::Yield => {
+                self.pop();
+                self.stack.push(Value::Nil);
+                if self.suspended.iter().any(Coroutine::can_run) {
+                    self.suspend(None);
+                    self.resume();
+                }
+            }
+            Scheduling::Receive => {
+                let channel = coroutine::channel(self.peek()).map_err(|err| self.located(err))?;
+                if let Some(value) = channel.receive() {
+                    self.stack.truncate(self.stack.len() - 2);
+                    self.stack.push(value);
+                } else if self.suspended.iter().any(Coroutine::can_run) {
+                    // The call runs again when the coroutine resumes.
+                    self.frame_mut().ip -= 1;
+                    self.suspend(Some(channel));
+                    self.resume();
+                } else if self.result.is_some() {
+                    // The script has ended, so nothing can send to it now.
+                    return Ok(self.finish_coroutine());
+                } else {
+                    return Err(self.error(DEADLOCK));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Starts a coroutine calling `function`, to run once the running one
+    /// lets it.
+    fn spawn(&mut self, function: Value) -> Result<()> {
+        let callable = match &function {
+            Value::Callable(callable) => callable.clone(),
+            _ => return Err(self.error("Only functions declared in Lox can be spawned")),
+        };
+
+        let any: Rc<dyn Any> = callable;
+        let (closure, receiver) = match any.downcast::<Closure>() {
+            Ok(closure) => (closure, function),
+            Err(any) => match any.downcast::<BoundMethod>() {
+                Ok(bound) => (bound.method.clone(), bound.receiver.clone()),
+                Err(_) => return Err(self.error("Only functions declared in Lox can be spawned")),
+            },
+        };
+        self.check_arity(closure.function.arity, false, 0)?;
+
+        self.suspended.push_back(Coroutine {
+            stack: vec![receiver],
+            frames: vec![Frame {
+                closure,
+                ip: 0,
+                base: 0,
+                called: false,
+            }],
+            handlers: vec![],
+            caught: vec![],
+            upvalues: vec![],
+            waiting: None,
+            is_main: false,
+        });
+        Ok(())
+    }
+
+    /// Sets the running coroutine aside, waiting to receive from `waiting`
+    /// if it's given.
+    fn suspend(&mut self, waiting: Option<Rc<Channel>>) {
+        let upvalues = std::mem::take(&mut self.open_upvalues)
+            .into_iter()
+            .map(|upvalue| {
+                let slot = match *upvalue.borrow() {
+                    Upvalue::Open(slot) => slot,
+                    Upvalue::Closed(_) => unreachable!("closed upvalue left open"),
+                };
+                *upvalue.borrow_mut() = Upvalue::Closed(self.stack[slot].clone());
+                (slot, upvalue)
+            })
+            .collect();
+
+        self.suspended.push_back(Coroutine {
+            stack: std::mem::take(&mut self.stack),
+            frames: std::mem::take(&mut self.frames),
+            handlers: std::mem::take(&mut self.handlers),
+            caught: std::mem::take(&mut self.caught),
+            upvalues,
+            waiting,
+            is_main: self.in_main,
+        });
+    }
+
+    /// Resumes the first suspended coroutine that can run, returning whether
+    /// there was one.
+    fn resume(&mut self) -> bool {
+        match self.suspended.iter().position(Coroutine::can_run) {
+            Some(index) => {
+                self.restore(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn restore(&mut self, index: usize) {
+        let coroutine = self
+            .suspended
+            .remove(index)
+            .expect("restoring a suspended coroutine");
+
+        self.stack = coroutine.stack;
+        self.frames = coroutine.frames;
+        self.handlers = coroutine.handlers;
+        self.caught = coroutine.caught;
+        self.in_main = coroutine.is_main;
+        for (slot, upvalue) in coroutine.upvalues {
+            let closed = std::mem::replace(&mut *upvalue.borrow_mut(), Upvalue::Open(slot));
+            if let Upvalue::Closed(value) = closed {
+                self.stack[slot] = value;
+            }
+            self.open_upvalues.push(upvalue);
+        }
+    }
+
+    /// Drops the running coroutine, which has returned or waits on a channel
+    /// nothing can send to, and resumes the next that can run. Failing that
+    /// it resumes the main coroutine if it's still waiting, for its `recv`
+    /// to report the deadlock. Gives back the main coroutine's result once
+    /// nothing is left to run.
+    fn finish_coroutine(&mut self) -> Option<Value> {
+        self.close_upvalues(0);
+        self.stack.clear();
+        self.frames.clear();
+        self.handlers.clear();
+
+        if self.resume() {
+            return None;
+        }
+        match self
+            .suspended
+            .iter()
+            .position(|coroutine| coroutine.is_main)
+        {
+            Some(index) => {
+                self.restore(index);
+                None
+            }
+            None => Some(self.result.take().expect("the main coroutine returned")),
+        }
+    }
+
+    /// Calls `class` to create an instance, which replaces the class on the
+    /// stack and is where the initializer finds `this`.
+    fn construct(&mut self, class: Rc<LoxClass>, count: usize) -> Result<()> {
+        let instance = gc::alloc(RefCell::new(LoxInstance::new(class.clone())));
+        let slot = self.stack.len() - count - 1;
+        self.stack[slot] = Value::Instance(instance.clone());
+
+        // The hook is told of a call to the class, as the tree-walker tells
+        // it, rather than of one to `init`.
+        let name = class.frame_name().unwrap_or_default();
+        let initializer = class
+            .find_method(Symbol::INIT)
+            .map(|initializer| initializer.bind(Value::Instance(instance.clone())));
+        let Some(initializer) = initializer else {
+            self.check_arity(0, false, count)?;
+            self.interpreter.hook_call(&name);
+            self.interpreter
+                .hook_return(Some(&Value::Instance(instance)));
+            return Ok(());
+        };
+
+        let any: Rc<dyn Any> = initializer.clone();
+        match any.downcast::<BoundMethod>() {
+            Ok(bound) => self.push_call(bound.method.clone(), count, &name),
+            Err(_) => self.call_value(Value::Callable(initializer), count),
+        }
+    }
+
+    /// Pushes the value of a property, running it first if it's a getter.
+    fn push_property(&mut self, property: Property) -> Result<()> {
+        match property {
+            Property::Value(value) => {
+                self.stack.push(value);
+                Ok(())
+            }
+            Property::Getter(getter) => {
+                let getter = Value::Callable(getter);
+                self.stack.push(getter.clone());
+                self.call_value(getter, 0)
+            }
+        }
+    }
+
+    /// Applies the operator being executed through the interpreter, for
+    /// operands the vm leaves to it: an instance, whose class may overload
+    /// the operator, an integer, or a string `+` converts the other to.
+    fn overload(&mut self, left: Value, right: Value) -> Result<Value> {
+        let operator = self.token(self.frame()).clone();
+        self.interpreter
+            .binary(left, &operator, right)
+            .map_err(|err| self.relocated(err))
+    }
+
+    /// Pushes a frame for a call to `closure`, telling the hook it's a call
+    /// to `name` unless the compiler made the function up.
+    fn push_call(&mut self, closure: Rc<Closure>, count: usize, name: &str) -> Result<()> {
+        let inline = closure.function.inline;
+        self.push_frame(closure, count)?;
+        if !inline {
+            self.interpreter.hook_call(name);
+            self.frame_mut().called = true;
+        }
+        Ok(())
+    }
+
+    fn push_frame(&mut self, closure: Rc<Closure>, count: usize) -> Result<()> {
+        self.check_arity(closure.function.arity, false, count)?;
+
+        // The script's own frame isn't a call.
+        let max_depth = self.interpreter.options().max_call_depth;
+        if max_depth.is_some_and(|max| self.frames.len() > max) {
+            return Err(self.error(Message::new(
+                ErrorCode::StackOverflow,
+                options::STACK_OVERFLOW,
+            )));
+        }
+
+        self.frames.push(Frame {
+            closure,
+            ip: 0,
+            base: self.stack.len() - count - 1,
+            called: false,
+        });
+
+        Ok(())
+    }
+
+    fn check_arity(&self, arity: usize, variadic: bool, count: usize) -> Result<()> {
+        match arity_mismatch(arity, variadic, count) {
+            Some(message) => Err(self.error(message)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the open upvalue for `slot`, creating it if no closure has
+    /// captured that variable yet.
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open) if open == slot));
+
+        if let Some(upvalue) = existing {
+            return upvalue.clone();
+        }
+
+        let upvalue = gc::alloc(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Closes every open upvalue pointing at `from` or above, as those stack
+    /// slots are about to be discarded.
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= from => {
+                    *upvalue = Upvalue::Closed(stack[slot].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("no active frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("no active frame")
+    }
+
+    fn chunk(&self) -> &Chunk {
+        &self.frame().closure.function.chunk
+    }
+
+    fn peek(&self) -> &Value {
+        self.stack.last().expect("stack underflow")
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    /// Pops the two operands of a binary instruction, left first.
+    fn pop_pair(&mut self) -> (Value, Value) {
+        let right = self.pop();
+        let left = self.pop();
+        (left, right)
+    }
+
+    /// The token the instruction `frame` is executing was compiled from.
+    fn token<'f>(&self, frame: &'f Frame) -> &'f Token {
+        &frame.closure.function.chunk.tokens[frame.ip.saturating_sub(1)]
+    }
+
+    /// The source line of the instruction `frame` is executing.
+    fn line(&self, frame: &Frame) -> usize {
+        self.token(frame).line
+    }
+
+    fn undefined_variable(&self, name: Symbol) -> RuntimeError {
+        self.error(Message::new(
+            ErrorCode::UndefinedVariable,
+            format!("Undefined variable `{name}`"),
+        ))
+    }
+
+    /// Gives an error without a location, such as one from a native function
+    /// or a [`Value`] helper, the line of the instruction being executed.
+    fn located(&self, err: RuntimeError) -> RuntimeError {
+        match err {
+            RuntimeError::GeneralError(message) => self.error(message),
+            err => err,
+        }
+    }
+
+    /// Adds the calls that are still running to an error raised at the token
+    /// of the instruction being executed, such as one from [`LoxInstance`].
+    fn relocated(&self, err: RuntimeError) -> RuntimeError {
+        match err {
+            RuntimeError::InterpretError(message, _) => self.error(message),
+            err => self.located(err),
+        }
+    }
+
+    /// Builds an error at the token of the instruction being executed, with
+    /// the calls that are still running.
+    fn error(&self, message: impl Into<Message>) -> RuntimeError {
+        let line = |frame: &Frame| self.line(frame);
+        let token = match self.frames.last() {
+            Some(frame) => self.token(frame).clone(),
+            None => Token::new(TokenValue::Eof, "", 0),
+        };
+        let error = RuntimeError::InterpretError(message.into(), token);
+
+        // Each function was called from the line its caller is on.
+        self.frames
+            .windows(2)
+            .rev()
+            .filter(|frames| !frames[1].closure.function.inline)
+            .fold(error, |error, frames| {
+                error.called_from(frames[1].closure.function.name.clone(), line(&frames[0]))
+            })
+    }
+}
+
+/// Which of the natives that switch coroutines `callee` is, if any.
+fn scheduling(callee: &Value) -> Option<Scheduling> {
+    let Value::Callable(callable) = callee else {
+        return None;
+    };
+
+    let any: Rc<dyn Any> = callable.clone();
+    any.downcast::<SchedulingNative>()
+        .ok()
+        .map(|native| native.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, Capture, Lox, LoxOptions, RuntimeError};
+
+    /// Runs `source` on both backends, checking they print the same.
+    fn run(source: &str) -> String {
+        run_with(LoxOptions::default(), source)
+    }
+
+    fn run_with(options: LoxOptions, source: &str) -> String {
+        let printed = [Backend::TreeWalk, Backend::Vm].map(|backend| {
+            let capture = Capture::default();
+            let mut lox = Lox::new()
+                .with_backend(backend)
+                .with_options(options)
+                .with_output(capture.clone());
+            if let Err(err) = lox.run_source(source) {
+                panic!("`{source}` failed on {backend:?}: {err}");
+            }
+            capture.printed()
+        });
+
+        assert_eq!(printed[0], printed[1], "`{source}`");
+        printed[1].clone()
+    }
+
+    #[test]
+    fn closures_keep_variables_after_their_scope_ends() {
+        assert_eq!(
+            run(
+                "fun counter() { var n = 0; fun next() { n = n + 1; return n; } return next; }\n\
+                 var a = counter(); var b = counter(); a(); print a(), b();"
+            ),
+            "2 1\n"
+        );
+        assert_eq!(
+            run("var fs = []; for (var i = 0; i < 3; i = i + 1) { var j = i; fun f() { return j; } push(fs, f); }\n\
+                 print fs[0](), fs[2]();"),
+            "0 2\n"
+        );
+    }
+
+    #[test]
+    fn classes_run_on_the_vm() {
+        assert_eq!(
+            run("class A { init(n) { this.n = n; } twice { return this.n * 2; } class make() { return this(5); } }\n\
+                 class B < A { init(n) { super.init(n + 1); } twice { return super.twice + 1; } }\n\
+                 var b = B(1); b.n += 1; print b.twice, A.make().n, B.make().twice;"),
+            "7 5 13\n"
+        );
+    }
+
+    #[test]
+    fn natives_can_call_methods_compiled_for_the_vm() {
+        assert_eq!(
+            run("class Box { init(n) { this.n = n; } label() { return \"box \" + str(this.n); } __str__() { return this.label(); } }\n\
+                 var box = Box(3); print str(box) + \"!\", [box];"),
+            "box 3! [Box instance]\n"
+        );
+    }
+
+    #[test]
+    fn runtime_errors_list_the_calls_still_running() {
+        let err = Lox::new()
+            .with_backend(Backend::Vm)
+            .with_output(Capture::default())
+            .run_source("class A { m() { return nil + 1; } }\nfun f() { return A().m(); }\nf();")
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "line 1:28 | Error[E0002]: Operands must be two numbers or two strings\n \
+             1 | class A { m() { return nil + 1; } }\n   |                            ^\n\
+             Traceback (most recent call last):\n  line 3, in script\n  line 2, in f\n  line 1, in m"
+        );
+    }
+
+    #[test]
+    fn try_catches_thrown_values_and_runtime_errors() {
+        assert_eq!(
+            run("try { throw \"oops\"; } catch (e) { print e; }\n\
+                 fun f() { return nil + 1; }\n\
+                 try { f(); } catch (e) { print e.message, e.line; }\n\
+                 class Missing < Error {}\n\
+                 try { throw Missing(\"file\"); } catch (e) { print e; }"),
+            "oops\nOperands must be two numbers or two strings 2\nMissing: file\n"
+        );
+    }
+
+    #[test]
+    fn finally_runs_on_every_way_out_of_try() {
+        assert_eq!(
+            run("fun f() { var a = 1; try { var b = 2; return a + b; } finally { print \"f\"; } }\n\
+                 print f();\n\
+                 for (var i = 0; i < 3; i += 1) { try { if (i == 1) continue; if (i == 2) break; print i; } finally { print \"end\", i; } }\n\
+                 try { try { throw 1; } finally { print \"inner\"; } } catch (e) { print \"caught\", e; }\n\
+                 try { try { throw 1; } catch (e) { throw e + 1; } finally { print \"again\"; } } catch (e) { print e; }"),
+            "f\n3\n0\nend 0\nend 1\nend 2\ninner\ncaught 1\nagain\n2\n"
+        );
+    }
+
+    #[test]
+    fn uncaught_throws_are_runtime_errors() {
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            let err = Lox::new()
+                .with_backend(backend)
+                .with_output(Capture::default())
+                .run_source("fun f() { throw \"boom\"; }\nf();")
+                .unwrap_err();
+
+            assert!(
+                err.to_string().contains("Uncaught boom"),
+                "{backend:?}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn deferred_statements_run_in_reverse_as_their_block_ends() {
+        assert_eq!(
+            run("fun f() { defer print \"first\"; defer print \"second\"; print \"body\"; return 1; }\n\
+                 print f();\n\
+                 for (var i = 0; i < 2; i += 1) { defer print \"next\", i; if (i == 0) continue; print \"last\"; }\n\
+                 fun g() { var n = 1; defer print \"cleanup\", n; var m = nil + n; }\n\
+                 try { g(); } catch (e) { print \"caught\"; }"),
+            "body\nsecond\nfirst\n1\nnext 0\nlast\nnext 1\ncleanup 1\ncaught\n"
+        );
+    }
+
+    #[test]
+    fn tuples_return_several_values_to_unpack() {
+        assert_eq!(
+            run("fun divmod(a, b) { return (a - a % b) / b, a % b; }\n\
+                 var (q, r) = divmod(7, 2); print q, r;\n\
+                 { var (x, y) = [1, 2]; fun sum() { return x + y; } print sum(); }\n\
+                 var t = (1, \"a\"); print t, t[1], len(t), t == (1, \"a\");"),
+            "3 1\n3\n(1, \"a\") a 2 true\n"
+        );
+    }
+
+    #[test]
+    fn match_picks_the_first_arm_whose_pattern_fits() {
+        assert_eq!(
+            run("class Point { init(x, y) { this.x = x; this.y = y; } }\n\
+                 class Point3 < Point { init(x, y, z) { super.init(x, y); this.z = z; } }\n\
+                 fun describe(v) {\n\
+                   return match (v) { 0 => \"zero\", -1 => \"minus one\", (a, 0) => a,\n\
+                     (a, (b, c)) => a + b + c, Point(x, y) => x * y, else => \"other\" };\n\
+                 }\n\
+                 print describe(0), describe(-1), describe((4, 0)), describe((1, (2, 3)));\n\
+                 print describe(Point(2, 3)), describe(Point3(2, 5, 1)), describe(\"x\");\n\
+                 print 1 + match (2) { n => n * 10, else => 0 };\n\
+                 class Box { init(v) { this.v = v; }\n\
+                   show() { return match (this.v) { nil => \"empty\", else => this.v }; } }\n\
+                 print Box(nil).show(), Box(7).show();"),
+            "zero minus one 4 6\n6 10 other\n21\nempty 7\n"
+        );
+    }
+
+    #[test]
+    fn coroutines_take_turns_and_pass_values_over_channels() {
+        let run_vm = |source: &str| {
+            let capture = Capture::default();
+            let result = Lox::new()
+                .with_backend(Backend::Vm)
+                .with_output(capture.clone())
+                .run_source(source);
+            (result.map_err(|err| err.to_string()), capture.printed())
+        };
+
+        let (result, printed) = run_vm(
+            "var ch = chanNew();\n\
+             fun producer() { for (var i = 1; i <= 3; i++) { print \"send\", i; send(ch, i); yieldTo(); } send(ch, nil); }\n\
+             fun consumer() { var total = 0; var n = recv(ch);\n\
+               while (n != nil) { print \"recv\", n; total += n; n = recv(ch); } print \"total\", total; }\n\
+             spawn(consumer); spawn(producer);\n\
+             { var count = 0; fun bump() { count += 1; } spawn(bump); yieldTo(); yieldTo(); print \"count\", count; }",
+        );
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(
+            printed,
+            "send 1\nrecv 1\nsend 2\ncount 1\nrecv 2\nsend 3\nrecv 3\ntotal 6\n"
+        );
+
+        let (result, _) = run_vm("recv(chanNew());");
+        assert!(result.is_err_and(|err| err.contains("Deadlock: every coroutine is waiting")),);
+    }
+
+    #[test]
+    fn use_closes_its_resource_however_the_body_ends() {
+        assert_eq!(
+            run("class File { init(name) { this.name = name; } close() { print \"close\", this.name; } }\n\
+                 use (var f = File(\"a\")) { print \"read\", f.name; }\n\
+                 fun first() { use (var f = File(\"b\")) { return f.name; } }\n\
+                 print first();\n\
+                 try { use (var f = File(\"c\")) { throw \"oops\"; } } catch (e) { print e; }\n\
+                 for (var i = 0; i < 2; i++) { use (var f = File(i)) { if (i == 0) continue; break; } }"),
+            "read a\nclose a\nclose b\nb\nclose c\noops\nclose 0\nclose 1\n"
+        );
+    }
+
+    #[test]
+    fn keyword_arguments_are_matched_to_parameter_names() {
+        assert_eq!(
+            run("fun area(width, height) { return width * height; }\n\
+                 class Box { init(width, depth) { this.size = width * depth; } grow(by) { return this.size + by; } }\n\
+                 print area(height: 2, width: 3), area(4, height: 5);\n\
+                 var b = Box(depth: 2, width: 5); print b.size, b.grow(by: 1);"),
+            "6 20\n10 11\n"
+        );
+
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            for (source, message) in [
+                ("fun f(a, b) {} f(1, c: 2);", "has no parameter named `c`"),
+                ("fun f(a, b) {} f(1, a: 2);", "Argument `a` is given twice"),
+                ("fun f(a, b) {} f(b: 2);", "Missing the argument `a`"),
+                (
+                    "fun f(a) {} f(1, 2, a: 3);",
+                    "Expected 1 arguments but got 3",
+                ),
+                ("clock(a: 1);", "doesn't take keyword arguments"),
+            ] {
+                let err = Lox::new()
+                    .with_backend(backend)
+                    .with_output(Capture::default())
+                    .run_source(source)
+                    .unwrap_err();
+                assert!(err.to_string().contains(message), "{backend:?}: {err}");
+            }
+        }
+    }
+
+    #[test]
+    fn string_conversion_lets_plus_join_any_value_to_a_string() {
+        let options = LoxOptions::default().with_string_conversion(true);
+        assert_eq!(
+            run_with(
+                options,
+                "class P { __str__() { return \"p\"; } }\n\
+                 var s = \"n: \"; s += 3; print s + \"!\", 1 + \"x\", \"at \" + P(), nil + \"\";"
+            ),
+            "n: 3! 1x at p nil\n"
+        );
+    }
+
+    #[test]
+    fn reopened_classes_gain_the_methods_of_later_declarations() {
+        let options = LoxOptions::default().with_class_reopening(true);
+        assert_eq!(
+            run_with(
+                options,
+                "class A { f() { return 1; } g() { return 2; } }\n\
+                 var a = A();\n\
+                 class A { g() { return 20; } h() { return this.f() + 100; } }\n\
+                 class B < A { f() { return super.f() + 10; } }\n\
+                 class B < A { class make() { return B(); } }\n\
+                 class Error { describe() { return \"error: \" + this.message; } }\n\
+                 print a.f(), a.g(), a.h(), B.make().f(), Error(\"x\").describe();"
+            ),
+            "1 20 101 11 error: x\n"
+        );
+
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            let err = Lox::new()
+                .with_backend(backend)
+                .with_options(options)
+                .with_output(Capture::default())
+                .run_source("class A {}\nclass B {}\nclass B < A {}")
+                .unwrap_err();
+
+            assert!(
+                err.to_string()
+                    .contains("Can't change the superclass of `B` by reopening it"),
+                "{backend:?}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn deep_recursion_is_a_stack_overflow() {
+        let options = LoxOptions::default().with_max_call_depth(50);
+        let err = Lox::new()
+            .with_backend(Backend::Vm)
+            .with_options(options)
+            .run_source("fun f(n) { return f(n + 1); } f(0);")
+            .unwrap_err();
+
+        assert!(err.to_string().contains("Stack overflow"), "{err}");
+    }
+
+    #[test]
+    fn the_default_limits_fit_a_spawned_threads_stack() {
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            let printed = std::thread::spawn(move || {
+                let capture = Capture::default();
+                let result = Lox::new()
+                    .with_backend(backend)
+                    .with_output(capture.clone())
+                    .run_source(
+                        "fun f(n) { return 1 + f(n + 1); }
+                         try { f(0); } catch (e) { print e.kind; }",
+                    );
+                result.map(|()| capture.printed()).ok()
+            })
+            .join()
+            .unwrap();
+            assert_eq!(printed.as_deref(), Some("stackOverflow\n"), "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn limits_raise_errors_a_catch_can_tell_apart() {
+        let source = "fun f(n) { return f(n + 1); }\n\
+                      try { f(0); } catch (e) { print e.kind, e.message; }\n\
+                      try { while (true) {} } catch (e) { print e.kind; }\n\
+                      try { throw Error(\"no\"); } catch (e) { print e.kind; }\n\
+                      try { nil(); } catch (e) { print e.kind; }";
+        let options = LoxOptions::default()
+            .with_max_call_depth(20)
+            .with_max_steps(5000);
+        assert_eq!(
+            run_with(options, source),
+            "stackOverflow Stack overflow\nstepLimit\nnil\nruntime\n"
+        );
+
+        // Catching it only buys a little time.
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            let err = Lox::new()
+                .with_backend(backend)
+                .with_options(LoxOptions::default().with_max_steps(100))
+                .with_output(Capture::default())
+                .run_source("while (true) { try { while (true) {} } catch (e) {} }")
+                .unwrap_err();
+            assert!(
+                err.to_string().contains("Exceeded the limit of 100 steps"),
+                "{backend:?}: {err}"
+            );
+        }
+
+        // The kind comes from what raised the error, not from its message.
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            let capture = Capture::default();
+            let mut lox = Lox::new()
+                .with_backend(backend)
+                .with_output(capture.clone());
+            lox.define_native("fail", 0, |_, _| {
+                Err(RuntimeError::GeneralError("Stack overflow".into()))
+            });
+            let source = "try { fail(); } catch (e) { print e.kind, e.message; }";
+            assert!(lox.run_source(source).is_ok(), "{backend:?}");
+            assert_eq!(capture.printed(), "runtime Stack overflow\n", "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn integers_keep_integer_arithmetic_when_enabled() {
+        let integers = LoxOptions::default().with_integers(true);
+        assert_eq!(
+            run_with(
+                integers,
+                "var i = 9; i++; print 7 / 2, -7 % 2, 2 ** 62, 2 ** -1, i, 1 + 0.5, 3 == 3.0;"
+            ),
+            "3 -1 4611686018427387904 0.5 10 1.5 true\n"
+        );
+        assert_eq!(run("print 7 / 2;"), "3.5\n");
+
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            for (source, message) in [
+                ("print 9223372036854775807 + 1;", "Integer overflow"),
+                (
+                    "var n = -9223372036854775807 - 1; print -n;",
+                    "Integer overflow",
+                ),
+                ("print 1 % 0;", "Division by zero"),
+            ] {
+                let err = Lox::new()
+                    .with_backend(backend)
+                    .with_options(integers)
+                    .with_output(Capture::default())
+                    .run_source(source)
+                    .unwrap_err();
+                assert!(err.to_string().contains(message), "{backend:?}: {err}");
+            }
+        }
+    }
+}
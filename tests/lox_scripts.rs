@@ -0,0 +1,40 @@
+//! Runs the scripts under `tests/lox` with `rlox test` on both backends, so
+//! `cargo test` checks them along with the unit tests. The conformance
+//! scripts, whose expectations come from jlox, are also run as the book's
+//! Lox.
+
+use std::process::Command;
+
+fn rlox_test(options: &[&str], dir: &str) {
+    let dir = format!("{}/tests/lox/{dir}", env!("CARGO_MANIFEST_DIR"));
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(options)
+        .args(["test", &dir])
+        .output()
+        .expect("failed to run rlox");
+
+    assert!(
+        output.status.success(),
+        "`rlox {} test {dir}` failed:\n{}{}",
+        options.join(" "),
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr),
+    );
+}
+
+#[test]
+fn scripts_pass_on_the_tree_walker() {
+    rlox_test(&["--backend=tree"], "");
+}
+
+#[test]
+fn scripts_pass_on_the_vm() {
+    rlox_test(&["--backend=vm"], "");
+}
+
+#[test]
+fn conformance_scripts_pass_as_the_books_lox() {
+    for backend in ["--backend=tree", "--backend=vm"] {
+        rlox_test(&[backend, "--dialect=book"], "conformance");
+    }
+}
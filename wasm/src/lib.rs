@@ -0,0 +1,68 @@
+//! JavaScript bindings for running Lox in a web page, such as a playground.
+//!
+//! ```js
+//! import init, { run } from "./pkg/rlox_wasm.js";
+//!
+//! await init();
+//! const value = run("print 1 + 2; 3 * 4", (line) => console.log(line));
+//! ```
+//!
+//! There is no filesystem or stdin in the browser, so `readFile` and
+//! `writeFile` are disabled and `readLine` always returns `nil`. Calls
+//! can't nest as deeply as in the `rlox` binary, since the tree-walking
+//! interpreter recurses on the WebAssembly stack.
+
+use js_sys::{Function, Math};
+use rlox::{Lox, LoxOptions, Value};
+use wasm_bindgen::prelude::*;
+
+/// How deeply calls may nest. The WebAssembly stack is a fixed 1 MiB by
+/// default, far smaller than the thread `rlox` runs scripts on.
+const MAX_CALL_DEPTH: usize = 128;
+
+/// How much of the WebAssembly stack calls may use, leaving the rest of the
+/// 1 MiB for the bindings' frames and the deepest call's.
+const MAX_STACK: usize = 512 * 1024;
+
+/// Runs `source` in a fresh interpreter, calling `print` with each line the
+/// program prints. Returns the value of the last statement if it is an
+/// expression, as `Lox::eval` does, and throws the error message if the
+/// program fails.
+#[wasm_bindgen]
+pub fn run(source: &str, print: Function) -> Result<JsValue, JsValue> {
+    let options = LoxOptions::default()
+        .with_max_call_depth(MAX_CALL_DEPTH)
+        .with_max_stack(MAX_STACK)
+        .with_filesystem(false);
+    let mut lox = Lox::new()
+        .with_options(options)
+        .with_output(move |line: &str| {
+            // A callback that throws can't stop the program, so its error
+            // is dropped.
+            let _ = print.call1(&JsValue::NULL, &JsValue::from_str(line));
+        });
+
+    // `std::time` panics on wasm32-unknown-unknown, so the natives that read
+    // the clock are replaced with ones that ask JavaScript.
+    lox.define_native("clock", 0, |_, _| {
+        Ok(Value::Number(js_sys::Date::now() / 1000.0))
+    });
+    lox.define_native("random", 0, |_, _| Ok(Value::Number(Math::random())));
+
+    match lox.eval(source) {
+        Ok(value) => Ok(to_js(&value)),
+        Err(err) => Err(JsValue::from_str(&err.to_string())),
+    }
+}
+
+/// Numbers, strings, booleans and `nil` become their JavaScript
+/// counterparts; anything else becomes the string Lox would print for it.
+fn to_js(value: &Value) -> JsValue {
+    match value {
+        Value::Nil => JsValue::NULL,
+        Value::Bool(b) => JsValue::from_bool(*b),
+        Value::Number(n) => JsValue::from_f64(*n),
+        Value::String(s) => JsValue::from_str(s),
+        value => JsValue::from_str(&value.to_string()),
+    }
+}